@@ -0,0 +1,116 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use tiny_cc::driver;
+use tiny_cc::lexer::Lexer;
+use tiny_cc::parser::Parser;
+use tiny_cc::token::TokenType;
+use tiny_cc::vm::TinyMachine;
+
+// 生成的程序的语句数
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+// 生成有count个最外层语句的程序：先为所有的变量赋值，之后为赋值、if与write语句
+// 程序能通过语义检查，每个语句都只执行一次
+fn generate(count: usize) -> String {
+    const VARIABLES: [&str; 16] = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p",
+    ];
+    let mut source = String::new();
+    for i in 0..count {
+        let x = VARIABLES[i % VARIABLES.len()];
+        let y = VARIABLES[(i + 5) % VARIABLES.len()];
+        let z = VARIABLES[(i + 11) % VARIABLES.len()];
+        let stmt = if i < VARIABLES.len() {
+            format!("{} := {}", x, i)
+        } else {
+            match i % 4 {
+                0 | 1 => format!("{} := {} * 3 + {} / 7 - {}", x, y, z, i % 100),
+                2 => format!("if {} < {} then {} := {} + 1 else write {} end", x, y, z, x, y),
+                _ => format!("write {} - {}", x, z),
+            }
+        };
+        source.push_str(&stmt);
+        source.push_str(if i + 1 < count { ";\n" } else { "\n" });
+    }
+    source
+}
+
+fn lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for count in SIZES {
+        let source = generate(count);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("tokens", count), &source, |b, source| {
+            b.iter(|| {
+                let mut lexer = Lexer::new(source);
+                while lexer.next_token().token_type != TokenType::Eof {}
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("lexemes", count), &source, |b, source| {
+            b.iter(|| {
+                let mut lexer = Lexer::new(source);
+                while lexer.next_lexeme().token_type != TokenType::Eof {}
+            })
+        });
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for count in SIZES {
+        let source = generate(count);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("program", count), &source, |b, source| {
+            b.iter(|| Parser::new(source).parse_program().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("arena", count), &source, |b, source| {
+            b.iter(|| Parser::new(source).parse_arena().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10);
+    for count in SIZES {
+        let source = generate(count);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &source, |b, source| {
+            b.iter(|| driver::compile(source).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// 虚拟机每秒执行的指令数，程序为嵌套的循环
+fn vm(c: &mut Criterion) {
+    let source = "
+read n;
+sum := 0;
+i := 0;
+while i < n do
+    j := 0;
+    repeat
+        sum := sum + i * j - j / 3;
+        j := j + 1
+    until j = 100;
+    i := i + 1
+end;
+write sum";
+    let artifacts = driver::compile(source).unwrap();
+    let run = || {
+        let mut vm = TinyMachine::new("100".as_bytes(), vec![]).with_memory_size(artifacts.memory_size);
+        vm.load(&artifacts.listing).unwrap();
+        vm.run().unwrap();
+        vm.executed()
+    };
+    let mut group = c.benchmark_group("vm");
+    group.throughput(Throughput::Elements(run()));
+    group.bench_function("nested_loops", |b| b.iter(|| black_box(run())));
+    group.finish();
+}
+
+criterion_group!(benches, lex, parse, compile, vm);
+criterion_main!(benches);
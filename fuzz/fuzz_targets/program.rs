@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tiny_cc::ast::Program;
+
+// 生成的程序总能通过语义检查，编译或运行失败都是编译器的缺陷
+fuzz_target!(|program: Program| tiny_cc::fuzz::check_program(&program));
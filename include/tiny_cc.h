@@ -0,0 +1,32 @@
+/* tiny_cc的C接口，实现见 src/ffi.rs
+ * 构建动态库：cargo rustc --lib --release --crate-type cdylib --features ffi
+ * 字符串都是以NUL结尾的UTF-8字符串 */
+#ifndef TINY_CC_H
+#define TINY_CC_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TINY_OK 0
+/* 参数为空指针，或源程序不是UTF-8 */
+#define TINY_INVALID_ARGUMENT 1
+/* 源程序有语法或语义错误 */
+#define TINY_COMPILE_ERROR 2
+
+/* 以默认选项编译source，成功时*out_buf为TM代码清单，须以tiny_free释放
+ * 失败时*out_buf为NULL，返回错误码，错误信息见tiny_last_error */
+int tiny_compile(const char *source, char **out_buf);
+
+/* 释放tiny_compile返回的字符串，buf为NULL时什么也不做 */
+void tiny_free(char *buf);
+
+/* 当前线程最近一次失败的调用的错误信息，每行一个错误，没有时为NULL
+ * 返回的字符串属于本库，在当前线程下一次调用tiny_compile之前有效 */
+const char *tiny_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,522 @@
+use crate::ast::{
+    AssignStatement, BlockStatement, Boolean, CallExpression, CallStatement, ErrorExpression, ErrorStatement, Expr,
+    FunctionStatement, Identifier, IfStatement, IndexExpression, InfixExpression, Number, PrefixExpression, Program,
+    ReadStatement, RepeatStatement, ReturnStatement, Stmt, StringLiteral, VarStatement, WhileStatement, WriteStatement,
+};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{Span, Token};
+
+// 以编号引用子节点的AST：所有的表达式与语句分别连续地存放在 Ast 中，不为每个节点单独分配内存，
+// 子节点总是存放在父节点之前，遍历大的程序时局部性更好
+// 由 Parser::parse_arena 直接构造，或由 Ast::from_program 转换；
+// 其余各阶段仍以 Program 为输入，可由 Ast::to_program 转换
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StmtId(u32);
+
+// 连续存放的一组实参，见 Ast::exprs
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ExprList {
+    start: u32,
+    len: u32,
+}
+
+// 连续存放的语句块中的语句，见 Ast::stmts
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct StmtList {
+    start: u32,
+    len: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprNode {
+    Prefix {
+        op: Token,
+        right: ExprId,
+        span: Span,
+    },
+    Infix {
+        op: Token,
+        left: ExprId,
+        right: ExprId,
+        span: Span,
+    },
+    Index {
+        name: Identifier,
+        index: ExprId,
+        span: Span,
+    },
+    Call {
+        name: Identifier,
+        args: ExprList,
+        span: Span,
+    },
+    Identifier(Identifier),
+    Number(Number),
+    Boolean(Boolean),
+    String(StringLiteral),
+    Error(ErrorExpression),
+}
+
+impl ExprNode {
+    pub fn span(&self) -> Span {
+        match self {
+            ExprNode::Prefix { span, .. }
+            | ExprNode::Infix { span, .. }
+            | ExprNode::Index { span, .. }
+            | ExprNode::Call { span, .. } => *span,
+            ExprNode::Identifier(ident) => ident.span,
+            ExprNode::Number(number) => number.span,
+            ExprNode::Boolean(boolean) => boolean.span,
+            ExprNode::String(string) => string.span,
+            ExprNode::Error(error) => error.span,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Block {
+    pub statements: StmtList,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtNode {
+    Assign {
+        name: Identifier,
+        index: Option<ExprId>,
+        value: ExprId,
+        span: Span,
+    },
+    If {
+        cond: ExprId,
+        consequence: Block,
+        alternative: Option<Block>,
+        span: Span,
+    },
+    Repeat {
+        consequence: Block,
+        cond: ExprId,
+        span: Span,
+    },
+    While {
+        cond: ExprId,
+        consequence: Block,
+        span: Span,
+    },
+    Read(ReadStatement),
+    Write {
+        value: ExprId,
+        span: Span,
+    },
+    Var(VarStatement),
+    Function {
+        name: Identifier,
+        params: Vec<Identifier>,
+        body: Block,
+        span: Span,
+    },
+    Return {
+        value: Option<ExprId>,
+        span: Span,
+    },
+    // call总是 ExprNode::Call
+    Call {
+        call: ExprId,
+        span: Span,
+    },
+    Error(ErrorStatement),
+}
+
+impl StmtNode {
+    pub fn span(&self) -> Span {
+        match self {
+            StmtNode::Assign { span, .. }
+            | StmtNode::If { span, .. }
+            | StmtNode::Repeat { span, .. }
+            | StmtNode::While { span, .. }
+            | StmtNode::Write { span, .. }
+            | StmtNode::Function { span, .. }
+            | StmtNode::Return { span, .. }
+            | StmtNode::Call { span, .. } => *span,
+            StmtNode::Read(read) => read.span,
+            StmtNode::Var(var) => var.span,
+            StmtNode::Error(error) => error.span,
+        }
+    }
+}
+
+// 不保存注释
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    pub name: Option<String>,
+    pub span: Span,
+    exprs: Vec<ExprNode>,
+    stmts: Vec<StmtNode>,
+    // ExprList与StmtList引用的编号
+    expr_lists: Vec<ExprId>,
+    stmt_lists: Vec<StmtId>,
+    // 最外层的语句
+    statements: Vec<StmtId>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_program(program: Program) -> Self {
+        let mut ast = Self {
+            name: program.name,
+            span: program.span,
+            ..Self::default()
+        };
+        for stmt in program.statements {
+            ast.push(stmt);
+        }
+        ast
+    }
+
+    pub fn to_program(&self) -> Program {
+        let mut program = Program::new();
+        program.name = self.name.clone();
+        program.span = self.span;
+        program.statements = self.statements.iter().map(|id| self.to_stmt(*id)).collect();
+        program
+    }
+
+    // 存入一个最外层语句
+    pub fn push(&mut self, stmt: Stmt) -> StmtId {
+        let id = self.add_stmt(stmt);
+        self.statements.push(id);
+        id
+    }
+
+    pub fn statements(&self) -> &[StmtId] {
+        &self.statements
+    }
+
+    pub fn expr(&self, id: ExprId) -> &ExprNode {
+        &self.exprs[id.0 as usize]
+    }
+
+    pub fn stmt(&self, id: StmtId) -> &StmtNode {
+        &self.stmts[id.0 as usize]
+    }
+
+    pub fn exprs(&self, list: ExprList) -> &[ExprId] {
+        &self.expr_lists[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    pub fn stmts(&self, list: StmtList) -> &[StmtId] {
+        &self.stmt_lists[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    // 表达式与语句的节点数
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    fn add_expr(&mut self, expr: Expr) -> ExprId {
+        let node = match expr {
+            Expr::Prefix(prefix) => ExprNode::Prefix {
+                op: prefix.op,
+                right: self.add_expr(*prefix.right),
+                span: prefix.span,
+            },
+            Expr::Infix(infix) => ExprNode::Infix {
+                op: infix.op,
+                left: self.add_expr(*infix.left),
+                right: self.add_expr(*infix.right),
+                span: infix.span,
+            },
+            Expr::Index(index) => ExprNode::Index {
+                name: index.name,
+                index: self.add_expr(*index.index),
+                span: index.span,
+            },
+            Expr::Call(call) => self.call_node(call),
+            Expr::Identifier(ident) => ExprNode::Identifier(ident),
+            Expr::Number(number) => ExprNode::Number(number),
+            Expr::Boolean(boolean) => ExprNode::Boolean(boolean),
+            Expr::String(string) => ExprNode::String(string),
+            Expr::Error(error) => ExprNode::Error(error),
+        };
+        self.exprs.push(node);
+        ExprId(self.exprs.len() as u32 - 1)
+    }
+
+    fn call_node(&mut self, call: CallExpression) -> ExprNode {
+        let args: Vec<_> = call.args.into_iter().map(|arg| self.add_expr(arg)).collect();
+        let start = self.expr_lists.len() as u32;
+        self.expr_lists.extend(&args);
+        ExprNode::Call {
+            name: call.name,
+            args: ExprList {
+                start,
+                len: args.len() as u32,
+            },
+            span: call.span,
+        }
+    }
+
+    fn add_block(&mut self, block: BlockStatement) -> Block {
+        let ids: Vec<_> = block.statements.into_iter().map(|stmt| self.add_stmt(stmt)).collect();
+        let start = self.stmt_lists.len() as u32;
+        self.stmt_lists.extend(&ids);
+        Block {
+            statements: StmtList {
+                start,
+                len: ids.len() as u32,
+            },
+            span: block.span,
+        }
+    }
+
+    fn add_stmt(&mut self, stmt: Stmt) -> StmtId {
+        let node = match stmt {
+            Stmt::Assign(assign) => StmtNode::Assign {
+                name: assign.name,
+                index: assign.index.map(|index| self.add_expr(index)),
+                value: self.add_expr(assign.value),
+                span: assign.span,
+            },
+            Stmt::If(if_stmt) => StmtNode::If {
+                cond: self.add_expr(if_stmt.cond),
+                consequence: self.add_block(if_stmt.consequence),
+                alternative: if_stmt.alternative.map(|block| self.add_block(block)),
+                span: if_stmt.span,
+            },
+            Stmt::Repeat(repeat) => StmtNode::Repeat {
+                consequence: self.add_block(repeat.consequence),
+                cond: self.add_expr(repeat.cond),
+                span: repeat.span,
+            },
+            Stmt::While(while_stmt) => StmtNode::While {
+                cond: self.add_expr(while_stmt.cond),
+                consequence: self.add_block(while_stmt.consequence),
+                span: while_stmt.span,
+            },
+            Stmt::Read(read) => StmtNode::Read(read),
+            Stmt::Write(write) => StmtNode::Write {
+                value: self.add_expr(write.value),
+                span: write.span,
+            },
+            Stmt::Var(var) => StmtNode::Var(var),
+            Stmt::Function(function) => StmtNode::Function {
+                name: function.name,
+                params: function.params,
+                body: self.add_block(function.body),
+                span: function.span,
+            },
+            Stmt::Return(ret) => StmtNode::Return {
+                value: ret.value.map(|value| self.add_expr(value)),
+                span: ret.span,
+            },
+            Stmt::Call(call) => {
+                let node = self.call_node(call.call);
+                self.exprs.push(node);
+                StmtNode::Call {
+                    call: ExprId(self.exprs.len() as u32 - 1),
+                    span: call.span,
+                }
+            }
+            Stmt::Error(error) => StmtNode::Error(error),
+        };
+        self.stmts.push(node);
+        StmtId(self.stmts.len() as u32 - 1)
+    }
+
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        match self.expr(id) {
+            ExprNode::Prefix { op, right, span } => Expr::Prefix(PrefixExpression {
+                op: op.clone(),
+                right: Box::new(self.to_expr(*right)),
+                span: *span,
+            }),
+            ExprNode::Infix { op, left, right, span } => Expr::Infix(InfixExpression {
+                op: op.clone(),
+                left: Box::new(self.to_expr(*left)),
+                right: Box::new(self.to_expr(*right)),
+                span: *span,
+            }),
+            ExprNode::Index { name, index, span } => Expr::Index(IndexExpression {
+                name: name.clone(),
+                index: Box::new(self.to_expr(*index)),
+                span: *span,
+            }),
+            ExprNode::Call { .. } => Expr::Call(self.to_call(id)),
+            ExprNode::Identifier(ident) => Expr::Identifier(ident.clone()),
+            ExprNode::Number(number) => Expr::Number(number.clone()),
+            ExprNode::Boolean(boolean) => Expr::Boolean(boolean.clone()),
+            ExprNode::String(string) => Expr::String(string.clone()),
+            ExprNode::Error(error) => Expr::Error(error.clone()),
+        }
+    }
+
+    fn to_call(&self, id: ExprId) -> CallExpression {
+        match self.expr(id) {
+            ExprNode::Call { name, args, span } => CallExpression {
+                name: name.clone(),
+                args: self.exprs(*args).iter().map(|arg| self.to_expr(*arg)).collect(),
+                span: *span,
+            },
+            node => panic!("expected a call, found {:?}", node),
+        }
+    }
+
+    fn to_block(&self, block: &Block) -> BlockStatement {
+        BlockStatement {
+            statements: self
+                .stmts(block.statements)
+                .iter()
+                .map(|id| self.to_stmt(*id))
+                .collect(),
+            span: block.span,
+        }
+    }
+
+    pub fn to_stmt(&self, id: StmtId) -> Stmt {
+        match self.stmt(id) {
+            StmtNode::Assign {
+                name,
+                index,
+                value,
+                span,
+            } => Stmt::Assign(AssignStatement {
+                name: name.clone(),
+                index: index.map(|index| self.to_expr(index)),
+                value: self.to_expr(*value),
+                span: *span,
+            }),
+            StmtNode::If {
+                cond,
+                consequence,
+                alternative,
+                span,
+            } => Stmt::If(IfStatement {
+                cond: self.to_expr(*cond),
+                consequence: self.to_block(consequence),
+                alternative: alternative.as_ref().map(|block| self.to_block(block)),
+                span: *span,
+            }),
+            StmtNode::Repeat {
+                consequence,
+                cond,
+                span,
+            } => Stmt::Repeat(RepeatStatement {
+                cond: self.to_expr(*cond),
+                consequence: self.to_block(consequence),
+                span: *span,
+            }),
+            StmtNode::While {
+                cond,
+                consequence,
+                span,
+            } => Stmt::While(WhileStatement {
+                cond: self.to_expr(*cond),
+                consequence: self.to_block(consequence),
+                span: *span,
+            }),
+            StmtNode::Read(read) => Stmt::Read(read.clone()),
+            StmtNode::Write { value, span } => Stmt::Write(WriteStatement {
+                value: self.to_expr(*value),
+                span: *span,
+            }),
+            StmtNode::Var(var) => Stmt::Var(var.clone()),
+            StmtNode::Function {
+                name,
+                params,
+                body,
+                span,
+            } => Stmt::Function(FunctionStatement {
+                name: name.clone(),
+                params: params.clone(),
+                body: self.to_block(body),
+                span: *span,
+            }),
+            StmtNode::Return { value, span } => Stmt::Return(ReturnStatement {
+                value: value.map(|value| self.to_expr(value)),
+                span: *span,
+            }),
+            StmtNode::Call { call, span } => Stmt::Call(CallStatement {
+                call: self.to_call(*call),
+                span: *span,
+            }),
+            StmtNode::Error(error) => Stmt::Error(error.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arena::{Ast, ExprNode, StmtNode};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_round_trip() {
+        let input = "
+fn add(a, b) return a + b end;
+var v[4];
+read x;
+if not x < 0 then v[x / 2] := add(x, -1) * 3 else write \"negative\" end;
+repeat x := x - 1; while x > 2 do x := x / 2 end until x <= 0;
+add(1, add(2, 3));
+write v[1]";
+        let program = Parser::new(input).parse_program().unwrap();
+        let ast = Ast::from_program(program.clone());
+        assert_eq!(ast.statements().len(), program.statements.len());
+        assert_eq!(ast.to_program().dump(), program.dump());
+
+        let parsed = Parser::new(input).parse_arena().unwrap();
+        assert_eq!(parsed.to_program().dump(), program.dump());
+        assert_eq!(parsed.expr_count(), ast.expr_count());
+        assert_eq!(parsed.stmt_count(), ast.stmt_count());
+    }
+
+    #[test]
+    fn test_layout() {
+        let ast = Parser::new("x := 1 + 2 * 3; f(x, 4); repeat write x until true")
+            .parse_arena()
+            .unwrap();
+        // 子节点总是存放在父节点之前
+        let StmtNode::Assign { value, .. } = ast.stmt(ast.statements()[0]) else {
+            unreachable!()
+        };
+        let ExprNode::Infix { left, right, .. } = ast.expr(*value) else {
+            unreachable!()
+        };
+        assert!(left < right && right < value);
+        let StmtNode::Call { call, .. } = ast.stmt(ast.statements()[1]) else {
+            unreachable!()
+        };
+        let ExprNode::Call { args, .. } = ast.expr(*call) else {
+            unreachable!()
+        };
+        assert_eq!(ast.exprs(*args).len(), 2);
+        let StmtNode::Repeat { consequence, .. } = ast.stmt(ast.statements()[2]) else {
+            unreachable!()
+        };
+        let body = ast.stmts(consequence.statements);
+        assert_eq!(body.len(), 1);
+        assert!(body[0] < ast.statements()[2]);
+        assert_eq!(ast.stmt(body[0]).span().column, 33);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for input in ["x := ; write 1", "program p; write 1 end", "program p; x := 1 end."] {
+            let expected = Parser::new(input).parse_program().map(|program| program.dump());
+            let actual = Parser::new(input).parse_arena().map(|ast| ast.to_program().dump());
+            assert_eq!(actual, expected, "{:?}", input);
+        }
+    }
+}
@@ -1,45 +1,421 @@
-use crate::token::{Token, TokenType};
-use std::any::Any;
-use std::fmt::{Debug, Formatter};
+use crate::interner::Name;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{Span, Token, TokenType};
+use alloc::collections::VecDeque;
+use core::fmt::{Debug, Formatter};
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NodeType {
     Program,
 
     AssignStatement,
     IfStatement,
     RepeatStatement,
+    WhileStatement,
     BlockStatement,
     ReadStatement,
     WriteStatement,
+    VarStatement,
+    FunctionStatement,
+    ReturnStatement,
+    CallStatement,
+    ErrorStatement,
 
+    PrefixExpression,
     InfixExpression,
+    IndexExpression,
+    CallExpression,
+    ErrorExpression,
     Identifier,
     Number,
+    Boolean,
+    StringLiteral,
+}
+
+impl NodeType {
+    pub fn is_statement(self) -> bool {
+        matches!(
+            self,
+            NodeType::Program
+                | NodeType::AssignStatement
+                | NodeType::IfStatement
+                | NodeType::RepeatStatement
+                | NodeType::WhileStatement
+                | NodeType::BlockStatement
+                | NodeType::ReadStatement
+                | NodeType::WriteStatement
+                | NodeType::VarStatement
+                | NodeType::FunctionStatement
+                | NodeType::ReturnStatement
+                | NodeType::CallStatement
+                | NodeType::ErrorStatement
+        )
+    }
+
+    pub fn is_expression(self) -> bool {
+        !self.is_statement()
+    }
+
+    pub fn is_error(self) -> bool {
+        matches!(self, NodeType::ErrorStatement | NodeType::ErrorExpression)
+    }
 }
 
 pub trait Node {
     fn token_type(&self) -> TokenType;
     fn node_type(&self) -> NodeType;
-    fn as_any(&self) -> &dyn Any;
+    // 节点在源程序中的位置
+    fn span(&self) -> Span;
 }
 
-pub trait Statement: Debug + Node {}
+// 语句，编译器等直接对其做模式匹配
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Stmt {
+    Assign(AssignStatement),
+    If(IfStatement),
+    Repeat(RepeatStatement),
+    While(WhileStatement),
+    Read(ReadStatement),
+    Write(WriteStatement),
+    Var(VarStatement),
+    Function(FunctionStatement),
+    Return(ReturnStatement),
+    // 调用函数并丢弃返回值
+    Call(CallStatement),
+    Error(ErrorStatement),
+}
 
-pub trait Expression: Debug + Node {}
+impl Stmt {
+    fn inner(&self) -> &dyn Node {
+        match self {
+            Stmt::Assign(stmt) => stmt,
+            Stmt::If(stmt) => stmt,
+            Stmt::Repeat(stmt) => stmt,
+            Stmt::While(stmt) => stmt,
+            Stmt::Read(stmt) => stmt,
+            Stmt::Write(stmt) => stmt,
+            Stmt::Var(stmt) => stmt,
+            Stmt::Function(stmt) => stmt,
+            Stmt::Return(stmt) => stmt,
+            Stmt::Call(stmt) => stmt,
+            Stmt::Error(stmt) => stmt,
+        }
+    }
 
+    // 对语句及其所有子节点中的每个span调用f，包括运算符与被跳过的token的span
+    pub fn for_each_span_mut(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            Stmt::Assign(stmt) => {
+                f(&mut stmt.name.span);
+                if let Some(index) = &mut stmt.index {
+                    index.for_each_span_mut(f);
+                }
+                stmt.value.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::If(stmt) => {
+                stmt.cond.for_each_span_mut(f);
+                stmt.consequence.for_each_span_mut(f);
+                if let Some(alternative) = &mut stmt.alternative {
+                    alternative.for_each_span_mut(f);
+                }
+                f(&mut stmt.span);
+            }
+            Stmt::Repeat(stmt) => {
+                stmt.consequence.for_each_span_mut(f);
+                stmt.cond.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::While(stmt) => {
+                stmt.cond.for_each_span_mut(f);
+                stmt.consequence.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::Read(stmt) => {
+                f(&mut stmt.name.span);
+                f(&mut stmt.span);
+            }
+            Stmt::Write(stmt) => {
+                stmt.value.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::Var(stmt) => {
+                for declaration in &mut stmt.declarations {
+                    f(&mut declaration.name.span);
+                    if let Some(size) = &mut declaration.size {
+                        f(&mut size.span);
+                    }
+                }
+                f(&mut stmt.span);
+            }
+            Stmt::Function(stmt) => {
+                f(&mut stmt.name.span);
+                for param in &mut stmt.params {
+                    f(&mut param.span);
+                }
+                stmt.body.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::Return(stmt) => {
+                if let Some(value) = &mut stmt.value {
+                    value.for_each_span_mut(f);
+                }
+                f(&mut stmt.span);
+            }
+            Stmt::Call(stmt) => {
+                stmt.call.for_each_span_mut(f);
+                f(&mut stmt.span);
+            }
+            Stmt::Error(stmt) => {
+                for token in &mut stmt.skipped {
+                    f(&mut token.span);
+                }
+                f(&mut stmt.span);
+            }
+        }
+    }
+}
+
+// 与内部节点的Debug输出一致
+impl Debug for Stmt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Stmt::Assign(stmt) => stmt.fmt(f),
+            Stmt::If(stmt) => stmt.fmt(f),
+            Stmt::Repeat(stmt) => stmt.fmt(f),
+            Stmt::While(stmt) => stmt.fmt(f),
+            Stmt::Read(stmt) => stmt.fmt(f),
+            Stmt::Write(stmt) => stmt.fmt(f),
+            Stmt::Var(stmt) => stmt.fmt(f),
+            Stmt::Function(stmt) => stmt.fmt(f),
+            Stmt::Return(stmt) => stmt.fmt(f),
+            Stmt::Call(stmt) => stmt.fmt(f),
+            Stmt::Error(stmt) => stmt.fmt(f),
+        }
+    }
+}
+
+impl Node for Stmt {
+    fn token_type(&self) -> TokenType {
+        self.inner().token_type()
+    }
+
+    fn node_type(&self) -> NodeType {
+        self.inner().node_type()
+    }
+
+    fn span(&self) -> Span {
+        self.inner().span()
+    }
+}
+
+// 表达式
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expr {
+    Prefix(PrefixExpression),
+    Infix(InfixExpression),
+    // 数组元素 a[i]
+    Index(IndexExpression),
+    Call(CallExpression),
+    Identifier(Identifier),
+    Number(Number),
+    // true 或 false
+    Boolean(Boolean),
+    // 字符串只能出现在write语句中，见 semantic::check
+    String(StringLiteral),
+    Error(ErrorExpression),
+}
+
+impl Expr {
+    fn inner(&self) -> &dyn Node {
+        match self {
+            Expr::Prefix(expr) => expr,
+            Expr::Infix(expr) => expr,
+            Expr::Index(expr) => expr,
+            Expr::Call(expr) => expr,
+            Expr::Identifier(expr) => expr,
+            Expr::Number(expr) => expr,
+            Expr::Boolean(expr) => expr,
+            Expr::String(expr) => expr,
+            Expr::Error(expr) => expr,
+        }
+    }
+
+    // 见 Stmt::for_each_span_mut
+    pub fn for_each_span_mut(&mut self, f: &mut impl FnMut(&mut Span)) {
+        match self {
+            Expr::Prefix(expr) => {
+                f(&mut expr.op.span);
+                expr.right.for_each_span_mut(f);
+                f(&mut expr.span);
+            }
+            Expr::Infix(expr) => {
+                expr.left.for_each_span_mut(f);
+                f(&mut expr.op.span);
+                expr.right.for_each_span_mut(f);
+                f(&mut expr.span);
+            }
+            Expr::Index(expr) => {
+                f(&mut expr.name.span);
+                expr.index.for_each_span_mut(f);
+                f(&mut expr.span);
+            }
+            Expr::Call(expr) => expr.for_each_span_mut(f),
+            Expr::Identifier(expr) => f(&mut expr.span),
+            Expr::Number(expr) => f(&mut expr.span),
+            Expr::Boolean(expr) => f(&mut expr.span),
+            Expr::String(expr) => f(&mut expr.span),
+            Expr::Error(expr) => {
+                for token in &mut expr.skipped {
+                    f(&mut token.span);
+                }
+                f(&mut expr.span);
+            }
+        }
+    }
+}
+
+impl Debug for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Expr::Prefix(expr) => expr.fmt(f),
+            Expr::Infix(expr) => expr.fmt(f),
+            Expr::Index(expr) => expr.fmt(f),
+            Expr::Call(expr) => expr.fmt(f),
+            Expr::Identifier(expr) => expr.fmt(f),
+            Expr::Number(expr) => expr.fmt(f),
+            Expr::Boolean(expr) => expr.fmt(f),
+            Expr::String(expr) => expr.fmt(f),
+            Expr::Error(expr) => expr.fmt(f),
+        }
+    }
+}
+
+impl Node for Expr {
+    fn token_type(&self) -> TokenType {
+        self.inner().token_type()
+    }
+
+    fn node_type(&self) -> NodeType {
+        self.inner().node_type()
+    }
+
+    fn span(&self) -> Span {
+        self.inner().span()
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
-    pub statements: Vec<Box<dyn Statement>>,
+    // 可选的 program name; ... end. 头部中给出的程序名
+    pub name: Option<String>,
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+    // 源程序中的注释，只在 Parser::with_comments 时收集
+    pub comments: Vec<Comment>,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Program {
     pub fn new() -> Self {
-        Self { statements: vec![] }
+        Self {
+            name: None,
+            statements: vec![],
+            span: Span::default(),
+            comments: vec![],
+        }
+    }
+
+    // 按位置将注释附加到语句上：与一个简单语句在同一行且位于其后的注释附加在该语句之后，
+    // 其余的注释附加在其后的第一个语句之前；之后没有语句的注释不附加到任何语句
+    pub fn attach_comments(&mut self, comments: Vec<Token>) {
+        let mut statements = vec![];
+        collect_statements(&self.statements, &mut statements);
+        self.comments = comments
+            .into_iter()
+            .map(|token| {
+                let span = token.span;
+                // 在注释之前结束的语句中结束得最晚的一个，同时结束时取外层的语句
+                let previous = statements.iter().filter(|(stmt, _)| stmt.end <= span.start).fold(
+                    None,
+                    |previous: Option<&(Span, bool)>, stmt| match previous {
+                        Some(previous) if previous.0.end >= stmt.0.end => Some(previous),
+                        _ => Some(stmt),
+                    },
+                );
+                let attachment = match previous {
+                    Some((stmt, true)) if stmt.line == span.line => Some(Attachment::Trailing(*stmt)),
+                    _ => statements
+                        .iter()
+                        .find(|(stmt, _)| stmt.start >= span.end)
+                        .map(|(stmt, _)| Attachment::Leading(*stmt)),
+                };
+                Comment {
+                    text: token.literal,
+                    span,
+                    attachment,
+                }
+            })
+            .collect();
+    }
+}
+
+// 按源程序中的顺序列出所有语句的位置，以及是否为不含语句块的简单语句
+fn collect_statements(statements: &[Stmt], output: &mut Vec<(Span, bool)>) {
+    for stmt in statements {
+        let blocks: Vec<&BlockStatement> = match stmt {
+            Stmt::If(if_stmt) => [Some(&if_stmt.consequence), if_stmt.alternative.as_ref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Stmt::Repeat(repeat) => vec![&repeat.consequence],
+            Stmt::While(while_stmt) => vec![&while_stmt.consequence],
+            Stmt::Function(function) => vec![&function.body],
+            _ => vec![],
+        };
+        output.push((stmt.span(), blocks.is_empty()));
+        for block in blocks {
+            collect_statements(&block.statements, output);
+        }
     }
 }
 
+// 源程序中的一个注释
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comment {
+    // 包括花括号在内的注释文本
+    pub text: String,
+    pub span: Span,
+    // 注释所属的语句，见 Program::attach_comments
+    pub attachment: Option<Attachment>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Attachment {
+    // 位于该位置的语句之前
+    Leading(Span),
+    // 与该位置的语句在同一行，位于其后
+    Trailing(Span),
+}
+
 impl Debug for Program {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Program [")?;
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self.name {
+            Some(name) => writeln!(f, "Program {} [", name)?,
+            None => writeln!(f, "Program [")?,
+        }
         for stmt in &self.statements {
             writeln!(f, "{:?},", stmt)?;
         }
@@ -57,20 +433,31 @@ impl Node for Program {
         NodeType::Program
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for Program {}
-
 // tiny语言中块语句的结束标志为TokenType::End或TokenType::Until
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlockStatement {
-    pub statements: Vec<Box<dyn Statement>>,
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl BlockStatement {
+    // 见 Stmt::for_each_span_mut
+    pub fn for_each_span_mut(&mut self, f: &mut impl FnMut(&mut Span)) {
+        for stmt in &mut self.statements {
+            stmt.for_each_span_mut(f);
+        }
+        f(&mut self.span);
+    }
 }
 
 impl Debug for BlockStatement {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "BlockStatement [")?;
         for stmt in &self.statements {
             writeln!(f, "{:?},", stmt)?;
@@ -89,17 +476,19 @@ impl Node for BlockStatement {
         NodeType::BlockStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for BlockStatement {}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AssignStatement {
     pub name: Identifier,
-    pub value: Box<dyn Expression>,
+    // 为数组元素赋值时的下标，即 a[index] := value
+    pub index: Option<Expr>,
+    pub value: Expr,
+    pub span: Span,
 }
 
 impl Node for AssignStatement {
@@ -111,16 +500,16 @@ impl Node for AssignStatement {
         NodeType::AssignStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for AssignStatement {}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReadStatement {
     pub name: Identifier,
+    pub span: Span,
 }
 
 impl Node for ReadStatement {
@@ -132,16 +521,16 @@ impl Node for ReadStatement {
         NodeType::ReadStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for ReadStatement {}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WriteStatement {
-    pub name: Identifier,
+    pub value: Expr,
+    pub span: Span,
 }
 
 impl Node for WriteStatement {
@@ -153,23 +542,185 @@ impl Node for WriteStatement {
         NodeType::WriteStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// var a; 或 var a, b[10];，声明变量或固定大小的数组
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VarStatement {
+    // 按出现的顺序，至少有一个
+    pub declarations: Vec<Declaration>,
+    pub span: Span,
+}
+
+// var语句声明的一个变量或数组
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Declaration {
+    pub name: Identifier,
+    // 数组的元素个数，声明普通变量时为None
+    pub size: Option<Number>,
+}
+
+impl Node for VarStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::Var
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::VarStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for WriteStatement {}
+// fn name(params) ... end，只能定义在程序的最外层
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionStatement {
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    pub body: BlockStatement,
+    pub span: Span,
+}
 
+impl FunctionStatement {
+    // 函数的局部变量（不含参数），按第一次出现的顺序
+    // 以var声明的变量总是局部变量；被赋值或read的变量若不是参数，也不是is_global判定的全局变量，也是局部变量
+    pub fn locals(&self, is_global: impl Fn(&Name) -> bool) -> Vec<Name> {
+        let mut locals = vec![];
+        self.collect_locals(&self.body.statements, &is_global, &mut locals);
+        locals
+    }
+
+    fn collect_locals(&self, statements: &[Stmt], is_global: &impl Fn(&Name) -> bool, locals: &mut Vec<Name>) {
+        for stmt in statements {
+            let (name, declared) = match stmt {
+                Stmt::Assign(assign) if assign.index.is_none() => (&assign.name, false),
+                Stmt::Read(read) => (&read.name, false),
+                Stmt::Var(var) => {
+                    for declaration in &var.declarations {
+                        self.add_local(&declaration.name, true, is_global, locals);
+                    }
+                    continue;
+                }
+                Stmt::If(if_stmt) => {
+                    self.collect_locals(&if_stmt.consequence.statements, is_global, locals);
+                    if let Some(alternative) = &if_stmt.alternative {
+                        self.collect_locals(&alternative.statements, is_global, locals);
+                    }
+                    continue;
+                }
+                Stmt::Repeat(repeat) => {
+                    self.collect_locals(&repeat.consequence.statements, is_global, locals);
+                    continue;
+                }
+                Stmt::While(while_stmt) => {
+                    self.collect_locals(&while_stmt.consequence.statements, is_global, locals);
+                    continue;
+                }
+                _ => continue,
+            };
+            self.add_local(name, declared, is_global, locals);
+        }
+    }
+
+    fn add_local(&self, name: &Identifier, declared: bool, is_global: &impl Fn(&Name) -> bool, locals: &mut Vec<Name>) {
+        let is_param = self.params.iter().any(|param| param.value == name.value);
+        if !is_param && (declared || !is_global(&name.value)) && !locals.contains(&name.value) {
+            locals.push(name.value.clone());
+        }
+    }
+}
+
+impl Debug for FunctionStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let params: Vec<_> = self.params.iter().map(|param| param.value.as_str()).collect();
+        writeln!(f, "FunctionStatement {}({}) {{", self.name.value, params.join(", "))?;
+        write!(f, "body:\n{:?}}}", self.body)
+    }
+}
+
+impl Node for FunctionStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::Fn
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::FunctionStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// return; 或 return value;，省略返回值时返回0
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReturnStatement {
+    pub value: Option<Expr>,
+    pub span: Span,
+}
+
+impl Node for ReturnStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::Return
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::ReturnStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallStatement {
+    pub call: CallExpression,
+    pub span: Span,
+}
+
+impl Node for CallStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::LeftParen
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::CallStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IfStatement {
-    pub cond: Box<dyn Expression>,
+    pub cond: Expr,
     pub consequence: BlockStatement,
+    pub alternative: Option<BlockStatement>,
+    pub span: Span,
 }
 
 impl Debug for IfStatement {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "IfStatement {{")?;
         writeln!(f, "cond: {:?}", self.cond)?;
-        write!(f, "consequence:\n{:?}}}", self.consequence)?;
+        write!(f, "consequence:\n{:?}", self.consequence)?;
+        if let Some(alternative) = &self.alternative {
+            write!(f, "\nalternative:\n{:?}", alternative)?;
+        }
+        write!(f, "}}")?;
         Ok(())
     }
 }
@@ -183,20 +734,21 @@ impl Node for IfStatement {
         NodeType::IfStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Statement for IfStatement {}
-
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RepeatStatement {
-    pub cond: Box<dyn Expression>,
+    pub cond: Expr,
     pub consequence: BlockStatement,
+    pub span: Span,
 }
 
 impl Debug for RepeatStatement {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "RepeatStatement {{")?;
         writeln!(f, "cond: {:?}", self.cond)?;
         write!(f, "consequence:\n{:?}}}", self.consequence)?;
@@ -213,18 +765,73 @@ impl Node for RepeatStatement {
         NodeType::RepeatStatement
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// while cond do ... end，先判断条件再执行循环体
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WhileStatement {
+    pub cond: Expr,
+    pub consequence: BlockStatement,
+    pub span: Span,
+}
+
+impl Debug for WhileStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "WhileStatement {{")?;
+        writeln!(f, "cond: {:?}", self.cond)?;
+        write!(f, "consequence:\n{:?}}}", self.consequence)?;
+        Ok(())
+    }
+}
+
+impl Node for WhileStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::While
     }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::WhileStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// 前缀运算，目前只有取负 -x
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrefixExpression {
+    pub op: Token,
+    pub right: Box<Expr>,
+    pub span: Span,
 }
 
-impl Statement for RepeatStatement {}
+impl Node for PrefixExpression {
+    fn token_type(&self) -> TokenType {
+        self.op.token_type
+    }
 
-#[derive(Debug)]
+    fn node_type(&self) -> NodeType {
+        NodeType::PrefixExpression
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InfixExpression {
     pub op: Token,
-    pub left: Box<dyn Expression>,
-    pub right: Box<dyn Expression>,
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+    pub span: Span,
 }
 
 impl Node for InfixExpression {
@@ -236,16 +843,72 @@ impl Node for InfixExpression {
         NodeType::InfixExpression
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Expression for InfixExpression {}
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexExpression {
+    pub name: Identifier,
+    pub index: Box<Expr>,
+    pub span: Span,
+}
+
+impl Node for IndexExpression {
+    fn token_type(&self) -> TokenType {
+        TokenType::LeftBracket
+    }
 
-#[derive(Eq, PartialEq, Debug)]
+    fn node_type(&self) -> NodeType {
+        NodeType::IndexExpression
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// name(args)
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallExpression {
+    pub name: Identifier,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+impl CallExpression {
+    // 见 Stmt::for_each_span_mut
+    pub fn for_each_span_mut(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.name.span);
+        for arg in &mut self.args {
+            arg.for_each_span_mut(f);
+        }
+        f(&mut self.span);
+    }
+}
+
+impl Node for CallExpression {
+    fn token_type(&self) -> TokenType {
+        TokenType::LeftParen
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::CallExpression
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Identifier {
-    pub value: String,
+    pub value: Name,
+    pub span: Span,
 }
 
 impl Node for Identifier {
@@ -257,16 +920,16 @@ impl Node for Identifier {
         NodeType::Identifier
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
-impl Expression for Identifier {}
-
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Number {
     pub value: i32,
+    pub span: Span,
 }
 
 impl Node for Number {
@@ -278,21 +941,748 @@ impl Node for Number {
         NodeType::Number
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Boolean {
+    pub value: bool,
+    pub span: Span,
+}
+
+impl Node for Boolean {
+    fn token_type(&self) -> TokenType {
+        if self.value {
+            TokenType::True
+        } else {
+            TokenType::False
+        }
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::Boolean
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringLiteral {
+    // 不包括两端的引号
+    pub value: String,
+    pub span: Span,
+}
+
+impl Node for StringLiteral {
+    fn token_type(&self) -> TokenType {
+        TokenType::String
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::StringLiteral
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// 解析失败的语句或表达式，记录错误信息和错误恢复时跳过的token
+// 使得语法错误之后，程序的其余部分仍然可以被分析
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorStatement {
+    pub message: String,
+    pub skipped: Vec<Token>,
+    pub span: Span,
+}
+
+impl Debug for ErrorStatement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ErrorStatement ")?;
+        fmt_skipped(f, &self.skipped)
+    }
+}
+
+impl Node for ErrorStatement {
+    fn token_type(&self) -> TokenType {
+        TokenType::Illegal
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::ErrorStatement
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorExpression {
+    pub message: String,
+    pub skipped: Vec<Token>,
+    pub span: Span,
+}
+
+impl Debug for ErrorExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ErrorExpression ")?;
+        fmt_skipped(f, &self.skipped)
+    }
+}
+
+impl Node for ErrorExpression {
+    fn token_type(&self) -> TokenType {
+        TokenType::Illegal
+    }
+
+    fn node_type(&self) -> NodeType {
+        NodeType::ErrorExpression
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+// 由AST重新生成TINY源程序，每一层缩进4个空格
+// 原有的空白不会保留，注释只在解析时保留了注释才会输出；解析→输出→解析得到相同的AST
+pub fn to_source(program: &Program) -> String {
+    to_source_with_indent(program, 4)
+}
+
+// indent为每一层缩进的空格数
+// Program::comments 中的注释按所在的位置输出：附加在语句之后的注释保留在该语句的行末，其余的单独成行
+pub fn to_source_with_indent(program: &Program, indent: usize) -> String {
+    let mut printer = Printer {
+        output: String::new(),
+        indent,
+        level: 0,
+        comments: program.comments.iter().collect(),
+    };
+    match &program.name {
+        Some(name) => {
+            printer.comments_before(program.span.start);
+            printer.line(&format!("program {};", name));
+            printer.level += 1;
+            printer.statements(&program.statements);
+            printer.comments_before(program.span.end);
+            printer.level -= 1;
+            printer.line("end.");
+        }
+        None => printer.statements(&program.statements),
+    }
+    printer.comments_before(usize::MAX);
+    printer.output
+}
+
+struct Printer<'a> {
+    output: String,
+    indent: usize,
+    level: usize,
+    // 尚未输出的注释
+    comments: VecDeque<&'a Comment>,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&" ".repeat(self.indent * self.level));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    // 以当前的缩进输出offset之前的注释
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.front().filter(|comment| comment.span.start < offset) {
+            let text = comment.text.clone();
+            self.comments.pop_front();
+            self.line(&text);
+        }
+    }
+
+    // 将附加在stmt之后的注释接在最后一行的末尾
+    fn trailing_comments(&mut self, stmt: Span) {
+        while let Some(comment) = self
+            .comments
+            .front()
+            .filter(|comment| comment.attachment == Some(Attachment::Trailing(stmt)))
+        {
+            let text = format!(" {}\n", comment.text);
+            self.comments.pop_front();
+            self.output.pop();
+            self.output.push_str(&text);
+        }
+    }
+
+    // end为块之后的第一个位置，块末尾的注释以块的缩进输出
+    fn block(&mut self, block: &BlockStatement, end: usize) {
+        self.level += 1;
+        self.statements(&block.statements);
+        self.comments_before(end);
+        self.level -= 1;
     }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.comments_before(stmt.span().start);
+            self.statement(stmt);
+            self.trailing_comments(stmt.span());
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let end = stmt.span().end;
+        match stmt {
+            Stmt::Assign(assign) => {
+                let target = match &assign.index {
+                    Some(index) => format!("{}[{}]", assign.name.value, expr_source(index)),
+                    None => assign.name.value.to_string(),
+                };
+                self.line(&format!("{} := {};", target, expr_source(&assign.value)))
+            }
+            Stmt::Read(read) => self.line(&format!("read {};", read.name.value)),
+            Stmt::Var(var) => self.line(&format!("var {};", declarations_source(&var.declarations))),
+            Stmt::Function(function) => {
+                self.line(&format!(
+                    "fn {}",
+                    call_source(&function.name, &function.params, |param| param.value.to_string())
+                ));
+                self.block(&function.body, end);
+                self.line("end;");
+            }
+            Stmt::Return(ret) => match &ret.value {
+                Some(value) => self.line(&format!("return {};", expr_source(value))),
+                None => self.line("return;"),
+            },
+            Stmt::Call(call) => self.line(&format!("{};", expr_source_call(&call.call))),
+            Stmt::Write(write) => self.line(&format!("write {};", expr_source(&write.value))),
+            Stmt::If(if_stmt) => {
+                self.line(&format!("if {} then", expr_source(&if_stmt.cond)));
+                let alternative = if_stmt.alternative.as_ref();
+                self.block(&if_stmt.consequence, alternative.map_or(end, |block| block.span.start));
+                if let Some(alternative) = alternative {
+                    self.line("else");
+                    self.block(alternative, end);
+                }
+                self.line("end;");
+            }
+            Stmt::Repeat(repeat) => {
+                self.line("repeat");
+                self.block(&repeat.consequence, repeat.cond.span().start);
+                self.line(&format!("until {};", expr_source(&repeat.cond)));
+            }
+            Stmt::While(while_stmt) => {
+                self.line(&format!("while {} do", expr_source(&while_stmt.cond)));
+                self.block(&while_stmt.consequence, end);
+                self.line("end;");
+            }
+            // 原样输出错误恢复时跳过的token
+            Stmt::Error(error) => self.line(&tokens_source(&error.skipped)),
+        }
+    }
+}
+
+// Graphviz DOT格式的语法树，语句为方框，表达式为椭圆
+// 语句的各部分以带标签的边连接，如if的cond、then、else
+pub fn to_dot(program: &Program) -> String {
+    let mut writer = DotWriter {
+        output: String::from("digraph ast {\n"),
+        next: 0,
+    };
+    let label = match &program.name {
+        Some(name) => format!("program {}", name),
+        None => "program".to_string(),
+    };
+    let root = writer.node(&label, "box");
+    writer.statements(root, "", &program.statements);
+    writer.output.push_str("}\n");
+    writer.output
+}
+
+struct DotWriter {
+    output: String,
+    // 下一个节点的编号
+    next: usize,
+}
+
+impl DotWriter {
+    fn node(&mut self, label: &str, shape: &str) -> usize {
+        let id = self.next;
+        self.next += 1;
+        self.output.push_str(&format!(
+            "    n{} [label=\"{}\", shape={}];\n",
+            id,
+            dot_escape(label),
+            shape
+        ));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        if label.is_empty() {
+            self.output.push_str(&format!("    n{} -> n{};\n", from, to));
+        } else {
+            self.output
+                .push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", from, to, label));
+        }
+    }
+
+    fn statements(&mut self, parent: usize, label: &str, statements: &[Stmt]) {
+        for stmt in statements {
+            let child = self.statement(stmt);
+            self.edge(parent, child, label);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> usize {
+        match stmt {
+            Stmt::Assign(assign) => {
+                let id = self.node(&format!("assign {}", assign.name.value), "box");
+                if let Some(index) = &assign.index {
+                    let index = self.expression(index);
+                    self.edge(id, index, "index");
+                }
+                let value = self.expression(&assign.value);
+                self.edge(id, value, "");
+                id
+            }
+            Stmt::Read(read) => self.node(&format!("read {}", read.name.value), "box"),
+            Stmt::Var(var) => self.node(&format!("var {}", declarations_source(&var.declarations)), "box"),
+            Stmt::Function(function) => {
+                let label = format!(
+                    "fn {}",
+                    call_source(&function.name, &function.params, |param| param.value.to_string())
+                );
+                let id = self.node(&label, "box");
+                self.statements(id, "body", &function.body.statements);
+                id
+            }
+            Stmt::Return(ret) => {
+                let id = self.node("return", "box");
+                if let Some(value) = &ret.value {
+                    let value = self.expression(value);
+                    self.edge(id, value, "");
+                }
+                id
+            }
+            Stmt::Call(call) => self.call(&call.call, "box"),
+            Stmt::Write(write) => {
+                let id = self.node("write", "box");
+                let value = self.expression(&write.value);
+                self.edge(id, value, "");
+                id
+            }
+            Stmt::If(if_stmt) => {
+                let id = self.node("if", "box");
+                let cond = self.expression(&if_stmt.cond);
+                self.edge(id, cond, "cond");
+                self.statements(id, "then", &if_stmt.consequence.statements);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.statements(id, "else", &alternative.statements);
+                }
+                id
+            }
+            Stmt::Repeat(repeat) => {
+                let id = self.node("repeat", "box");
+                self.statements(id, "body", &repeat.consequence.statements);
+                let cond = self.expression(&repeat.cond);
+                self.edge(id, cond, "until");
+                id
+            }
+            Stmt::While(while_stmt) => {
+                let id = self.node("while", "box");
+                let cond = self.expression(&while_stmt.cond);
+                self.edge(id, cond, "cond");
+                self.statements(id, "body", &while_stmt.consequence.statements);
+                id
+            }
+            Stmt::Error(error) => self.node(&format!("error: {}", error.message), "box"),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> usize {
+        match expr {
+            Expr::Prefix(prefix) => {
+                let id = self.node(&format!("op {}", prefix.op.literal), "ellipse");
+                let right = self.expression(&prefix.right);
+                self.edge(id, right, "");
+                id
+            }
+            Expr::Infix(infix) => {
+                let id = self.node(&format!("op {}", infix.op.literal), "ellipse");
+                let left = self.expression(&infix.left);
+                self.edge(id, left, "");
+                let right = self.expression(&infix.right);
+                self.edge(id, right, "");
+                id
+            }
+            Expr::Index(index) => {
+                let id = self.node(&format!("index {}", index.name.value), "ellipse");
+                let child = self.expression(&index.index);
+                self.edge(id, child, "");
+                id
+            }
+            Expr::Call(call) => self.call(call, "ellipse"),
+            Expr::Identifier(ident) => self.node(&format!("id {}", ident.value), "ellipse"),
+            Expr::Number(number) => self.node(&format!("const {}", number.value), "ellipse"),
+            Expr::Boolean(boolean) => self.node(&format!("const {}", boolean.value), "ellipse"),
+            Expr::String(string) => self.node(&format!("string \"{}\"", string.value), "ellipse"),
+            Expr::Error(error) => self.node(&format!("error: {}", error.message), "ellipse"),
+        }
+    }
+
+    fn call(&mut self, call: &CallExpression, shape: &str) -> usize {
+        let id = self.node(&format!("call {}", call.name.value), shape);
+        for arg in &call.args {
+            let arg = self.expression(arg);
+            self.edge(id, arg, "");
+        }
+        id
+    }
+}
+
+// 语法树的文本格式，供golden测试与外部工具使用，各版本之间保持稳定：
+// - 每个节点一行，子节点比父节点多缩进2个空格
+// - 一行的格式为 `[角色: ]类型[ 属性] @行:列`，如 `cond: Infix < @2:4`
+// - 字段按固定的顺序输出：assign为index、value；if为cond、then、else；repeat为body、until；
+//   while为cond、body；infix为左、右操作数；call为各个参数
+// - 语句块输出为单独的一行 `角色:`，其中的语句作为它的子节点
+// - 字符串与错误信息以Rust的Debug格式加引号转义
+impl Program {
+    pub fn dump(&self) -> String {
+        let mut writer = DumpWriter { output: String::new() };
+        let name = self.name.as_ref().map_or(String::new(), |name| format!(" {}", name));
+        writer.line(0, "", &format!("Program{}", name), self.span);
+        for stmt in &self.statements {
+            writer.statement(1, "", stmt);
+        }
+        writer.output
+    }
+}
+
+struct DumpWriter {
+    output: String,
+}
+
+impl DumpWriter {
+    fn line(&mut self, depth: usize, role: &str, text: &str, span: Span) {
+        let role = if role.is_empty() {
+            String::new()
+        } else {
+            format!("{}: ", role)
+        };
+        self.output
+            .push_str(&format!("{}{}{} @{}\n", "  ".repeat(depth), role, text, span));
+    }
+
+    fn block(&mut self, depth: usize, role: &str, block: &BlockStatement) {
+        self.output.push_str(&format!("{}{}:\n", "  ".repeat(depth), role));
+        for stmt in &block.statements {
+            self.statement(depth + 1, "", stmt);
+        }
+    }
+
+    fn statement(&mut self, depth: usize, role: &str, stmt: &Stmt) {
+        let span = stmt.span();
+        match stmt {
+            Stmt::Assign(assign) => {
+                self.line(depth, role, &format!("Assign {}", assign.name.value), span);
+                if let Some(index) = &assign.index {
+                    self.expression(depth + 1, "index", index);
+                }
+                self.expression(depth + 1, "value", &assign.value);
+            }
+            Stmt::Read(read) => self.line(depth, role, &format!("Read {}", read.name.value), span),
+            Stmt::Write(write) => {
+                self.line(depth, role, "Write", span);
+                self.expression(depth + 1, "value", &write.value);
+            }
+            Stmt::Var(var) => self.line(
+                depth,
+                role,
+                &format!("Var {}", declarations_source(&var.declarations)),
+                span,
+            ),
+            Stmt::Function(function) => {
+                let label = call_source(&function.name, &function.params, |param| param.value.to_string());
+                self.line(depth, role, &format!("Function {}", label), span);
+                self.block(depth + 1, "body", &function.body);
+            }
+            Stmt::Return(ret) => {
+                self.line(depth, role, "Return", span);
+                if let Some(value) = &ret.value {
+                    self.expression(depth + 1, "value", value);
+                }
+            }
+            Stmt::Call(call) => self.call(depth, role, &call.call, span),
+            Stmt::If(if_stmt) => {
+                self.line(depth, role, "If", span);
+                self.expression(depth + 1, "cond", &if_stmt.cond);
+                self.block(depth + 1, "then", &if_stmt.consequence);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.block(depth + 1, "else", alternative);
+                }
+            }
+            Stmt::Repeat(repeat) => {
+                self.line(depth, role, "Repeat", span);
+                self.block(depth + 1, "body", &repeat.consequence);
+                self.expression(depth + 1, "until", &repeat.cond);
+            }
+            Stmt::While(while_stmt) => {
+                self.line(depth, role, "While", span);
+                self.expression(depth + 1, "cond", &while_stmt.cond);
+                self.block(depth + 1, "body", &while_stmt.consequence);
+            }
+            Stmt::Error(error) => self.line(depth, role, &format!("ErrorStatement {:?}", error.message), span),
+        }
+    }
+
+    fn expression(&mut self, depth: usize, role: &str, expr: &Expr) {
+        let span = expr.span();
+        match expr {
+            Expr::Prefix(prefix) => {
+                self.line(depth, role, &format!("Prefix {}", prefix.op.literal), span);
+                self.expression(depth + 1, "", &prefix.right);
+            }
+            Expr::Infix(infix) => {
+                self.line(depth, role, &format!("Infix {}", infix.op.literal), span);
+                self.expression(depth + 1, "", &infix.left);
+                self.expression(depth + 1, "", &infix.right);
+            }
+            Expr::Index(index) => {
+                self.line(depth, role, &format!("Index {}", index.name.value), span);
+                self.expression(depth + 1, "index", &index.index);
+            }
+            Expr::Call(call) => self.call(depth, role, call, span),
+            Expr::Identifier(ident) => self.line(depth, role, &format!("Identifier {}", ident.value), span),
+            Expr::Number(number) => self.line(depth, role, &format!("Number {}", number.value), span),
+            Expr::Boolean(boolean) => self.line(depth, role, &format!("Boolean {}", boolean.value), span),
+            Expr::String(string) => self.line(depth, role, &format!("String {:?}", string.value), span),
+            Expr::Error(error) => self.line(depth, role, &format!("ErrorExpression {:?}", error.message), span),
+        }
+    }
+
+    fn call(&mut self, depth: usize, role: &str, call: &CallExpression, span: Span) {
+        self.line(depth, role, &format!("Call {}", call.name.value), span);
+        for arg in &call.args {
+            self.expression(depth + 1, "", arg);
+        }
+    }
+}
+
+fn dot_escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn expr_source(expr: &Expr) -> String {
+    match expr {
+        // not 与操作数之间需要空格
+        Expr::Prefix(prefix) if prefix.op.token_type.is_keyword() => {
+            format!("{} {}", prefix.op.literal, expr_source(&prefix.right))
+        }
+        Expr::Prefix(prefix) => format!("{}{}", prefix.op.literal, expr_source(&prefix.right)),
+        Expr::Infix(infix) => format!(
+            "{} {} {}",
+            expr_source(&infix.left),
+            infix.op.literal,
+            expr_source(&infix.right)
+        ),
+        Expr::Index(index) => format!("{}[{}]", index.name.value, expr_source(&index.index)),
+        Expr::Call(call) => expr_source_call(call),
+        Expr::Identifier(ident) => ident.value.to_string(),
+        Expr::Number(number) => number.value.to_string(),
+        Expr::Boolean(boolean) => boolean.value.to_string(),
+        Expr::String(string) => format!("\"{}\"", string.value),
+        Expr::Error(error) => tokens_source(&error.skipped),
+    }
+}
+
+fn expr_source_call(call: &CallExpression) -> String {
+    call_source(&call.name, &call.args, expr_source)
+}
+
+// name(a, b)
+fn call_source<T>(name: &Identifier, args: &[T], source: impl Fn(&T) -> String) -> String {
+    let args: Vec<_> = args.iter().map(source).collect();
+    format!("{}({})", name.value, args.join(", "))
 }
 
-impl Expression for Number {}
+// a, b[10]
+fn declarations_source(declarations: &[Declaration]) -> String {
+    let declarations: Vec<_> = declarations
+        .iter()
+        .map(|declaration| match &declaration.size {
+            Some(size) => format!("{}[{}]", declaration.name.value, size.value),
+            None => declaration.name.value.to_string(),
+        })
+        .collect();
+    declarations.join(", ")
+}
+
+fn tokens_source(tokens: &[Token]) -> String {
+    let literals: Vec<_> = tokens.iter().map(|token| token.literal.as_str()).collect();
+    literals.join(" ")
+}
+
+fn fmt_skipped(f: &mut Formatter<'_>, skipped: &[Token]) -> core::fmt::Result {
+    write!(f, "[")?;
+    for (i, token) in skipped.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{:?}({})", token.token_type, token.literal)?;
+    }
+    write!(f, "]")
+}
 
 #[cfg(test)]
 mod test {
-    use crate::ast::{Node, Program, Statement};
+    use crate::ast::{to_dot, to_source, to_source_with_indent, Expr, Node, NodeType, Number};
+    use crate::parser::Parser;
+    use crate::token::Span;
+
+    #[test]
+    fn test_node_type() {
+        assert!(NodeType::IfStatement.is_statement());
+        assert!(NodeType::InfixExpression.is_expression());
+        assert!(NodeType::PrefixExpression.is_expression());
+        assert!(NodeType::ErrorExpression.is_error());
+        assert!(!NodeType::Number.is_error());
+    }
+
+    #[test]
+    fn test_expr() {
+        let number = Number {
+            value: 1,
+            span: Span::default(),
+        };
+        let debug = format!("{:?}", number);
+        let expr = Expr::Number(number);
+        assert_eq!(expr.node_type(), NodeType::Number);
+        assert_eq!(format!("{:?}", expr), debug);
+        match expr {
+            Expr::Number(number) => assert_eq!(number.value, 1),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_to_source() {
+        let input = "read x; if x<10 then y:=x*2 else repeat x := x-1 until x=10; y := x end; while y<x do y := y+1 end; write y";
+        let source = to_source(&Parser::new(input).parse_program().unwrap());
+        assert_eq!(
+            source,
+            "read x;\nif x < 10 then\n    y := x * 2;\nelse\n    repeat\n        x := x - 1;\n    until x = 10;\n    y := x;\nend;\nwhile y < x do\n    y := y + 1;\nend;\nwrite y;\n"
+        );
+        // 输出的源程序可以被重新解析，并得到相同的结果
+        let reparsed = Parser::new(&source).parse_program().unwrap();
+        assert_eq!(to_source(&reparsed), source);
+    }
+
+    #[test]
+    fn test_to_source_with_header() {
+        let input = "program demo; read x; if 0 < x then write x end end.";
+        let source = to_source_with_indent(&Parser::new(input).parse_program().unwrap(), 2);
+        assert_eq!(
+            source,
+            "program demo;\n  read x;\n  if 0 < x then\n    write x;\n  end;\nend.\n"
+        );
+        assert_eq!(
+            to_source_with_indent(&Parser::new(&source).parse_program().unwrap(), 2),
+            source
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let program = Parser::new("program demo; read x; if x < 10 then y := -x else write x end end.")
+            .parse_program()
+            .unwrap();
+        assert_eq!(
+            to_dot(&program),
+            r#"digraph ast {
+    n0 [label="program demo", shape=box];
+    n1 [label="read x", shape=box];
+    n0 -> n1;
+    n2 [label="if", shape=box];
+    n3 [label="op <", shape=ellipse];
+    n4 [label="id x", shape=ellipse];
+    n3 -> n4;
+    n5 [label="const 10", shape=ellipse];
+    n3 -> n5;
+    n2 -> n3 [label="cond"];
+    n6 [label="assign y", shape=box];
+    n7 [label="op -", shape=ellipse];
+    n8 [label="id x", shape=ellipse];
+    n7 -> n8;
+    n6 -> n7;
+    n2 -> n6 [label="then"];
+    n9 [label="write", shape=box];
+    n10 [label="id x", shape=ellipse];
+    n9 -> n10;
+    n2 -> n9 [label="else"];
+    n0 -> n2;
+}
+"#
+        );
+        let (program, _) = Parser::new("read @").parse_program_partial();
+        assert!(
+            to_dot(&program).contains(r#"[label="error: unexpected character '@' at line 1, column 6", shape=box]"#)
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        let input = "program demo;\nread x;\nif x < 10 then y := -x else a[0] := f(x, 1) end;\n\
+                     repeat write \"hi\" until true\nend.";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert_eq!(
+            program.dump(),
+            r#"Program demo @1:1
+  Read x @2:1
+  If @3:1
+    cond: Infix < @3:4
+      Identifier x @3:4
+      Number 10 @3:8
+    then:
+      Assign y @3:16
+        value: Prefix - @3:21
+          Identifier x @3:22
+    else:
+      Assign a @3:29
+        index: Number 0 @3:31
+        value: Call f @3:37
+          Identifier x @3:39
+          Number 1 @3:42
+  Repeat @4:1
+    body:
+      Write @4:8
+        value: String "hi" @4:14
+    until: Boolean true @4:25
+"#
+        );
+        let (program, _) = Parser::new("write 1 +").parse_program_partial();
+        assert!(program.dump().contains("ErrorExpression"));
+    }
 
+    #[cfg(feature = "serde")]
     #[test]
-    fn test_downcast() {
-        let program = Program::new();
-        let node: &dyn Node = &program;
-        let down: &Program = node.as_any().downcast_ref().expect("");
+    fn test_serde() {
+        let program = Parser::new("program demo; read x; if x < 10 then write x end end.")
+            .parse_program()
+            .unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.starts_with(r#"{"name":"demo","statements":[{"Read":"#));
+        let decoded: crate::ast::Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(to_source(&decoded), to_source(&program));
+        assert_eq!(decoded.span, program.span);
     }
 }
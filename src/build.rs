@@ -0,0 +1,147 @@
+use crate::driver::{compile_to_tm, Options};
+use crate::lexer;
+use crate::object::ObjectFile;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 生成的Rust模块文件名，下游crate中通过
+// include!(concat!(env!("OUT_DIR"), "/tiny_programs.rs")) 引入
+pub const MODULE_FILE: &str = "tiny_programs.rs";
+
+// 供下游crate的build.rs使用：编译src_dir下所有的.tny文件
+// 每个文件在out_dir中产生同名的.tm清单与.tmo目标文件，并生成一个以include_bytes!引用这些文件的Rust模块，
+// 如 fact.tny 对应 FACT_TM 与 FACT_TMO；两个文件名对应同一个常量名时返回错误
+// 返回生成的所有产物路径
+pub fn compile_dir(src_dir: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let src_dir = src_dir.as_ref();
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir)?;
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    let mut sources = vec![];
+    for entry in fs::read_dir(src_dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "tny") {
+            sources.push(path);
+        }
+    }
+    // 保证生成的模块内容稳定
+    sources.sort();
+    let mut names: BTreeMap<String, &PathBuf> = BTreeMap::new();
+    for source in &sources {
+        let name = const_name(&source.file_stem().unwrap().to_string_lossy());
+        if let Some(other) = names.insert(name.clone(), source) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} both map to the constant {}_TM",
+                    other.display(),
+                    source.display(),
+                    name
+                ),
+            ));
+        }
+    }
+
+    let mut artifacts = vec![];
+    let mut module = String::from("// @generated by tiny_cc::build, do not edit\n");
+    for source in &sources {
+        println!("cargo:rerun-if-changed={}", source.display());
+        let stem = source.file_stem().unwrap().to_string_lossy().to_string();
+        let (input, warnings) = lexer::decode_source(&fs::read(source)?);
+        for warning in warnings {
+            println!("cargo:warning={}: {}", source.display(), warning);
+        }
+        let compiled = compile_to_tm(&input, &Options::default())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", source.display(), err)))?;
+
+        let tm = out_dir.join(format!("{}.tm", stem));
+        fs::write(&tm, &compiled.listing)?;
+        let tmo = out_dir.join(format!("{}.tmo", stem));
+        fs::write(&tmo, ObjectFile::from_artifacts(&compiled).to_bytes())?;
+        let name = const_name(&stem);
+        for (suffix, path) in [("TM", &tm), ("TMO", &tmo)] {
+            module.push_str(&format!(
+                "pub const {}_{}: &[u8] = include_bytes!({:?});\n",
+                name,
+                suffix,
+                fs::canonicalize(path)?
+            ));
+        }
+        artifacts.push(tm);
+        artifacts.push(tmo);
+    }
+    let module_path = out_dir.join(MODULE_FILE);
+    fs::write(&module_path, module)?;
+    artifacts.push(module_path);
+    Ok(artifacts)
+}
+
+// 文件名转换为Rust常量名的前缀，如 "fact-2.tny" => "FACT_2"
+fn const_name(stem: &str) -> String {
+    let mut name: String = stem
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() {
+                ch.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|ch: char| ch.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use crate::build::{compile_dir, const_name, MODULE_FILE};
+    use crate::object::ObjectFile;
+    use std::fs;
+    use std::io;
+
+    #[test]
+    fn test_const_name() {
+        assert_eq!(const_name("fact"), "FACT");
+        assert_eq!(const_name("fact-2"), "FACT_2");
+        assert_eq!(const_name("1st"), "_1ST");
+    }
+
+    #[test]
+    fn test_compile_dir() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_build_{}", std::process::id()));
+        let src = root.join("programs");
+        let out = root.join("out");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("read.tny"), "read x; write x;").unwrap();
+        fs::write(src.join("notes.txt"), "not a program").unwrap();
+
+        let artifacts = compile_dir(&src, &out).unwrap();
+        assert_eq!(
+            artifacts,
+            vec![out.join("read.tm"), out.join("read.tmo"), out.join(MODULE_FILE)]
+        );
+        let module = fs::read_to_string(out.join(MODULE_FILE)).unwrap();
+        assert!(module.contains("pub const READ_TM: &[u8] = include_bytes!("));
+        assert!(module.contains("pub const READ_TMO: &[u8] = include_bytes!("));
+        assert!(fs::read_to_string(out.join("read.tm")).unwrap().contains("OUT"));
+        let object = ObjectFile::from_bytes(&fs::read(out.join("read.tmo")).unwrap()).unwrap();
+        assert!(!object.instructions.is_empty());
+
+        // 不同的文件名对应同一个常量名
+        fs::write(src.join("read-2.tny"), "write 1").unwrap();
+        fs::write(src.join("read_2.tny"), "write 2").unwrap();
+        let err = compile_dir(&src, &out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(
+            err.to_string().ends_with("both map to the constant READ_2_TM"),
+            "{}",
+            err
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
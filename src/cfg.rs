@@ -0,0 +1,162 @@
+use crate::code::OpCode::{HALT, JEQ, JGE, JGT, JLE, JLT, JNE, LD, LDA};
+use crate::code::{Instruction, RegisterCode};
+
+// 基本块：只能从第一条指令进入、从最后一条指令离开的一段连续指令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    // 第一条指令的地址
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+    // 后继基本块的编号；条件跳转时第一个为跳转目标，第二个为顺序执行的下一块
+    pub successors: Vec<usize>,
+}
+
+// 由TM指令序列构造的控制流图，基本块按地址排列，编号即下标
+// 跳转到指令序列之外（如最后一条指令之后）视为停机，不产生边
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+}
+
+// 一条指令执行后控制流的去向
+enum Flow {
+    Next,
+    Jump(usize),
+    Branch(usize),
+    // 停机，或以PC以外的寄存器为基址、无法静态确定目标的跳转
+    Stop,
+}
+
+fn flow(loc: usize, instruction: &Instruction) -> Flow {
+    let pc = usize::from(RegisterCode::PC);
+    let target = |d: i32| usize::try_from(loc as i64 + 1 + d as i64).ok();
+    match *instruction {
+        Instruction::Ro { op: HALT, .. } => Flow::Stop,
+        Instruction::Rm { op: LDA, r, d, s } if r == pc => match target(d) {
+            Some(target) if s == pc => Flow::Jump(target),
+            _ => Flow::Stop,
+        },
+        Instruction::Rm { op: LD, r, .. } if r == pc => Flow::Stop,
+        Instruction::Rm {
+            op: JLT | JLE | JEQ | JGT | JGE | JNE,
+            d,
+            s,
+            ..
+        } => match target(d) {
+            Some(target) if s == pc => Flow::Branch(target),
+            _ => Flow::Stop,
+        },
+        _ => Flow::Next,
+    }
+}
+
+impl ControlFlowGraph {
+    pub fn build(instructions: &[Instruction]) -> Self {
+        // 基本块的首条指令：第0条、跳转目标以及跳转或停机之后的一条
+        let mut leaders = vec![false; instructions.len() + 1];
+        leaders[0] = true;
+        for (loc, instruction) in instructions.iter().enumerate() {
+            match flow(loc, instruction) {
+                Flow::Next => continue,
+                Flow::Jump(target) | Flow::Branch(target) => {
+                    if let Some(leader) = leaders.get_mut(target) {
+                        *leader = true;
+                    }
+                }
+                Flow::Stop => {}
+            }
+            leaders[loc + 1] = true;
+        }
+        leaders.truncate(instructions.len());
+
+        let starts: Vec<_> = (0..instructions.len()).filter(|loc| leaders[*loc]).collect();
+        let block_at = |loc: usize| starts.binary_search(&loc).ok();
+        let mut blocks = vec![];
+        for (index, start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).copied().unwrap_or(instructions.len());
+            let last = end - 1;
+            let successors = match flow(last, &instructions[last]) {
+                Flow::Next => block_at(end).into_iter().collect(),
+                Flow::Jump(target) => block_at(target).into_iter().collect(),
+                Flow::Branch(target) => block_at(target).into_iter().chain(block_at(end)).collect(),
+                Flow::Stop => vec![],
+            };
+            blocks.push(BasicBlock {
+                start: *start,
+                instructions: instructions[*start..end].to_vec(),
+                successors,
+            });
+        }
+        Self { blocks }
+    }
+
+    // 包含地址为loc的指令的基本块编号
+    pub fn block_of(&self, loc: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| (block.start..block.start + block.instructions.len()).contains(&loc))
+    }
+
+    // Graphviz DOT格式，每个基本块为一个节点，列出其中的指令
+    // 条件跳转的目标边标记为taken，回到地址更小的块的边（循环）以虚线表示
+    pub fn to_dot(&self) -> String {
+        let mut output = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (offset, instruction) in block.instructions.iter().enumerate() {
+                label.push_str(&format!("{}: {}\\l", block.start + offset, instruction));
+            }
+            output.push_str(&format!("    b{} [label=\"{}\"];\n", index, label));
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            let branch = block.successors.len() == 2;
+            for (position, successor) in block.successors.iter().enumerate() {
+                let mut attributes = vec![];
+                if branch && position == 0 {
+                    attributes.push("label=\"taken\"");
+                }
+                if *successor <= index {
+                    attributes.push("style=dashed");
+                }
+                let attributes = if attributes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", attributes.join(", "))
+                };
+                output.push_str(&format!("    b{} -> b{}{};\n", index, successor, attributes));
+            }
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cfg::ControlFlowGraph;
+    use crate::driver::compile;
+
+    #[test]
+    fn test_build() {
+        let artifacts = compile("x := 0; while x < 3 do x := x + 1 end; write x").unwrap();
+        let graph = ControlFlowGraph::build(&artifacts.instructions);
+        let blocks: usize = graph.blocks.iter().map(|block| block.instructions.len()).sum();
+        assert_eq!(blocks, artifacts.instructions.len());
+        // 序言与赋值、循环条件、比较结果的两个分支、条件跳转、循环体、循环之后
+        assert_eq!(graph.blocks.len(), 7);
+        assert_eq!(graph.block_of(4), Some(1));
+        // 条件为假时跳出循环，否则进入循环体
+        let exit = graph.block_of(13).unwrap();
+        assert_eq!(graph.blocks[exit].successors, [6, 5]);
+        // 循环体跳回条件的计算
+        assert_eq!(graph.blocks[5].successors, [1]);
+        // 最后一块以HALT结束，没有后继
+        assert!(graph.blocks.last().unwrap().successors.is_empty());
+        assert_eq!(graph.block_of(artifacts.instructions.len()), None);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b4 -> b6 [label=\"taken\"];\n    b4 -> b5;\n"));
+        assert!(dot.contains("    b5 -> b1 [style=dashed];\n"));
+    }
+}
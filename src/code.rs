@@ -1,8 +1,15 @@
-use crate::ast::NodeType::WriteStatement;
-use std::fmt::{write, Display, Formatter};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::Span;
+use alloc::collections::BTreeMap;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+
+// 数据存储器的大小，与参考TM实现一致
+pub const MEMORY_SIZE: usize = 1024;
 
 // 操作码定义
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum OpCode {
     LDC, // load constant: LDC a,b(c) 表示将b+c地址处的值存储到寄存器a中
     LD,  // load: LD a,b(c) 表示将b+寄存器c地址处的值存储到寄存器a中
@@ -10,6 +17,7 @@ pub enum OpCode {
     ST, // store: ST a,b(c) 表示寄存器a存储到b+寄存器c中
     IN,
     OUT,
+    OUTS, // output string: OUTS a,0,0 输出从寄存器a中的数据地址开始、以0结尾的字符串
 
     ADD, // add: ADD a,b,c 表示将寄存器b+寄存器c存储到寄存器a中，其中a、b、c恒定为累加器1、2、1
     SUB, // sub: SUB a,b,c
@@ -17,11 +25,17 @@ pub enum OpCode {
     DIV,
 
     JLT,
+    JLE,
     JEQ,
+    JGT,
+    JGE,
+    JNE,
+
+    HALT, // halt: HALT 0,0,0 停机
 }
 
 impl Display for OpCode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             OpCode::LDC => write!(f, "LDC"),
             OpCode::LD => write!(f, "LD"),
@@ -29,24 +43,63 @@ impl Display for OpCode {
             OpCode::ST => write!(f, "ST"),
             OpCode::IN => write!(f, "IN"),
             OpCode::OUT => write!(f, "OUT"),
+            OpCode::OUTS => write!(f, "OUTS"),
             OpCode::ADD => write!(f, "ADD"),
             OpCode::SUB => write!(f, "SUB"),
             OpCode::MUL => write!(f, "MUL"),
             OpCode::DIV => write!(f, "DIV"),
             OpCode::JLT => write!(f, "JLT"),
+            OpCode::JLE => write!(f, "JLE"),
             OpCode::JEQ => write!(f, "JEQ"),
+            OpCode::JGT => write!(f, "JGT"),
+            OpCode::JGE => write!(f, "JGE"),
+            OpCode::JNE => write!(f, "JNE"),
+            OpCode::HALT => write!(f, "HALT"),
+        }
+    }
+}
+
+impl FromStr for OpCode {
+    type Err = String;
+
+    // 助记符不区分大小写
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LDC" => Ok(OpCode::LDC),
+            "LD" => Ok(OpCode::LD),
+            "LDA" => Ok(OpCode::LDA),
+            "ST" => Ok(OpCode::ST),
+            "IN" => Ok(OpCode::IN),
+            "OUT" => Ok(OpCode::OUT),
+            "OUTS" => Ok(OpCode::OUTS),
+            "ADD" => Ok(OpCode::ADD),
+            "SUB" => Ok(OpCode::SUB),
+            "MUL" => Ok(OpCode::MUL),
+            "DIV" => Ok(OpCode::DIV),
+            "JLT" => Ok(OpCode::JLT),
+            "JLE" => Ok(OpCode::JLE),
+            "JEQ" => Ok(OpCode::JEQ),
+            "JGT" => Ok(OpCode::JGT),
+            "JGE" => Ok(OpCode::JGE),
+            "JNE" => Ok(OpCode::JNE),
+            "HALT" => Ok(OpCode::HALT),
+            _ => Err(format!("unknown opcode `{}`", s)),
         }
     }
 }
 
 // 寄存器的操作数定义
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RegisterCode {
     AC,  // 累加器1
     AC1, // 累加器2
-    GP,  // Global Pointer，全局指针，指向全局变量存储的底端
-    MP,  // Memory Pointer，指向
-    PC,  // 程序计数器
+    // 参考实现不使用2到4号寄存器，见 environment::RegisterGroup
+    R2,
+    R3,
+    R4,
+    GP, // Global Pointer，全局指针，指向全局变量存储的底端
+    MP, // Memory Pointer，指向
+    PC, // 程序计数器
 }
 
 impl From<usize> for RegisterCode {
@@ -54,6 +107,9 @@ impl From<usize> for RegisterCode {
         match value {
             0 => RegisterCode::AC,
             1 => RegisterCode::AC1,
+            2 => RegisterCode::R2,
+            3 => RegisterCode::R3,
+            4 => RegisterCode::R4,
             5 => RegisterCode::GP,
             6 => RegisterCode::MP,
             7 => RegisterCode::PC,
@@ -62,11 +118,14 @@ impl From<usize> for RegisterCode {
     }
 }
 
-impl Into<usize> for RegisterCode {
-    fn into(self) -> usize {
-        match self {
+impl From<RegisterCode> for usize {
+    fn from(value: RegisterCode) -> Self {
+        match value {
             RegisterCode::AC => 0,
             RegisterCode::AC1 => 1,
+            RegisterCode::R2 => 2,
+            RegisterCode::R3 => 3,
+            RegisterCode::R4 => 4,
             RegisterCode::GP => 5,
             RegisterCode::MP => 6,
             RegisterCode::PC => 7,
@@ -75,8 +134,530 @@ impl Into<usize> for RegisterCode {
 }
 
 impl Display for RegisterCode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         let number: usize = (*self).into();
         write!(f, "{}", number)
     }
 }
+
+// 一条TM指令，寄存器以编号表示
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    // 寄存器到内存的指令：op r,d(s)
+    Rm { op: OpCode, r: usize, d: i32, s: usize },
+    // 寄存器之间的指令：op r,s,t
+    Ro { op: OpCode, r: usize, s: usize, t: usize },
+}
+
+impl Instruction {
+    pub fn rm(op: OpCode, r: impl Into<usize>, d: i32, s: impl Into<usize>) -> Self {
+        Instruction::Rm {
+            op,
+            r: r.into(),
+            d,
+            s: s.into(),
+        }
+    }
+
+    pub fn ro(op: OpCode, r: impl Into<usize>, s: impl Into<usize>, t: impl Into<usize>) -> Self {
+        Instruction::Ro {
+            op,
+            r: r.into(),
+            s: s.into(),
+            t: t.into(),
+        }
+    }
+
+    pub fn op(&self) -> OpCode {
+        match self {
+            Instruction::Rm { op, .. } | Instruction::Ro { op, .. } => *op,
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Instruction::Rm { op, r, d, s } => write!(f, "{} {},{}({})", op, r, d, s),
+            Instruction::Ro { op, r, s, t } => write!(f, "{} {},{},{}", op, r, s, t),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    // 解析不带地址的指令，如 "LDC 0,5(0)" 或 "ADD 0,1,0"，操作数之间可以有空白
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (instruction, rest) = parse_instruction(s)?;
+        match rest.trim() {
+            "" => Ok(instruction),
+            rest => Err(format!("unexpected `{}` after the instruction", rest)),
+        }
+    }
+}
+
+// 解析一条指令，返回指令以及其后剩余的文本（参考实现的清单中为注释）
+// 指令格式由操作数的写法决定：r,d(s) 为RM，r,s,t 为RO
+fn parse_instruction(s: &str) -> Result<(Instruction, &str), String> {
+    let s = s.trim_start();
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    let op: OpCode = s[..end].parse()?;
+    let text = &s[end..];
+    let malformed = || format!("malformed operands `{}`", text.trim());
+    let mut operands = Operands { rest: text };
+    let r = operands.register().ok_or_else(malformed)?;
+    operands.expect(',').ok_or_else(malformed)?;
+    let second = operands.number().ok_or_else(malformed)?;
+    let instruction = if operands.expect('(').is_some() {
+        let base = operands.register().ok_or_else(malformed)?;
+        operands.expect(')').ok_or_else(malformed)?;
+        Instruction::rm(op, r, second, base)
+    } else {
+        let s = usize::try_from(second).map_err(|_| malformed())?;
+        operands.expect(',').ok_or_else(malformed)?;
+        let t = operands.register().ok_or_else(malformed)?;
+        Instruction::ro(op, r, s, t)
+    };
+    Ok((instruction, operands.rest))
+}
+
+struct Operands<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Operands<'a> {
+    fn expect(&mut self, ch: char) -> Option<()> {
+        self.rest = self.rest.trim_start().strip_prefix(ch)?;
+        Some(())
+    }
+
+    // 可以带有正负号的十进制整数
+    fn number(&mut self) -> Option<i32> {
+        let rest = self.rest.trim_start();
+        let sign = rest.starts_with(['-', '+']) as usize;
+        let end = rest[sign..]
+            .find(|ch: char| !ch.is_ascii_digit())
+            .map_or(rest.len(), |end| end + sign);
+        let value = rest[..end].parse().ok()?;
+        self.rest = &rest[end..];
+        Some(value)
+    }
+
+    fn register(&mut self) -> Option<usize> {
+        usize::try_from(self.number()?).ok()
+    }
+}
+
+// 解析清单中带地址的一行指令，如 "  3:     ST  0,-1(6)  注释"
+pub(crate) fn parse_listing_line(line: &str) -> Result<(usize, Instruction), String> {
+    let (addr, rest) = line
+        .split_once(':')
+        .ok_or_else(|| format!("expected an instruction, found `{}`", line.trim()))?;
+    let addr = addr
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a number, found `{}`", addr.trim()))?;
+    let (instruction, _) = parse_instruction(rest)?;
+    Ok((addr, instruction))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DisasmError {
+    // 出错的行号，从1开始
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for DisasmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DisasmError {}
+
+// 将文本形式的TM清单解析为指令序列，下标即指令地址
+// 兼容参考实现的清单格式：以*开头的注释行，以及指令之后的注释
+// 指令可以不按地址顺序出现（如回填的跳转），同一地址出现多次时以最后一次为准
+// 未出现的地址与参考模拟器一致视为 HALT 0,0,0；.DATA、.RELOC 等指示被忽略
+pub fn disassemble(listing: &str) -> Result<Vec<Instruction>, DisasmError> {
+    let mut instructions = vec![];
+    for (index, line) in listing.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('*') || trimmed.starts_with('.') {
+            continue;
+        }
+        let (addr, instruction) = parse_listing_line(trimmed).map_err(|message| DisasmError {
+            line: index + 1,
+            message,
+        })?;
+        if instructions.len() <= addr {
+            instructions.resize(addr + 1, Instruction::ro(OpCode::HALT, 0usize, 0usize, 0usize));
+        }
+        instructions[addr] = instruction;
+    }
+    Ok(instructions)
+}
+
+// 数据存储器(dMem)的初始内容，与指令存储器(iMem)中的代码分开保存
+// 地址为相对GP的数据地址
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DataImage {
+    words: BTreeMap<usize, i32>,
+}
+
+impl DataImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, addr: usize, value: i32) {
+        self.words.insert(addr, value);
+    }
+
+    pub fn get(&self, addr: usize) -> Option<i32> {
+        self.words.get(&addr).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    // 按地址从小到大遍历 (地址, 值)
+    pub fn iter(&self) -> impl Iterator<Item = (usize, i32)> + '_ {
+        self.words.iter().map(|(addr, value)| (*addr, *value))
+    }
+}
+
+// 重定位表中的一项：第index条指令的d字段是一个数据地址，
+// 装载到新的数据基址时需要加上该基址
+// 跳转指令都以PC为基址，是位置无关的，不需要重定位
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Relocation {
+    pub index: usize,
+}
+
+// 调试信息：每条指令所属的语句在源程序中的位置，下标为指令地址
+// 标准序言等不属于任何语句的指令没有位置
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugInfo {
+    spans: Vec<Option<Span>>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 设置地址addr处指令的位置，地址可以不连续
+    pub fn set(&mut self, addr: usize, span: Option<Span>) {
+        if self.spans.len() <= addr {
+            self.spans.resize(addr + 1, None);
+        }
+        self.spans[addr] = span;
+    }
+
+    pub fn span(&self, addr: usize) -> Option<Span> {
+        self.spans.get(addr).copied().flatten()
+    }
+
+    // 地址addr处的指令所属的源程序行号
+    pub fn line(&self, addr: usize) -> Option<usize> {
+        self.span(addr).map(|span| span.line)
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    // 按地址从小到大遍历有位置的指令
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Span)> + '_ {
+        self.spans
+            .iter()
+            .enumerate()
+            .filter_map(|(addr, span)| span.map(|span| (addr, span)))
+    }
+
+    // 死代码消除之后只保留locations中仍然存在的指令，见 optimizer::eliminate_dead_code
+    pub fn retain(&mut self, locations: &[Option<usize>]) {
+        let mut spans = vec![];
+        for (span, location) in self.spans.iter().zip(locations) {
+            if location.is_some() {
+                spans.push(*span);
+            }
+        }
+        self.spans = spans;
+    }
+}
+
+// 解析 .SPAN 指示的操作数：addr,line,column,start,end
+pub fn parse_span_directive(operands: &str) -> Result<(usize, Span), String> {
+    let numbers = operands
+        .split(',')
+        .map(|number| {
+            number
+                .trim()
+                .parse()
+                .map_err(|_| format!("expected a number, found `{}`", number.trim()))
+        })
+        .collect::<Result<Vec<usize>, _>>()?;
+    match numbers.as_slice() {
+        [addr, line, column, start, end] => Ok((
+            *addr,
+            Span {
+                line: *line,
+                column: *column,
+                start: *start,
+                end: *end,
+            },
+        )),
+        _ => Err(format!("malformed span directive `{}`", operands)),
+    }
+}
+
+// 中间代码清单的文本格式
+// 默认格式与参考TM实现一致：地址宽3位右对齐，助记符宽5位右对齐，操作数以逗号分隔
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingStyle {
+    pub address_width: usize,
+    pub mnemonic_width: usize,
+    pub operand_separator: String,
+    pub lowercase_mnemonics: bool,
+}
+
+impl Default for ListingStyle {
+    fn default() -> Self {
+        Self {
+            address_width: 3,
+            mnemonic_width: 5,
+            operand_separator: ",".to_string(),
+            lowercase_mnemonics: false,
+        }
+    }
+}
+
+impl ListingStyle {
+    // 输出第loc条指令
+    pub fn format(&self, loc: usize, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::Rm { op, r, d, s } => self.format_rm(loc, op, r, d, s),
+            Instruction::Ro { op, r, s, t } => self.format_ro(loc, op, r, s, t),
+        }
+    }
+
+    // 寄存器到内存的指令：op r,d(s)
+    pub fn format_rm(&self, loc: usize, op: OpCode, target: usize, offset: i32, base: usize) -> String {
+        format!(
+            "{}  {}{}{}({})",
+            self.format_head(loc, op),
+            target,
+            self.operand_separator,
+            offset,
+            base
+        )
+    }
+
+    // 寄存器之间的指令：op r,s,t
+    pub fn format_ro(&self, loc: usize, op: OpCode, target: usize, first: usize, second: usize) -> String {
+        format!(
+            "{}  {}{sep}{}{sep}{}",
+            self.format_head(loc, op),
+            target,
+            first,
+            second,
+            sep = self.operand_separator
+        )
+    }
+
+    // 数据初始化指示：.DATA addr,value 表示dMem[addr]的初始值为value
+    pub fn format_data(&self, addr: usize, value: i32) -> String {
+        format!(
+            "{:>address_width$}   {:>mnemonic_width$}  {}{}{}",
+            "",
+            self.mnemonic(".DATA"),
+            addr,
+            self.operand_separator,
+            value,
+            address_width = self.address_width,
+            mnemonic_width = self.mnemonic_width
+        )
+    }
+
+    // 重定位指示：.RELOC index 表示第index条指令引用了数据地址
+    pub fn format_relocation(&self, relocation: Relocation) -> String {
+        format!(
+            "{:>address_width$}   {:>mnemonic_width$}  {}",
+            "",
+            self.mnemonic(".RELOC"),
+            relocation.index,
+            address_width = self.address_width,
+            mnemonic_width = self.mnemonic_width
+        )
+    }
+
+    // 调试信息指示：.SPAN addr,line,column,start,end 表示第addr条指令属于源程序中的这段区间
+    pub fn format_span(&self, addr: usize, span: Span) -> String {
+        format!(
+            "{:>address_width$}   {:>mnemonic_width$}  {}{sep}{}{sep}{}{sep}{}{sep}{}",
+            "",
+            self.mnemonic(".SPAN"),
+            addr,
+            span.line,
+            span.column,
+            span.start,
+            span.end,
+            sep = self.operand_separator,
+            address_width = self.address_width,
+            mnemonic_width = self.mnemonic_width
+        )
+    }
+
+    fn mnemonic(&self, mnemonic: &str) -> String {
+        if self.lowercase_mnemonics {
+            mnemonic.to_lowercase()
+        } else {
+            mnemonic.to_string()
+        }
+    }
+
+    fn format_head(&self, loc: usize, op: OpCode) -> String {
+        let mnemonic = self.mnemonic(&op.to_string());
+        format!(
+            "{:>address_width$}:  {:>mnemonic_width$}",
+            loc,
+            mnemonic,
+            address_width = self.address_width,
+            mnemonic_width = self.mnemonic_width
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::code::OpCode::{ADD, HALT, IN, LD, LDC, ST};
+    use crate::code::{disassemble, parse_span_directive, DataImage, DebugInfo, Instruction, ListingStyle, OpCode};
+    use crate::token::Span;
+
+    #[test]
+    fn test_default_style() {
+        let style = ListingStyle::default();
+        assert_eq!(style.format_rm(2, LDC, 0, 5, 0), "  2:    LDC  0,5(0)");
+        assert_eq!(style.format_ro(13, ADD, 0, 1, 0), " 13:    ADD  0,1,0");
+        assert_eq!(style.format_data(4, -7), "      .DATA  4,-7");
+    }
+
+    #[test]
+    fn test_opcode_from_str() {
+        assert!(matches!("ldc".parse(), Ok(LDC)));
+        assert!(matches!("ADD".parse(), Ok(ADD)));
+        assert!(matches!("halt".parse(), Ok(OpCode::HALT)));
+        assert!("NOP".parse::<OpCode>().is_err());
+    }
+
+    #[test]
+    fn test_instruction() {
+        let store = Instruction::rm(ST, 0usize, -1, 6usize);
+        assert_eq!(store.to_string(), "ST 0,-1(6)");
+        assert_eq!("st  0, -1(6)".parse(), Ok(store));
+        assert_eq!("ADD 0,1,0".parse(), Ok(Instruction::ro(ADD, 0usize, 1usize, 0usize)));
+        assert!("ADD 0,1".parse::<Instruction>().is_err());
+        assert!("ADD 0,1,0 2".parse::<Instruction>().is_err());
+        assert_eq!(ListingStyle::default().format(3, &store), "  3:     ST  0,-1(6)");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // 参考实现的输出：指令之后带有注释，回填的跳转不按地址顺序出现
+        let listing = "* TINY Compilation to TM Code
+* Standard prelude:
+  0:     LD  6,0(0) \tload maxaddress from location 0
+  1:     ST  0,0(0) \tclear location 0
+* End of standard prelude.
+  2:     IN  0,0,0 \tread integer value
+  4:    LDC  0,-3(0) \tload const
+  3:    JEQ  0,1(7) \tif: jmp to else
+      .DATA  1,10
+  6:   HALT  0,0,0 \t
+";
+        let instructions = disassemble(listing).unwrap();
+        assert_eq!(instructions.len(), 7);
+        assert_eq!(instructions[0], Instruction::rm(LD, 6usize, 0, 0usize));
+        assert_eq!(instructions[2], Instruction::ro(IN, 0usize, 0usize, 0usize));
+        assert_eq!(instructions[3], Instruction::rm(OpCode::JEQ, 0usize, 1, 7usize));
+        assert_eq!(instructions[4], Instruction::rm(LDC, 0usize, -3, 0usize));
+        // 未出现的地址5
+        assert_eq!(instructions[5], Instruction::ro(HALT, 0usize, 0usize, 0usize));
+        // 与清单格式互逆
+        let style = ListingStyle::default();
+        let text: String = instructions
+            .iter()
+            .enumerate()
+            .map(|(loc, instruction)| style.format(loc, instruction) + "\n")
+            .collect();
+        assert_eq!(disassemble(&text).unwrap(), instructions);
+
+        let err = disassemble("  0:     LD  6,0(0)\n  1:    NOP  0,0,0").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown opcode `NOP`");
+        let err = disassemble("  0:     LD  6,0").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: malformed operands `6,0`");
+    }
+
+    #[test]
+    fn test_data_image() {
+        let mut data = DataImage::new();
+        data.set(3, 30);
+        data.set(1, 10);
+        data.set(3, 31);
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.get(3), Some(31));
+        assert_eq!(data.get(2), None);
+        assert_eq!(data.iter().collect::<Vec<_>>(), vec![(1, 10), (3, 31)]);
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let span = Span {
+            line: 2,
+            column: 1,
+            start: 8,
+            end: 15,
+        };
+        let mut info = DebugInfo::new();
+        info.set(3, Some(span));
+        info.set(1, None);
+        assert_eq!(info.len(), 4);
+        assert_eq!(info.line(3), Some(2));
+        assert_eq!(info.span(1), None);
+        assert_eq!(info.span(10), None);
+        assert_eq!(info.iter().collect::<Vec<_>>(), [(3, span)]);
+        let directive = ListingStyle::default().format_span(3, span);
+        assert_eq!(directive, "      .SPAN  3,2,1,8,15");
+        assert_eq!(parse_span_directive("3,2,1,8,15"), Ok((3, span)));
+        assert!(parse_span_directive("3,2").is_err());
+        info.retain(&[Some(0), None, None, Some(1)]);
+        assert_eq!(info.iter().collect::<Vec<_>>(), [(1, span)]);
+    }
+
+    #[test]
+    fn test_custom_style() {
+        let style = ListingStyle {
+            address_width: 0,
+            mnemonic_width: 0,
+            operand_separator: ", ".to_string(),
+            lowercase_mnemonics: true,
+        };
+        assert_eq!(style.format_rm(2, LDC, 0, 5, 0), "2:  ldc  0, 5(0)");
+        assert_eq!(style.format_ro(13, ADD, 0, 1, 0), "13:  add  0, 1, 0");
+    }
+}
@@ -1,216 +1,1162 @@
-use crate::ast::{AssignStatement, BlockStatement, Identifier, IfStatement, InfixExpression, Node, NodeType, Number, Program, ReadStatement, WriteStatement};
-use crate::code::OpCode::{ADD, DIV, IN, JEQ, JLT, LD, LDA, LDC, MUL, OUT, ST, SUB};
+use crate::ast::{
+    BlockStatement, CallExpression, Expr, FunctionStatement, Identifier, InfixExpression, Node, Program, Stmt,
+};
+use crate::code::OpCode::{ADD, DIV, HALT, IN, JEQ, JGE, JGT, JLE, JLT, JNE, LD, LDA, LDC, MUL, OUT, OUTS, ST, SUB};
 use crate::code::RegisterCode::{AC, AC1, GP, MP, PC};
-use crate::code::{OpCode, RegisterCode};
-use crate::environment::{RegisterGroup, SymbolTable};
-use crate::token::TokenType;
+use crate::code::{self, DataImage, DebugInfo, Instruction, ListingStyle, OpCode, RegisterCode, Relocation};
+use crate::environment::{ConstantPool, RegisterGroup, SymbolTable};
+use crate::error::CodegenError;
+use crate::interner::Name;
+use crate::optimizer::{self, Reduction};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{Span, TokenType};
+use alloc::collections::BTreeMap;
+
+// 跳转的目标，见 Compiler::new_label
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Label(usize);
+
+// 保存在寄存器或临时区域中的中间结果，见 Compiler::save_ac
+#[derive(Debug, Copy, Clone)]
+enum Saved {
+    Register(RegisterCode),
+    Temp(i32),
+}
+
+// 顶层代码或一个函数对临时区域的使用
+#[derive(Debug, Default)]
+struct StackUsage {
+    // 自身最多同时使用的单元数；函数的栈帧包括返回地址、参数与局部变量
+    depth: usize,
+    // 调用的函数，以及被调用函数的栈帧之前已使用的单元数
+    calls: Vec<(usize, Name)>,
+}
+
+// 优化级别，决定启用哪些优化，在生成代码的速度与可读性之间取舍
+// O0生成的代码与参考实现一致，便于对照阅读
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    // 数字表示的级别，大于2的级别视为O2
+    pub fn from_level(level: u8) -> Self {
+        match level {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            _ => OptLevel::O2,
+        }
+    }
+
+    pub fn level(self) -> u8 {
+        self as u8
+    }
+
+    // 折叠常量子表达式，见 optimizer::fold_constants
+    pub fn folds_constants(self) -> bool {
+        self >= OptLevel::O1
+    }
+
+    // 删除无法到达的指令，见 optimizer::eliminate_dead_code
+    pub fn eliminates_dead_code(self) -> bool {
+        self >= OptLevel::O1
+    }
+
+    // 一个语句中重复的子表达式只计算一次，见 optimizer::common_subexpressions
+    pub fn eliminates_common_subexpressions(self) -> bool {
+        self >= OptLevel::O1
+    }
+
+    // 将被赋值为常量的变量替换为常量，见 optimizer::propagate_constants
+    pub fn propagates_constants(self) -> bool {
+        self >= OptLevel::O2
+    }
+
+    // 在进入循环之前计算循环中不变的表达式，见 optimizer::loop_invariants
+    pub fn hoists_loop_invariants(self) -> bool {
+        self >= OptLevel::O2
+    }
+
+    // 省略与常量0或1的恒等运算，乘以2的幂改为加法，见 optimizer::reduce_strength
+    pub fn reduces_strength(self) -> bool {
+        self >= OptLevel::O1
+    }
+
+    // 在空闲寄存器中保存中间结果，变量与常量直接读入AC1
+    pub fn allocates_registers(self) -> bool {
+        self >= OptLevel::O2
+    }
+
+    // 程序开头将常量赋给全局变量的语句写入数据存储器的初始内容，见 Compiler::initialize_data
+    pub fn initializes_data(self) -> bool {
+        self >= OptLevel::O1
+    }
+}
+
+// 生成代码的目标
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Backend {
+    // 装载到地址0的TM代码
+    #[default]
+    Tm,
+    // 清单中附带重定位表，可由relocate::relocate装载到任意基址
+    RelocatableTm,
+}
+
+// 影响生成的代码的选项，见 Compiler::new
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompilerOptions {
+    pub opt_level: OptLevel,
+    // 在任意优化级别下折叠常量子表达式
+    pub constant_folding: bool,
+    // 与参考实现的TraceCode一致，在清单中插入 * -> assign 等注释
+    pub trace: bool,
+    // 在清单末尾输出调试信息，见 ListingStyle::format_span
+    pub debug_info: bool,
+    pub backend: Backend,
+}
+
+// 正在编译的语句中公共子表达式的状态，见 Compiler::with_subexpressions
+#[derive(Debug, Default)]
+struct Subexpressions {
+    // 子表达式 => 保存其结果的临时单元相对MP的偏移量，以及结果是否已经保存
+    temps: BTreeMap<String, (i32, bool)>,
+    // 正在编译的and、or的右操作数的层数，其中计算的结果不一定执行，不能保存
+    conditional: usize,
+}
 
 pub struct Compiler {
-    pub intermedia: Vec<String>,
+    pub intermedia: Vec<Instruction>,
+    // 注释行及其所在的位置，输出在该地址的指令之前
+    pub comments: Vec<(usize, String)>,
+    // 每条指令所属的语句在源程序中的位置
+    debug_info: DebugInfo,
+    current_span: Option<Span>,
     pub registers: RegisterGroup,
+    // 从registers占用的两个累加器：表达式的值放在ac中，ac1保存另一个操作数或数组元素的地址
+    ac: RegisterCode,
+    ac1: RegisterCode,
     pub symbol_table: SymbolTable,
+    // 下一个空闲的临时单元相对MP的偏移量，见 Compiler::push_temp
     pub tmp_offset: i32,
-    pub emit_loc: usize,
+    // 正在编译的顶层代码或函数对临时区域的使用
+    stack_usage: StackUsage,
+    functions_stack_usage: BTreeMap<Name, StackUsage>,
+    // 目标机器数据存储器的大小，全局数据与临时区域不能超出
+    pub memory_size: usize,
+    // 每个标签绑定的指令地址，尚未绑定时为None
+    labels: Vec<Option<usize>>,
+    // 跳转到标签的指令，在编译结束时回填偏移量
+    fixups: Vec<(usize, Label)>,
+    // 程序头部中声明的程序名，会输出在中间代码的开头
+    pub program_name: Option<String>,
+    pub listing_style: ListingStyle,
+    // 数据存储器的初始内容，与代码一起输出
+    pub data: DataImage,
+    pub constant_pool: ConstantPool,
+    // 字符串常量的地址，相同的字符串只保存一份
+    strings: BTreeMap<String, i32>,
+    // 函数入口的标签，调用可以出现在定义之前
+    functions: BTreeMap<Name, Label>,
+    // 每个被调用的函数第一次被调用的位置，用于报告未定义的函数
+    calls: BTreeMap<Name, Span>,
+    // 正在编译的函数中参数与局部变量相对MP的偏移量，函数之外为None
+    frame: Option<BTreeMap<Name, i32>>,
+    // 引用了数据地址的指令，输出可重定位的代码时写入清单
+    pub relocations: Vec<Relocation>,
+    pub options: CompilerOptions,
+    subexpressions: Subexpressions,
+    // 公共子表达式消除省去的重复计算的次数
+    pub eliminated_subexpressions: usize,
+    // 外提到正在编译的循环之前的不变量 => 保存其结果的临时单元相对MP的偏移量
+    invariants: BTreeMap<String, i32>,
+    // 外提的循环不变量的个数
+    pub hoisted_invariants: usize,
+    // 编译中遇到的第一个错误，见 Compiler::compile
+    error: Option<CodegenError>,
+    // 常量传播替换的变量的次数
+    pub propagated_constants: usize,
+    // 以数据存储器的初始内容代替运行时赋值的语句的个数
+    pub initialized_globals: usize,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new(CompilerOptions::default())
+    }
 }
 
 impl Compiler {
-    pub fn new() -> Self {
+    pub fn new(options: CompilerOptions) -> Self {
+        // 与参考实现一致，累加器为0号与1号寄存器
+        let mut registers = RegisterGroup::new();
+        let ac = registers
+            .acquire(AC)
+            .expect("a new register group has every accumulator free");
+        let ac1 = registers
+            .acquire(AC1)
+            .expect("a new register group has every accumulator free");
         Self {
             intermedia: vec![],
-            registers: RegisterGroup::new(),
+            comments: vec![],
+            debug_info: DebugInfo::new(),
+            current_span: None,
+            registers,
+            ac,
+            ac1,
             symbol_table: SymbolTable::new(),
             tmp_offset: 0,
-            emit_loc: 0usize,
+            stack_usage: StackUsage::default(),
+            functions_stack_usage: BTreeMap::new(),
+            memory_size: code::MEMORY_SIZE,
+            labels: vec![],
+            fixups: vec![],
+            program_name: None,
+            listing_style: ListingStyle::default(),
+            data: DataImage::new(),
+            constant_pool: ConstantPool::new(),
+            strings: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            frame: None,
+            relocations: vec![],
+            options,
+            subexpressions: Subexpressions::default(),
+            eliminated_subexpressions: 0,
+            invariants: BTreeMap::new(),
+            hoisted_invariants: 0,
+            propagated_constants: 0,
+            initialized_globals: 0,
+            error: None,
         }
     }
 
-    pub fn compile(&mut self, node: &dyn Node) {
-        match node.node_type() {
-            NodeType::Program => {
-                let program: &Program = node.as_any().downcast_ref().expect(""); // Rust中的向下转型语法
-                for s in &program.statements {
-                    self.compile(&**s)
-                }
+    // 输出可重定位的代码：清单中附带重定位表，可由relocate::relocate装载到任意基址
+    pub fn with_relocatable(mut self, relocatable: bool) -> Self {
+        self.options.backend = if relocatable {
+            Backend::RelocatableTm
+        } else {
+            Backend::Tm
+        };
+        self
+    }
+
+    // 预置一个全局变量的初始值，它会被写入数据存储器的初始内容，而不是在运行时赋值
+    pub fn preset(&mut self, name: &str, value: i32) {
+        let loc = match self.symbol_table.address(name) {
+            Some(loc) => loc,
+            None => self.symbol_table.insert(name, None).unwrap(),
+        };
+        self.data.set(loc as usize, value);
+    }
+
+    pub fn with_opt_level(mut self, opt_level: u8) -> Self {
+        self.options.opt_level = OptLevel::from_level(opt_level);
+        self
+    }
+
+    pub fn with_constant_folding(mut self, constant_folding: bool) -> Self {
+        self.options.constant_folding = constant_folding;
+        self
+    }
+
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.options.trace = trace;
+        self
+    }
+
+    pub fn with_debug_info(mut self, debug_info: bool) -> Self {
+        self.options.debug_info = debug_info;
+        self
+    }
+
+    pub fn with_listing_style(mut self, style: ListingStyle) -> Self {
+        self.listing_style = style;
+        self
+    }
+
+    pub fn with_memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    // 临时区域最多同时使用的单元数，包括嵌套调用的栈帧；递归调用的栈帧不计入
+    pub fn stack_depth(&self) -> usize {
+        self.nested_stack_depth(&self.stack_usage, &mut vec![])
+    }
+
+    fn nested_stack_depth(&self, usage: &StackUsage, active: &mut Vec<Name>) -> usize {
+        let mut depth = usage.depth;
+        for (base, name) in &usage.calls {
+            let Some(callee) = self.functions_stack_usage.get(name) else {
+                continue;
+            };
+            if active.contains(name) {
+                continue;
             }
-            NodeType::BlockStatement => {
-                let block: &BlockStatement = node.as_any().downcast_ref().expect("");
-                for s in &block.statements {
-                    self.compile(&**s);
+            active.push(name.clone());
+            depth = depth.max(base + self.nested_stack_depth(callee, active));
+            active.pop();
+        }
+        depth
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    // 语法树中有 semantic::check 会报告的错误，或数据存储器不足时返回第一个错误，此时生成的代码不完整
+    pub fn compile(&mut self, program: &Program) -> Result<(), CodegenError> {
+        if self.options.opt_level.propagates_constants() {
+            let mut program = program.clone();
+            self.propagated_constants += optimizer::propagate_constants(&mut program);
+            self.compile_program(&program);
+        } else {
+            self.compile_program(program);
+        }
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    // 记录错误并继续编译，只保留第一个错误
+    fn fail(&mut self, err: CodegenError) {
+        self.error.get_or_insert(err);
+    }
+
+    fn compile_program(&mut self, program: &Program) {
+        self.program_name = program.name.clone();
+        debug!(
+            "compiling {} statements at opt level {}",
+            program.statements.len(),
+            self.options.opt_level.level()
+        );
+        // 标准序言：从地址0读取数据存储器的最大地址作为MP，然后将地址0清零
+        self.emit_comment("Standard prelude:");
+        self.emit_rm(LD, MP, 0, self.ac);
+        self.emit_rm(ST, self.ac, 0, self.ac);
+        self.emit_comment("End of standard prelude.");
+        // 在第一个会执行的语句之前，变量的值只能来自数据存储器的初始内容
+        let mut initializing = self.options.opt_level.initializes_data();
+        for s in &program.statements {
+            if initializing {
+                match s {
+                    Stmt::Var(_) | Stmt::Function(_) => {}
+                    _ if self.initialize_data(s) => continue,
+                    _ => initializing = false,
                 }
             }
-            NodeType::ReadStatement => {
-                let read: &ReadStatement = node.as_any().downcast_ref().expect("");
-                self.emit_r0(IN, AC, 0usize, 0usize);
-                let mut loc = self.symbol_table.look_up(&read.name.value);
-                if loc == -1 {
-                    loc = self.symbol_table.insert(&read.name.value);
+            self.compile_statement(s);
+        }
+        self.emit_comment("End of execution.");
+        self.emit_r0(HALT, self.ac, self.ac, self.ac);
+        self.check_memory();
+        // 出错时跳过的函数的标签没有绑定
+        if self.error.is_some() {
+            return;
+        }
+        self.resolve_labels();
+        if self.options.opt_level.eliminates_dead_code() {
+            self.eliminate_dead_code();
+        }
+    }
+
+    // 将常量赋给全局变量的语句写入数据存储器的初始内容，不产生指令；其他语句返回false
+    fn initialize_data(&mut self, stmt: &Stmt) -> bool {
+        let Stmt::Assign(assign) = stmt else {
+            return false;
+        };
+        if assign.index.is_some() {
+            return false;
+        }
+        let Some(value) = optimizer::constant_value(&assign.value) else {
+            return false;
+        };
+        let (loc, _) = self.variable(&assign.name);
+        self.data.set(loc as usize, value);
+        self.initialized_globals += 1;
+        true
+    }
+
+    fn compile_block(&mut self, block: &BlockStatement) {
+        for s in &block.statements {
+            self.compile_statement(s);
+        }
+    }
+
+    fn compile_statement(&mut self, stmt: &Stmt) {
+        let name = match stmt {
+            Stmt::Assign(_) => "assign",
+            Stmt::If(_) => "if",
+            Stmt::Repeat(_) => "repeat",
+            Stmt::While(_) => "while",
+            Stmt::Read(_) => "read",
+            Stmt::Write(_) => "write",
+            Stmt::Var(_) => "var",
+            Stmt::Function(_) => "fn",
+            Stmt::Return(_) => "return",
+            Stmt::Call(_) => "call",
+            Stmt::Error(_) => "error",
+        };
+        self.emit_trace(&format!("-> {}", name));
+        debug!("{} statement at {}", name, stmt.span());
+        // 复合语句中的子语句结束后，之后的指令仍属于外层的语句
+        let outer_span = self.current_span.replace(stmt.span());
+        match stmt {
+            Stmt::Read(read) => {
+                self.emit_r0(IN, self.ac, self.ac, self.ac);
+                let (loc, base) = self.variable(&read.name);
+                self.emit_rm(ST, self.ac, loc, base);
+            }
+            Stmt::Write(write) => match &write.value {
+                Expr::String(string) => {
+                    let loc = self.compile_string(&string.value);
+                    self.emit_rm(LDA, self.ac, loc, GP);
+                    self.emit_r0(OUTS, self.ac, self.ac, self.ac);
+                }
+                value => {
+                    self.with_subexpressions(&[value], |compiler| compiler.compile_expression(value));
+                    self.emit_r0(OUT, self.ac, self.ac, self.ac);
                 }
-                self.emit_rm(ST, AC, loc as usize, GP);
+            },
+            Stmt::Assign(assign) => {
+                let exprs: Vec<_> = core::iter::once(&assign.value).chain(&assign.index).collect();
+                self.with_subexpressions(&exprs, |compiler| {
+                    compiler.compile_expression(&assign.value);
+                    match &assign.index {
+                        Some(index) => {
+                            // 保存要赋的值，再计算元素的地址
+                            let saved = compiler.save_ac();
+                            compiler.compile_element_address(&assign.name, index);
+                            let value = compiler.restore(saved, compiler.ac);
+                            compiler.emit_rm(ST, value, 0, compiler.ac1);
+                        }
+                        None => {
+                            let (loc, base) = compiler.variable(&assign.name);
+                            compiler.emit_rm(ST, compiler.ac, loc, base);
+                        }
+                    }
+                });
             }
-            NodeType::WriteStatement => {
-                let write: &WriteStatement = node.as_any().downcast_ref().expect("");
-                self.compile(&write.name);
-                self.emit_r0(OUT, AC, 0usize, 0usize);
+            // 只分配存储单元，不产生指令；数组的元素初值为0
+            // 函数中的变量在进入函数时已经分配
+            Stmt::Var(var) if self.frame.is_some() => {
+                if var.declarations.iter().any(|declaration| declaration.size.is_some()) {
+                    self.fail(CodegenError::NestedArray { span: var.span });
+                }
             }
-            NodeType::AssignStatement => {
-                let assign: &AssignStatement = node.as_any().downcast_ref().expect("");
-                self.compile(&*assign.value);
-                let mut loc = self.symbol_table.look_up(&assign.name.value);
-                if loc == -1 {
-                    loc = self.symbol_table.insert(&assign.name.value);
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    let name = &declaration.name;
+                    if self.symbol_table.look_up(&name.value).is_none() {
+                        let definition = Some(name.span);
+                        match &declaration.size {
+                            Some(size) => self
+                                .symbol_table
+                                .insert_array(&name.value, size.value as usize, definition),
+                            None => self.symbol_table.insert(&name.value, definition),
+                        };
+                    }
+                    self.symbol_table.add_line(&name.value, name.span.line);
                 }
-                self.emit_rm(ST, AC, loc as usize, GP);
             }
-            NodeType::IfStatement => {
-                let if_stmt: &IfStatement = node.as_any().downcast_ref().expect("");
+            Stmt::If(if_stmt) => {
                 // 编译条件
-                self.compile(&*if_stmt.cond);
-                // 条件地址
-                let after_cond = self.emit_skip(1usize);
+                self.compile_condition(&if_stmt.cond);
+                let else_label = self.new_label();
+                let end_label = self.new_label();
+                // 条件为假时跳到else序列
+                self.emit_trace("if: jump to else belongs here");
+                self.emit_jump(JEQ, self.ac, else_label);
                 // 编译then序列
-                self.compile(&if_stmt.consequence);
-                let after_seq = self.emit_skip(1usize);
-                let current_loc = self.emit_skip(0usize);
-                self.emit_backup(after_cond);
-                self.emit_rm_abs(JEQ, AC, current_loc);
-                self.emit_restore();
-                let current_loc = self.emit_skip(0usize);
-                self.emit_backup(after_seq);
-                self.emit_rm_abs(LDA, PC, current_loc);
-                self.emit_restore();
+                self.compile_block(&if_stmt.consequence);
+                self.emit_trace("if: jump to end belongs here");
+                self.emit_jump(LDA, PC, end_label);
+                // 编译else序列，then序列末尾的跳转越过它
+                self.bind_label(else_label);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.compile_block(alternative);
+                }
+                self.bind_label(end_label);
             }
-            NodeType::InfixExpression => {
-                let infix: &InfixExpression = node.as_any().downcast_ref().expect("");
-                self.compile(&*infix.left);
-                // 保存左操作数
-                self.emit_rm(ST, AC, self.tmp_offset as usize, MP);
-                self.tmp_offset -= 1;
-                self.compile(&*infix.right);
-                self.tmp_offset += 1;
-                self.emit_rm(LD, AC1, self.tmp_offset as usize, MP);
-                match infix.op.token_type {
-                    TokenType::Add => self.emit_r0(ADD, AC, AC1, AC),
-                    TokenType::Minus => self.emit_r0(SUB, AC, AC1, AC),
-                    TokenType::Mul => self.emit_r0(MUL, AC, AC1, AC),
-                    TokenType::Divide => self.emit_r0(DIV, AC, AC1, AC),
-                    TokenType::LessThan => {
-                        self.emit_r0(SUB, AC, AC1, AC);
-                        self.emit_rm(JLT, AC, 2usize, PC);
-                        self.emit_rm(LDC, AC, 0usize, AC);
-                        self.emit_rm(LDA, PC, 1usize, PC);
-                        self.emit_rm(LDC, AC, 1usize, AC);
-                    }
-                    TokenType::Equal => {
-                        self.emit_r0(SUB, AC, AC1, AC);
-                        self.emit_rm(JEQ, AC, 2usize, PC);
-                        self.emit_rm(LDC, AC, 0usize, AC);
-                        self.emit_rm(LDA, PC, 1usize, PC);
-                        self.emit_rm(LDC, AC, 1usize, AC);
+            Stmt::Repeat(repeat) => {
+                let invariants = self.hoist_invariants(&repeat.consequence, &repeat.cond);
+                // 循环体的起始地址
+                let body_label = self.new_label();
+                self.bind_label(body_label);
+                self.emit_trace("repeat: jump after body comes back here");
+                self.compile_block(&repeat.consequence);
+                // 条件在循环体之后，调试信息中属于until所在的位置
+                let repeat_span = self.current_span.replace(repeat.cond.span());
+                self.compile_condition(&repeat.cond);
+                // 条件为假时跳回循环体开头
+                self.emit_jump(JEQ, self.ac, body_label);
+                self.current_span = repeat_span;
+                self.release_invariants(invariants);
+            }
+            Stmt::While(while_stmt) => {
+                let invariants = self.hoist_invariants(&while_stmt.consequence, &while_stmt.cond);
+                // 条件的起始地址，循环体末尾跳回这里
+                let cond_label = self.new_label();
+                let end_label = self.new_label();
+                self.bind_label(cond_label);
+                self.emit_trace("while: jump after body comes back here");
+                self.compile_condition(&while_stmt.cond);
+                // 条件为假时跳出循环
+                self.emit_trace("while: jump to end belongs here");
+                self.emit_jump(JEQ, self.ac, end_label);
+                self.compile_block(&while_stmt.consequence);
+                self.emit_jump(LDA, PC, cond_label);
+                self.bind_label(end_label);
+                self.release_invariants(invariants);
+            }
+            Stmt::Function(function) => self.compile_function(function),
+            Stmt::Return(ret) => {
+                if self.frame.is_none() {
+                    self.fail(CodegenError::ReturnOutsideFunction { span: ret.span });
+                }
+                match &ret.value {
+                    Some(value) => self.with_subexpressions(&[value], |compiler| compiler.compile_expression(value)),
+                    None => self.emit_rm(LDC, self.ac, 0, self.ac),
+                }
+                self.emit_rm(LD, PC, 0, MP);
+            }
+            Stmt::Call(call) => self.compile_call(&call.call),
+            Stmt::Error(error) => self.fail(CodegenError::SyntaxError {
+                span: error.span,
+                message: error.message.clone(),
+            }),
+        }
+        self.current_span = outer_span;
+        self.emit_trace(&format!("<- {}", name));
+    }
+
+    // 函数体紧接在定义处，顺序执行时跳过它
+    // 栈帧以MP为基址：0(MP)为返回地址，-1(MP)起依次为参数与局部变量，临时值在它们之后
+    // 调用方将AC设为返回地址后跳转到函数入口，返回值放在AC中
+    fn compile_function(&mut self, function: &FunctionStatement) {
+        if self.frame.is_some() {
+            self.fail(CodegenError::NestedFunction { span: function.span });
+            return;
+        }
+        let end_label = self.new_label();
+        self.emit_jump(LDA, PC, end_label);
+        let entry = self.function_label(function.name.value.clone());
+        self.bind_label(entry);
+        self.symbol_table
+            .define_function(&function.name.value, function.params.len(), self.intermedia.len());
+
+        // 栈帧中的单元与临时单元一样从MP向下分配
+        let saved_offset = self.tmp_offset;
+        let saved_usage = core::mem::take(&mut self.stack_usage);
+        self.tmp_offset = 0;
+        self.push_temp();
+        let locals = function.locals(|name| self.symbol_table.look_up(name).is_some());
+        let mut frame = BTreeMap::new();
+        for name in function.params.iter().map(|param| &param.value).chain(&locals) {
+            let offset = self.push_temp();
+            frame.entry(name.clone()).or_insert(offset);
+        }
+        self.frame = Some(frame);
+
+        self.emit_rm(ST, self.ac, 0, MP);
+        self.compile_block(&function.body);
+        // 执行到函数末尾时返回0
+        self.emit_rm(LDC, self.ac, 0, self.ac);
+        self.emit_rm(LD, PC, 0, MP);
+
+        self.frame = None;
+        self.tmp_offset = saved_offset;
+        let usage = core::mem::replace(&mut self.stack_usage, saved_usage);
+        self.functions_stack_usage.insert(function.name.value.clone(), usage);
+        self.bind_label(end_label);
+    }
+
+    // 被调用函数的栈帧从第一个空闲的临时单元开始，依次存放返回地址与实参
+    fn compile_call(&mut self, call: &CallExpression) {
+        // 被调用的函数会使用空闲寄存器，调用前保存正在使用的寄存器
+        let mut saved = vec![];
+        for register in self.registers.used().to_vec() {
+            let offset = self.push_temp();
+            self.emit_rm(ST, register, offset, MP);
+            saved.push((register, offset));
+        }
+        self.stack_usage
+            .calls
+            .push((self.tmp_offset.unsigned_abs() as usize, call.name.value.clone()));
+        let frame = self.push_temp();
+        for arg in &call.args {
+            self.compile_expression(arg);
+            let offset = self.push_temp();
+            self.emit_rm(ST, self.ac, offset, MP);
+        }
+        self.tmp_offset = frame;
+        self.calls.entry(call.name.value.clone()).or_insert(call.name.span);
+        let entry = self.function_label(call.name.value.clone());
+        if frame != 0 {
+            self.emit_rm(LDA, MP, frame, MP);
+        }
+        self.emit_rm(LDA, self.ac, 1, PC);
+        self.emit_jump(LDA, PC, entry);
+        if frame != 0 {
+            self.emit_rm(LDA, MP, -frame, MP);
+        }
+        for (register, offset) in saved.into_iter().rev() {
+            self.pop_temp();
+            self.emit_rm(LD, register, offset, MP);
+        }
+    }
+
+    // 在临时区域中分配一个单元，返回它相对MP的偏移量；临时单元按栈的顺序由pop_temp释放
+    fn push_temp(&mut self) -> i32 {
+        let offset = self.tmp_offset;
+        self.tmp_offset -= 1;
+        self.stack_usage.depth = self.stack_usage.depth.max(self.tmp_offset.unsigned_abs() as usize);
+        offset
+    }
+
+    fn pop_temp(&mut self) {
+        self.tmp_offset += 1;
+    }
+
+    // 全局数据从地址0向上分配，临时区域从MP（数据存储器的最大地址）向下分配，两者不能重叠
+    // 递归调用的深度在编译时无法确定，不在检查之列
+    fn check_memory(&mut self) {
+        let data = self.symbol_table.data_size();
+        let stack = self.stack_depth();
+        if data + stack > self.memory_size {
+            self.fail(CodegenError::OutOfMemory {
+                data,
+                stack,
+                available: self.memory_size,
+            });
+        }
+    }
+
+    fn function_label(&mut self, name: Name) -> Label {
+        if let Some(label) = self.functions.get(&name) {
+            return *label;
+        }
+        let label = self.new_label();
+        self.functions.insert(name, label);
+        label
+    }
+
+    // 赋值或read的目标变量的地址与基址寄存器，全局变量不存在时定义它
+    fn variable(&mut self, ident: &Identifier) -> (i32, RegisterCode) {
+        if let Some(loc) = self.frame.as_ref().and_then(|frame| frame.get(&ident.value)) {
+            return (*loc, MP);
+        }
+        let loc = match self.symbol_table.address(&ident.value) {
+            Some(loc) => loc,
+            None => self.symbol_table.insert(&ident.value, Some(ident.span)).unwrap(),
+        };
+        self.symbol_table.add_line(&ident.value, ident.span.line);
+        (loc, GP)
+    }
+
+    // 优化级别允许时，在循环之前计算循环中的不变量并保存在临时单元中，循环中直接读取
+    // 外层循环已经外提的不变量不再重复；返回新外提的不变量，循环编译完成后交给 release_invariants
+    fn hoist_invariants(&mut self, body: &BlockStatement, cond: &Expr) -> Vec<String> {
+        if !self.options.opt_level.hoists_loop_invariants() {
+            return vec![];
+        }
+        let mut hoisted = vec![];
+        for expr in optimizer::loop_invariants(body, cond) {
+            let Some(key) = optimizer::subexpression_key(expr) else {
+                continue;
+            };
+            if self.invariants.contains_key(&key) {
+                continue;
+            }
+            self.emit_trace("loop invariant");
+            self.compile_expression(expr);
+            let offset = self.push_temp();
+            self.emit_rm(ST, self.ac, offset, MP);
+            self.invariants.insert(key.clone(), offset);
+            hoisted.push(key);
+        }
+        self.hoisted_invariants += hoisted.len();
+        hoisted
+    }
+
+    fn release_invariants(&mut self, hoisted: Vec<String>) {
+        for key in hoisted.iter().rev() {
+            self.invariants.remove(key);
+            self.pop_temp();
+        }
+    }
+
+    fn compile_condition(&mut self, cond: &Expr) {
+        self.with_subexpressions(&[cond], |compiler| compiler.compile_expression(cond));
+    }
+
+    // 优化级别允许时，为exprs中重复的子表达式分配临时单元，第一次计算之后保存结果，之后直接读取
+    // exprs必须与compile中编译的表达式相同，并且按同样的顺序；compile结束后释放这些临时单元
+    fn with_subexpressions(&mut self, exprs: &[&Expr], compile: impl FnOnce(&mut Self)) {
+        let reused = if self.options.opt_level.eliminates_common_subexpressions() {
+            optimizer::common_subexpressions(exprs)
+        } else {
+            vec![]
+        };
+        for key in &reused {
+            let offset = self.push_temp();
+            self.subexpressions.temps.insert(key.clone(), (offset, false));
+        }
+        compile(self);
+        self.subexpressions.temps.clear();
+        for _ in &reused {
+            self.pop_temp();
+        }
+    }
+
+    fn compile_expression(&mut self, expr: &Expr) {
+        if !self.invariants.is_empty() {
+            if let Some(offset) = optimizer::subexpression_key(expr).and_then(|key| self.invariants.get(&key).copied())
+            {
+                self.emit_trace("-> Invariant");
+                self.emit_rm(LD, self.ac, offset, MP);
+                self.emit_trace("<- Invariant");
+                return;
+            }
+        }
+        let temp = if self.subexpressions.temps.is_empty() {
+            None
+        } else {
+            optimizer::subexpression_key(expr).and_then(|key| self.subexpressions.temps.get_mut(&key))
+        };
+        match temp {
+            Some(&mut (offset, true)) => {
+                self.emit_trace("-> Reuse");
+                self.emit_rm(LD, self.ac, offset, MP);
+                self.emit_trace("<- Reuse");
+                self.eliminated_subexpressions += 1;
+            }
+            Some((offset, stored)) if self.subexpressions.conditional == 0 => {
+                let offset = *offset;
+                *stored = true;
+                self.compile_value(expr);
+                self.emit_rm(ST, self.ac, offset, MP);
+            }
+            _ => self.compile_value(expr),
+        }
+    }
+
+    fn compile_value(&mut self, expr: &Expr) {
+        if self.options.constant_folding || self.options.opt_level.folds_constants() {
+            if let Some(value) = optimizer::constant_value(expr) {
+                self.emit_trace("-> Const");
+                self.compile_number(value, self.ac);
+                self.emit_trace("<- Const");
+                return;
+            }
+        }
+        let name = match expr {
+            Expr::Prefix(_) | Expr::Infix(_) => "Op",
+            Expr::Index(_) => "Index",
+            Expr::Call(_) => "Call",
+            Expr::Identifier(_) => "Id",
+            Expr::Number(_) | Expr::Boolean(_) => "Const",
+            Expr::String(_) => "String",
+            Expr::Error(_) => "Error",
+        };
+        self.emit_trace(&format!("-> {}", name));
+        match expr {
+            Expr::Prefix(prefix) => {
+                self.compile_expression(&prefix.right);
+                match prefix.op.token_type {
+                    // 0 - AC
+                    TokenType::Minus => {
+                        self.emit_rm(LDC, self.ac1, 0, self.ac1);
+                        self.emit_r0(SUB, self.ac, self.ac1, self.ac);
                     }
-                    _ => panic!("token type {:?} is not infix operator", infix.op.token_type),
+                    TokenType::Not => self.emit_truth(JEQ),
+                    token_type => self.fail(CodegenError::InvalidPrefixOperator {
+                        token_type,
+                        span: prefix.op.span,
+                    }),
+                }
+            }
+            // 短路求值：左操作数已能决定结果时不计算右操作数，结果即为左操作数的值
+            Expr::Infix(infix) if infix.op.token_type.is_logical() => {
+                self.compile_expression(&infix.left);
+                let end_label = self.new_label();
+                let jump = if infix.op.token_type == TokenType::And {
+                    JEQ
+                } else {
+                    JNE
+                };
+                self.emit_jump(jump, self.ac, end_label);
+                self.subexpressions.conditional += 1;
+                self.compile_expression(&infix.right);
+                self.subexpressions.conditional -= 1;
+                self.bind_label(end_label);
+            }
+            Expr::Infix(infix) => {
+                if !self.compile_reduced(infix) {
+                    self.compile_infix(infix);
+                }
+            }
+            Expr::Index(index) => {
+                self.compile_element_address(&index.name, &index.index);
+                self.emit_rm(LD, self.ac, 0, self.ac1);
+            }
+            Expr::Call(call) => self.compile_call(call),
+            Expr::Identifier(ident) => self.compile_identifier(ident, self.ac),
+            Expr::Number(number) => self.compile_number(number.value, self.ac),
+            Expr::Boolean(boolean) => self.emit_rm(LDC, self.ac, boolean.value as i32, self.ac),
+            // semantic::check 会提前报告这种错误
+            Expr::String(string) => self.fail(CodegenError::StringNotWritable {
+                value: string.value.clone(),
+                span: string.span,
+            }),
+            Expr::Error(error) => self.fail(CodegenError::SyntaxError {
+                span: error.span,
+                message: error.message.clone(),
+            }),
+        }
+        self.emit_trace(&format!("<- {}", name));
+    }
+
+    fn compile_infix(&mut self, infix: &InfixExpression) {
+        self.compile_expression(&infix.left);
+        // 右操作数为变量或常量时直接读入AC1，否则先保存左操作数
+        let (left, right) = if self.options.opt_level.allocates_registers() && self.compile_leaf(&infix.right, self.ac1)
+        {
+            (self.ac, self.ac1)
+        } else {
+            let saved = self.save_ac();
+            self.compile_expression(&infix.right);
+            (self.restore(saved, self.ac1), self.ac)
+        };
+        match infix.op.token_type {
+            TokenType::Add => self.emit_r0(ADD, self.ac, left, right),
+            TokenType::Minus => self.emit_r0(SUB, self.ac, left, right),
+            TokenType::Mul => self.emit_r0(MUL, self.ac, left, right),
+            TokenType::Divide => self.emit_r0(DIV, self.ac, left, right),
+            TokenType::LessThan => self.emit_comparison(JLT, left, right),
+            TokenType::EqualLessThan => self.emit_comparison(JLE, left, right),
+            TokenType::GreaterThan => self.emit_comparison(JGT, left, right),
+            TokenType::EqualGreaterThan => self.emit_comparison(JGE, left, right),
+            TokenType::Equal => self.emit_comparison(JEQ, left, right),
+            TokenType::NotEqual => self.emit_comparison(JNE, left, right),
+            token_type => self.fail(CodegenError::InvalidInfixOperator {
+                token_type,
+                span: infix.op.span,
+            }),
+        }
+    }
+
+    // 优化级别允许时按 optimizer::reduce_strength 编译infix，返回是否已经编译
+    // 乘以2的幂时，只在加法的条数少于一般的乘法所需的额外指令时才削减：
+    // 读入常量并执行MUL，O2以下还要保存与取回另一个操作数
+    fn compile_reduced(&mut self, infix: &InfixExpression) -> bool {
+        if !self.options.opt_level.reduces_strength() {
+            return false;
+        }
+        match optimizer::reduce_strength(infix) {
+            Some(Reduction::Identity(operand)) => self.compile_expression(operand),
+            Some(Reduction::Double(operand, times)) => {
+                let cost = if self.options.opt_level.allocates_registers() {
+                    2
+                } else {
+                    4
+                };
+                if times >= cost {
+                    return false;
                 }
+                self.compile_expression(operand);
+                for _ in 0..times {
+                    self.emit_r0(ADD, self.ac, self.ac, self.ac);
+                }
+            }
+            None => return false,
+        }
+        true
+    }
+
+    // 比较左操作数left与右操作数right，条件成立时AC为1，否则为0
+    // jump为对差值 left - right 判断的跳转指令
+    fn emit_comparison(&mut self, jump: OpCode, left: RegisterCode, right: RegisterCode) {
+        self.emit_r0(SUB, self.ac, left, right);
+        self.emit_truth(jump);
+    }
+
+    // AC满足jump的条件时AC为1，否则为0
+    fn emit_truth(&mut self, jump: OpCode) {
+        self.emit_rm(jump, self.ac, 2, PC);
+        self.emit_rm(LDC, self.ac, 0, self.ac);
+        self.emit_rm(LDA, PC, 1, PC);
+        self.emit_rm(LDC, self.ac, 1, self.ac);
+    }
+
+    fn compile_number(&mut self, value: i32, target: RegisterCode) {
+        if self.constant_pool.should_pool(value) {
+            let (loc, new) = self.constant_pool.intern(value, || self.symbol_table.allocate());
+            if new {
+                self.data.set(loc as usize, value);
+            }
+            self.emit_rm(LD, target, loc, GP);
+        } else {
+            self.emit_rm(LDC, target, value, target);
+        }
+    }
+
+    // 将变量或常量直接读入target，不经过AC；其他表达式返回false，不产生指令
+    fn compile_leaf(&mut self, expr: &Expr, target: RegisterCode) -> bool {
+        if let Some(value) = optimizer::constant_value(expr) {
+            self.emit_trace("-> Const");
+            self.compile_number(value, target);
+            self.emit_trace("<- Const");
+            return true;
+        }
+        let Expr::Identifier(ident) = expr else {
+            return false;
+        };
+        self.emit_trace("-> Id");
+        self.compile_identifier(ident, target);
+        self.emit_trace("<- Id");
+        true
+    }
+
+    // 保存AC中的中间结果：优化级别2及以上优先放在空闲寄存器中，否则保存到临时区域
+    fn save_ac(&mut self) -> Saved {
+        if self.options.opt_level.allocates_registers() {
+            if let Ok(register) = self.registers.allocate() {
+                self.emit_rm(LDA, register, 0, self.ac);
+                return Saved::Register(register);
             }
-            NodeType::Identifier => {
-                let ident: &Identifier = node.as_any().downcast_ref().expect("");
-                let loc = self.symbol_table.look_up(&ident.value);
-                self.emit_rm(LD, AC, loc as usize, GP);
+        }
+        let offset = self.push_temp();
+        self.emit_rm(ST, self.ac, offset, MP);
+        Saved::Temp(offset)
+    }
+
+    // 取回save_ac保存的中间结果，返回存放它的寄存器；保存在临时区域中的结果读入target
+    fn restore(&mut self, saved: Saved, target: RegisterCode) -> RegisterCode {
+        match saved {
+            Saved::Register(register) => {
+                if let Err(err) = self.registers.free(register) {
+                    self.fail(CodegenError::Register(err));
+                }
+                register
             }
-            NodeType::Number => {
-                let number: &Number = node.as_any().downcast_ref().expect("");
-                self.emit_rm(LDC, AC, number.value as usize, 0usize);
+            Saved::Temp(offset) => {
+                self.pop_temp();
+                self.emit_rm(LD, target, offset, MP);
+                target
             }
-            _ => {}
         }
     }
 
+    // 字符串保存在数据存储器中，每个单元一个字符，以0结尾；返回第一个字符的地址
+    fn compile_string(&mut self, value: &str) -> i32 {
+        if let Some(loc) = self.strings.get(value) {
+            return *loc;
+        }
+        let start = self.symbol_table.allocate();
+        let mut loc = start;
+        for ch in value.chars() {
+            self.data.set(loc as usize, ch as i32);
+            loc = self.symbol_table.allocate();
+        }
+        self.data.set(loc as usize, 0);
+        self.strings.insert(value.to_string(), start);
+        start
+    }
+
+    // 计算数组元素name[index]的地址，放在AC1中：AC1 = GP + 数组的地址 + 下标
+    // 以GP为基址的LDA会被记入重定位表；不检查下标是否越界
+    fn compile_element_address(&mut self, name: &Identifier, index: &Expr) {
+        self.compile_expression(index);
+        // semantic::check 会提前报告这种错误
+        let Some(loc) = self.symbol_table.address(&name.value) else {
+            self.fail(CodegenError::NotAnArray {
+                name: name.value.clone().into(),
+                span: name.span,
+            });
+            return;
+        };
+        self.symbol_table.add_line(&name.value, name.span.line);
+        self.emit_rm(LDA, self.ac1, loc, GP);
+        self.emit_r0(ADD, self.ac1, self.ac1, self.ac);
+    }
+
+    fn compile_identifier(&mut self, ident: &Identifier, target: RegisterCode) {
+        if let Some(loc) = self.frame.as_ref().and_then(|frame| frame.get(&ident.value)) {
+            self.emit_rm(LD, target, *loc, MP);
+            return;
+        }
+        // semantic::check 会提前报告这种错误
+        let Some(loc) = self.symbol_table.address(&ident.value) else {
+            self.fail(CodegenError::UnassignedVariable {
+                name: ident.value.clone().into(),
+                span: ident.span,
+            });
+            return;
+        };
+        self.symbol_table.add_line(&ident.value, ident.span.line);
+        self.emit_rm(LD, target, loc, GP);
+    }
+
     pub fn to_intermedia_code(&self) -> String {
         let mut output = String::new();
-        for line in &self.intermedia {
-            output.push_str(&format!("{}\n", line));
+        if let Some(name) = &self.program_name {
+            output.push_str(&format!("* program {}\n", name));
+        }
+        output.push_str("* TINY Compilation to TM Code\n");
+        let mut comments = self.comments.iter().peekable();
+        for (loc, instruction) in self.intermedia.iter().enumerate() {
+            while let Some((_, comment)) = comments.next_if(|(at, _)| *at <= loc) {
+                output.push_str(&format!("* {}\n", comment));
+            }
+            output.push_str(&format!("{}\n", self.listing_style.format(loc, instruction)));
+        }
+        for (_, comment) in comments {
+            output.push_str(&format!("* {}\n", comment));
+        }
+        for (addr, value) in self.data.iter() {
+            output.push_str(&format!("{}\n", self.listing_style.format_data(addr, value)));
+        }
+        if self.options.backend == Backend::RelocatableTm {
+            for relocation in &self.relocations {
+                output.push_str(&format!("{}\n", self.listing_style.format_relocation(*relocation)));
+            }
+        }
+        if self.options.debug_info {
+            for (addr, span) in self.debug_info.iter() {
+                output.push_str(&format!("{}\n", self.listing_style.format_span(addr, span)));
+            }
         }
         output
     }
 
-    fn emit_code(&mut self, code: String) {
-        if self.emit_loc == self.intermedia.len() {
-            self.intermedia.push(code);
-        } else {
-            self.intermedia[self.emit_loc] = code;
+    fn emit_comment(&mut self, comment: &str) {
+        self.comments.push((self.intermedia.len(), comment.to_string()));
+    }
+
+    // 只在with_trace(true)时产生的注释
+    fn emit_trace(&mut self, comment: &str) {
+        if self.options.trace {
+            self.emit_comment(comment);
         }
-        self.emit_loc += 1;
+    }
+
+    fn emit_code(&mut self, code: Instruction) {
+        trace!("emit {:>4}: {}", self.intermedia.len(), code);
+        self.intermedia.push(code);
+        self.debug_info.set(self.intermedia.len() - 1, self.current_span);
     }
 
     // 产生一个寄存器到内存的指令
-    fn emit_rm(&mut self, op: OpCode, target: impl Into<usize>, offset: impl Into<usize>, base: impl Into<usize>) {
-        let code = format!(
-            "{:>3}:  {:>5}  {},{}({})",
-            self.intermedia.len(),
-            op,
-            target.into(),
-            offset.into(),
-            base.into()
-        );
-        self.emit_code(code);
+    fn emit_rm(&mut self, op: OpCode, target: impl Into<usize>, offset: i32, base: impl Into<usize>) {
+        let base = base.into();
+        if base == usize::from(GP) {
+            self.relocations.push(Relocation {
+                index: self.intermedia.len(),
+            });
+        }
+        self.emit_code(Instruction::rm(op, target, offset, base));
     }
 
     // 产生一个寄存器的指令
     fn emit_r0(&mut self, op: OpCode, target: impl Into<usize>, first: impl Into<usize>, second: impl Into<usize>) {
-        let code = format!(
-            "{:>3}:  {:>5}  {},{},{}",
-            self.intermedia.len(),
-            op,
-            target.into(),
-            first.into(),
-            second.into()
-        );
-        self.emit_code(code);
+        self.emit_code(Instruction::ro(op, target, first, second));
     }
 
-    // 跳过中间段的指令
-    // 返回跳过之前的指令地址
-    fn emit_skip(&mut self, skip: usize) -> usize {
-        let loc = self.intermedia.len();
-        for _ in 0..skip {
-            self.intermedia.push("".to_string());
-        }
-        self.emit_loc += skip;
-        loc
+    // 创建一个尚未绑定地址的标签
+    fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
     }
 
-    //
-    fn emit_backup(&mut self, loc: usize) {
-        self.emit_loc = loc;
+    // 将标签绑定到下一条指令的地址
+    fn bind_label(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.intermedia.len());
     }
 
-    //
-    fn emit_restore(&mut self) {
-        self.emit_loc = self.intermedia.len();
+    // 产生一条以PC为基址、跳转到标签的指令，偏移量由resolve_labels回填
+    fn emit_jump(&mut self, op: OpCode, target: impl Into<usize>, label: Label) {
+        self.fixups.push((self.intermedia.len(), label));
+        self.emit_rm(op, target, 0, PC);
     }
 
-    fn emit_rm_abs(&mut self, op: OpCode, target: impl Into<usize>, absolute: impl Into<usize>) {
-        let code = format!(
-            "{:>3}:  {:>5} {},{}({})",
-            self.emit_loc,
-            op,
-            target.into(),
-            absolute.into() - (self.emit_loc + 1),
-            PC
-        );
-        self.emit_code(code);
+    // 删除无法到达的指令，同时更新重定位表与注释的位置
+    fn eliminate_dead_code(&mut self) {
+        let Some((code, locations)) = optimizer::eliminate_dead_code(&self.intermedia) else {
+            return;
+        };
+        // 被删除的位置对应之后第一条保留的指令
+        let relocate = |loc: usize| {
+            locations[loc.min(locations.len())..]
+                .iter()
+                .find_map(|location| *location)
+                .unwrap_or(code.len())
+        };
+        self.relocations
+            .retain(|relocation| locations[relocation.index].is_some());
+        for relocation in &mut self.relocations {
+            relocation.index = relocate(relocation.index);
+        }
+        for (loc, _) in &mut self.comments {
+            *loc = relocate(*loc);
+        }
+        self.symbol_table.relocate_functions(relocate);
+        self.debug_info.retain(&locations);
+        debug!("removed {} dead instructions", self.intermedia.len() - code.len());
+        self.intermedia = code;
+    }
+
+    // 计算所有跳转指令相对于PC的偏移量
+    fn resolve_labels(&mut self) {
+        // 没有定义的函数的入口标签不会被绑定，报告第一次调用的位置最靠前的一个
+        let undefined = self
+            .functions
+            .iter()
+            .filter(|(_, label)| self.labels[label.0].is_none())
+            .filter_map(|(name, _)| Some((name.clone(), *self.calls.get(name)?)))
+            .min_by_key(|(_, span)| span.start);
+        if let Some((name, span)) = undefined {
+            self.fail(CodegenError::UndefinedFunction {
+                name: name.to_string(),
+                span,
+            });
+            return;
+        }
+        for (loc, label) in self.fixups.drain(..) {
+            // 其余的标签总是在编译对应的语句时绑定
+            let Some(target) = self.labels[label.0] else {
+                continue;
+            };
+            if let Instruction::Rm { d, .. } = &mut self.intermedia[loc] {
+                *d = target as i32 - (loc as i32 + 1);
+                trace!("backpatch {}: label {} at {}, offset {}", loc, label.0, target, d);
+            }
+        }
     }
 }
 
-#[cfg(test)]
+// 测试在虚拟机中运行生成的代码，需要std
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use crate::compiler::Compiler;
+    use crate::code::OpCode::{self, ADD, LDC, MUL};
+    use crate::code::RegisterCode::AC;
+    use crate::code::{Instruction, ListingStyle};
+    use crate::compiler::{Backend, Compiler, CompilerOptions, OptLevel};
+    use crate::environment::SymbolType;
+    use crate::error::CodegenError;
     use crate::parser::Parser;
+    use crate::vm::TinyMachine;
 
     #[test]
     fn test_read() {
         let input = "read x;";
         let mut parser = Parser::new(input);
-        let mut compiler = Compiler::new();
-        compiler.compile(&parser.parse_program());
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
         println!("{:?}", compiler.intermedia);
     }
 
@@ -218,8 +1164,8 @@ mod test {
     fn test_write() {
         let input = "read x;write x;";
         let mut parser = Parser::new(input);
-        let mut compiler = Compiler::new();
-        compiler.compile(&parser.parse_program());
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
         println!("{:?}", compiler.intermedia);
     }
 
@@ -227,8 +1173,8 @@ mod test {
     fn test_assign() {
         let input = "x := 5;";
         let mut parser = Parser::new(input);
-        let mut compiler = Compiler::new();
-        compiler.compile(&parser.parse_program());
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
         println!("{:?}", compiler.intermedia);
     }
 
@@ -239,11 +1185,587 @@ x := 5 + 3;
 y := x * 4;
 z := x < y;";
         let mut parser = Parser::new(input);
-        let mut compiler = Compiler::new();
-        compiler.compile(&parser.parse_program());
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        println!("{}", compiler.to_intermedia_code());
+    }
+
+    #[test]
+    fn test_listing_style() {
+        let style = ListingStyle {
+            lowercase_mnemonics: true,
+            ..ListingStyle::default()
+        };
+        let mut parser = Parser::new("if 0 < x then write x; end");
+        let mut compiler = Compiler::default().with_listing_style(style);
+        compiler.preset("x", 0);
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        // 回填的跳转指令与其他指令格式一致
+        let code = compiler.to_intermedia_code();
+        let instructions: Vec<_> = code.lines().filter(|line| !line.starts_with('*')).collect();
+        assert!(instructions.iter().all(|line| *line == line.to_lowercase()));
+        assert!(instructions.contains(&" 11:    jeq  0,3(7)"));
+        println!("{}", code);
+    }
+
+    #[test]
+    fn test_preset() {
+        let mut parser = Parser::new("write limit;");
+        let mut compiler = Compiler::default();
+        compiler.preset("limit", 42);
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        assert_eq!(compiler.data.get(1), Some(42));
+        let code = compiler.to_intermedia_code();
+        assert!(code.ends_with(".DATA  1,42\n"));
+        println!("{}", code);
+    }
+
+    #[test]
+    fn test_constant_pool() {
+        let input = "x := 100000; y := 100000 + 5; z := 70000;";
+        let mut parser = Parser::new(input);
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        let stats = compiler.constant_pool.stats();
+        assert_eq!((stats.entries, stats.hits), (2, 1));
+        assert_eq!(compiler.data.iter().collect::<Vec<_>>(), vec![(1, 100000), (4, 70000)]);
+        assert!(compiler.intermedia.contains(&Instruction::rm(LDC, AC, 5, AC)));
         println!("{}", compiler.to_intermedia_code());
     }
 
+    #[test]
+    fn test_constant_folding() {
+        let input = "x := 3 * 4; y := x + 1;";
+        let mut compiler = Compiler::default().with_constant_folding(true);
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        assert_eq!(compiler.intermedia[2], Instruction::rm(LDC, AC, 12, AC));
+        // 3 * 4 只需一条LDC，x + 1 不能折叠；另有序言的2条指令与HALT
+        assert_eq!(compiler.intermedia.len(), 11);
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut compiler = Compiler::default();
+        compiler
+            .compile(&Parser::new("read x;").parse_program().unwrap())
+            .unwrap();
+        assert_eq!(
+            compiler.to_intermedia_code(),
+            "* TINY Compilation to TM Code
+* Standard prelude:
+  0:     LD  6,0(0)
+  1:     ST  0,0(0)
+* End of standard prelude.
+  2:     IN  0,0,0
+  3:     ST  0,1(5)
+* End of execution.
+  4:   HALT  0,0,0
+"
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut compiler = Compiler::default().with_trace(true);
+        compiler
+            .compile(&Parser::new("read x; x := x + 1;").parse_program().unwrap())
+            .unwrap();
+        let code = compiler.to_intermedia_code();
+        assert!(code.contains(
+            "* -> assign
+* -> Op
+* -> Id
+  4:     LD  0,1(5)
+* <- Id
+  5:     ST  0,0(6)
+* -> Const
+  6:    LDC  0,1(0)
+* <- Const
+  7:     LD  1,0(6)
+  8:    ADD  0,1,0
+* <- Op
+  9:     ST  0,1(5)
+* <- assign
+"
+        ));
+        // 默认不产生这些注释
+        let mut compiler = Compiler::default();
+        compiler
+            .compile(&Parser::new("read x; x := x + 1;").parse_program().unwrap())
+            .unwrap();
+        assert!(!compiler.to_intermedia_code().contains("->"));
+    }
+
+    #[test]
+    fn test_symbol_listing() {
+        let input = "
+read x;
+if 0 < x then
+    fact := 1;
+    repeat
+        fact := fact * x;
+        x := x - 1
+    until x = 0;
+    write fact
+end";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        assert_eq!(compiler.symbol_table.lines("x"), [2, 3, 6, 7, 7, 8]);
+        assert_eq!(
+            compiler.symbol_table.to_listing(),
+            "Variable Name  Location   Line Numbers
+-------------  --------   ------------
+x              1            2    3    6    7    7    8
+fact           2            4    6    6    9
+"
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        let input = "var a[3]; i := 2; a[i] := 7; write a[i] + a[0];";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        // a占用地址1到3，i的地址为4
+        assert_eq!(compiler.symbol_table.address("i"), Some(4));
+        let a = compiler.symbol_table.look_up("a").unwrap();
+        assert_eq!(a.ty, SymbolType::Array(3));
+        assert_eq!(a.definition.map(|span| span.start), Some(4));
+        assert_eq!(a.uses(), 4);
+        let code = compiler.to_intermedia_code();
+        assert!(code.contains(
+            "  4:    LDC  0,7(0)
+  5:     ST  0,0(6)
+  6:     LD  0,4(5)
+  7:    LDA  1,1(5)
+  8:    ADD  1,1,0
+  9:     LD  0,0(6)
+ 10:     ST  0,0(1)
+"
+        ));
+
+        let mut vm = TinyMachine::new("".as_bytes(), vec![]);
+        vm.load(&code).unwrap();
+        vm.run().unwrap();
+        assert_eq!(String::from_utf8(vm.output).unwrap(), "7\n");
+    }
+
+    #[test]
+    fn test_function() {
+        let input = "
+calls := 0;
+read x;
+write fact(x) + 1;
+write 2 * fact(3);
+write calls;
+fn fact(n)
+    calls := calls + 1;
+    if n < 2 then return 1 end;
+    m := n - 1;
+    return n * fact(m)
+end;
+fn skip() end;";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let fact = compiler.symbol_table.function("fact").unwrap();
+        assert_eq!(fact.arity, 1);
+        // 局部变量m不占用全局地址
+        assert_eq!(compiler.symbol_table.look_up("m"), None);
+        let code = compiler.to_intermedia_code();
+        // 函数入口保存返回地址
+        assert!(code.contains(&format!("{:3}:     ST  0,0(6)", fact.entry)));
+
+        let mut vm = TinyMachine::new("5".as_bytes(), vec![]);
+        vm.load(&code).unwrap();
+        vm.run().unwrap();
+        assert_eq!(String::from_utf8(vm.output).unwrap(), "121\n12\n8\n");
+    }
+
+    #[test]
+    fn test_logical() {
+        let input = "
+read x;
+if x <> 0 and 10 / x > 2 then write 1 else write 0 end;
+if x = 0 or not 10 / x < 3 then write 2 end;
+while true and x < 3 do x := x + 1 end;
+write x";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let code = compiler.to_intermedia_code();
+        // 左操作数为0时跳过除法，不会除以0
+        for (stdin, expected) in [("0", "0\n2\n3\n"), ("2", "1\n2\n3\n"), ("5", "0\n5\n")] {
+            let mut vm = TinyMachine::new(stdin.as_bytes(), vec![]);
+            vm.load(&code).unwrap();
+            vm.run().unwrap();
+            assert_eq!(String::from_utf8(vm.output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_temporaries() {
+        // 每一层调用的返回地址与实参都保存在临时区域中
+        let input = "
+x := 1;
+y := 2;
+fn add(a, b) return a + b end;
+write x + y * add(x, y - add(y, x * add(1, 2 - x)));
+write x;
+write y";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        assert_eq!(compiler.stack_depth(), 12);
+        let code = compiler.to_intermedia_code();
+
+        // 数据存储器恰好能容纳全局变量与临时区域时，临时单元不会覆盖变量
+        let size = compiler.symbol_table.data_size() + compiler.stack_depth();
+        let mut vm = TinyMachine::new("".as_bytes(), vec![]);
+        vm.memory = vec![0; size];
+        vm.memory[0] = size as i32 - 1;
+        vm.load(&code).unwrap();
+        vm.run().unwrap();
+        assert_eq!(String::from_utf8(vm.output).unwrap(), "-1\n1\n2\n");
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let input = "read x;\nwhile x > 0 do\n    x := x - 1\nend;\nwrite x";
+        let mut compiler = Compiler::default().with_debug_info(true).with_opt_level(1);
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let info = compiler.debug_info();
+        assert_eq!(info.len(), compiler.intermedia.len());
+        let lines: Vec<_> = (0..info.len()).map(|addr| info.line(addr)).collect();
+        // 标准序言与HALT不属于任何语句，循环末尾的跳转属于while语句
+        let mut expected = vec![None, None, Some(1), Some(1)];
+        expected.extend([Some(2); 10]);
+        expected.extend([Some(3); 6]);
+        expected.extend([Some(2), Some(5), Some(5), None]);
+        assert_eq!(lines, expected);
+        assert_eq!(info.span(2).map(|span| (span.start, span.end)), Some((0, 7)));
+
+        let code = compiler.to_intermedia_code();
+        assert!(code.contains("      .SPAN  2,1,1,0,7\n"));
+        let mut vm = TinyMachine::new("2".as_bytes(), vec![]);
+        vm.load(&code).unwrap();
+        assert!(vm.debug_info.iter().eq(info.iter()));
+        vm.run().unwrap();
+        assert_eq!(vm.output, b"0\n");
+    }
+
+    #[test]
+    fn test_registers() {
+        // 嵌套的表达式用完空闲寄存器后溢出到临时区域；调用前后保存正在使用的寄存器
+        let input = "
+x := 1;
+y := 2;
+var a[8];
+a[3] := 3;
+a[7] := 6;
+fn sq(n) return n * n end;
+write x + y * a[x + y * a[x + y]];
+write x + y * sq(x + y * sq(y - x));
+write x < y and x + y * 2 = 5;
+a[x + y * a[3] - 6] := y - x * sq(y);
+write a[1]";
+        let run = |opt_level: u8| {
+            let mut compiler = Compiler::default().with_opt_level(opt_level);
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            let code = compiler.to_intermedia_code();
+            let mut vm = TinyMachine::new("".as_bytes(), vec![]);
+            vm.load(&code).unwrap();
+            vm.run().unwrap();
+            let output = String::from_utf8(vm.output).unwrap();
+            (output, code.lines().filter(|line| !line.starts_with('*')).count())
+        };
+        let (output, size) = run(1);
+        let (register_output, register_size) = run(2);
+        assert_eq!(output, "13\n19\n1\n-2\n");
+        assert_eq!(register_output, output);
+        assert!(register_size < size, "{} >= {}", register_size, size);
+    }
+
+    // 没有经过 semantic::check 的程序以错误报告，而不是panic
+    #[test]
+    fn test_unchecked_program() {
+        let compile = |input: &str| Compiler::default().compile(&Parser::new(input).parse_program().unwrap());
+        assert!(matches!(
+            compile("x := 1;\nwrite y"),
+            Err(CodegenError::UnassignedVariable { ref name, span }) if name == "y" && span.line == 2
+        ));
+        assert!(matches!(compile("x := a[0]"), Err(CodegenError::NotAnArray { .. })));
+        assert!(matches!(
+            compile("return 1"),
+            Err(CodegenError::ReturnOutsideFunction { .. })
+        ));
+        assert!(matches!(
+            compile("fn f() fn g() return 1 end; return 2 end; write f()"),
+            Err(CodegenError::NestedFunction { .. })
+        ));
+        assert!(matches!(
+            compile("x := \"s\""),
+            Err(CodegenError::StringNotWritable { .. })
+        ));
+        assert!(matches!(
+            compile("x := 1;\nwrite f(1);\ng()"),
+            Err(CodegenError::UndefinedFunction { ref name, span }) if name == "f" && span.line == 2
+        ));
+        let (program, _) = Parser::new("write 1 +").parse_program_partial();
+        assert!(matches!(
+            Compiler::default().compile(&program),
+            Err(CodegenError::SyntaxError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_temporaries_overflow() {
+        let mut compiler = Compiler::default().with_memory_size(3);
+        let err = compiler
+            .compile(&Parser::new("x := 1; write x + x * x").parse_program().unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CodegenError::OutOfMemory {
+                data: 2,
+                stack: 2,
+                available: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "program needs 4 words of data memory (2 for data, 2 for temporaries), but only 3 are available"
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(OptLevel::from_level(7), OptLevel::O2);
+        assert_eq!(OptLevel::O1.level(), 1);
+        assert!(!OptLevel::O0.folds_constants() && OptLevel::O1.folds_constants());
+        assert!(!OptLevel::O1.allocates_registers() && OptLevel::O2.allocates_registers());
+
+        let input = "x := 2 * 3; if false then write x end; write x + 1";
+        let compile = |options: CompilerOptions| {
+            let mut compiler = Compiler::new(options);
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            compiler.to_intermedia_code()
+        };
+        let readable = compile(CompilerOptions {
+            trace: true,
+            debug_info: true,
+            backend: Backend::RelocatableTm,
+            ..CompilerOptions::default()
+        });
+        assert!(readable.contains("* -> assign"));
+        assert!(readable.contains(".SPAN"));
+        assert!(readable.contains(".RELOC"));
+        assert!(readable.contains("MUL"));
+        let optimized = compile(CompilerOptions {
+            opt_level: OptLevel::O1,
+            ..CompilerOptions::default()
+        });
+        assert!(!optimized.contains("MUL") && !optimized.contains(".SPAN"));
+        let size = |code: &str| code.lines().filter(|line| !line.starts_with('*')).count();
+        assert!(size(&optimized) < size(&compile(CompilerOptions::default())));
+    }
+
+    #[test]
+    fn test_strength_reduction() {
+        let input = "read x; write x * 8; write 2 * x + 0; write x * 1 - 0 / 1; write x * 16; write x / 4";
+        let compile = |opt_level: OptLevel| {
+            let mut compiler = Compiler::new(CompilerOptions {
+                opt_level,
+                ..CompilerOptions::default()
+            });
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            compiler.intermedia
+        };
+        let count = |code: &[Instruction], op: OpCode| code.iter().filter(|inst| inst.op() == op).count();
+        let plain = compile(OptLevel::O0);
+        let reduced = compile(OptLevel::O1);
+        assert_eq!((count(&plain, MUL), count(&reduced, MUL)), (4, 1));
+        assert_eq!(count(&reduced, ADD), 4);
+        assert!(
+            reduced.len() + 15 <= plain.len(),
+            "{} vs {}",
+            reduced.len(),
+            plain.len()
+        );
+        // O2 直接将常量读入AC1，只有乘以2时加法更短
+        assert_eq!(count(&compile(OptLevel::O2), MUL), 2);
+
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let mut compiler = Compiler::new(CompilerOptions {
+                opt_level: level,
+                ..CompilerOptions::default()
+            });
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            let mut vm = TinyMachine::new("-268435457".as_bytes(), vec![]);
+            vm.load(&compiler.to_intermedia_code()).unwrap();
+            vm.run().unwrap();
+            let output = String::from_utf8(vm.output).unwrap();
+            assert_eq!(
+                output, "2147483640\n-536870914\n-268435457\n-16\n-67108864\n",
+                "{:?}",
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn test_propagate_constants() {
+        let input = "x := 5; y := x + 1; write y";
+        let compile = |opt_level: OptLevel| {
+            let mut compiler = Compiler::new(CompilerOptions {
+                opt_level,
+                ..CompilerOptions::default()
+            });
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            compiler
+        };
+        let compiler = compile(OptLevel::O1);
+        assert_eq!(compiler.propagated_constants, 0);
+        assert!(compiler.intermedia.iter().any(|inst| inst.op() == ADD));
+        let compiler = compile(OptLevel::O2);
+        assert_eq!(compiler.propagated_constants, 2);
+        assert!(compiler.intermedia.iter().all(|inst| inst.op() != ADD));
+        // x与y的初值写入数据存储器，write y只是一条LDC 6
+        assert_eq!(compiler.data.get(2), Some(6));
+        let loads = compiler
+            .intermedia
+            .iter()
+            .filter(|inst| {
+                **inst
+                    == Instruction::Rm {
+                        op: LDC,
+                        r: 0,
+                        d: 6,
+                        s: 0,
+                    }
+            })
+            .count();
+        assert_eq!(loads, 1);
+    }
+
+    #[test]
+    fn test_initialize_data() {
+        let input = "var a[2]; x := 5; y := 0 - 3 * 2; big := 100000; read x; z := 7; write x + y + big + z";
+        let compile = |opt_level: u8| {
+            let mut compiler = Compiler::default().with_opt_level(opt_level);
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            compiler
+        };
+        let compiler = compile(0);
+        assert_eq!(compiler.initialized_globals, 0);
+        assert_eq!(compiler.data.get(3), None);
+
+        // 第一个会执行的语句read x之后的赋值仍在运行时执行
+        let compiler = compile(1);
+        assert_eq!(compiler.initialized_globals, 3);
+        let code = compiler.to_intermedia_code();
+        assert!(code.contains(".DATA  3,5\n"));
+        assert!(code.contains(".DATA  4,-6\n"));
+        assert!(code.contains(".DATA  5,100000\n"));
+        assert!(compiler.intermedia.iter().all(|inst| inst.op() != MUL));
+        assert!(compiler
+            .intermedia
+            .iter()
+            .any(|inst| matches!(inst, Instruction::Rm { op: LDC, d: 7, .. })));
+
+        let mut vm = TinyMachine::new("1\n".as_bytes(), vec![]);
+        vm.load(&code).unwrap();
+        vm.run().unwrap();
+        assert_eq!(String::from_utf8(vm.output).unwrap(), "100002\n");
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let input = "
+var a[4];
+read x; read y;
+a[x * y - 5] := x * y;
+z := a[x * y - 5] + a[x * y - 5] * 2;
+if x * y > 6 and x * y < 100 then write -z + -z end;
+while x - y < 0 and not x - y = -3 do x := x + 1 end;
+write x - y;
+write a[3]";
+        let compile = |opt_level: OptLevel| {
+            let mut compiler = Compiler::new(CompilerOptions {
+                opt_level,
+                ..CompilerOptions::default()
+            });
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            let mut vm = TinyMachine::new("2\n4".as_bytes(), vec![]);
+            vm.load(&compiler.to_intermedia_code()).unwrap();
+            vm.run().unwrap();
+            let output = String::from_utf8(vm.output).unwrap();
+            (output, compiler.intermedia.len(), compiler.eliminated_subexpressions)
+        };
+        let (output, size, eliminated) = compile(OptLevel::O0);
+        assert_eq!(output, "-48\n0\n8\n");
+        assert_eq!(eliminated, 0);
+        for level in [OptLevel::O1, OptLevel::O2] {
+            let (optimized, optimized_size, eliminated) = compile(level);
+            assert_eq!(optimized, output);
+            assert_eq!(eliminated, 5, "{:?}", level);
+            assert!(optimized_size < size);
+        }
+    }
+
+    #[test]
+    fn test_loop_invariants() {
+        let input = "
+read n; read m;
+i := 0; sum := 0;
+while i < n * m do
+    j := 0;
+    repeat
+        sum := sum + n * m - m * 3 + j;
+        j := j + 1
+    until j = m * 3;
+    i := i + 1
+end;
+write sum";
+        let run = |opt_level: OptLevel| {
+            let mut compiler = Compiler::new(CompilerOptions {
+                opt_level,
+                ..CompilerOptions::default()
+            });
+            compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+            let mut vm = TinyMachine::new("4\n5".as_bytes(), vec![]);
+            vm.load(&compiler.to_intermedia_code()).unwrap();
+            vm.run().unwrap();
+            let executed = vm.executed();
+            (
+                String::from_utf8(vm.output).unwrap(),
+                executed,
+                compiler.hoisted_invariants,
+            )
+        };
+        let (output, executed, hoisted) = run(OptLevel::O1);
+        assert_eq!(output, "3600\n");
+        assert_eq!(hoisted, 0);
+        let (optimized, optimized_executed, hoisted) = run(OptLevel::O2);
+        assert_eq!(optimized, output);
+        // 外层循环外提n * m与m * 3，内层循环直接使用它们
+        assert_eq!(hoisted, 2);
+        assert!(
+            optimized_executed * 3 < executed * 2,
+            "{} vs {}",
+            optimized_executed,
+            executed
+        );
+    }
+
+    #[test]
+    fn test_program_name() {
+        let input = "program echo; read x; write x; end.";
+        let mut parser = Parser::new(input);
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        let code = compiler.to_intermedia_code();
+        assert!(code.starts_with("* program echo\n"));
+        println!("{}", code);
+    }
+
     #[test]
     fn test_unit() {
         let input = "
@@ -258,8 +1780,8 @@ if 0 < x then { don't compute if x <= 0 }
     write fact; { output factorial of x }
 end";
         let mut parser = Parser::new(input);
-        let mut compiler = Compiler::new();
-        compiler.compile(&parser.parse_program());
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
         println!("{}", compiler.to_intermedia_code());
     }
 }
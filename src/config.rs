@@ -0,0 +1,292 @@
+use crate::lint::LINTS;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 项目配置文件名，从输入文件所在目录开始逐级向上查找
+pub const CONFIG_FILE: &str = "tinycc.toml";
+
+// tinycc.toml 的内容，只支持TOML的一个子集：
+//
+// opt-level = 1
+// max-depth = 64
+// strict = true
+//
+// [warnings]
+// unused-variable = "deny"
+//
+// [memory]
+// size = 1024
+//
+// [format]
+// indent = 4
+//
+// 未出现的项为None，由使用方决定默认值
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub opt_level: Option<u8>,
+    // 语句与表达式嵌套的最大深度，见 Parser::with_max_depth
+    pub max_depth: Option<usize>,
+    // 变量必须先以var声明才能被赋值或read，见 semantic::check_with_options
+    pub strict: Option<bool>,
+    pub warnings: BTreeMap<String, WarningLevel>,
+    pub memory_size: Option<usize>,
+    pub format_indent: Option<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WarningLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+impl Config {
+    pub fn parse(input: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (index, line) in input.lines().enumerate() {
+            let error = |message: String| ConfigError {
+                path: None,
+                line: index + 1,
+                message,
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = name.trim().to_string();
+                if !matches!(section.as_str(), "warnings" | "memory" | "format") {
+                    return Err(error(format!("unknown section `[{}]`", section)));
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `key = value`, found `{}`", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            match (section.as_str(), key) {
+                ("", "opt-level") => config.opt_level = Some(parse_integer(value).map_err(error)?),
+                ("", "max-depth") => config.max_depth = Some(parse_integer(value).map_err(error)?),
+                ("", "strict") => config.strict = Some(parse_bool(value).map_err(error)?),
+                ("warnings", name) => {
+                    check_lint(name).map_err(error)?;
+                    let level = WarningLevel::parse(&parse_string(value).map_err(error)?).map_err(error)?;
+                    config.warnings.insert(name.to_string(), level);
+                }
+                ("memory", "size") => config.memory_size = Some(parse_integer(value).map_err(error)?),
+                ("format", "indent") => config.format_indent = Some(parse_integer(value).map_err(error)?),
+                _ => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let input = fs::read_to_string(path).map_err(|err| ConfigError {
+            path: Some(path.to_path_buf()),
+            line: 0,
+            message: err.to_string(),
+        })?;
+        Self::parse(&input).map_err(|err| ConfigError {
+            path: Some(path.to_path_buf()),
+            ..err
+        })
+    }
+
+    // 从input所在目录开始向上查找最近的配置文件
+    pub fn discover(input: &Path) -> Option<PathBuf> {
+        let start = if input.is_dir() { Some(input) } else { input.parent() };
+        let start = start.map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        })?;
+        let start = fs::canonicalize(start).ok()?;
+        start
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|candidate| candidate.is_file())
+    }
+
+    // 查找并读取input对应的配置文件，找不到时返回默认配置
+    pub fn load_for(input: &Path) -> Result<Self, ConfigError> {
+        match Self::discover(input) {
+            Some(path) => Self::from_path(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    // 用other中设置过的项覆盖当前配置，通常other来自命令行参数
+    pub fn merge(&mut self, other: &Config) {
+        if other.opt_level.is_some() {
+            self.opt_level = other.opt_level;
+        }
+        if other.max_depth.is_some() {
+            self.max_depth = other.max_depth;
+        }
+        if other.strict.is_some() {
+            self.strict = other.strict;
+        }
+        for (name, level) in &other.warnings {
+            self.warnings.insert(name.clone(), *level);
+        }
+        if other.memory_size.is_some() {
+            self.memory_size = other.memory_size;
+        }
+        if other.format_indent.is_some() {
+            self.format_indent = other.format_indent;
+        }
+    }
+}
+
+impl WarningLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "allow" => Ok(WarningLevel::Allow),
+            "warn" => Ok(WarningLevel::Warn),
+            "deny" => Ok(WarningLevel::Deny),
+            _ => Err(format!(
+                "unknown warning level `{}`, expected allow, warn or deny",
+                value
+            )),
+        }
+    }
+}
+
+// name必须是 lint::LINTS 中的一个警告
+pub fn check_lint(name: &str) -> Result<(), String> {
+    if LINTS.contains(&name) {
+        return Ok(());
+    }
+    Err(format!("unknown lint `{}`, expected one of {}", name, LINTS.join(", ")))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected a quoted string, found `{}`", value))
+}
+
+fn parse_integer<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("expected an integer, found `{}`", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false, found `{}`", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Config, WarningLevel, CONFIG_FILE};
+    use std::fs;
+
+    #[test]
+    fn test_parse() {
+        let input = r#"
+# course settings
+opt-level = 1
+max-depth = 64
+strict = true
+
+[warnings]
+unused-variable = "deny" # no unused variables
+
+[memory]
+size = 2048
+
+[format]
+indent = 2
+"#;
+        let config = Config::parse(input).unwrap();
+        assert_eq!(config.opt_level, Some(1));
+        assert_eq!(config.max_depth, Some(64));
+        assert_eq!(config.strict, Some(true));
+        assert_eq!(config.warnings.get("unused-variable"), Some(&WarningLevel::Deny));
+        assert_eq!(config.memory_size, Some(2048));
+        assert_eq!(config.format_indent, Some(2));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = Config::parse("opt-level = 1\n[memory]\nsize = big").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "expected an integer, found `big`");
+        assert!(Config::parse("colour = \"red\"").is_err());
+        assert!(Config::parse("[linker]").is_err());
+        assert!(Config::parse("dialect = \"standard\"").is_err());
+
+        let err = Config::parse("[warnings]\nunused = \"deny\"").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(
+            err.message,
+            "unknown lint `unused`, expected one of division-by-zero, unused-variable, maybe-uninitialized, never-assigned"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut config = Config::parse("opt-level = 1\n[memory]\nsize = 2048").unwrap();
+        let flags = Config {
+            opt_level: Some(2),
+            ..Config::default()
+        };
+        config.merge(&flags);
+        assert_eq!(config.opt_level, Some(2));
+        assert_eq!(config.memory_size, Some(2048));
+    }
+
+    #[test]
+    fn test_discover() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_config_{}", std::process::id()));
+        let nested = root.join("week1").join("alice");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(CONFIG_FILE), "opt-level = 2").unwrap();
+        let source = nested.join("fact.tny");
+
+        let found = Config::discover(&source).unwrap();
+        assert_eq!(found, fs::canonicalize(root.join(CONFIG_FILE)).unwrap());
+        assert_eq!(Config::load_for(&source).unwrap().opt_level, Some(2));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,427 @@
+use crate::code::{DebugInfo, RegisterCode};
+use crate::compiler::Compiler;
+use crate::environment::SymbolTable;
+use crate::vm::{TinyMachine, VmError, REGISTER_COUNT};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "(debug) ";
+
+const HELP: &str = "commands:
+    break <line>      stop before the first instruction of a source line
+    break *<addr>     stop before the instruction at an address
+    delete <line>|*<addr>
+                      remove a breakpoint
+    watch <var>       stop after a global variable changes
+    unwatch <var>     remove a watchpoint
+    step, s           execute one instruction
+    next, n           execute until the source line changes
+    continue, c       execute until a breakpoint, a watchpoint or the end
+    print <var>, p    show the value of a global variable
+    registers         show all registers
+    memory <addr> [n] show n words of data memory starting at addr
+    quit, q           stop debugging";
+
+// 断点：源程序的行号或指令地址
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Breakpoint {
+    Line(usize),
+    Address(usize),
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+            Breakpoint::Address(addr) => write!(f, "address {}", addr),
+        }
+    }
+}
+
+// 监视的全局变量，value为上一次检查时的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub name: String,
+    pub addr: usize,
+    pub value: i32,
+}
+
+// 执行暂停的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    // 完成了单步执行
+    Step,
+    Breakpoint(Breakpoint),
+    Watchpoint { name: String, old: i32, new: i32 },
+    Halted,
+}
+
+// 在TM虚拟机上调试编译器产生的程序
+// 断点可以设在源程序的行号或指令地址上，监视点通过符号表找到全局变量的地址
+pub struct Debugger<R, W> {
+    pub vm: TinyMachine<R, W>,
+    debug_info: DebugInfo,
+    symbol_table: SymbolTable,
+    breakpoints: BTreeSet<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(vm: TinyMachine<R, W>, debug_info: DebugInfo, symbol_table: SymbolTable) -> Self {
+        Self {
+            vm,
+            debug_info,
+            symbol_table,
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+        }
+    }
+
+    // 装载compiler编译的程序，程序的read与write使用input与output
+    pub fn from_compiler(compiler: &Compiler, input: R, output: W) -> Result<Self, VmError> {
+        let mut vm = TinyMachine::new(input, output);
+        vm.load(&compiler.to_intermedia_code())?;
+        Ok(Self::new(
+            vm,
+            compiler.debug_info().clone(),
+            compiler.symbol_table.clone(),
+        ))
+    }
+
+    // 添加断点，已存在时返回false
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.remove(&breakpoint)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = Breakpoint> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // 监视全局变量name，变量不存在时返回false
+    pub fn watch(&mut self, name: &str) -> bool {
+        let Some(addr) = self.address(name) else {
+            return false;
+        };
+        if !self.watchpoints.iter().any(|watchpoint| watchpoint.name == name) {
+            let value = self.memory(addr).unwrap_or(0);
+            self.watchpoints.push(Watchpoint {
+                name: name.to_string(),
+                addr,
+                value,
+            });
+        }
+        true
+    }
+
+    pub fn unwatch(&mut self, name: &str) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.name != name);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // 下一条要执行的指令所属的源程序行号
+    pub fn current_line(&self) -> Option<usize> {
+        self.debug_info.line(self.vm.pc())
+    }
+
+    pub fn register(&self, register: RegisterCode) -> i32 {
+        self.vm.registers[usize::from(register)]
+    }
+
+    pub fn memory(&self, addr: usize) -> Option<i32> {
+        self.vm.memory.get(addr).copied()
+    }
+
+    // 全局变量的当前值，数组为第一个元素的值
+    pub fn variable(&self, name: &str) -> Option<i32> {
+        self.memory(self.address(name)?)
+    }
+
+    // 全局变量在数据存储器中的地址，相对GP
+    fn address(&self, name: &str) -> Option<usize> {
+        let loc = self.symbol_table.address(name)?;
+        usize::try_from(self.register(RegisterCode::GP) + loc).ok()
+    }
+
+    // 执行一条指令
+    pub fn step(&mut self) -> Result<Stop, VmError> {
+        if self.vm.is_halted() {
+            return Ok(Stop::Halted);
+        }
+        self.vm.step()?;
+        for index in 0..self.watchpoints.len() {
+            let new = self.memory(self.watchpoints[index].addr).unwrap_or(0);
+            let watchpoint = &mut self.watchpoints[index];
+            if watchpoint.value != new {
+                let old = std::mem::replace(&mut watchpoint.value, new);
+                return Ok(Stop::Watchpoint {
+                    name: watchpoint.name.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+        if self.vm.is_halted() {
+            return Ok(Stop::Halted);
+        }
+        Ok(Stop::Step)
+    }
+
+    // 执行到源程序的行号改变为止，会进入被调用的函数
+    pub fn step_line(&mut self) -> Result<Stop, VmError> {
+        let start = self.current_line();
+        loop {
+            let stop = self.step_checked()?;
+            if stop != Stop::Step || self.current_line().is_some_and(|line| Some(line) != start) {
+                return Ok(stop);
+            }
+        }
+    }
+
+    // 执行到断点、监视点或停机为止
+    pub fn resume(&mut self) -> Result<Stop, VmError> {
+        loop {
+            let stop = self.step_checked()?;
+            if stop != Stop::Step {
+                return Ok(stop);
+            }
+        }
+    }
+
+    // 执行一条指令，下一条指令处有断点时返回 Stop::Breakpoint
+    // 行号断点只在从其他行进入该行时命中，循环的每一次迭代都会命中
+    fn step_checked(&mut self) -> Result<Stop, VmError> {
+        let line = self.current_line();
+        let stop = self.step()?;
+        if stop != Stop::Step {
+            return Ok(stop);
+        }
+        let address = Breakpoint::Address(self.vm.pc());
+        if self.breakpoints.contains(&address) {
+            return Ok(Stop::Breakpoint(address));
+        }
+        match self.current_line() {
+            Some(current) if Some(current) != line && self.breakpoints.contains(&Breakpoint::Line(current)) => {
+                Ok(Stop::Breakpoint(Breakpoint::Line(current)))
+            }
+            _ => Ok(Stop::Step),
+        }
+    }
+
+    // 从程序的输入中逐行读取调试命令并执行，结果写入out，直到quit或输入结束
+    // 与 Repl 一致，程序执行read时从同一个输入中读取下一行
+    pub fn run(&mut self, mut out: impl Write) -> io::Result<()> {
+        loop {
+            write!(out, "{}", PROMPT)?;
+            out.flush()?;
+            let mut line = String::new();
+            if self.vm.input.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["quit" | "q"] => return Ok(()),
+                ["help" | "h"] => writeln!(out, "{}", HELP)?,
+                ["step" | "s"] => self.execute(Self::step, &mut out)?,
+                ["next" | "n"] => self.execute(Self::step_line, &mut out)?,
+                ["continue" | "c"] => self.execute(Self::resume, &mut out)?,
+                ["break" | "b", location] => match parse_breakpoint(location) {
+                    Some(breakpoint) => {
+                        self.add_breakpoint(breakpoint);
+                        writeln!(out, "breakpoint at {}", breakpoint)?;
+                    }
+                    None => writeln!(out, "error: expected a line number or *address, found `{}`", location)?,
+                },
+                ["delete" | "d", location] => match parse_breakpoint(location) {
+                    Some(breakpoint) if self.remove_breakpoint(breakpoint) => {
+                        writeln!(out, "deleted breakpoint at {}", breakpoint)?
+                    }
+                    _ => writeln!(out, "error: no breakpoint at `{}`", location)?,
+                },
+                ["watch" | "w", name] => {
+                    if self.watch(name) {
+                        writeln!(out, "watching {}", name)?;
+                    } else {
+                        writeln!(out, "error: no global variable `{}`", name)?;
+                    }
+                }
+                ["unwatch", name] => {
+                    if !self.unwatch(name) {
+                        writeln!(out, "error: `{}` is not watched", name)?;
+                    }
+                }
+                ["print" | "p", name] => match self.variable(name) {
+                    Some(value) => writeln!(out, "{} = {}", name, value)?,
+                    None => writeln!(out, "error: no global variable `{}`", name)?,
+                },
+                ["registers" | "r"] => {
+                    let registers: Vec<_> = (0..REGISTER_COUNT)
+                        .map(|index| format!("r{}={}", index, self.vm.registers[index]))
+                        .collect();
+                    writeln!(out, "{}", registers.join(" "))?;
+                }
+                ["memory" | "x", addr, rest @ ..] if rest.len() <= 1 => {
+                    let addr = addr.parse::<usize>();
+                    let count = rest.first().map_or(Ok(1), |count| count.parse::<usize>());
+                    match (addr, count) {
+                        (Ok(addr), Ok(count)) => {
+                            for addr in addr..addr + count {
+                                match self.memory(addr) {
+                                    Some(value) => writeln!(out, "{}: {}", addr, value)?,
+                                    None => {
+                                        writeln!(out, "error: data address {} out of range", addr)?;
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                        _ => writeln!(out, "error: expected `memory <addr> [count]`")?,
+                    }
+                }
+                _ => writeln!(out, "error: unknown command `{}`, try `help`", line.trim())?,
+            }
+        }
+    }
+
+    // 执行一条运行命令并报告暂停的位置
+    fn execute(&mut self, command: fn(&mut Self) -> Result<Stop, VmError>, out: &mut impl Write) -> io::Result<()> {
+        match command(self) {
+            Ok(Stop::Halted) => writeln!(out, "program halted"),
+            Ok(stop) => {
+                match stop {
+                    Stop::Breakpoint(breakpoint) => writeln!(out, "breakpoint at {}", breakpoint)?,
+                    Stop::Watchpoint { name, old, new } => writeln!(out, "{} changed from {} to {}", name, old, new)?,
+                    _ => {}
+                }
+                self.print_location(out)
+            }
+            Err(err) => writeln!(out, "error: {}", err),
+        }
+    }
+
+    // 输出下一条要执行的指令及其所属的行号
+    fn print_location(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.vm.pc();
+        let instruction = self
+            .vm
+            .instruction(pc)
+            .map_or("<no instruction>".to_string(), |instruction| instruction.to_string());
+        match self.current_line() {
+            Some(line) => writeln!(out, "{:>4}: {}    (line {})", pc, instruction, line),
+            None => writeln!(out, "{:>4}: {}", pc, instruction),
+        }
+    }
+}
+
+// 行号，或以*开头的指令地址
+fn parse_breakpoint(location: &str) -> Option<Breakpoint> {
+    match location.strip_prefix('*') {
+        Some(addr) => addr.parse().ok().map(Breakpoint::Address),
+        None => location.parse().ok().map(Breakpoint::Line),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::debugger::{Breakpoint, Debugger, Stop};
+    use crate::parser::Parser;
+
+    fn debugger<'a>(input: &str, stdin: &'a str) -> Debugger<&'a [u8], Vec<u8>> {
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        Debugger::from_compiler(&compiler, stdin.as_bytes(), vec![]).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let input = "read x;\ny := 0;\nwhile x > 0 do\n    y := y + x;\n    x := x - 1\nend;\nwrite y";
+        let mut debugger = debugger(input, "3");
+        assert!(debugger.add_breakpoint(Breakpoint::Line(4)));
+        assert!(!debugger.add_breakpoint(Breakpoint::Line(4)));
+        // 每一次迭代都会命中
+        for x in [3, 2, 1] {
+            assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(Breakpoint::Line(4)));
+            assert_eq!(debugger.current_line(), Some(4));
+            assert_eq!(debugger.variable("x"), Some(x));
+        }
+        assert!(debugger.remove_breakpoint(Breakpoint::Line(4)));
+        let pc = debugger.vm.pc();
+        assert_eq!(debugger.step().unwrap(), Stop::Step);
+        assert_eq!(debugger.vm.pc(), pc + 1);
+        assert_eq!(debugger.step_line().unwrap(), Stop::Step);
+        assert_eq!(debugger.current_line(), Some(5));
+        assert_eq!(debugger.resume().unwrap(), Stop::Halted);
+        assert_eq!(debugger.variable("y"), Some(6));
+        assert_eq!(debugger.vm.output, b"6\n");
+
+        let mut debugger = self::debugger(input, "3");
+        debugger.add_breakpoint(Breakpoint::Address(4));
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(Breakpoint::Address(4)));
+        assert_eq!(debugger.vm.pc(), 4);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut debugger = debugger("x := 1;\nx := x;\ny := 2;\nx := x + y", "");
+        assert!(debugger.watch("x"));
+        assert!(!debugger.watch("z"));
+        assert_eq!(
+            debugger.resume().unwrap(),
+            Stop::Watchpoint {
+                name: "x".to_string(),
+                old: 0,
+                new: 1
+            }
+        );
+        assert_eq!(debugger.current_line(), Some(2));
+        // 赋相同的值不会暂停
+        assert_eq!(
+            debugger.resume().unwrap(),
+            Stop::Watchpoint {
+                name: "x".to_string(),
+                old: 1,
+                new: 3
+            }
+        );
+        assert!(debugger.unwatch("x"));
+        assert_eq!(debugger.resume().unwrap(), Stop::Halted);
+    }
+
+    #[test]
+    fn test_commands() {
+        // 第4行为程序的read读取的输入
+        let commands = "break 2\nbreak *0\nc\n5\nprint x\nx 1 2\nregisters\nstep\nfoo\nc\nc\n";
+        let mut debugger = debugger("read x;\nwrite x * 2", commands);
+        let mut out = vec![];
+        debugger.run(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "(debug) breakpoint at line 2
+(debug) breakpoint at address 0
+(debug) breakpoint at line 2
+   4: LD 0,1(5)    (line 2)
+(debug) x = 5
+(debug) 1: 5
+2: 0
+(debug) r0=5 r1=0 r2=0 r3=0 r4=0 r5=0 r6=1023 r7=4
+(debug)    5: ST 0,0(6)    (line 2)
+(debug) error: unknown command `foo`, try `help`
+(debug) program halted
+(debug) program halted
+(debug) \n"
+        );
+        assert_eq!(debugger.vm.output, b"10\n");
+    }
+}
@@ -0,0 +1,244 @@
+use crate::interpreter::RuntimeError;
+use crate::parser::ParseError;
+use crate::semantic::{SemanticError, Warning};
+use crate::token::Span;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// 一条诊断信息，span为None时与源程序中的位置无关，如源文件的编码问题
+// 由多个源文件拼接编译时，file为所在的文件，span相对于该文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Option<Span>,
+    pub message: String,
+    pub help: Option<String>,
+    pub file: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Option<Span>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            span,
+            message: message.into(),
+            help: None,
+            file: None,
+        }
+    }
+
+    pub fn error(span: Option<Span>, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Option<Span>, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, span, message)
+    }
+
+    pub fn note(span: Option<Span>, message: impl Into<String>) -> Self {
+        Self::new(Severity::Note, span, message)
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    // 以类似rustc的格式输出，附带源程序片段并用^标出span
+    // source必须是产生span的同一份源程序文本
+    pub fn render(&self, source: &str, file: &str) -> String {
+        let mut output = format!("{}: {}\n", self.severity, self.message);
+        let Some(span) = self.span else {
+            output.push_str(&format!(" --> {}\n", file));
+            if let Some(help) = &self.help {
+                output.push_str(&format!("  = help: {}\n", help));
+            }
+            return output;
+        };
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let text = source.lines().nth(span.line - 1).unwrap_or("");
+        // 标记不超过该行的末尾，空的span至少标记一个字符
+        let remaining = text.chars().count().saturating_sub(span.column - 1);
+        let width = (span.end - span.start).min(remaining).max(1);
+        output.push_str(&format!("{}--> {}:{}\n", gutter, file, span));
+        output.push_str(&format!("{} |\n", gutter));
+        output.push_str(&format!("{} | {}\n", number, text));
+        output.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(span.column - 1),
+            "^".repeat(width)
+        ));
+        if let Some(help) = &self.help {
+            output.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+        output
+    }
+}
+
+// 形如 path:line:column: message，没有文件或位置时省略对应的部分
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file.display())?;
+            write!(f, "{}", if self.span.is_some() { ":" } else { ": " })?;
+        }
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        Diagnostic::error(Some(err.found.span), err.message.clone())
+    }
+}
+
+impl From<&SemanticError> for Diagnostic {
+    fn from(err: &SemanticError) -> Self {
+        Diagnostic::error(Some(err.span), err.message.clone())
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic::warning(Some(warning.span), warning.message.clone())
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(err: &RuntimeError) -> Self {
+        Diagnostic::error(Some(err.span), err.message.clone())
+    }
+}
+
+// 词法分析、语法分析、语义检查与代码生成各阶段收集的诊断信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiagnosticBag {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: impl Into<Diagnostic>) {
+        self.diagnostics.push(diagnostic.into());
+    }
+
+    pub fn extend<T: Into<Diagnostic>>(&mut self, diagnostics: impl IntoIterator<Item = T>) {
+        self.diagnostics.extend(diagnostics.into_iter().map(Into::into));
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    // 按源程序中的位置排序输出所有诊断信息，没有位置的排在最前
+    pub fn render(&self, source: &str, file: &str) -> String {
+        let mut diagnostics: Vec<_> = self.diagnostics.iter().collect();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.map(|span| span.start));
+        let rendered: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(source, file))
+            .collect();
+        rendered.join("\n")
+    }
+}
+
+impl<'a> IntoIterator for &'a DiagnosticBag {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostics::{Diagnostic, DiagnosticBag, Severity};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_render() {
+        let source = "read x;\nx := x + ;\nwrite x";
+        let errors = Parser::new(source).parse_program().unwrap_err();
+        let mut bag = DiagnosticBag::new();
+        bag.extend(&errors);
+        bag.push(Diagnostic::warning(None, "source is not valid UTF-8").with_help("save the file as UTF-8"));
+        assert!(bag.has_errors());
+        assert_eq!(
+            bag.render(source, "demo.tny"),
+            "warning: source is not valid UTF-8
+ --> demo.tny
+  = help: save the file as UTF-8
+
+error: token type: SemiColon is not prefix expression
+ --> demo.tny:2:10
+  |
+2 | x := x + ;
+  |          ^
+"
+        );
+    }
+
+    #[test]
+    fn test_span_width() {
+        let diagnostic = Diagnostic::new(
+            Severity::Note,
+            Some(crate::token::Span {
+                line: 1,
+                column: 4,
+                start: 3,
+                end: 9,
+            }),
+            "here",
+        );
+        assert_eq!(
+            diagnostic.render("x :=  yy", "a.tny").lines().last(),
+            Some("  |    ^^^^^")
+        );
+        assert_eq!(diagnostic.to_string(), "1:4: here");
+    }
+}
@@ -0,0 +1,292 @@
+use crate::code::RegisterCode;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+// 比较两份生成的TM程序
+// 比较前会忽略指令地址、注释行、调试与重定位指示以及临时变量槽位的具体编号，只报告真正的语义差异
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+#[derive(Debug, Default)]
+pub struct ProgramDiff {
+    pub lines: Vec<DiffLine>,
+}
+
+impl ProgramDiff {
+    // 两份程序在语义上是否相同
+    pub fn is_empty(&self) -> bool {
+        self.changes().next().is_none()
+    }
+
+    pub fn changes(&self) -> impl Iterator<Item = &DiffLine> {
+        self.lines.iter().filter(|line| !matches!(line, DiffLine::Same(_)))
+    }
+}
+
+impl Display for ProgramDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            match line {
+                DiffLine::Same(code) => writeln!(f, "  {}", code)?,
+                DiffLine::Removed(code) => writeln!(f, "- {}", code)?,
+                DiffLine::Added(code) => writeln!(f, "+ {}", code)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn diff(old: &str, new: &str) -> ProgramDiff {
+    diff_lines(&normalize(old), &normalize(new))
+}
+
+// 逐行比较，不做规范化
+// Myers的O((N+M)D)算法，以中间蛇分治，只需线性的空间，D为两份程序之间增删的行数
+pub fn diff_lines(old: &[String], new: &[String]) -> ProgramDiff {
+    let mut lines = vec![];
+    diff_range(old, new, &mut lines);
+    ProgramDiff { lines }
+}
+
+fn diff_range(old: &[String], new: &[String], lines: &mut Vec<DiffLine>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    lines.extend(old[..prefix].iter().cloned().map(DiffLine::Same));
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    if a.is_empty() || b.is_empty() {
+        lines.extend(a.iter().cloned().map(DiffLine::Removed));
+        lines.extend(b.iter().cloned().map(DiffLine::Added));
+    } else {
+        let (x, y, u, v) = middle_snake(a, b);
+        diff_range(&a[..x], &b[..y], lines);
+        lines.extend(a[x..u].iter().cloned().map(DiffLine::Same));
+        diff_range(&a[u..], &b[v..], lines);
+    }
+    lines.extend(old[old.len() - suffix..].iter().cloned().map(DiffLine::Same));
+}
+
+// 最短编辑路径中间的一段对角线(x, y)..(u, v)，a与b均不为空且首尾不同
+// 同时从两端搜索，forward[k]与backward[k]分别为从起点与从终点出发、走了d步后在对角线k上到达的最远的x
+fn middle_snake(a: &[String], b: &[String]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let mut forward = vec![0; 2 * offset as usize + 1];
+    let mut backward = vec![0; 2 * offset as usize + 1];
+    let index = |k: isize| (k + offset) as usize;
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[index(k)] = x;
+            // 从终点出发的对角线delta - k上已经走了d - 1步
+            let c = delta - k;
+            if odd && -d < c && c < d && x + backward[index(c)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, (x - k) as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let x0 = x;
+            while x < n && x - k < m && a[(n - x - 1) as usize] == b[(m - x + k - 1) as usize] {
+                x += 1;
+            }
+            backward[index(k)] = x;
+            let c = delta - k;
+            if !odd && -d <= c && c <= d && x + forward[index(c)] >= n {
+                return (
+                    (n - x) as usize,
+                    (m - x + k) as usize,
+                    (n - x0) as usize,
+                    (m - x0 + k) as usize,
+                );
+            }
+        }
+    }
+    unreachable!("two sequences always have an edit path of at most n + m steps")
+}
+
+// 将一份TM程序规范化为逐条指令的文本
+// 1. 去除空行与以'*'开头的注释行，以及.SPAN、.RELOC等调试与重定位指示，只保留描述数据存储器初值的.DATA
+// 2. 去除行首的指令地址 "  3:"
+// 3. 合并空白，助记符统一为大写
+// 4. 以MP为基址的临时变量槽位按首次出现的顺序重新编号为 t0, t1, ...
+pub fn normalize(listing: &str) -> Vec<String> {
+    let mp: usize = RegisterCode::MP.into();
+    let mp_base = format!("({})", mp);
+    let mut slots: HashMap<String, usize> = HashMap::new();
+    let mut output = vec![];
+    for line in listing.lines() {
+        let mut line = line.trim();
+        if line.is_empty() || line.starts_with('*') {
+            continue;
+        }
+        if line.starts_with('.') && !line.to_uppercase().starts_with(".DATA") {
+            continue;
+        }
+        if let Some((addr, rest)) = line.split_once(':') {
+            if !addr.is_empty() && addr.trim().chars().all(|ch| ch.is_ascii_digit()) {
+                line = rest.trim();
+            }
+        }
+        let (op, operands) = match line.split_once(char::is_whitespace) {
+            Some((op, operands)) => (op, operands),
+            None => (line, ""),
+        };
+        let mut operands: String = operands.chars().filter(|ch| !ch.is_whitespace()).collect();
+        if let Some(offset) = operands
+            .strip_suffix(&mp_base)
+            .and_then(|rest| rest.split(',').nth(1))
+            .map(str::to_string)
+        {
+            let next = slots.len();
+            let slot = *slots.entry(offset.clone()).or_insert(next);
+            let prefix_len = operands.len() - mp_base.len() - offset.len();
+            operands = format!("{}t{}{}", &operands[..prefix_len], slot, mp_base);
+        }
+        output.push(format!("{} {}", op.to_uppercase(), operands));
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::diff::{diff, diff_lines, normalize, DiffLine};
+    use crate::driver::{compile_to_tm, Options};
+    use crate::parser::Parser;
+
+    fn compile(input: &str) -> String {
+        let mut parser = Parser::new(input);
+        let mut compiler = Compiler::default();
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        compiler.to_intermedia_code()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let listing = "
+* a comment
+  0:  LDC  0,5(0)
+  1:  st  0,7(6)
+  2:  LD  1,7(6)
+  3:  ST  0,3(6)";
+        assert_eq!(
+            normalize(listing),
+            vec!["LDC 0,5(0)", "ST 0,t0(6)", "LD 1,t0(6)", "ST 0,t1(6)"]
+        );
+    }
+
+    #[test]
+    fn test_same_program() {
+        let old = compile("x := 5 + 3; write x;");
+        let new = format!("* recompiled\n{}", old.replace("  ", " "));
+        assert!(diff(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn test_changed_constant() {
+        let old = compile("x := 5; write x;");
+        let new = compile("x := 6; write x;");
+        let result = diff(&old, &new);
+        let changes: Vec<&DiffLine> = result.changes().collect();
+        assert_eq!(
+            changes,
+            vec![
+                &DiffLine::Removed("LDC 0,5(0)".to_string()),
+                &DiffLine::Added("LDC 0,6(0)".to_string())
+            ]
+        );
+        assert!(result.to_string().contains("- LDC 0,5(0)\n+ LDC 0,6(0)\n"));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let compile = |input: &str, options: &Options| compile_to_tm(input, options).unwrap().listing;
+        // 只增加注释与空行时，.SPAN中的源码位置会移动，程序本身不变
+        let options = Options {
+            debug_info: true,
+            ..Options::default()
+        };
+        let old = compile("x := 5;\nwrite x", &options);
+        let new = compile("{ the answer }\nx := 5;\n\nwrite x", &options);
+        assert!(old.contains(".SPAN"));
+        assert_ne!(old, new);
+        assert!(diff(&old, &new).is_empty());
+
+        // .DATA中的初值仍参与比较
+        let options = Options {
+            debug_info: true,
+            opt_level: 2,
+            ..Options::default()
+        };
+        let old = compile("x := 5;\nwrite x", &options);
+        let new = compile("x := 6;\nwrite x", &options);
+        assert!(old.contains(".DATA"));
+        assert!(!diff(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn test_minimal_diff() {
+        let mut seed: u64 = 12345;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound.max(1)
+        };
+        for _ in 0..300 {
+            let old: Vec<String> = (0..next(12)).map(|_| next(4).to_string()).collect();
+            let new: Vec<String> = (0..next(12)).map(|_| next(4).to_string()).collect();
+            let result = diff_lines(&old, &new);
+            // 去掉新增的行得到old，去掉删除的行得到new
+            let side = |keep_added: bool| -> Vec<String> {
+                result
+                    .lines
+                    .iter()
+                    .filter_map(|line| match line {
+                        DiffLine::Same(code) => Some(code.clone()),
+                        DiffLine::Added(code) if keep_added => Some(code.clone()),
+                        DiffLine::Removed(code) if !keep_added => Some(code.clone()),
+                        _ => None,
+                    })
+                    .collect()
+            };
+            assert_eq!(side(false), old);
+            assert_eq!(side(true), new);
+            // 相同的行数等于最长公共子序列的长度
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
+                }
+            }
+            let same = result.lines.len() - result.changes().count();
+            assert_eq!(same, lcs[0][0], "{:?} {:?}", old, new);
+        }
+    }
+}
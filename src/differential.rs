@@ -0,0 +1,167 @@
+use crate::driver::{compile_to_tm, Error, Options};
+use crate::interner::Name;
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::vm::{TinyMachine, VmError};
+
+// 解释执行时最多执行的语句数与虚拟机最多执行的指令数，超过时结果不作比较
+pub const STEP_LIMIT: usize = 10_000;
+pub const INSTRUCTION_LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Termination {
+    Finished,
+    // 运行时错误，如除以0或输入结束
+    Failed,
+    // 超过了执行的上限或函数调用的深度，程序可能不会结束
+    LimitExceeded,
+}
+
+// 一种执行方式的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: String,
+    pub termination: Termination,
+}
+
+impl Outcome {
+    // 两次执行的行为是否一致：都超过上限之前的输出相同，并以同样的方式结束
+    // 有一方超过上限时，只要求已有的输出一方是另一方的前缀
+    pub fn agrees_with(&self, other: &Outcome) -> bool {
+        if self.termination == Termination::LimitExceeded || other.termination == Termination::LimitExceeded {
+            return self.output.starts_with(&other.output) || other.output.starts_with(&self.output);
+        }
+        self == other
+    }
+}
+
+// 以同样的输入分别解释执行与编译后在虚拟机中运行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Behavior {
+    pub interpreted: Outcome,
+    pub compiled: Outcome,
+}
+
+impl Behavior {
+    pub fn is_consistent(&self) -> bool {
+        self.interpreted.agrees_with(&self.compiled)
+    }
+}
+
+// 以默认选项编译并运行source，input为标准输入；无法编译时返回编译错误
+pub fn run_both(source: &str, input: &str) -> Result<Behavior, Error> {
+    run_both_with(source, input, &Options::default())
+}
+
+pub fn run_both_with(source: &str, input: &str, options: &Options) -> Result<Behavior, Error> {
+    let artifacts = compile_to_tm(source, options)?;
+    let program = Parser::new(source).parse_program().map_err(Error::Parse)?;
+
+    let mut interpreter = Interpreter::new(input.as_bytes(), vec![]).with_step_limit(STEP_LIMIT);
+    for (name, value) in &options.presets {
+        interpreter.variables.insert(Name::new(name), *value);
+    }
+    let termination = match interpreter.run(&program) {
+        Ok(()) => Termination::Finished,
+        Err(_) if interpreter.steps() > STEP_LIMIT => Termination::LimitExceeded,
+        Err(err) if err.message.starts_with("call depth of") => Termination::LimitExceeded,
+        Err(_) => Termination::Failed,
+    };
+    let interpreted = Outcome {
+        output: String::from_utf8_lossy(&interpreter.output).into_owned(),
+        termination,
+    };
+
+    let mut vm = TinyMachine::new(input.as_bytes(), vec![])
+        .with_memory_size(artifacts.memory_size)
+        .with_instruction_limit(INSTRUCTION_LIMIT);
+    vm.load(&artifacts.listing)?;
+    let termination = match vm.run() {
+        Ok(()) => Termination::Finished,
+        Err(VmError::ExecutionLimitExceeded { .. }) => Termination::LimitExceeded,
+        Err(_) => Termination::Failed,
+    };
+    let compiled = Outcome {
+        output: String::from_utf8_lossy(&vm.output).into_owned(),
+        termination,
+    };
+    Ok(Behavior { interpreted, compiled })
+}
+
+// 对每个输入分别解释执行与编译执行source，两者的输出或结束方式不同时panic
+// 用于在测试中自动发现代码生成的缺陷
+pub fn assert_same_behavior(source: &str, inputs: &[&str]) {
+    for input in inputs {
+        let behavior = run_both(source, input).unwrap_or_else(|err| panic!("{:?} does not compile: {}", source, err));
+        assert!(
+            behavior.is_consistent(),
+            "interpreter and compiled code disagree on {:?} with input {:?}\ninterpreted: {:?}\ncompiled: {:?}",
+            source,
+            input,
+            behavior.interpreted,
+            behavior.compiled
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::differential::{assert_same_behavior, run_both, run_both_with, Outcome, Termination};
+    use crate::driver::Options;
+
+    #[test]
+    fn test_same_behavior() {
+        let factorial =
+            "read x; if 0 < x then fact := 1; repeat fact := fact * x; x := x - 1 until x = 0; write fact end";
+        assert_same_behavior(factorial, &["0", "1", "5", "12", "-3"]);
+
+        let input = "
+fn gcd(a, b)
+    while b <> 0 do t := b; b := a - a / b * b; a := t end;
+    return a
+end;
+var seen[4];
+read x; read y;
+seen[x / 10] := gcd(x, y);
+write seen[x / 10];
+if not x < y or y = 0 then write \"ordered\" else write x / y end";
+        assert_same_behavior(input, &["12 18", "35 0", "0 7", "7 0", "1"]);
+        assert_same_behavior("x := 2147483647; x := x + 1; write x; write -x * 3", &[""]);
+
+        // 比较两数之差，溢出时与常量折叠的结果也一致
+        let input = "read x; if x > -x then write 1 else write 0 end; if 2147483647 > -2147483647 then write 2 end";
+        assert_same_behavior(input, &["2147483647", "5"]);
+        let options = Options {
+            constant_folding: true,
+            ..Options::default()
+        };
+        let behavior = run_both_with(input, "2147483647", &options).unwrap();
+        assert_eq!(behavior.compiled.output, "0\n");
+        assert!(behavior.is_consistent());
+    }
+
+    #[test]
+    fn test_termination() {
+        let behavior = run_both("read x; write 10 / x; write 1", "0").unwrap();
+        assert_eq!(behavior.interpreted, behavior.compiled);
+        assert_eq!(behavior.compiled.termination, Termination::Failed);
+
+        let behavior = run_both("write 1; repeat x := 1 until false", "").unwrap();
+        assert_eq!(behavior.interpreted.termination, Termination::LimitExceeded);
+        assert_eq!(behavior.compiled.termination, Termination::LimitExceeded);
+        assert!(behavior.is_consistent());
+
+        let finished = |output: &str| Outcome {
+            output: output.to_string(),
+            termination: Termination::Finished,
+        };
+        let limited = Outcome {
+            output: "1\n".to_string(),
+            termination: Termination::LimitExceeded,
+        };
+        assert!(limited.agrees_with(&finished("1\n2\n")));
+        assert!(!limited.agrees_with(&finished("2\n")));
+        assert!(!finished("1\n").agrees_with(&finished("1\n2\n")));
+        assert!(run_both("write y", "").is_err());
+    }
+}
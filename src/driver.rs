@@ -0,0 +1,570 @@
+use crate::ast::Program;
+use crate::code::{DataImage, DebugInfo, Instruction, ListingStyle};
+use crate::compiler::{Backend, Compiler, CompilerOptions, OptLevel};
+use crate::config::WarningLevel;
+use crate::diagnostics::Diagnostic;
+use crate::environment::{PoolStats, SymbolTable};
+pub use crate::error::{CodegenError, CompileError};
+use crate::lexer::LexError;
+use crate::lint;
+use crate::parser::{ParseError, Parser};
+use crate::semantic::{self, SemanticError};
+use crate::source_map::{SourceFile, SourceMap};
+use crate::token::Span;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// compile_to_tm 的编译选项
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub listing_style: ListingStyle,
+    pub relocatable: bool,
+    // 见 Compiler::with_opt_level
+    pub opt_level: u8,
+    // 见 Compiler::with_constant_folding
+    pub constant_folding: bool,
+    // 见 Compiler::with_trace
+    pub trace: bool,
+    // 见 Compiler::with_debug_info
+    pub debug_info: bool,
+    // 数据存储器的大小，None时为 vm::MEMORY_SIZE，见 Compiler::with_memory_size
+    pub memory_size: Option<usize>,
+    // 语句与表达式嵌套的最大深度，None时为 parser::MAX_DEPTH，见 Parser::with_max_depth
+    pub max_depth: Option<usize>,
+    // 严格模式，变量必须先以var声明，见 semantic::check_with_options
+    pub strict: bool,
+    // 预置初始值的全局变量，见 Compiler::preset
+    pub presets: Vec<(String, i32)>,
+    // 警告的级别，未设置的警告为 WarningLevel::Warn
+    pub warnings: BTreeMap<String, WarningLevel>,
+}
+
+impl Options {
+    // 传给 Compiler::new 的选项
+    pub fn compiler_options(&self) -> CompilerOptions {
+        CompilerOptions {
+            opt_level: OptLevel::from_level(self.opt_level),
+            constant_folding: self.constant_folding,
+            trace: self.trace,
+            debug_info: self.debug_info,
+            backend: if self.relocatable {
+                Backend::RelocatableTm
+            } else {
+                Backend::Tm
+            },
+        }
+    }
+}
+
+// 一次编译的全部产物
+#[derive(Debug)]
+pub struct Artifacts {
+    pub program_name: Option<String>,
+    // 逐条的指令
+    pub instructions: Vec<Instruction>,
+    // 完整的清单文本，包括数据段与重定位表
+    pub listing: String,
+    pub data: DataImage,
+    pub symbol_table: SymbolTable,
+    // 每条指令在源程序中的位置，见 Compiler::debug_info
+    pub debug_info: DebugInfo,
+    // 编译时假定的数据存储器大小，运行时应使用同样大小的存储器，见 TinyMachine::with_memory_size
+    pub memory_size: usize,
+    // 不影响编译结果的诊断信息，如警告，附带在源程序中的位置
+    pub diagnostics: Vec<Diagnostic>,
+    pub stats: Stats,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub instructions: usize,
+    pub data_words: usize,
+    // 临时区域最多使用的单元数，见 Compiler::stack_depth
+    pub stack_words: usize,
+    pub constant_pool: PoolStats,
+    // 公共子表达式消除省去的重复计算的次数
+    pub eliminated_subexpressions: usize,
+    // 外提到循环之前的不变量的个数
+    pub hoisted_invariants: usize,
+    // 常量传播替换的变量的次数
+    pub propagated_constants: usize,
+    // 以数据存储器的初始内容代替运行时赋值的语句的个数
+    pub initialized_globals: usize,
+}
+
+// 读取源文件或编译时的错误
+#[derive(Debug)]
+pub enum FileError {
+    Io { path: PathBuf, error: io::Error },
+    // 按所在的文件分组的编译错误，span相对于该文件
+    // 代码生成的错误与源程序中的位置无关，归入第一个文件
+    Compile(Vec<(SourceFile, Error)>),
+    // 没有给出任何源文件
+    NoSources,
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            FileError::NoSources => write!(f, "no source files to compile"),
+            FileError::Compile(errors) => {
+                let messages: Vec<_> = errors
+                    .iter()
+                    .flat_map(|(file, err)| {
+                        // 词法、语法与语义错误以位置开头，形如 path:line:column: message
+                        let separator = if matches!(err, Error::Codegen(_) | Error::Runtime(_)) {
+                            ": "
+                        } else {
+                            ":"
+                        };
+                        let path = file.path.display().to_string();
+                        err.to_string()
+                            .lines()
+                            .map(|line| format!("{}{}{}", path, separator, line))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FileError::Io { error, .. } => Some(error),
+            FileError::Compile(_) | FileError::NoSources => None,
+        }
+    }
+}
+
+// compile 的返回类型，与 compile_to_tm 相同
+pub type CompilationOutput = Artifacts;
+pub type Error = CompileError;
+
+// 以默认选项编译TINY源程序，得到指令、清单、符号表与诊断信息
+pub fn compile(source: &str) -> Result<CompilationOutput, CompileError> {
+    compile_to_tm(source, &Options::default())
+}
+
+// 将TINY源程序编译为TM代码，Parser与Compiler的组合
+pub fn compile_to_tm(source: &str, options: &Options) -> Result<Artifacts, Error> {
+    compile_with(source, options)
+}
+
+// 编译已经解析的程序，如增量解析得到的 incremental::Document 中的程序
+// options.max_depth 不起作用，嵌套深度由解析时限制，见 Document::with_max_depth
+pub fn compile_program(program: &Program, options: &Options) -> Result<Artifacts, Error> {
+    compile_parsed(program, vec![], options)
+}
+
+// 读取并以默认选项编译一个源文件，诊断信息与错误都带有文件名
+pub fn compile_file(path: impl AsRef<Path>) -> Result<Artifacts, FileError> {
+    compile_files([path], &Options::default())
+}
+
+// 将多个源文件按顺序拼接为一个程序编译，如在一个文件中定义函数，在另一个文件中调用
+// 只有一个文件时才能使用program头
+pub fn compile_files<P: AsRef<Path>>(
+    paths: impl IntoIterator<Item = P>,
+    options: &Options,
+) -> Result<Artifacts, FileError> {
+    let sources = SourceMap::read(paths).map_err(|(path, error)| FileError::Io { path, error })?;
+    compile_sources(&sources, options)
+}
+
+// 将每个源文件分别编译为一个程序，结果与paths的顺序一致，诊断信息与错误都带有文件名
+// 各个文件的编译互不影响；启用rayon特性时并行编译，否则依次编译
+pub fn compile_many<P: AsRef<Path> + Sync>(paths: &[P], options: &Options) -> Vec<Result<Artifacts, FileError>> {
+    let compile = |path: &P| compile_files([path], options);
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        paths.par_iter().map(compile).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    paths.iter().map(compile).collect()
+}
+
+// 编译拼接后的源程序，sources为空时返回 FileError::NoSources
+// 产物中的debug_info仍相对于拼接后的源程序，可以通过 SourceMap::locate 映射回各个文件
+pub fn compile_sources(sources: &SourceMap, options: &Options) -> Result<Artifacts, FileError> {
+    if sources.files().is_empty() {
+        return Err(FileError::NoSources);
+    }
+    let locate = |span: Span| sources.locate(span).unwrap_or((0, span));
+    let mut artifacts = compile_with(sources.text(), options).map_err(|err| {
+        let file = |index: usize| sources.files()[index].clone();
+        FileError::Compile(match err {
+            Error::Lex(errors) => {
+                let mut files: BTreeMap<usize, Vec<LexError>> = BTreeMap::new();
+                for mut err in errors {
+                    let (index, span) = locate(err.span);
+                    err.span = span;
+                    files.entry(index).or_default().push(err);
+                }
+                files
+                    .into_iter()
+                    .map(|(index, errors)| (file(index), Error::Lex(errors)))
+                    .collect()
+            }
+            Error::Parse(errors) => {
+                let mut files: BTreeMap<usize, Vec<ParseError>> = BTreeMap::new();
+                for mut err in errors {
+                    let (index, span) = locate(err.found.span);
+                    err.found.span = span;
+                    files.entry(index).or_default().push(err);
+                }
+                files
+                    .into_iter()
+                    .map(|(index, errors)| (file(index), Error::Parse(errors)))
+                    .collect()
+            }
+            Error::Semantic(errors) => {
+                let mut files: BTreeMap<usize, Vec<SemanticError>> = BTreeMap::new();
+                for mut err in errors {
+                    let (index, span) = locate(err.span);
+                    err.span = span;
+                    if let Some((_, message)) = locate_message(sources, &err.message) {
+                        err.message = message;
+                    }
+                    files.entry(index).or_default().push(err);
+                }
+                files
+                    .into_iter()
+                    .map(|(index, errors)| (file(index), Error::Semantic(errors)))
+                    .collect()
+            }
+            // 代码生成的错误信息以 "at line N" 结尾，行号与位置一起映射
+            Error::Codegen(mut err) => match err.span_mut() {
+                Some(span) => {
+                    let (index, located) = locate(*span);
+                    *span = located;
+                    vec![(file(index), Error::Codegen(err))]
+                }
+                None => vec![(file(0), Error::Codegen(err))],
+            },
+            Error::Runtime(err) => vec![(file(0), Error::Runtime(err))],
+        })
+    })?;
+    let mut diagnostics: Vec<_> = sources
+        .files()
+        .iter()
+        .flat_map(|file| {
+            file.warnings
+                .iter()
+                .map(move |warning| Diagnostic::warning(None, warning.clone()).with_file(&file.path))
+        })
+        .collect();
+    // 与位置无关的诊断信息归入第一个文件
+    for mut diagnostic in artifacts.diagnostics.drain(..) {
+        let index = match diagnostic.span {
+            Some(span) => {
+                let (index, located) = locate(span);
+                diagnostic.span = Some(located);
+                index
+            }
+            None => 0,
+        };
+        diagnostics.push(diagnostic.with_file(&sources.files()[index].path));
+    }
+    artifacts.diagnostics = diagnostics;
+    Ok(artifacts)
+}
+
+// 部分错误信息以 "at line N" 结尾，将其中的行号映射为所在文件中的行号
+fn locate_message(sources: &SourceMap, message: &str) -> Option<(usize, String)> {
+    let (prefix, line) = message.rsplit_once("at line ")?;
+    let (index, line) = sources.locate_line(line.parse().ok()?)?;
+    Some((index, format!("{}at line {}", prefix, line)))
+}
+
+// 警告按Options::warnings设置的级别处理，保留的警告加入诊断信息
+fn compile_with(source: &str, options: &Options) -> Result<Artifacts, Error> {
+    let mut parser = Parser::new(source);
+    if let Some(max_depth) = options.max_depth {
+        parser = parser.with_max_depth(max_depth);
+    }
+    let program = parser.parse_program().map_err(Error::Parse)?;
+    let warnings = parser
+        .warnings()
+        .iter()
+        .map(|warning| Diagnostic::warning(None, warning.clone()));
+    compile_parsed(&program, warnings.collect(), options)
+}
+
+// 语义检查并生成代码，diagnostics为之前阶段的警告
+fn compile_parsed(program: &Program, mut diagnostics: Vec<Diagnostic>, options: &Options) -> Result<Artifacts, Error> {
+    let presets = || options.presets.iter().map(|(name, _)| name.as_str());
+    let (mut errors, mut warnings) = semantic::check_with_options(program, presets(), options.strict);
+    if errors.is_empty() {
+        warnings.extend(lint::lint(program, presets()));
+        warnings.sort_by_key(|warning| warning.span.start);
+    }
+    for warning in warnings {
+        match options.warnings.get(warning.lint).unwrap_or(&WarningLevel::Warn) {
+            WarningLevel::Allow => {}
+            WarningLevel::Warn => diagnostics.push(Diagnostic::from(&warning)),
+            WarningLevel::Deny => errors.push(SemanticError {
+                span: warning.span,
+                message: warning.message,
+            }),
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|err| err.span.start);
+        return Err(Error::Semantic(errors));
+    }
+    let mut compiler = Compiler::new(options.compiler_options()).with_listing_style(options.listing_style.clone());
+    if let Some(memory_size) = options.memory_size {
+        compiler = compiler.with_memory_size(memory_size);
+    }
+    for (name, value) in &options.presets {
+        compiler.preset(name, *value);
+    }
+    compiler.compile(program)?;
+
+    let listing = compiler.to_intermedia_code();
+    let debug_info = compiler.debug_info().clone();
+    let stats = Stats {
+        instructions: compiler.intermedia.len(),
+        data_words: compiler.data.len(),
+        stack_words: compiler.stack_depth(),
+        constant_pool: compiler.constant_pool.stats(),
+        eliminated_subexpressions: compiler.eliminated_subexpressions,
+        hoisted_invariants: compiler.hoisted_invariants,
+        propagated_constants: compiler.propagated_constants,
+        initialized_globals: compiler.initialized_globals,
+    };
+    Ok(Artifacts {
+        program_name: compiler.program_name,
+        instructions: compiler.intermedia,
+        listing,
+        data: compiler.data,
+        symbol_table: compiler.symbol_table,
+        debug_info,
+        memory_size: compiler.memory_size,
+        diagnostics,
+        stats,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::WarningLevel;
+    use crate::diagnostics::{Diagnostic, Severity};
+    use crate::driver::{compile, compile_file, compile_files, compile_many, compile_to_tm, Error, FileError, Options};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter().map(Diagnostic::to_string).collect()
+    }
+
+    #[test]
+    fn test_compile_to_tm() {
+        let options = Options {
+            presets: vec![("limit".to_string(), 10)],
+            ..Options::default()
+        };
+        let artifacts = compile_to_tm("program demo; read x; write limit; end.", &options).unwrap();
+        assert_eq!(artifacts.program_name.as_deref(), Some("demo"));
+        assert_eq!(artifacts.stats.instructions, 7);
+        assert_eq!(artifacts.stats.data_words, 1);
+        assert_eq!(artifacts.stats.stack_words, 0);
+        assert_eq!(artifacts.symbol_table.address("x"), Some(2));
+        assert!(artifacts.listing.contains(".DATA  1,10"));
+
+        let options = Options {
+            opt_level: 1,
+            ..Options::default()
+        };
+        let artifacts = compile_to_tm("read x; write x * x - x * x", &options).unwrap();
+        assert_eq!(artifacts.stats.eliminated_subexpressions, 1);
+    }
+
+    #[test]
+    fn test_compile() {
+        let output = compile("read x; write x").unwrap();
+        assert_eq!(output.instructions.len(), 7);
+        assert_eq!(output.symbol_table.address("x"), Some(1));
+        assert!(output.listing.contains("OUT  0,0,0"));
+        assert!(output.diagnostics.is_empty());
+        assert!(matches!(compile("write x"), Err(Error::Semantic(_))));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let source = "read x;\nwrite x / 0;";
+        let artifacts = compile(source).unwrap();
+        let warning = &artifacts.diagnostics[0];
+        assert_eq!(artifacts.diagnostics.len(), 1);
+        assert_eq!(
+            (warning.severity, warning.message.as_str()),
+            (Severity::Warning, "division by zero")
+        );
+        assert_eq!(warning.span.map(|span| (span.line, span.column)), Some((2, 11)));
+        assert_eq!(warning.to_string(), "2:11: division by zero");
+
+        let mut options = Options::default();
+        options
+            .warnings
+            .insert("division-by-zero".to_string(), WarningLevel::Allow);
+        assert!(compile_to_tm(source, &options).unwrap().diagnostics.is_empty());
+        options
+            .warnings
+            .insert("division-by-zero".to_string(), WarningLevel::Deny);
+        let err = compile_to_tm(source, &options).unwrap_err();
+        assert_eq!(err.to_string(), "2:11: division by zero");
+    }
+
+    #[test]
+    fn test_lint() {
+        let source = "read x;\nif x > 0 then y := x end;\nwrite y / 0;\nz := 1";
+        assert_eq!(
+            messages(&compile(source).unwrap().diagnostics),
+            [
+                "3:7: variable `y` may be used before assignment",
+                "3:11: division by zero",
+                "4:1: variable `z` is assigned but never used"
+            ]
+        );
+        let mut options = Options::default();
+        options
+            .warnings
+            .insert("unused-variable".to_string(), WarningLevel::Deny);
+        options
+            .warnings
+            .insert("maybe-uninitialized".to_string(), WarningLevel::Allow);
+        let err = compile_to_tm(source, &options).unwrap_err();
+        assert_eq!(err.to_string(), "4:1: variable `z` is assigned but never used");
+    }
+
+    #[test]
+    fn test_compile_files() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_files_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let lib = root.join("lib.tny");
+        let main = root.join("main.tny");
+        fs::write(&lib, "fn double(n)\n    return n * 2\nend").unwrap();
+        fs::write(&main, "\u{feff}read x;\nwrite double(x) / 0").unwrap();
+
+        let artifacts = compile_files([&lib, &main], &Options::default()).unwrap();
+        assert_eq!(
+            messages(&artifacts.diagnostics),
+            [format!("{}:2:19: division by zero", main.display())]
+        );
+        assert_eq!(artifacts.symbol_table.address("x"), Some(1));
+
+        fs::write(&main, "read x;\nwrite y;\nwrite double(x)").unwrap();
+        let err = compile_files([&lib, &main], &Options::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:2:7: variable `y` used before assignment at line 2", main.display())
+        );
+        match err {
+            FileError::Compile(errors) => assert_eq!(errors[0].0.path, main),
+            _ => unreachable!(),
+        }
+
+        // 与位置无关的错误归入第一个文件
+        fs::write(&main, "read x;\nwrite double(x)").unwrap();
+        let options = Options {
+            memory_size: Some(1),
+            ..Options::default()
+        };
+        let err = compile_files([&lib, &main], &options).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: program needs", lib.display())));
+
+        let err = compile_file(root.join("missing.tny")).unwrap_err();
+        assert!(matches!(err, FileError::Io { ref path, .. } if path.ends_with("missing.tny")));
+        let err = compile_files(Vec::<PathBuf>::new(), &Options::default()).unwrap_err();
+        assert!(matches!(err, FileError::NoSources));
+        assert!(compile_many(&Vec::<PathBuf>::new(), &Options::default()).is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_compile_many() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_many_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let paths: Vec<_> = (0..8).map(|i| root.join(format!("{}.tny", i))).collect();
+        for (i, path) in paths.iter().enumerate() {
+            let source = match i {
+                3 => "write y".to_string(),
+                _ => format!("program p; read x; write x + {}; end.", i),
+            };
+            fs::write(path, source).unwrap();
+        }
+        let mut all = paths.clone();
+        all.push(root.join("missing.tny"));
+
+        let results = compile_many(&all, &Options::default());
+        assert_eq!(results.len(), 9);
+        for (i, result) in results.iter().enumerate() {
+            match (i, result) {
+                (3, Err(err)) => assert!(err.to_string().starts_with(&paths[3].display().to_string())),
+                (8, Err(err)) => assert!(matches!(err, FileError::Io { .. })),
+                (_, Ok(artifacts)) => {
+                    let expected = compile_file(&paths[i]).unwrap();
+                    assert_eq!(artifacts.listing, expected.listing);
+                }
+                (_, Err(err)) => panic!("{}: {}", i, err),
+            }
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = compile_to_tm("read ;", &Options::default()).unwrap_err();
+        assert_eq!(err.to_string(), "1:6: expected TokenType::Ident, found: SemiColon");
+        match err {
+            Error::Parse(errors) => assert_eq!(errors[0].position, 1),
+            _ => unreachable!(),
+        }
+    }
+
+    // 嵌套深度不超过 parser::MAX_DEPTH 的程序在测试线程的栈中也能完成编译
+    #[test]
+    fn test_max_depth() {
+        use crate::parser::MAX_DEPTH;
+
+        let ifs = MAX_DEPTH / 2;
+        let source = format!(
+            "read x;\n{}write {}x{}",
+            "if 0 < x then\n".repeat(ifs),
+            "- ".repeat(MAX_DEPTH - ifs - 2),
+            "\nend".repeat(ifs)
+        );
+        for opt_level in 0..=2 {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            assert!(compile_to_tm(&source, &options).is_ok());
+        }
+        let options = Options {
+            max_depth: Some(MAX_DEPTH - 1),
+            ..Options::default()
+        };
+        assert!(matches!(compile_to_tm(&source, &options), Err(Error::Parse(errors)) if errors.len() == 1));
+    }
+
+    #[test]
+    fn test_semantic_error() {
+        let err = compile_to_tm("x := 1;\nwrite y;", &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::Semantic(_)));
+        assert_eq!(err.to_string(), "2:7: variable `y` used before assignment at line 2");
+        assert_eq!(
+            err.diagnostics().render("x := 1;\nwrite y;", "a.tny"),
+            "error: variable `y` used before assignment at line 2\n --> a.tny:2:7\n  |\n2 | write y;\n  |       ^\n"
+        );
+    }
+}
@@ -1,39 +1,517 @@
-use std::collections::HashMap;
+use crate::code::RegisterCode;
+use crate::interner::Name;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::Span;
+use alloc::collections::BTreeMap;
+use core::fmt::{Display, Formatter};
 
-pub struct RegisterGroup {}
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RegisterState {
+    Free,
+    // 由acquire占用，在整个编译过程中有固定用途（如累加器）
+    Acquired,
+    // 由allocate分配的临时寄存器
+    Allocated,
+    // GP、MP、PC由机器约定使用，不能占用
+    Reserved,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    // 没有空闲的寄存器可以分配
+    Exhausted,
+    Reserved(RegisterCode),
+    InUse(RegisterCode),
+    NotAllocated(RegisterCode),
+}
+
+impl Display for RegisterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RegisterError::Exhausted => write!(f, "no free register"),
+            RegisterError::Reserved(register) => write!(f, "register {} is reserved", register),
+            RegisterError::InUse(register) => write!(f, "register {} is already in use", register),
+            RegisterError::NotAllocated(register) => write!(f, "register {} is not allocated", register),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RegisterError {}
+
+// 寄存器的使用状态：GP、MP、PC保留，其余寄存器可以占用或作为临时寄存器分配
+#[derive(Debug, Clone)]
+pub struct RegisterGroup {
+    // 按寄存器编号
+    states: [RegisterState; 8],
+    // 已分配的临时寄存器，按分配的顺序
+    used: Vec<RegisterCode>,
+}
+
+impl Default for RegisterGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl RegisterGroup {
     pub fn new() -> Self {
-        Self {}
+        let mut states = [RegisterState::Free; 8];
+        for register in [RegisterCode::GP, RegisterCode::MP, RegisterCode::PC] {
+            states[usize::from(register)] = RegisterState::Reserved;
+        }
+        Self { states, used: vec![] }
     }
+
+    // 占用指定的寄存器，不会被allocate分配，也不会被释放
+    pub fn acquire(&mut self, register: RegisterCode) -> Result<RegisterCode, RegisterError> {
+        match self.states[usize::from(register)] {
+            RegisterState::Free => {
+                self.states[usize::from(register)] = RegisterState::Acquired;
+                Ok(register)
+            }
+            RegisterState::Reserved => Err(RegisterError::Reserved(register)),
+            RegisterState::Acquired | RegisterState::Allocated => Err(RegisterError::InUse(register)),
+        }
+    }
+
+    // 分配编号最小的空闲寄存器
+    pub fn allocate(&mut self) -> Result<RegisterCode, RegisterError> {
+        let index = self
+            .states
+            .iter()
+            .position(|state| *state == RegisterState::Free)
+            .ok_or(RegisterError::Exhausted)?;
+        self.states[index] = RegisterState::Allocated;
+        let register = RegisterCode::from(index);
+        self.used.push(register);
+        Ok(register)
+    }
+
+    // 释放由allocate分配的寄存器
+    pub fn free(&mut self, register: RegisterCode) -> Result<(), RegisterError> {
+        match self.states[usize::from(register)] {
+            RegisterState::Allocated => {
+                self.states[usize::from(register)] = RegisterState::Free;
+                self.used.retain(|used| *used != register);
+                Ok(())
+            }
+            RegisterState::Reserved => Err(RegisterError::Reserved(register)),
+            RegisterState::Free | RegisterState::Acquired => Err(RegisterError::NotAllocated(register)),
+        }
+    }
+
+    // 已分配的临时寄存器，按分配的顺序
+    pub fn used(&self) -> &[RegisterCode] {
+        &self.used
+    }
+}
+
+// 函数的参数个数与第一条指令的地址
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FunctionSymbol {
+    pub arity: usize,
+    pub entry: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolType {
+    Integer,
+    // 有size个元素的数组
+    Array(usize),
 }
 
+// 符号表中的一个变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: Name,
+    // 相对GP的地址，数组为第一个元素的地址
+    pub address: i32,
+    pub ty: SymbolType,
+    // 第一次定义的位置，预置的变量没有
+    pub definition: Option<Span>,
+    // 变量在源程序中出现的行号，按记录的顺序，见 SymbolTable::add_line
+    pub lines: Vec<usize>,
+}
+
+impl Symbol {
+    // 变量在源程序中出现的次数，包括定义
+    pub fn uses(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+// 作用域嵌套的符号表，最外层为全局作用域
+// 内层作用域中的变量可以遮蔽外层的同名变量；作用域退出后其变量的地址不会被重新分配
+// 地址按定义的顺序递增分配，与BTreeMap的遍历顺序无关
+// 名字以驻留的 Name 保存，参数可以是 Name 或字符串
+#[derive(Debug, Clone)]
 pub struct SymbolTable {
-    table: HashMap<String, i32>,
+    // 变量名 => 在symbols中的下标
+    scopes: Vec<BTreeMap<Name, usize>>,
+    // 所有定义过的变量，按定义的顺序，包括已退出的作用域中的变量
+    symbols: Vec<Symbol>,
+    // 下一个可分配的内存地址，变量和匿名存储单元（如常量池）共用
+    next_addr: i32,
+    // 函数与变量的名字互不冲突
+    functions: BTreeMap<Name, FunctionSymbol>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
-        Self { table: HashMap::new() }
+        Self {
+            scopes: vec![BTreeMap::new()],
+            symbols: vec![],
+            // 地址0保存数据存储器的最大地址，由标准序言读取后清零，不分配给变量
+            next_addr: 1,
+            functions: BTreeMap::new(),
+        }
+    }
+
+    // 进入一个新的作用域
+    pub fn push_scope(&mut self) {
+        self.scopes.push(BTreeMap::new());
     }
 
-    // 返回名字为name的变量的内存地址
-    // 若不存在，返回-1
-    pub fn look_up(&self, name: &str) -> i32 {
-        if self.table.contains_key(name) {
-            *self.table.get(name).unwrap()
-        } else {
-            -1
+    // 退出当前作用域，全局作用域不能退出
+    pub fn pop_scope(&mut self) {
+        assert!(self.scopes.len() > 1, "cannot pop the global scope");
+        self.scopes.pop();
+    }
+
+    // 当前作用域的深度，全局作用域为0
+    pub fn depth(&self) -> usize {
+        self.scopes.len() - 1
+    }
+
+    // 名字为name的变量，从内层作用域向外查找
+    pub fn look_up(&self, name: impl Into<Name>) -> Option<&Symbol> {
+        let name = name.into();
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .map(|index| &self.symbols[*index])
+    }
+
+    // 名字为name的变量的内存地址
+    pub fn address(&self, name: impl Into<Name>) -> Option<i32> {
+        self.look_up(name).map(|symbol| symbol.address)
+    }
+
+    // 在当前作用域中定义变量，返回其地址；若当前作用域中已存在，返回None
+    pub fn insert(&mut self, name: impl Into<Name>, definition: Option<Span>) -> Option<i32> {
+        self.define(name.into(), SymbolType::Integer, definition)
+    }
+
+    // 在当前作用域中定义有size个元素的数组，元素占用连续的地址，返回第一个元素的地址
+    // 若当前作用域中已存在，返回None
+    pub fn insert_array(&mut self, name: impl Into<Name>, size: usize, definition: Option<Span>) -> Option<i32> {
+        let loc = self.define(name.into(), SymbolType::Array(size), definition)?;
+        self.next_addr += size.saturating_sub(1) as i32;
+        Some(loc)
+    }
+
+    fn define(&mut self, name: Name, ty: SymbolType, definition: Option<Span>) -> Option<i32> {
+        let depth = self.depth();
+        if self.scopes[depth].contains_key(&name) {
+            return None;
         }
+        let address = self.allocate();
+        self.scopes[depth].insert(name.clone(), self.symbols.len());
+        self.symbols.push(Symbol {
+            name,
+            address,
+            ty,
+            definition,
+            lines: vec![],
+        });
+        Some(address)
+    }
+
+    // 分配一个不对应任何变量名的存储单元，返回其地址
+    pub fn allocate(&mut self) -> i32 {
+        let loc = self.next_addr;
+        self.next_addr += 1;
+        loc
+    }
+
+    // 已分配的数据单元数，包括保留的地址0
+    pub fn data_size(&self) -> usize {
+        self.next_addr as usize
     }
 
-    pub fn insert(&mut self, name: &str) -> i32 {
-        if self.table.contains_key(name) {
-            -1
-        } else {
-            let size = self.table.len();
-            self.table.insert(name.to_string(), size as i32);
-            size as i32
+    // 定义函数，若已存在同名的函数，返回false
+    pub fn define_function(&mut self, name: impl Into<Name>, arity: usize, entry: usize) -> bool {
+        let name = name.into();
+        if self.functions.contains_key(&name) {
+            return false;
         }
+        self.functions.insert(name, FunctionSymbol { arity, entry });
+        true
+    }
+
+    pub fn function(&self, name: impl Into<Name>) -> Option<FunctionSymbol> {
+        self.functions.get(&name.into()).copied()
+    }
+
+    // 代码移动之后更新函数的入口地址
+    pub fn relocate_functions(&mut self, relocate: impl Fn(usize) -> usize) {
+        for function in self.functions.values_mut() {
+            function.entry = relocate(function.entry);
+        }
+    }
+
+    // 所有函数，按入口地址排序
+    pub fn functions(&self) -> impl Iterator<Item = (&str, FunctionSymbol)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, function)| (name.as_str(), *function))
+            .collect();
+        functions.sort_by_key(|(_, function)| function.entry);
+        functions.into_iter()
+    }
+
+    // 记录变量name在第line行出现，变量不存在时不做任何事
+    pub fn add_line(&mut self, name: impl Into<Name>, line: usize) {
+        let name = name.into();
+        let index = self.scopes.iter().rev().find_map(|scope| scope.get(&name));
+        if let Some(&index) = index {
+            self.symbols[index].lines.push(line);
+        }
+    }
+
+    // 变量出现的行号，按记录的顺序
+    pub fn lines(&self, name: impl Into<Name>) -> &[usize] {
+        self.look_up(name).map_or(&[], |symbol| symbol.lines.as_slice())
+    }
+
+    // 与参考实现一致的符号表清单：变量名、地址以及出现的行号
+    // 包括已退出的作用域中的变量
+    pub fn to_listing(&self) -> String {
+        let mut output =
+            String::from("Variable Name  Location   Line Numbers\n-------------  --------   ------------\n");
+        for symbol in self.symbols_in_order() {
+            let mut line = format!("{:<14} {:<8}  ", symbol.name, symbol.address);
+            for number in &symbol.lines {
+                line.push_str(&format!("{:4} ", number));
+            }
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+        output
+    }
+
+    // 所有定义过的变量，按定义的顺序，也就是地址递增的顺序
+    // 同名的变量在不同的作用域中定义时出现多次
+    pub fn symbols_in_order(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    // 深度为depth的作用域中定义的变量，按地址排序
+    pub fn symbols(&self, depth: usize) -> impl Iterator<Item = &Symbol> {
+        let mut indices: Vec<_> = self.scopes[depth].values().copied().collect();
+        indices.sort();
+        indices.into_iter().map(|index| &self.symbols[index])
+    }
+}
+
+// 常量池：绝对值超过阈值的常量只在数据存储器中保存一份，通过LD读取
+// 较小的常量仍然使用立即数LDC
+#[derive(Debug, Clone)]
+pub struct ConstantPool {
+    // 常量值 => 数据地址
+    entries: BTreeMap<i32, i32>,
+    pub threshold: u32,
+    hits: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    // 池中常量的个数
+    pub entries: usize,
+    // 复用池中已有常量的次数
+    pub hits: usize,
+}
+
+impl Default for ConstantPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstantPool {
+    // 默认阈值：超出16位有符号立即数范围的常量进入常量池
+    pub const DEFAULT_THRESHOLD: u32 = i16::MAX as u32;
+
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            threshold: Self::DEFAULT_THRESHOLD,
+            hits: 0,
+        }
+    }
+
+    pub fn should_pool(&self, value: i32) -> bool {
+        value.unsigned_abs() > self.threshold
+    }
+
+    // 返回value在数据存储器中的地址，以及它是否是新加入的
+    // 新加入的常量通过allocate分配地址，调用方负责写入数据存储器的初始内容
+    pub fn intern(&mut self, value: i32, allocate: impl FnOnce() -> i32) -> (i32, bool) {
+        match self.entries.get(&value) {
+            Some(loc) => {
+                self.hits += 1;
+                (*loc, false)
+            }
+            None => {
+                let loc = allocate();
+                self.entries.insert(value, loc);
+                (loc, true)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            entries: self.entries.len(),
+            hits: self.hits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::code::RegisterCode::{AC, AC1, MP, PC, R2, R3, R4};
+    use crate::environment::{
+        ConstantPool, FunctionSymbol, PoolStats, RegisterError, RegisterGroup, SymbolTable, SymbolType,
+    };
+    use crate::token::Span;
+
+    #[test]
+    fn test_register_group() {
+        let mut registers = RegisterGroup::new();
+        assert_eq!(registers.acquire(AC), Ok(AC));
+        assert_eq!(registers.acquire(AC1), Ok(AC1));
+        assert_eq!(registers.acquire(AC), Err(RegisterError::InUse(AC)));
+        assert_eq!(registers.acquire(MP), Err(RegisterError::Reserved(MP)));
+        assert_eq!(registers.allocate(), Ok(R2));
+        assert_eq!(registers.allocate(), Ok(R3));
+        assert_eq!(registers.allocate(), Ok(R4));
+        // GP、MP、PC不会被分配
+        assert_eq!(registers.allocate(), Err(RegisterError::Exhausted));
+        assert_eq!(registers.used(), [R2, R3, R4]);
+        assert_eq!(registers.free(R3), Ok(()));
+        assert_eq!(registers.free(R3), Err(RegisterError::NotAllocated(R3)));
+        assert_eq!(registers.free(AC), Err(RegisterError::NotAllocated(AC)));
+        assert_eq!(registers.free(PC), Err(RegisterError::Reserved(PC)));
+        assert_eq!(registers.allocate(), Ok(R3));
+        assert_eq!(registers.used(), [R2, R4, R3]);
+        assert_eq!(RegisterError::Exhausted.to_string(), "no free register");
+    }
+
+    #[test]
+    fn test_allocate() {
+        let mut table = SymbolTable::new();
+        // 地址0保留给标准序言
+        assert_eq!(table.insert("x", None), Some(1));
+        assert_eq!(table.allocate(), 2);
+        assert_eq!(table.insert("y", None), Some(3));
+        assert_eq!(table.insert("x", None), None);
+        assert_eq!(table.address("y"), Some(3));
+        assert_eq!(table.insert_array("a", 10, None), Some(4));
+        assert_eq!(table.insert("z", None), Some(14));
+        assert_eq!(table.insert_array("a", 2, None), None);
+        assert_eq!(table.data_size(), 15);
+        assert_eq!(table.look_up("a").unwrap().ty, SymbolType::Array(10));
+        assert_eq!(table.look_up("w"), None);
+    }
+
+    #[test]
+    fn test_symbol() {
+        let mut table = SymbolTable::new();
+        let span = Span {
+            line: 2,
+            column: 5,
+            start: 12,
+            end: 13,
+        };
+        table.insert("x", Some(span));
+        table.add_line("x", 2);
+        table.add_line("x", 4);
+        table.add_line("y", 4);
+        let x = table.look_up("x").unwrap();
+        assert_eq!((x.name.as_str(), x.address, x.ty), ("x", 1, SymbolType::Integer));
+        assert_eq!(x.definition, Some(span));
+        assert_eq!(x.uses(), 2);
+        assert_eq!(table.lines("x"), [2, 4]);
+        assert!(table.lines("y").is_empty());
+    }
+
+    #[test]
+    fn test_scope() {
+        let mut table = SymbolTable::new();
+        let addresses = |symbols: &mut dyn Iterator<Item = &crate::environment::Symbol>| {
+            symbols
+                .map(|symbol| (symbol.name.to_string(), symbol.address))
+                .collect::<Vec<_>>()
+        };
+        let x = table.insert("x", None).unwrap();
+        table.push_scope();
+        assert_eq!(table.depth(), 1);
+        assert_eq!(table.address("x"), Some(x));
+        // 内层的x遮蔽外层的x
+        let inner = table.insert("x", None).unwrap();
+        assert_ne!(inner, x);
+        let y = table.insert("y", None).unwrap();
+        assert_eq!(table.address("x"), Some(inner));
+        assert_eq!(
+            addresses(&mut table.symbols(1)),
+            [("x".to_string(), inner), ("y".to_string(), y)]
+        );
+        assert_eq!(addresses(&mut table.symbols(0)), [("x".to_string(), x)]);
+        table.pop_scope();
+        assert_eq!(table.address("x"), Some(x));
+        assert_eq!(table.address("y"), None);
+        // 地址不会被重新分配
+        assert_eq!(table.insert("z", None), Some(y + 1));
+        let names: Vec<_> = table.symbols_in_order().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["x", "x", "y", "z"]);
+    }
+
+    #[test]
+    fn test_function() {
+        let mut table = SymbolTable::new();
+        assert!(table.define_function("max", 2, 10));
+        assert!(table.define_function("main", 0, 3));
+        assert!(!table.define_function("max", 1, 20));
+        assert_eq!(table.function("max"), Some(FunctionSymbol { arity: 2, entry: 10 }));
+        assert_eq!(table.function("min"), None);
+        // 与变量的名字互不冲突
+        assert_eq!(table.insert("max", None), Some(1));
+        let names: Vec<_> = table.functions().map(|(name, _)| name).collect();
+        assert_eq!(names, ["main", "max"]);
+    }
+
+    #[test]
+    fn test_constant_pool() {
+        let mut table = SymbolTable::new();
+        let mut pool = ConstantPool::new();
+        assert!(!pool.should_pool(5));
+        assert!(!pool.should_pool(-32767));
+        assert!(pool.should_pool(100000));
+        assert!(pool.should_pool(i32::MIN));
+        assert_eq!(pool.intern(100000, || table.allocate()), (1, true));
+        assert_eq!(pool.intern(70000, || table.allocate()), (2, true));
+        assert_eq!(pool.intern(100000, || table.allocate()), (1, false));
+        assert_eq!(pool.stats(), PoolStats { entries: 2, hits: 1 });
     }
 }
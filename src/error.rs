@@ -0,0 +1,238 @@
+#[cfg(feature = "std")]
+use crate::diagnostics::{Diagnostic, DiagnosticBag};
+use crate::environment::RegisterError;
+#[cfg(feature = "std")]
+use crate::lexer::LexError;
+#[cfg(feature = "std")]
+use crate::parser::ParseError;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use crate::semantic::SemanticError;
+use crate::token::{Span, TokenType};
+#[cfg(feature = "std")]
+use crate::vm::VmError;
+use core::fmt::{Display, Formatter};
+
+// 编译与运行各阶段的错误，库的使用者可以按阶段与种类匹配，不需要解析错误信息
+#[cfg(feature = "std")]
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CompileError {
+    // 词法错误，如未闭合的注释
+    #[error("{}", lines(.0))]
+    Lex(Vec<LexError>),
+    // 所有的语法错误
+    #[error("{}", lines(.0))]
+    Parse(Vec<ParseError>),
+    // 语义检查发现的错误，如使用未赋值的变量
+    #[error("{}", lines(.0))]
+    Semantic(Vec<SemanticError>),
+    // 代码生成阶段的错误
+    #[error(transparent)]
+    Codegen(#[from] CodegenError),
+    // 装载或运行生成的代码时的错误
+    #[error(transparent)]
+    Runtime(#[from] VmError),
+}
+
+// 代码生成阶段的错误：语法树中有 semantic::check 或语法分析会提前报告的错误，
+// 或程序所需的数据存储器超过了上限
+// 代码生成不依赖std，Display不通过thiserror实现
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodegenError {
+    SyntaxError {
+        span: Span,
+        message: String,
+    },
+    UnassignedVariable {
+        name: String,
+        span: Span,
+    },
+    NotAnArray {
+        name: String,
+        span: Span,
+    },
+    StringNotWritable {
+        value: String,
+        span: Span,
+    },
+    NestedArray {
+        span: Span,
+    },
+    NestedFunction {
+        span: Span,
+    },
+    ReturnOutsideFunction {
+        span: Span,
+    },
+    // span为第一次调用该函数的位置
+    UndefinedFunction {
+        name: String,
+        span: Span,
+    },
+    InvalidPrefixOperator {
+        token_type: TokenType,
+        span: Span,
+    },
+    InvalidInfixOperator {
+        token_type: TokenType,
+        span: Span,
+    },
+    OutOfMemory {
+        data: usize,
+        stack: usize,
+        available: usize,
+    },
+    // 保存中间结果的寄存器的状态与预期不一致
+    Register(RegisterError),
+}
+
+impl Display for CodegenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CodegenError::SyntaxError { message, .. } => {
+                write!(f, "cannot compile a program with syntax errors: {}", message)
+            }
+            CodegenError::UnassignedVariable { name, span } => {
+                write!(f, "variable `{}` used before assignment at line {}", name, span.line)
+            }
+            CodegenError::NotAnArray { name, span } => write!(f, "`{}` is not an array, at line {}", name, span.line),
+            CodegenError::StringNotWritable { value, span } => {
+                write!(f, "string \"{}\" can only be written, at line {}", value, span.line)
+            }
+            CodegenError::NestedArray { span } => {
+                write!(f, "arrays can only be declared at the top level, at line {}", span.line)
+            }
+            CodegenError::NestedFunction { span } => {
+                write!(
+                    f,
+                    "functions can only be defined at the top level, at line {}",
+                    span.line
+                )
+            }
+            CodegenError::ReturnOutsideFunction { span } => {
+                write!(f, "return outside of a function, at line {}", span.line)
+            }
+            CodegenError::UndefinedFunction { name, span } => {
+                write!(f, "function `{}` is not defined, at line {}", name, span.line)
+            }
+            CodegenError::InvalidPrefixOperator { token_type, .. } => {
+                write!(f, "token type {:?} is not prefix operator", token_type)
+            }
+            CodegenError::InvalidInfixOperator { token_type, .. } => {
+                write!(f, "token type {:?} is not infix operator", token_type)
+            }
+            CodegenError::OutOfMemory { data, stack, available } => write!(
+                f,
+                "program needs {} words of data memory ({} for data, {} for temporaries), but only {} are available",
+                data + stack,
+                data,
+                stack,
+                available
+            ),
+            CodegenError::Register(err) => write!(f, "cannot restore a saved result: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CodegenError {}
+
+#[cfg(feature = "std")]
+fn lines<T: Display>(errors: &[T]) -> String {
+    let messages: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+    messages.join("\n")
+}
+
+#[cfg(feature = "std")]
+impl CompileError {
+    // 转换为可以附带源程序片段输出的诊断信息
+    pub fn diagnostics(&self) -> DiagnosticBag {
+        let mut bag = DiagnosticBag::new();
+        match self {
+            CompileError::Lex(errors) => bag.extend(
+                errors
+                    .iter()
+                    .map(|err| Diagnostic::error(Some(err.span), err.message.clone())),
+            ),
+            CompileError::Parse(errors) => bag.extend(errors),
+            CompileError::Semantic(errors) => bag.extend(errors),
+            CompileError::Codegen(err) => bag.push(Diagnostic::error(err.span(), err.to_string())),
+            CompileError::Runtime(err) => bag.push(Diagnostic::error(None, err.to_string())),
+        }
+        bag
+    }
+}
+
+impl CodegenError {
+    // 出错处在源程序中的位置，数据存储器不足等与源程序位置无关的错误为None
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CodegenError::SyntaxError { span, .. }
+            | CodegenError::UnassignedVariable { span, .. }
+            | CodegenError::NotAnArray { span, .. }
+            | CodegenError::StringNotWritable { span, .. }
+            | CodegenError::NestedArray { span }
+            | CodegenError::NestedFunction { span }
+            | CodegenError::ReturnOutsideFunction { span }
+            | CodegenError::UndefinedFunction { span, .. }
+            | CodegenError::InvalidPrefixOperator { span, .. }
+            | CodegenError::InvalidInfixOperator { span, .. } => Some(*span),
+            CodegenError::OutOfMemory { .. } | CodegenError::Register(_) => None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn span_mut(&mut self) -> Option<&mut Span> {
+        match self {
+            CodegenError::SyntaxError { span, .. }
+            | CodegenError::UnassignedVariable { span, .. }
+            | CodegenError::NotAnArray { span, .. }
+            | CodegenError::StringNotWritable { span, .. }
+            | CodegenError::NestedArray { span }
+            | CodegenError::NestedFunction { span }
+            | CodegenError::ReturnOutsideFunction { span }
+            | CodegenError::UndefinedFunction { span, .. }
+            | CodegenError::InvalidPrefixOperator { span, .. }
+            | CodegenError::InvalidInfixOperator { span, .. } => Some(span),
+            CodegenError::OutOfMemory { .. } | CodegenError::Register(_) => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use crate::error::{CodegenError, CompileError};
+    use crate::token::Span;
+    use crate::vm::VmError;
+    use std::error::Error;
+
+    #[test]
+    fn test_compile_error() {
+        let span = Span {
+            line: 3,
+            column: 1,
+            start: 20,
+            end: 21,
+        };
+        let err = CompileError::from(CodegenError::NotAnArray {
+            name: "a".to_string(),
+            span,
+        });
+        assert_eq!(err.to_string(), "`a` is not an array, at line 3");
+        assert!(err.source().is_none());
+        assert_eq!(err.diagnostics().iter().next().unwrap().span, Some(span));
+
+        let err = CompileError::from(CodegenError::OutOfMemory {
+            data: 1000,
+            stack: 24,
+            available: 1000,
+        });
+        assert!(err
+            .to_string()
+            .starts_with("program needs 1024 words of data memory (1000 for data"));
+
+        let err: CompileError = VmError::DivisionByZero { pc: 7 }.into();
+        assert!(matches!(err, CompileError::Runtime(VmError::DivisionByZero { pc: 7 })));
+    }
+}
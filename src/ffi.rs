@@ -0,0 +1,103 @@
+// 供C程序嵌入编译器的接口，声明见 include/tiny_cc.h
+// 构建动态库：cargo rustc --lib --release --crate-type cdylib --features ffi
+// 字符串都是以NUL结尾的UTF-8字符串
+// 指针参数须为空指针或有效的指针，由调用者保证，因此这些函数都是unsafe的
+#![allow(clippy::missing_safety_doc)]
+
+use crate::driver;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+pub const TINY_OK: c_int = 0;
+// 参数为空指针，或源程序不是UTF-8
+pub const TINY_INVALID_ARGUMENT: c_int = 1;
+// 源程序有语法或语义错误
+pub const TINY_COMPILE_ERROR: c_int = 2;
+
+thread_local! {
+    // 当前线程最近一次失败的调用的错误信息，调用成功时清空
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// 以默认选项编译source，成功时*out_buf为TM代码清单，须以 tiny_free 释放
+// 失败时*out_buf为空指针，返回错误码，错误信息见 tiny_last_error
+#[no_mangle]
+pub unsafe extern "C" fn tiny_compile(source: *const c_char, out_buf: *mut *mut c_char) -> c_int {
+    if out_buf.is_null() {
+        return fail(TINY_INVALID_ARGUMENT, "out_buf is null".to_string());
+    }
+    *out_buf = ptr::null_mut();
+    if source.is_null() {
+        return fail(TINY_INVALID_ARGUMENT, "source is null".to_string());
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(err) => return fail(TINY_INVALID_ARGUMENT, format!("source is not valid UTF-8: {}", err)),
+    };
+    match driver::compile(source) {
+        Ok(artifacts) => {
+            LAST_ERROR.with(|last| last.borrow_mut().take());
+            *out_buf = c_string(artifacts.listing).into_raw();
+            TINY_OK
+        }
+        // 每行一个错误，格式为 行:列: 信息
+        Err(err) => fail(TINY_COMPILE_ERROR, err.to_string()),
+    }
+}
+
+// 释放 tiny_compile 返回的字符串，buf为空指针时什么也不做
+#[no_mangle]
+pub unsafe extern "C" fn tiny_free(buf: *mut c_char) {
+    if !buf.is_null() {
+        drop(CString::from_raw(buf));
+    }
+}
+
+// 当前线程最近一次失败的调用的错误信息，没有时为空指针
+// 返回的字符串属于本库，在当前线程下一次调用 tiny_compile 之前有效
+#[no_mangle]
+pub extern "C" fn tiny_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+fn fail(code: c_int, message: String) -> c_int {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(c_string(message)));
+    code
+}
+
+// 源程序中不会有NUL，清单与错误信息中也就没有
+fn c_string(text: String) -> CString {
+    CString::new(text).expect("no NUL in compiler output")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ffi::{tiny_compile, tiny_free, tiny_last_error, TINY_COMPILE_ERROR, TINY_INVALID_ARGUMENT, TINY_OK};
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let mut buf = ptr::null_mut();
+            let source = CString::new("read x; write x * 2").unwrap();
+            assert_eq!(tiny_compile(source.as_ptr(), &mut buf), TINY_OK);
+            assert!(CStr::from_ptr(buf).to_str().unwrap().contains("HALT"));
+            assert!(tiny_last_error().is_null());
+            tiny_free(buf);
+
+            let source = CString::new("write y").unwrap();
+            assert_eq!(tiny_compile(source.as_ptr(), &mut buf), TINY_COMPILE_ERROR);
+            assert!(buf.is_null());
+            let message = CStr::from_ptr(tiny_last_error()).to_str().unwrap();
+            assert!(message.starts_with("1:7: "), "{}", message);
+
+            let source = CString::new(vec![0xff]).unwrap();
+            assert_eq!(tiny_compile(source.as_ptr(), &mut buf), TINY_INVALID_ARGUMENT);
+            assert_eq!(tiny_compile(ptr::null(), &mut buf), TINY_INVALID_ARGUMENT);
+            assert_eq!(tiny_compile(source.as_ptr(), ptr::null_mut()), TINY_INVALID_ARGUMENT);
+            tiny_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::ast;
+use crate::parser::{ParseError, Parser};
+
+// 默认每一层缩进的空格数，与 ast::to_source 一致
+pub const DEFAULT_INDENT: usize = 4;
+
+// 将源程序格式化为统一的风格：if、repeat等语句体每层缩进4个空格，:=与二元运算符两侧各一个空格，
+// 每个语句独占一行并以;结尾；注释保留在原来所在的语句附近
+// 存在语法错误时不做格式化，返回所有的错误
+pub fn format_source(source: &str) -> Result<String, Vec<ParseError>> {
+    format_with_indent(source, DEFAULT_INDENT)
+}
+
+pub fn format_with_indent(source: &str, indent: usize) -> Result<String, Vec<ParseError>> {
+    let program = Parser::new(source).with_comments(true).parse_program()?;
+    Ok(ast::to_source_with_indent(&program, indent))
+}
+
+// 源程序是否已经是格式化后的样子
+pub fn is_formatted(source: &str) -> Result<bool, Vec<ParseError>> {
+    Ok(format_source(source)? == source)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{format_source, format_with_indent, is_formatted};
+
+    #[test]
+    fn test_format() {
+        let input = "{ factorial }\nread x; { input }\nif 0<x then { positive }\n  fact:=1;\n  repeat fact:=fact*x; x:=x-1 { next }\n  until x=0;\n  write fact\n  { done }\nend";
+        let formatted = format_source(input).unwrap();
+        assert_eq!(
+            formatted,
+            "{ factorial }
+read x; { input }
+if 0 < x then
+    { positive }
+    fact := 1;
+    repeat
+        fact := fact * x;
+        x := x - 1; { next }
+    until x = 0;
+    write fact;
+    { done }
+end;
+"
+        );
+        assert!(is_formatted(&formatted).unwrap());
+        assert!(!is_formatted(input).unwrap());
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_program_header() {
+        let input = "{ header }\nprogram demo; read x;\n{ last }\nend. { trailer }";
+        assert_eq!(
+            format_with_indent(input, 2).unwrap(),
+            "{ header }\nprogram demo;\n  read x;\n  { last }\nend.\n{ trailer }\n"
+        );
+        assert!(format_source("read x; write").is_err());
+    }
+}
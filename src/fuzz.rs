@@ -0,0 +1,310 @@
+use crate::ast::{self, Program};
+use crate::differential;
+use crate::driver;
+use crate::lexer::{decode_source, Lexer};
+use crate::parser::Parser;
+use crate::token::TokenType;
+use crate::vm::TinyMachine;
+
+// 供cargo-fuzz与proptest使用的入口：任意输入都能正常结束，错误的输入以错误报告，
+// 只有发现编译器自身的缺陷时才会panic，如生成的代码无法装载
+
+// 运行生成的代码时最多执行的指令数，避免死循环
+pub const INSTRUCTION_LIMIT: u64 = 100_000;
+
+// 运行生成的代码时的标准输入
+const INPUT: &str = "3\n-1\n0\n7\n3\n-1\n0\n7\n3\n-1\n0\n7\n3\n-1\n0\n7\n";
+
+// 对任意字节做词法分析，检查token的位置单调递增并且位于源程序之内
+pub fn lex_fuzz(input: &[u8]) {
+    let (source, _) = decode_source(input);
+    let length = source.chars().count();
+    let mut lexer = Lexer::new(&source).with_comments(true);
+    let mut end = 0;
+    loop {
+        let token = lexer.next_token();
+        assert!(
+            end <= token.span.start && token.span.start <= token.span.end && token.span.end <= length,
+            "token {:?} at {} out of order",
+            token.token_type,
+            token.span
+        );
+        end = token.span.end;
+        if token.token_type == TokenType::Eof {
+            break;
+        }
+    }
+    lexer.take_errors();
+}
+
+// 对任意字节做语法分析；没有语法错误时，由语法树生成的源程序必须能再次解析为相同的源程序
+pub fn parse_fuzz(input: &[u8]) {
+    let (source, _) = decode_source(input);
+    let (program, errors) = Parser::new(&source).parse_program_partial();
+    program.dump();
+    if errors.is_empty() {
+        check_round_trip(&program);
+    }
+}
+
+// 编译任意字节，编译成功时在虚拟机中运行生成的代码
+pub fn compile_fuzz(input: &[u8]) {
+    let (source, _) = decode_source(input);
+    if let Ok(artifacts) = driver::compile(&source) {
+        run(&artifacts);
+    }
+}
+
+// 检查一个没有语法错误的语法树：生成的源程序能再次解析并且能够编译，
+// 编译后运行的结果与解释执行一致，见 differential::assert_same_behavior
+// 供 Arbitrary 生成的语法树使用，这样的程序总能通过语义检查
+pub fn check_program(program: &Program) {
+    let source = check_round_trip(program);
+    differential::assert_same_behavior(&source, &[INPUT]);
+}
+
+fn check_round_trip(program: &Program) -> String {
+    let source = ast::to_source(program);
+    let reparsed = Parser::new(&source)
+        .parse_program()
+        .unwrap_or_else(|errors| panic!("{:?} does not parse again: {:?}", source, errors));
+    assert_eq!(ast::to_source(&reparsed), source, "printing is not stable");
+    source
+}
+
+fn run(artifacts: &driver::Artifacts) {
+    let mut vm = TinyMachine::new(INPUT.as_bytes(), vec![])
+        .with_memory_size(artifacts.memory_size)
+        .with_instruction_limit(INSTRUCTION_LIMIT);
+    vm.load(&artifacts.listing)
+        .unwrap_or_else(|err| panic!("generated code does not load: {}", err));
+    // 除以0、读取结束与超过指令数都是正常的运行时错误
+    let _ = vm.run();
+}
+
+// 生成总能通过语义检查的程序：变量在使用之前一定已被赋值，条件为布尔值，其余表达式为整数
+// TINY没有括号，运算的结合完全由优先级决定
+// 覆盖赋值、read、write、if、repeat与while语句，以及全部的运算符
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let source = arbitrary_source(u)?;
+        Ok(Parser::new(&source)
+            .parse_program()
+            .unwrap_or_else(|errors| panic!("generated source {:?} does not parse: {:?}", source, errors)))
+    }
+}
+
+// 与 Program 的 Arbitrary 实现相同的程序的源程序
+#[cfg(feature = "arbitrary")]
+pub fn arbitrary_source(u: &mut arbitrary::Unstructured) -> arbitrary::Result<String> {
+    let mut generator = Generator {
+        u,
+        assigned: vec![],
+        output: String::new(),
+    };
+    generator.block(0)?;
+    Ok(generator.output)
+}
+
+#[cfg(feature = "arbitrary")]
+struct Generator<'u, 'a> {
+    u: &'u mut arbitrary::Unstructured<'a>,
+    // 已经赋值的变量，只有它们可以出现在表达式中
+    assigned: Vec<&'static str>,
+    output: String,
+}
+
+#[cfg(feature = "arbitrary")]
+impl Generator<'_, '_> {
+    // 语句与表达式嵌套的最大深度
+    const MAX_DEPTH: usize = 3;
+    const NAMES: [&'static str; 4] = ["x", "y", "z", "n"];
+
+    fn block(&mut self, depth: usize) -> arbitrary::Result<()> {
+        let count = self.u.int_in_range(1..=4)?;
+        for i in 0..count {
+            if i > 0 {
+                self.output.push_str(";\n");
+            }
+            self.statement(depth)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, depth: usize) -> arbitrary::Result<()> {
+        let kinds = if depth < Self::MAX_DEPTH { 6 } else { 3 };
+        match self.u.choose_index(kinds)? {
+            0 => {
+                let name = *self.u.choose(&Self::NAMES)?;
+                self.output.push_str(&format!("{} := ", name));
+                self.integer(depth)?;
+                self.assign(name);
+            }
+            1 => {
+                let name = *self.u.choose(&Self::NAMES)?;
+                self.output.push_str(&format!("read {}", name));
+                self.assign(name);
+            }
+            2 => {
+                self.output.push_str("write ");
+                if self.u.ratio(1, 4)? {
+                    self.output.push_str("\"done\"");
+                } else {
+                    self.integer(depth)?;
+                }
+            }
+            3 => {
+                self.output.push_str("if ");
+                self.boolean(depth)?;
+                self.output.push_str(" then\n");
+                self.conditional_block(depth + 1)?;
+                if self.u.arbitrary()? {
+                    self.output.push_str("\nelse\n");
+                    self.conditional_block(depth + 1)?;
+                }
+                self.output.push_str("\nend");
+            }
+            4 => {
+                self.output.push_str("repeat\n");
+                self.block(depth + 1)?;
+                self.output.push_str("\nuntil ");
+                self.boolean(depth)?;
+            }
+            _ => {
+                self.output.push_str("while ");
+                self.boolean(depth)?;
+                self.output.push_str(" do\n");
+                self.conditional_block(depth + 1)?;
+                self.output.push_str("\nend");
+            }
+        }
+        Ok(())
+    }
+
+    // 不一定执行的语句块，其中赋值的变量在之后仍不能使用，否则解释执行时会报错
+    fn conditional_block(&mut self, depth: usize) -> arbitrary::Result<()> {
+        let assigned = self.assigned.len();
+        self.block(depth)?;
+        self.assigned.truncate(assigned);
+        Ok(())
+    }
+
+    fn assign(&mut self, name: &'static str) {
+        if !self.assigned.contains(&name) {
+            self.assigned.push(name);
+        }
+    }
+
+    fn integer(&mut self, depth: usize) -> arbitrary::Result<()> {
+        let kinds = if depth < Self::MAX_DEPTH { 4 } else { 2 };
+        match self.u.choose_index(kinds)? {
+            1 if !self.assigned.is_empty() => {
+                let name = *self.u.choose(&self.assigned)?;
+                self.output.push_str(name);
+            }
+            0 | 1 => {
+                let value: i16 = self.u.arbitrary()?;
+                self.output.push_str(&value.unsigned_abs().to_string());
+            }
+            2 => {
+                self.output.push('-');
+                self.integer(depth + 1)?;
+            }
+            _ => {
+                self.integer(depth + 1)?;
+                let op = *self.u.choose(&["+", "-", "*", "/"])?;
+                self.output.push_str(&format!(" {} ", op));
+                self.integer(depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn boolean(&mut self, depth: usize) -> arbitrary::Result<()> {
+        let kinds = if depth < Self::MAX_DEPTH { 4 } else { 2 };
+        match self.u.choose_index(kinds)? {
+            0 => {
+                let value: bool = self.u.arbitrary()?;
+                self.output.push_str(if value { "true" } else { "false" });
+            }
+            1 => {
+                self.integer(depth + 1)?;
+                let op = *self.u.choose(&["<", "<=", ">", ">=", "=", "<>"])?;
+                self.output.push_str(&format!(" {} ", op));
+                self.integer(depth + 1)?;
+            }
+            2 => {
+                self.output.push_str("not ");
+                self.boolean(depth + 1)?;
+            }
+            _ => {
+                self.boolean(depth + 1)?;
+                let op = *self.u.choose(&["and", "or"])?;
+                self.output.push_str(&format!(" {} ", op));
+                self.boolean(depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fuzz::{compile_fuzz, lex_fuzz, parse_fuzz};
+
+    #[test]
+    fn test_malformed_input() {
+        let inputs: [&[u8]; 8] = [
+            b"",
+            b"\xef\xbb\xbf\xff\xfe read x",
+            b"{ { }",
+            b"x := 99999999999999; write x",
+            b"program p; fn f(a) return f(a) end; write f(1) end.",
+            b"var a[0]; a[5] := 1; write a[-1]",
+            b"repeat until",
+            b"\"unterminated",
+        ];
+        for input in inputs {
+            lex_fuzz(input);
+            parse_fuzz(input);
+            compile_fuzz(input);
+        }
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let alphabet = b"xy01 ;:=+-*/<>(){}[],\"\nreadwriteifthenendrepeatuntilfnvar";
+        let mut seed: u64 = 2024;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        for _ in 0..300 {
+            let input: Vec<u8> = (0..next(60)).map(|_| alphabet[next(alphabet.len())]).collect();
+            lex_fuzz(&input);
+            parse_fuzz(&input);
+            compile_fuzz(&input);
+        }
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_program() {
+        use crate::ast::Program;
+        use crate::fuzz::check_program;
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut seed: u64 = 7;
+        for _ in 0..200 {
+            let data: Vec<u8> = (0..256)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (seed >> 56) as u8
+                })
+                .collect();
+            let program = Program::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            check_program(&program);
+        }
+    }
+}
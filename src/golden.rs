@@ -0,0 +1,184 @@
+use crate::diff::{diff_lines, DiffLine};
+use crate::driver::{compile_to_tm, Options};
+use crate::lexer;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 设置该环境变量（非空且不为0）时，check_dir 以实际的输出更新期望的文件，而不是报告差异
+pub const BLESS_VAR: &str = "TINY_BLESS";
+
+// 一个样例的实际输出与期望的文件不一致
+#[derive(Debug)]
+pub struct Mismatch {
+    pub source: PathBuf,
+    pub expected: PathBuf,
+    // 逐行的差异，-为期望的内容，+为实际的输出；期望的文件不存在时为空
+    pub changes: Vec<DiffLine>,
+}
+
+#[derive(Debug, Default)]
+pub struct GoldenReport {
+    pub passed: Vec<PathBuf>,
+    // bless时被创建或更新的期望文件
+    pub blessed: Vec<PathBuf>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl GoldenReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Display for GoldenReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for mismatch in &self.mismatches {
+            if mismatch.changes.is_empty() {
+                writeln!(
+                    f,
+                    "{}: missing {}",
+                    mismatch.source.display(),
+                    mismatch.expected.display()
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{}: differs from {}",
+                mismatch.source.display(),
+                mismatch.expected.display()
+            )?;
+            for change in &mismatch.changes {
+                match change {
+                    DiffLine::Same(line) => writeln!(f, "  {}", line)?,
+                    DiffLine::Removed(line) => writeln!(f, "- {}", line)?,
+                    DiffLine::Added(line) => writeln!(f, "+ {}", line)?,
+                }
+            }
+        }
+        if !self.mismatches.is_empty() {
+            writeln!(f, "rerun with {}=1 to update the expected files", BLESS_VAR)?;
+        }
+        Ok(())
+    }
+}
+
+// 是否设置了 BLESS_VAR
+pub fn blessing() -> bool {
+    std::env::var(BLESS_VAR).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+// 以默认选项编译dir下所有的.tny样例，与同名的期望文件比较：
+// 编译成功时为清单.tm，失败时为错误信息.err，文本完全一致才算通过
+pub fn check_dir(dir: impl AsRef<Path>) -> io::Result<GoldenReport> {
+    check_dir_with(dir, &Options::default(), blessing())
+}
+
+// bless为true时写入实际的输出，并删除另一种已经过时的期望文件
+pub fn check_dir_with(dir: impl AsRef<Path>, options: &Options, bless: bool) -> io::Result<GoldenReport> {
+    let mut sources = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "tny") {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+
+    let mut report = GoldenReport::default();
+    for source in sources {
+        let (input, _) = lexer::decode_source(&fs::read(&source)?);
+        let (actual, expected, stale) = match compile_to_tm(&input, options) {
+            Ok(artifacts) => (
+                artifacts.listing,
+                source.with_extension("tm"),
+                source.with_extension("err"),
+            ),
+            Err(err) => (
+                format!("{}\n", err),
+                source.with_extension("err"),
+                source.with_extension("tm"),
+            ),
+        };
+        if bless {
+            if fs::read_to_string(&expected).ok().as_deref() != Some(actual.as_str()) {
+                fs::write(&expected, &actual)?;
+                report.blessed.push(expected);
+            }
+            if stale.exists() {
+                fs::remove_file(&stale)?;
+            }
+            continue;
+        }
+        let changes = match fs::read_to_string(&expected) {
+            Ok(text) if text == actual => {
+                report.passed.push(source);
+                continue;
+            }
+            Ok(text) => diff_lines(&lines(&text), &lines(&actual)).lines,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        report.mismatches.push(Mismatch {
+            source,
+            expected,
+            changes,
+        });
+    }
+    Ok(report)
+}
+
+fn lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::DiffLine;
+    use crate::driver::Options;
+    use crate::golden::{check_dir, check_dir_with};
+    use std::fs;
+
+    #[test]
+    fn test_golden() {
+        let report = check_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden")).unwrap();
+        assert!(report.is_ok(), "\n{}", report);
+    }
+
+    #[test]
+    fn test_bless() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_golden_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("echo.tny"), "read x; write x").unwrap();
+        fs::write(root.join("bad.tny"), "write y").unwrap();
+
+        let report = check_dir_with(&root, &Options::default(), false).unwrap();
+        assert_eq!(report.mismatches.len(), 2);
+        assert!(report.to_string().contains("missing"));
+
+        let report = check_dir_with(&root, &Options::default(), true).unwrap();
+        assert_eq!(report.blessed, [root.join("bad.err"), root.join("echo.tm")]);
+        assert_eq!(
+            fs::read_to_string(root.join("bad.err")).unwrap(),
+            "1:7: variable `y` used before assignment at line 1\n"
+        );
+        assert!(check_dir_with(&root, &Options::default(), false).unwrap().is_ok());
+
+        fs::write(root.join("echo.tny"), "read x; write x + 1").unwrap();
+        let report = check_dir_with(&root, &Options::default(), false).unwrap();
+        assert_eq!(report.passed, [root.join("bad.tny")]);
+        let changes = &report.mismatches[0].changes;
+        assert!(changes
+            .iter()
+            .any(|line| matches!(line, DiffLine::Added(line) if line.contains("ADD"))));
+
+        // 修复错误之后，过时的.err被删除
+        fs::write(root.join("bad.tny"), "y := 1; write y").unwrap();
+        check_dir_with(&root, &Options::default(), true).unwrap();
+        assert!(!root.join("bad.err").exists());
+        assert!(root.join("bad.tm").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::lexer::Lexer;
+use crate::token::{Span, TokenType};
+
+// 语法高亮中一段源程序的类别
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Comment,
+    // 算术、比较与赋值运算符
+    Operator,
+    // ; . , 与括号
+    Punctuation,
+    // 无法识别的字符与未闭合的字符串
+    Error,
+}
+
+impl HighlightKind {
+    // 生成HTML时使用的class
+    pub fn css_class(self) -> &'static str {
+        match self {
+            HighlightKind::Keyword => "keyword",
+            HighlightKind::Identifier => "identifier",
+            HighlightKind::Number => "number",
+            HighlightKind::String => "string",
+            HighlightKind::Comment => "comment",
+            HighlightKind::Operator => "operator",
+            HighlightKind::Punctuation => "punctuation",
+            HighlightKind::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    pub kind: HighlightKind,
+    pub span: Span,
+}
+
+// 按源程序中的顺序列出每个token与注释的类别，空白不在其中
+// span与词法分析器一致，为去除BOM之后的字符偏移量
+pub fn highlight(source: &str) -> Vec<Highlight> {
+    let mut lexer = Lexer::new(source).with_comments(true);
+    let mut highlights = vec![];
+    loop {
+        // 只需要token的类型与位置，不复制字面量
+        let lexeme = lexer.next_lexeme();
+        let kind = match lexeme.token_type {
+            TokenType::Eof => break,
+            TokenType::Ident => HighlightKind::Identifier,
+            TokenType::Number => HighlightKind::Number,
+            TokenType::String => HighlightKind::String,
+            TokenType::Comment => HighlightKind::Comment,
+            TokenType::Illegal => HighlightKind::Error,
+            TokenType::Assign => HighlightKind::Operator,
+            token_type if token_type.is_keyword() => HighlightKind::Keyword,
+            token_type if token_type.is_operator() => HighlightKind::Operator,
+            _ => HighlightKind::Punctuation,
+        };
+        highlights.push(Highlight {
+            kind,
+            span: lexeme.span,
+        });
+    }
+    highlights
+}
+
+// 生成高亮的HTML片段：<pre class="tiny">中每个token为一个 <span class="类别">，样式由使用者提供
+pub fn to_html(source: &str) -> String {
+    let chars: Vec<char> = source.strip_prefix('\u{feff}').unwrap_or(source).chars().collect();
+    let text = |start: usize, end: usize| html_escape(&chars[start..end].iter().collect::<String>());
+    let mut output = String::from("<pre class=\"tiny\">");
+    let mut pos = 0;
+    for Highlight { kind, span } in highlight(source) {
+        output.push_str(&text(pos, span.start));
+        output.push_str(&format!(
+            "<span class=\"{}\">{}</span>",
+            kind.css_class(),
+            text(span.start, span.end)
+        ));
+        pos = span.end;
+    }
+    output.push_str(&text(pos, chars.len()));
+    output.push_str("</pre>\n");
+    output
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::highlight::{highlight, to_html, HighlightKind};
+
+    #[test]
+    fn test_highlight() {
+        let kinds: Vec<_> = highlight("{ c } if x<10 then write \"a\"; @")
+            .into_iter()
+            .map(|highlight| (highlight.kind, highlight.span.start, highlight.span.end))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (HighlightKind::Comment, 0, 5),
+                (HighlightKind::Keyword, 6, 8),
+                (HighlightKind::Identifier, 9, 10),
+                (HighlightKind::Operator, 10, 11),
+                (HighlightKind::Number, 11, 13),
+                (HighlightKind::Keyword, 14, 18),
+                (HighlightKind::Keyword, 19, 24),
+                (HighlightKind::String, 25, 28),
+                (HighlightKind::Punctuation, 28, 29),
+                (HighlightKind::Error, 30, 31),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_html() {
+        assert_eq!(
+            to_html("\u{feff}x := a<b; { <&> }\n"),
+            "<pre class=\"tiny\"><span class=\"identifier\">x</span> <span class=\"operator\">:=</span> \
+             <span class=\"identifier\">a</span><span class=\"operator\">&lt;</span>\
+             <span class=\"identifier\">b</span><span class=\"punctuation\">;</span> \
+             <span class=\"comment\">{ &lt;&amp;&gt; }</span>\n</pre>\n"
+        );
+    }
+}
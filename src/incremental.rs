@@ -0,0 +1,420 @@
+use crate::ast::{Node, Program};
+use crate::lexer::{LexError, Lexer};
+use crate::parser::{ParseError, Parser, MAX_DEPTH};
+use crate::token::{Span, TokenType};
+
+// 编辑器中的一次修改：将字符偏移量start..end之间的文本替换为text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+// 一次修改之后重新解析与直接复用的最外层语句数
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Reparse {
+    pub reparsed: usize,
+    pub reused: usize,
+}
+
+// 最外层语句的解析信息，见 ParsedStatement
+#[derive(Debug, Clone)]
+struct Entry {
+    tokens: usize,
+    examined: usize,
+    errors: Vec<ParseError>,
+}
+
+// 支持增量解析的源程序：修改之后只重新解析受影响的最外层语句，
+// 从修改处之后第一个与原来的语句边界重合的位置起，直接复用原来的语句并平移其位置
+// 结果与重新解析整个源程序相同；有program头的程序每次修改都重新解析整个源程序
+pub struct Document {
+    text: String,
+    program: Program,
+    // 与program.statements一一对应，有program头或没有语句时为空
+    entries: Vec<Entry>,
+    // 到达输入结束时产生的错误，如未闭合的注释；重新解析整个源程序时为所有的错误
+    trailing_errors: Vec<ParseError>,
+    header: bool,
+    max_depth: usize,
+}
+
+impl Document {
+    // 偏移量与 Lexer 一致，不计开头的BOM
+    pub fn new(text: &str) -> Self {
+        Self::with_max_depth(text, MAX_DEPTH)
+    }
+
+    // 每次解析都限制语句与表达式嵌套的深度，见 Parser::with_max_depth
+    pub fn with_max_depth(text: &str, max_depth: usize) -> Self {
+        Self::parse(text.strip_prefix('\u{feff}').unwrap_or(text).to_string(), max_depth)
+    }
+
+    fn parse(text: String, max_depth: usize) -> Self {
+        let mut first = None;
+        let (parsed, trailing_errors) = Parser::new(&text)
+            .with_max_depth(max_depth)
+            .parse_statements(|token| first.get_or_insert(token.token_type) == &TokenType::Program);
+        let header = first == Some(TokenType::Program);
+        if header || parsed.is_empty() {
+            let (program, errors) = Parser::new(&text).with_max_depth(max_depth).parse_program_partial();
+            return Self {
+                text,
+                program,
+                entries: vec![],
+                trailing_errors: errors,
+                header,
+                max_depth,
+            };
+        }
+        let mut program = Program::new();
+        let mut entries = vec![];
+        for parsed in parsed {
+            program.statements.push(parsed.stmt);
+            entries.push(Entry {
+                tokens: parsed.tokens,
+                examined: parsed.examined,
+                errors: parsed.errors,
+            });
+        }
+        program.span = program_span(&program);
+        Self {
+            text,
+            program,
+            entries,
+            trailing_errors,
+            header,
+            max_depth,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    // 与 Parser::parse_program_partial 返回的错误相同
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.entries
+            .iter()
+            .flat_map(|entry| entry.errors.iter().cloned())
+            .chain(self.trailing_errors.iter().cloned())
+            .collect()
+    }
+
+    // 以新的文本代替整个源程序，如文件在编辑器外被改写：只把与原来不同的中间部分作为一次修改
+    pub fn replace_text(&mut self, text: &str) -> Reparse {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let old: Vec<char> = self.text.chars().collect();
+        let new: Vec<char> = text.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.edit(&TextEdit {
+            start: prefix,
+            end: old.len() - suffix,
+            text: new[prefix..new.len() - suffix].iter().collect(),
+        })
+    }
+
+    // 应用一次修改并重新解析，edit的范围必须在源程序之内
+    pub fn edit(&mut self, edit: &TextEdit) -> Reparse {
+        let length = self.text.chars().count();
+        assert!(
+            edit.start <= edit.end && edit.end <= length,
+            "edit {}..{} out of range (length {})",
+            edit.start,
+            edit.end,
+            length
+        );
+        let mut text = self.text.clone();
+        text.replace_range(byte_offset(&text, edit.start)..byte_offset(&text, edit.end), &edit.text);
+        if self.header || self.entries.is_empty() {
+            return self.reparse_all(text);
+        }
+
+        let statements = &self.program.statements;
+        // 解析时读取的源程序结束于修改处之前的语句不受影响，重新解析从前一个语句的末尾开始
+        let first = self
+            .entries
+            .iter()
+            .position(|entry| entry.examined >= edit.start)
+            .unwrap_or(self.entries.len());
+        let region = match first {
+            0 => position(&self.text, 0),
+            _ => position(&self.text, statements[first - 1].span().end),
+        };
+        let inserted = edit.text.chars().count();
+        let edit_end = edit.start + inserted;
+        let shift = |offset: usize| (offset as isize + inserted as isize - (edit.end - edit.start) as isize) as usize;
+        // 可能被复用的语句必须开始于修改的范围之后
+        // 无法识别的字符的错误信息中含有其位置，其后的语句都不直接复用
+        let positional = self
+            .entries
+            .iter()
+            .rposition(|entry| {
+                entry
+                    .errors
+                    .iter()
+                    .any(|err| err.found.token_type == TokenType::Illegal)
+            })
+            .map_or(0, |index| index + 1);
+        let mut candidate = statements
+            .partition_point(|stmt| stmt.span().start < edit.end)
+            .max(positional);
+        let mut sync = None;
+        let mut header = false;
+        let mut parser = Parser::from_lexer(Lexer::starting_at(&text[byte_offset(&text, region.start)..], region))
+            .with_max_depth(self.max_depth);
+        let (parsed, trailing_errors) = parser.parse_statements(|token| {
+            if token.span.start == region.start && region.start == 0 && token.token_type == TokenType::Program {
+                header = true;
+                return true;
+            }
+            if token.span.start < edit_end {
+                return false;
+            }
+            while candidate < statements.len() && shift(statements[candidate].span().start) < token.span.start {
+                candidate += 1;
+            }
+            if candidate < statements.len() && shift(statements[candidate].span().start) == token.span.start {
+                sync = Some(candidate);
+            }
+            sync.is_some()
+        });
+        if header || parsed.is_empty() && sync.is_none() && first == 0 {
+            return self.reparse_all(text);
+        }
+
+        let old_end = position(&self.text, edit.end);
+        let new_end = position(&text, edit_end);
+        let move_span = |span: &mut Span| {
+            if span.line == old_end.line {
+                span.column = span.column + new_end.column - old_end.column;
+            }
+            span.line = span.line + new_end.line - old_end.line;
+            span.start = shift(span.start);
+            span.end = shift(span.end);
+        };
+        let base: usize = self.entries[..first].iter().map(|entry| entry.tokens).sum();
+        let reused_from = sync.unwrap_or(self.entries.len());
+        let replaced: usize = self.entries[first..reused_from].iter().map(|entry| entry.tokens).sum();
+        let reparsed: usize = parsed.iter().map(|parsed| parsed.tokens).sum();
+        // 未闭合的注释的错误信息中含有其位置
+        let move_error = |err: &mut ParseError| {
+            move_span(&mut err.found.span);
+            err.position = err.position + reparsed - replaced;
+            if err.found.token_type == TokenType::Comment {
+                err.message = LexError::unterminated_comment(err.found.span).message;
+            }
+        };
+
+        let mut tail_statements = self.program.statements.split_off(reused_from);
+        let mut tail_entries = self.entries.split_off(reused_from);
+        self.program.statements.truncate(first);
+        self.entries.truncate(first);
+        let stats = Reparse {
+            reparsed: parsed.len(),
+            reused: first + tail_statements.len(),
+        };
+        for mut parsed in parsed {
+            for err in &mut parsed.errors {
+                err.position += base;
+            }
+            self.program.statements.push(parsed.stmt);
+            self.entries.push(Entry {
+                tokens: parsed.tokens,
+                examined: parsed.examined,
+                errors: parsed.errors,
+            });
+        }
+        for stmt in &mut tail_statements {
+            stmt.for_each_span_mut(&mut |span| move_span(span));
+        }
+        for entry in &mut tail_entries {
+            entry.examined = shift(entry.examined);
+            entry.errors.iter_mut().for_each(move_error);
+        }
+        self.program.statements.append(&mut tail_statements);
+        self.entries.append(&mut tail_entries);
+        match sync {
+            Some(_) => self.trailing_errors.iter_mut().for_each(move_error),
+            None => {
+                self.trailing_errors = trailing_errors;
+                for err in &mut self.trailing_errors {
+                    err.position += base;
+                }
+            }
+        }
+        self.text = text;
+        if self.program.statements.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            return self.reparse_all(text);
+        }
+        self.program.span = program_span(&self.program);
+        stats
+    }
+
+    fn reparse_all(&mut self, text: String) -> Reparse {
+        *self = Self::parse(text, self.max_depth);
+        Reparse {
+            reparsed: self.program.statements.len(),
+            reused: 0,
+        }
+    }
+}
+
+// 没有program头时，程序的范围为第一个语句的起点到最后一个语句的终点
+fn program_span(program: &Program) -> Span {
+    let first = program.statements[0].span();
+    let last = program.statements[program.statements.len() - 1].span();
+    first.to(last)
+}
+
+// 字符偏移量offset处的位置
+fn position(text: &str, offset: usize) -> Span {
+    let (mut line, mut column) = (1, 1);
+    for ch in text.chars().take(offset) {
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    Span {
+        line,
+        column,
+        start: offset,
+        end: offset,
+    }
+}
+
+fn byte_offset(text: &str, offset: usize) -> usize {
+    text.char_indices().nth(offset).map_or(text.len(), |(index, _)| index)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::incremental::{Document, Reparse, TextEdit};
+    use crate::parser::Parser;
+
+    fn edit(start: usize, end: usize, text: &str) -> TextEdit {
+        TextEdit {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    // 增量解析的结果必须与重新解析整个源程序相同
+    fn assert_same(document: &Document) {
+        let (program, errors) = Parser::new(document.text())
+            .with_max_depth(document.max_depth)
+            .parse_program_partial();
+        assert_eq!(document.program().dump(), program.dump(), "{:?}", document.text());
+        assert_eq!(document.errors(), errors, "{:?}", document.text());
+    }
+
+    #[test]
+    fn test_edit() {
+        let source = "read x;\nif x > 0 then\n  write x\nend;\ny := x * 2; write y;\nwrite \"done\"";
+        let mut document = Document::new(source);
+        assert_same(&document);
+
+        // 只修改if语句中的一个字符
+        let reparse = document.edit(&edit(15, 16, "10"));
+        assert_eq!(reparse, Reparse { reparsed: 1, reused: 4 });
+        assert_same(&document);
+
+        // 在同一行中插入语句，之后的语句的列号随之改变
+        let offset = document.text().find("y :=").unwrap();
+        assert_eq!(document.edit(&edit(offset, offset, "z := 1; ")).reparsed, 1);
+        assert_same(&document);
+
+        // 产生语法错误，之后再修复
+        let offset = document.text().find(":= x").unwrap();
+        document.edit(&edit(offset, offset + 2, "="));
+        assert_same(&document);
+        assert_eq!(document.errors().len(), 1);
+        document.edit(&edit(offset, offset + 1, ":="));
+        assert_same(&document);
+        assert!(document.errors().is_empty());
+
+        // 未闭合的注释使之后的语句都需要重新解析
+        let reparse = document.edit(&edit(8, 8, "{ "));
+        assert_eq!(reparse.reused, 1);
+        assert_same(&document);
+        assert_eq!(document.program().statements.len(), 1);
+        document.edit(&edit(8, 10, ""));
+        assert_same(&document);
+
+        // 添加program头时重新解析整个源程序
+        document.edit(&edit(0, 0, "program demo;\n"));
+        let length = document.text().chars().count();
+        document.edit(&edit(length, length, "\nend."));
+        assert_same(&document);
+        assert_eq!(document.program().name.as_deref(), Some("demo"));
+    }
+
+    #[test]
+    fn test_replace_text() {
+        let mut document = Document::new("read x;\nwrite x;\ny := 1;\nwrite y");
+        let reparse = document.replace_text("read x;\nwrite x + 1;\ny := 1;\nwrite y");
+        assert_eq!(reparse, Reparse { reparsed: 1, reused: 3 });
+        assert_same(&document);
+        assert_eq!(document.replace_text("\u{feff}write 1").reused, 0);
+        assert_eq!(document.text(), "write 1");
+        assert_same(&document);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut document = Document::with_max_depth("write 1;\nwrite 2;\nwrite 3", 3);
+        assert!(document.errors().is_empty());
+        // 修改中间的语句使其嵌套过深，报告错误并跳过其余的源程序
+        let offset = document.text().find('2').unwrap();
+        document.edit(&edit(offset, offset + 1, "- - -2"));
+        assert_same(&document);
+        assert!(document.errors()[0].message.starts_with("nesting too deep"));
+        assert_eq!(document.program().statements.len(), 2);
+        document.edit(&edit(offset, offset + 6, "2"));
+        assert_same(&document);
+        assert!(document.errors().is_empty());
+        assert_eq!(document.program().statements.len(), 3);
+    }
+
+    #[test]
+    fn test_random_edits() {
+        let pieces = [
+            "", ";", " ", "\n", "x", "1", ":=", "read ", "write ", "if ", " then ", "end", "repeat ", "until ", "{",
+            "}", "\"", "+", "<", "fn f(a) ", "return a", "f(1)", "a[",
+        ];
+        let mut document = Document::new("read x;\nrepeat\n  x := x - 1;\n  write x\nuntil x = 0;\nwrite x");
+        let mut seed: u64 = 12345;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound.max(1)
+        };
+        for _ in 0..500 {
+            let length = document.text().chars().count();
+            let start = next(length + 1);
+            let end = (start + next(4)).min(length);
+            let text = pieces[next(pieces.len())];
+            document.edit(&edit(start, end, text));
+            assert_same(&document);
+        }
+    }
+}
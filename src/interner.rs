@@ -0,0 +1,257 @@
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use core::cmp::Ordering;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, OnceLock};
+
+// 驻留的名字：相同的字符串只保存一份，比较与散列时只需比较地址
+// 字符串由引用它的名字共同持有，最后一个名字（通常随一次编译的AST与符号表）释放时随之释放；
+// 进程内全局的驻留表只保存弱引用，失效的表项在表增长时清理，因此长时间运行的LSP与watch不会累积内存
+#[derive(Clone)]
+pub struct Name {
+    text: Arc<str>,
+}
+
+// 表项个数至少达到这个值才清理失效的表项
+const MIN_PRUNE_LEN: usize = 64;
+
+struct Interner {
+    #[cfg(feature = "std")]
+    names: HashMap<Box<str>, Weak<str>>,
+    #[cfg(not(feature = "std"))]
+    names: BTreeMap<Box<str>, Weak<str>>,
+    // 表项个数达到这个值时清理失效的表项，清理后取存活个数的两倍，使清理的代价均摊为常数
+    prune_at: usize,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner {
+            names: Default::default(),
+            prune_at: MIN_PRUNE_LEN,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    let mut interner = INTERNER
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    f(&mut interner)
+}
+
+// 没有std时以自旋锁保护驻留表
+#[cfg(not(feature = "std"))]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::{self, AtomicBool};
+
+    struct Lock {
+        locked: AtomicBool,
+        interner: UnsafeCell<Interner>,
+    }
+    // SAFETY: interner只在把locked由false置为true的线程中访问，直到该线程以Release把locked置回false，
+    // 因此任何时刻至多有一个线程持有对interner的引用；Interner中的BTreeMap与Weak<str>都是Send的
+    unsafe impl Sync for Lock {}
+
+    static INTERNER: Lock = Lock {
+        locked: AtomicBool::new(false),
+        interner: UnsafeCell::new(Interner {
+            names: BTreeMap::new(),
+            prune_at: MIN_PRUNE_LEN,
+        }),
+    };
+    while INTERNER
+        .locked
+        .compare_exchange_weak(false, true, atomic::Ordering::Acquire, atomic::Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    // SAFETY: 持有锁，没有其他对interner的引用
+    let result = f(unsafe { &mut *INTERNER.interner.get() });
+    INTERNER.locked.store(false, atomic::Ordering::Release);
+    result
+}
+
+impl Name {
+    pub fn new(text: &str) -> Self {
+        with_interner(|interner| interner.intern(text))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Interner {
+    fn intern(&mut self, text: &str) -> Name {
+        if let Some(text) = self.names.get(text).and_then(Weak::upgrade) {
+            return Name { text };
+        }
+        if self.names.len() >= self.prune_at {
+            self.names.retain(|_, weak| weak.strong_count() > 0);
+            self.prune_at = MIN_PRUNE_LEN.max(self.names.len() * 2);
+        }
+        let name = Name { text: text.into() };
+        self.names.insert(text.into(), Arc::downgrade(&name.text));
+        name
+    }
+
+    #[cfg(test)]
+    fn live(&self) -> usize {
+        self.names.values().filter(|weak| weak.strong_count() > 0).count()
+    }
+}
+
+// 同一个字符串在存活期间只有一份，地址相同即名字相同
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.text, &other.text)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.text) as *const u8).hash(state);
+    }
+}
+
+// 按字符串排序，与驻留的顺序无关
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.text.cmp(&other.text)
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        &*self.text == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.text == *other
+    }
+}
+
+impl From<&str> for Name {
+    fn from(text: &str) -> Self {
+        Name::new(text)
+    }
+}
+
+impl From<&Name> for Name {
+    fn from(name: &Name) -> Self {
+        name.clone()
+    }
+}
+
+impl From<&String> for Name {
+    fn from(text: &String) -> Self {
+        Name::new(text)
+    }
+}
+
+impl From<Name> for String {
+    fn from(name: Name) -> Self {
+        name.text.as_ref().into()
+    }
+}
+
+impl Debug for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(&*self.text, f)
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(&*self.text, f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(Name::new(&text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interner::{with_interner, Name};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_intern() {
+        let x = Name::new("interned");
+        let y = Name::new(&String::from("interned"));
+        assert_eq!(x, y);
+        assert!(std::ptr::eq(x.as_str(), y.as_str()));
+        assert_ne!(x, Name::new("other"));
+        assert_eq!(x, "interned");
+        assert_eq!(format!("{} {:?}", x, x), "interned \"interned\"");
+        assert!(Name::new("a") < Name::new("b") && Name::new("b") > Name::new("a"));
+
+        let mut map = HashMap::new();
+        map.insert(x, 1);
+        assert_eq!(map.get(&Name::from("interned")), Some(&1));
+    }
+
+    #[test]
+    fn test_release() {
+        // 名字全部释放后字符串随之释放，重复驻留不会使驻留表无限增长
+        for i in 0..1000 {
+            let name = Name::new(&format!("released_{}", i));
+            assert_eq!(name, Name::new(&format!("released_{}", i)));
+        }
+        let kept = Name::new("kept");
+        let (len, live) = with_interner(|interner| (interner.names.len(), interner.live()));
+        assert!(len < 1000, "{} entries", len);
+        assert!(live >= 1);
+        assert_eq!(kept, Name::new("kept"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::ast::{BlockStatement, CallExpression, Expr, FunctionStatement, Identifier, Node, Program, Stmt};
+use crate::interner::Name;
+use crate::optimizer;
+use crate::token::{Span, TokenType};
+use crate::vm;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+use std::rc::Rc;
+
+// 函数调用默认的最大嵌套深度，超过时报错而不是耗尽Rust的栈
+// 每一层调用在debug构建中占用十几KB的栈
+pub const DEFAULT_CALL_LIMIT: usize = 100;
+
+// 直接遍历AST执行程序，不经过代码生成与虚拟机
+// 运算的语义与虚拟机一致：整数运算溢出时回绕，比较的结果为1或0，条件以非0为真
+// 函数在定义语句执行之后才能调用
+pub struct Interpreter<R, W> {
+    pub variables: HashMap<Name, i32>,
+    // 以var声明的数组，元素初值为0
+    pub arrays: HashMap<Name, Vec<i32>>,
+    functions: HashMap<Name, Rc<Function>>,
+    frames: Vec<Frame>,
+    // 执行return之后的返回值，直到函数调用结束
+    returned: Option<i32>,
+    pub input: R,
+    pub output: W,
+    // 最多执行的语句数，用于终止死循环
+    pub step_limit: Option<usize>,
+    steps: usize,
+    pub call_limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+struct Function {
+    definition: FunctionStatement,
+    // 参数与局部变量，在定义时根据已存在的全局变量确定
+    locals: HashSet<Name>,
+}
+
+struct Frame {
+    function: Rc<Function>,
+    variables: HashMap<Name, i32>,
+}
+
+impl Interpreter<BufReader<Stdin>, Stdout> {
+    // 使用标准输入输出
+    pub fn with_stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            functions: HashMap::new(),
+            frames: vec![],
+            returned: None,
+            input,
+            output,
+            step_limit: None,
+            steps: 0,
+            call_limit: DEFAULT_CALL_LIMIT,
+        }
+    }
+
+    pub fn with_step_limit(mut self, limit: usize) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    pub fn with_call_limit(mut self, limit: usize) -> Self {
+        self.call_limit = limit;
+        self
+    }
+
+    // 已执行的语句数
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.execute_all(&program.statements)?;
+        self.output.flush().map_err(|err| RuntimeError {
+            span: program.span,
+            message: err.to_string(),
+        })
+    }
+
+    fn execute_all(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for stmt in statements {
+            self.execute(stmt)?;
+            if self.returned.is_some() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_block(&mut self, block: &BlockStatement) -> Result<(), RuntimeError> {
+        self.execute_all(&block.statements)
+    }
+
+    // 执行一条语句
+    pub fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let error = |message: String| RuntimeError {
+            span: stmt.span(),
+            message,
+        };
+        self.steps += 1;
+        if let Some(limit) = self.step_limit {
+            if self.steps > limit {
+                return Err(error(format!("step limit of {} exceeded", limit)));
+            }
+        }
+        match stmt {
+            Stmt::Read(read) => {
+                let value = vm::read_integer(&mut self.input).map_err(error)?;
+                *self.variable(&read.name) = value;
+            }
+            Stmt::Write(write) => {
+                let text = match &write.value {
+                    Expr::String(string) => string.value.clone(),
+                    value => self.evaluate(value)?.to_string(),
+                };
+                writeln!(self.output, "{}", text).map_err(|err| error(err.to_string()))?;
+            }
+            Stmt::Assign(assign) => {
+                let value = self.evaluate(&assign.value)?;
+                match &assign.index {
+                    Some(index) => *self.element(&assign.name, index)? = value,
+                    None => *self.variable(&assign.name) = value,
+                }
+            }
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    if let Some(size) = &declaration.size {
+                        if !self.frames.is_empty() {
+                            return Err(error("arrays can only be declared at the top level".to_string()));
+                        }
+                        self.arrays
+                            .insert(declaration.name.value.clone(), vec![0; size.value.max(0) as usize]);
+                    }
+                }
+            }
+            Stmt::If(if_stmt) => {
+                if self.evaluate(&if_stmt.cond)? != 0 {
+                    self.execute_block(&if_stmt.consequence)?;
+                } else if let Some(alternative) = &if_stmt.alternative {
+                    self.execute_block(alternative)?;
+                }
+            }
+            Stmt::Repeat(repeat) => loop {
+                self.execute_block(&repeat.consequence)?;
+                if self.returned.is_some() || self.evaluate(&repeat.cond)? != 0 {
+                    break;
+                }
+            },
+            Stmt::While(while_stmt) => {
+                while self.evaluate(&while_stmt.cond)? != 0 {
+                    self.execute_block(&while_stmt.consequence)?;
+                    if self.returned.is_some() {
+                        break;
+                    }
+                }
+            }
+            Stmt::Function(function) => {
+                if !self.frames.is_empty() {
+                    return Err(error("functions can only be defined at the top level".to_string()));
+                }
+                let mut locals: HashSet<_> = function
+                    .locals(|name| self.variables.contains_key(name) || self.arrays.contains_key(name))
+                    .into_iter()
+                    .collect();
+                locals.extend(function.params.iter().map(|param| param.value.clone()));
+                let definition = function.clone();
+                self.functions
+                    .insert(function.name.value.clone(), Rc::new(Function { definition, locals }));
+            }
+            Stmt::Return(ret) => {
+                if self.frames.is_empty() {
+                    return Err(error("return outside of a function".to_string()));
+                }
+                let value = match &ret.value {
+                    Some(value) => self.evaluate(value)?,
+                    None => 0,
+                };
+                self.returned = Some(value);
+            }
+            Stmt::Call(call) => {
+                self.call(&call.call)?;
+            }
+            Stmt::Error(stmt) => {
+                return Err(error(format!(
+                    "cannot run a program with syntax errors: {}",
+                    stmt.message
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // 计算表达式的值
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<i32, RuntimeError> {
+        match expr {
+            Expr::Prefix(prefix) => {
+                let right = self.evaluate(&prefix.right)?;
+                match prefix.op.token_type {
+                    TokenType::Minus => Ok(right.wrapping_neg()),
+                    TokenType::Not => Ok((right == 0) as i32),
+                    token_type => Err(RuntimeError {
+                        span: prefix.op.span,
+                        message: format!("token type {:?} is not prefix operator", token_type),
+                    }),
+                }
+            }
+            // 短路求值，与编译器生成的代码一致
+            Expr::Infix(infix) if infix.op.token_type.is_logical() => {
+                let left = self.evaluate(&infix.left)?;
+                match infix.op.token_type {
+                    TokenType::And if left == 0 => Ok(left),
+                    TokenType::Or if left != 0 => Ok(left),
+                    _ => self.evaluate(&infix.right),
+                }
+            }
+            Expr::Infix(infix) => {
+                let left = self.evaluate(&infix.left)?;
+                let right = self.evaluate(&infix.right)?;
+                if infix.op.token_type == TokenType::Divide && right == 0 {
+                    return Err(RuntimeError {
+                        span: infix.span,
+                        message: "division by zero".to_string(),
+                    });
+                }
+                optimizer::eval_infix(infix.op.token_type, left, right).ok_or_else(|| RuntimeError {
+                    span: infix.op.span,
+                    message: format!("token type {:?} is not infix operator", infix.op.token_type),
+                })
+            }
+            Expr::Index(index) => self.element(&index.name, &index.index).map(|element| *element),
+            Expr::Call(call) => self.call(call),
+            Expr::Identifier(ident) => self.look_up(ident),
+            Expr::Number(number) => Ok(number.value),
+            Expr::Boolean(boolean) => Ok(boolean.value as i32),
+            Expr::String(string) => Err(RuntimeError {
+                span: string.span,
+                message: format!("string \"{}\" can only be written", string.value),
+            }),
+            Expr::Error(error) => Err(RuntimeError {
+                span: error.span,
+                message: format!("cannot run a program with syntax errors: {}", error.message),
+            }),
+        }
+    }
+
+    // 数组元素name[index]，下标越界时报错
+    fn element(&mut self, name: &Identifier, index: &Expr) -> Result<&mut i32, RuntimeError> {
+        let position = self.evaluate(index)?;
+        // 局部变量遮蔽同名的数组
+        let shadowed = matches!(self.frames.last(), Some(frame) if frame.function.locals.contains(&name.value));
+        let array = self
+            .arrays
+            .get_mut(&name.value)
+            .filter(|_| !shadowed)
+            .ok_or_else(|| RuntimeError {
+                span: name.span,
+                message: format!("`{}` is not an array", name.value),
+            })?;
+        let size = array.len();
+        usize::try_from(position)
+            .ok()
+            .and_then(|position| array.get_mut(position))
+            .ok_or_else(|| RuntimeError {
+                span: index.span(),
+                message: format!(
+                    "index {} is out of bounds for array `{}` of size {}",
+                    position, name.value, size
+                ),
+            })
+    }
+
+    // 调用函数并返回它的返回值，执行到函数末尾时返回0
+    fn call(&mut self, call: &CallExpression) -> Result<i32, RuntimeError> {
+        let error = |message: String| RuntimeError {
+            span: call.span,
+            message,
+        };
+        let function = self
+            .functions
+            .get(&call.name.value)
+            .cloned()
+            .ok_or_else(|| error(format!("function `{}` is not defined", call.name.value)))?;
+        let params = &function.definition.params;
+        if params.len() != call.args.len() {
+            return Err(error(format!(
+                "function `{}` takes {} arguments, found {}",
+                call.name.value,
+                params.len(),
+                call.args.len()
+            )));
+        }
+        if self.frames.len() >= self.call_limit {
+            return Err(error(format!("call depth of {} exceeded", self.call_limit)));
+        }
+        let mut variables = HashMap::new();
+        for (param, arg) in params.iter().zip(&call.args) {
+            variables.insert(param.value.clone(), self.evaluate(arg)?);
+        }
+        self.frames.push(Frame {
+            function: function.clone(),
+            variables,
+        });
+        let result = self.execute_block(&function.definition.body);
+        self.frames.pop();
+        let value = self.returned.take().unwrap_or(0);
+        result.map(|_| value)
+    }
+
+    // 赋值或read的目标变量，函数中的局部变量保存在当前的栈帧中
+    fn variable(&mut self, ident: &Identifier) -> &mut i32 {
+        match self.frames.last_mut() {
+            Some(frame) if frame.function.locals.contains(&ident.value) => {
+                frame.variables.entry(ident.value.clone()).or_default()
+            }
+            _ => self.variables.entry(ident.value.clone()).or_default(),
+        }
+    }
+
+    fn look_up(&self, ident: &Identifier) -> Result<i32, RuntimeError> {
+        let variables = match self.frames.last() {
+            Some(frame) if frame.function.locals.contains(&ident.value) => &frame.variables,
+            _ => &self.variables,
+        };
+        variables.get(&ident.value).copied().ok_or_else(|| RuntimeError {
+            span: ident.span,
+            message: format!(
+                "variable `{}` used before assignment at line {}",
+                ident.value, ident.span.line
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+
+    fn run(input: &str, stdin: &str) -> Result<String, String> {
+        let program = Parser::new(input).parse_program().unwrap();
+        let mut interpreter = Interpreter::new(stdin.as_bytes(), vec![]).with_step_limit(1000);
+        interpreter.run(&program).map_err(|err| err.to_string())?;
+        Ok(String::from_utf8(interpreter.output).unwrap())
+    }
+
+    #[test]
+    fn test_run() {
+        let input = "
+read x;
+fact := 1;
+repeat
+    fact := fact * x;
+    x := x - 1
+until x = 0;
+write fact;
+while fact > 100 do
+    fact := fact / 10
+end;
+if fact <> 12 then y := 0 else y := -fact end;
+write y;
+write \"done\";
+write y * 2;
+var a[3];
+a[2] := y;
+a[a[1]] := a[2] + 1;
+write a[0]";
+        assert_eq!(run(input, "5").unwrap(), "120\n-12\ndone\n-24\n-11\n");
+    }
+
+    #[test]
+    fn test_logical() {
+        let input = "
+read x;
+if x <> 0 and 10 / x > 2 then write 1 else write 0 end;
+if x = 0 or not 10 / x < 3 then write 2 end;
+while true and x < 3 do x := x + 1 end;
+write x";
+        assert_eq!(run(input, "0").unwrap(), "0\n2\n3\n");
+        assert_eq!(run(input, "5").unwrap(), "0\n5\n");
+    }
+
+    #[test]
+    fn test_function() {
+        let input = "
+calls := 0;
+fn fib(n)
+    calls := calls + 1;
+    if n < 2 then return n end;
+    a := fib(n - 1);
+    return a + fib(n - 2)
+end;
+fn show(x) write x end;
+read n;
+show(fib(n));
+write calls;
+write show(1)";
+        assert_eq!(run(input, "10").unwrap(), "55\n177\n1\n0\n");
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        assert_eq!(run("read x; y := 1 / x;", "0").unwrap_err(), "1:14: division by zero");
+        assert_eq!(
+            run("write y;", "").unwrap_err(),
+            "1:7: variable `y` used before assignment at line 1"
+        );
+        assert_eq!(run("read x;", "").unwrap_err(), "1:1: unexpected end of input");
+        assert_eq!(
+            run("var a[2]; read i; a[i] := 1;", "2").unwrap_err(),
+            "1:21: index 2 is out of bounds for array `a` of size 2"
+        );
+        assert_eq!(
+            run("fn f(n) return f(n) end; write f(1)", "").unwrap_err(),
+            "1:16: call depth of 100 exceeded"
+        );
+        assert_eq!(
+            run("x := 0; repeat x := x + 1 until 0", "").unwrap_err(),
+            "1:16: step limit of 1000 exceeded"
+        );
+    }
+}
@@ -1,73 +1,417 @@
-use crate::token::{self, Token, TokenType};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{self, Span, Token, TokenType};
+use alloc::borrow::Cow;
+use core::fmt::{Display, Formatter};
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, BufReader, Read};
 
-pub struct Lexer {
-    input: Vec<char>,
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+pub struct Lexer<'a> {
+    // 尚未处理的输入，以及当前token之前已处理的一部分
+    // 由 Lexer::new 创建时借用整个源程序，不做复制；从输入流读取时为逐行追加的缓冲区
+    input: Cow<'a, str>,
+    // 下一个字符在input中的字节下标
+    index: usize,
+    // 最近一个被消耗的字符在源程序中的偏移量
     pos: i32,
+    // 已加入input的字符在源程序中的结束偏移量
+    end: usize,
+    // 每一行第一个字符的偏移量，用于计算token的行号与列号
+    line_starts: Vec<usize>,
+    // 作为迭代器使用时，是否已经产生过Eof
+    finished: bool,
+    // 逐行读取源程序的输入流，读完或出错后为None
+    #[cfg(feature = "std")]
+    source: Option<Box<dyn BufRead>>,
+    // 已从source读取的字节数，不含BOM，用于报告非法UTF-8字节的位置
+    #[cfg(feature = "std")]
+    bytes_read: usize,
+    // 是否产生注释token，默认跳过注释
+    comments: bool,
+    warnings: Vec<String>,
+    #[cfg(feature = "std")]
+    error: Option<io::Error>,
+    errors: Vec<LexError>,
+}
+
+// 不复制字面量的token，literal为输入中的切片，在下一次读取token之前有效
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lexeme<'s> {
+    pub token_type: TokenType,
+    pub literal: &'s str,
+    pub span: Span,
+}
+
+impl Lexeme<'_> {
+    pub fn to_token(self) -> Token {
+        Token {
+            token_type: self.token_type,
+            literal: self.literal.to_string(),
+            span: self.span,
+        }
+    }
+}
+
+// 词法错误，如未闭合的注释；无法识别的字符与未闭合的字符串以TokenType::Illegal交给语法分析器报告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LexError {}
+
+impl LexError {
+    pub(crate) fn unterminated_comment(span: Span) -> Self {
+        LexError {
+            span,
+            message: format!(
+                "unterminated comment starting at line {}, column {}",
+                span.line, span.column
+            ),
+        }
+    }
+}
+
+// 将源文件的字节解码为文本：去除UTF-8 BOM，将\r\n与单独的\r统一为\n
+// 非法的UTF-8字节以U+FFFD替代，并返回相应的诊断信息
+pub fn decode_source(bytes: &[u8]) -> (String, Vec<String>) {
+    let bytes = bytes.strip_prefix(BOM).unwrap_or(bytes);
+    let mut diagnostics = vec![];
+    if let Err(err) = core::str::from_utf8(bytes) {
+        diagnostics.push(invalid_utf8(err.valid_up_to()));
+    }
+    (decode_text(bytes), diagnostics)
 }
 
-impl Lexer {
-    pub fn new(input: &str) -> Self {
+fn invalid_utf8(offset: usize) -> String {
+    format!(
+        "source is not valid UTF-8 (first invalid byte at offset {}), invalid bytes were replaced with U+FFFD",
+        offset
+    )
+}
+
+fn decode_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).replace("\r\n", "\n").replace('\r', "\n")
+}
+
+// 对整个源程序做词法分析，最后一个token为Eof
+pub fn tokenize(source: &str) -> Vec<Token> {
+    Lexer::new(source).collect()
+}
+
+// 以教科书中TINY扫描器的格式列出token，每个token一行："\t行号: 描述"
+// 保留字为 "reserved word: read"，标识符与数为 "ID, name= x" 与 "NUM, val= 10"，
+// 无法识别的字符为 "ERROR: @"，其余为token本身
+pub fn format_tokens(tokens: &[Token]) -> String {
+    let mut output = String::new();
+    for token in tokens {
+        let text = match token.token_type {
+            TokenType::Eof => "EOF".to_string(),
+            TokenType::Illegal => format!("ERROR: {}", token.literal),
+            TokenType::Ident => format!("ID, name= {}", token.literal),
+            TokenType::Number => format!("NUM, val= {}", token.literal),
+            TokenType::String => format!("STRING, val= {}", token.literal),
+            token_type if token_type.is_keyword() => format!("reserved word: {}", token.literal),
+            _ => token.literal.clone(),
+        };
+        output.push_str(&format!("\t{}: {}\n", token.span.line, text));
+    }
+    output
+}
+
+impl<'a> Lexer<'a> {
+    // input可以是借用的源程序，也可以是String
+    pub fn new(input: impl Into<Cow<'a, str>>) -> Self {
+        let input = input.into();
+        let mut lexer = Self::empty();
+        if input.starts_with('\u{feff}') {
+            lexer.index = '\u{feff}'.len_utf8();
+        }
+        lexer.add_lines(&input[lexer.index..]);
+        lexer.input = input;
+        lexer
+    }
+
+    // input为源程序从position起点处开始的部分，产生的token的位置相对于整个源程序
+    // position必须位于token之间，不能在注释或字符串之中
+    pub fn starting_at(input: &'a str, position: Span) -> Self {
+        let mut lexer = Self::empty();
+        lexer.pos = position.start as i32 - 1;
+        lexer.end = position.start;
+        // 只需要知道当前行的起点，之前各行的起点不会被用到
+        lexer.line_starts = vec![0; position.line - 1];
+        lexer.line_starts.push(position.start + 1 - position.column);
+        lexer.add_lines(input);
+        lexer.input = Cow::Borrowed(input);
+        lexer
+    }
+
+    // 从输入流逐行读取源程序，不需要事先将整个文件读入内存
+    // 与 decode_source 一样处理BOM、CRLF换行以及非法的UTF-8字节，后者见 Lexer::take_warnings
+    // 读取出错时当作输入在此结束，错误见 Lexer::take_error
+    #[cfg(feature = "std")]
+    pub fn from_buf_read(reader: impl BufRead + 'static) -> Self {
         Self {
-            input: Self::remove_comment(input).chars().collect(),
+            source: Some(Box::new(reader)),
+            ..Self::empty()
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        Self::from_buf_read(BufReader::new(reader))
+    }
+
+    fn empty() -> Self {
+        Self {
+            input: Cow::Owned(String::new()),
+            index: 0,
             pos: -1,
+            end: 0,
+            line_starts: vec![0],
+            finished: false,
+            #[cfg(feature = "std")]
+            source: None,
+            #[cfg(feature = "std")]
+            bytes_read: 0,
+            comments: false,
+            warnings: vec![],
+            #[cfg(feature = "std")]
+            error: None,
+            errors: vec![],
+        }
+    }
+
+    // 将注释作为TokenType::Comment产生，而不是跳过
+    pub fn with_comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    // 读取输入流时产生的警告，取出后清空
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        core::mem::take(&mut self.warnings)
+    }
+
+    // 读取输入流时发生的错误
+    #[cfg(feature = "std")]
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // 到目前为止发现的词法错误，取出后清空
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        core::mem::take(&mut self.errors)
+    }
+
+    // 记录紧接在已有输入之后的text中每一行的起点
+    fn add_lines(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.end += 1;
+            if ch == '\n' {
+                self.line_starts.push(self.end);
+            }
+        }
+    }
+
+    // 从输入流读取下一行，没有更多输入时返回false
+    #[cfg(feature = "std")]
+    fn refill(&mut self) -> bool {
+        let Some(source) = &mut self.source else {
+            return false;
+        };
+        let mut bytes = vec![];
+        match source.read_until(b'\n', &mut bytes) {
+            Ok(0) => {
+                self.source = None;
+                false
+            }
+            Ok(_) => {
+                let bytes = match self.bytes_read {
+                    0 => bytes.strip_prefix(BOM).unwrap_or(&bytes),
+                    _ => &bytes,
+                };
+                // 换行符不会出现在多字节字符的中间，逐行检查即可
+                if let (Err(err), true) = (core::str::from_utf8(bytes), self.warnings.is_empty()) {
+                    self.warnings.push(invalid_utf8(self.bytes_read + err.valid_up_to()));
+                }
+                self.bytes_read += bytes.len();
+                let text = decode_text(bytes);
+                self.add_lines(&text);
+                self.input.to_mut().push_str(&text);
+                true
+            }
+            Err(err) => {
+                self.error = Some(err);
+                self.source = None;
+                false
+            }
         }
     }
 
+    // 没有std时只能分析内存中的源程序
+    #[cfg(not(feature = "std"))]
+    fn refill(&mut self) -> bool {
+        false
+    }
+
     pub fn next_token(&mut self) -> Token {
+        self.next_lexeme().to_token()
+    }
+
+    // 与 Lexer::next_token 相同，但不为字面量分配内存
+    pub fn next_lexeme(&mut self) -> Lexeme<'_> {
+        let (token_type, literal, span) = loop {
+            let lexeme = self.read_lexeme();
+            if lexeme.0 != TokenType::Comment || self.comments {
+                break lexeme;
+            }
+        };
+        Lexeme {
+            token_type,
+            literal: &self.input[literal],
+            span,
+        }
+    }
+
+    // 读取下一个token或注释，返回它的类型、字面量在input中的范围与位置
+    fn read_lexeme(&mut self) -> (TokenType, Range<usize>, Span) {
         self.consume_spaces();
+        // 丢弃当前token之前的输入，超过一半时才移动，使得每个字节平均只移动常数次
+        // 借用的源程序不需要丢弃
+        if let Cow::Owned(input) = &mut self.input {
+            if self.index * 2 > input.len() {
+                input.drain(..self.index);
+                self.index = 0;
+            }
+        }
+        let start = (self.pos + 1) as usize;
+        let (token_type, literal) = self.read_token();
+        let end = (self.pos + 1) as usize;
+        let line = self.line_starts.partition_point(|line_start| *line_start <= start);
+        let span = Span {
+            line,
+            column: start - self.line_starts[line - 1] + 1,
+            start,
+            end,
+        };
+        let text = &self.input[literal.clone()];
+        // 注释在花括号配对时结束，否则一直延续到输入结束
+        if token_type == TokenType::Comment && text.matches('{').count() > text.matches('}').count() {
+            self.errors.push(LexError::unterminated_comment(span));
+        }
+        trace!("token {:?} `{}` at {}", token_type, text, span);
+        (token_type, literal, span)
+    }
+
+    fn read_token(&mut self) -> (TokenType, Range<usize>) {
+        let start = self.index;
+        if self.at_end() {
+            return (TokenType::Eof, start..start);
+        }
         let ch = self.next_char();
-        match ch {
-            ';' => Token::new(TokenType::SemiColon, ";"),
+        let token_type = match ch {
+            ';' => TokenType::SemiColon,
+            '.' => TokenType::Dot,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
+            '(' => TokenType::LeftParen,
+            ')' => TokenType::RightParen,
+            ',' => TokenType::Comma,
             '<' => {
                 if self.peek_char() == '=' {
                     self.next_char();
-                    Token::new(TokenType::EqualLessThan, "<=")
+                    TokenType::EqualLessThan
+                } else if self.peek_char() == '>' {
+                    self.next_char();
+                    TokenType::NotEqual
                 } else {
-                    Token::new(TokenType::LessThan, "<")
+                    TokenType::LessThan
                 }
             }
-            '=' => Token::new(TokenType::Equal, "="),
-            ':' => {
+            '>' => {
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    TokenType::EqualGreaterThan
+                } else {
+                    TokenType::GreaterThan
+                }
+            }
+            '!' if self.peek_char() == '=' => {
                 self.next_char();
-                Token::new(TokenType::Assign, ":=")
+                TokenType::NotEqual
+            }
+            '=' => TokenType::Equal,
+            ':' if self.peek_char() == '=' => {
+                self.next_char();
+                TokenType::Assign
+            }
+            '*' => TokenType::Mul,
+            '-' => TokenType::Minus,
+            '+' => TokenType::Add,
+            '/' => TokenType::Divide,
+            '{' => {
+                self.read_comment();
+                TokenType::Comment
             }
-            '*' => Token::new(TokenType::Mul, "*"),
-            '-' => Token::new(TokenType::Minus, "-"),
-            '+' => Token::new(TokenType::Add, "+"),
-            '/' => Token::new(TokenType::Divide, "/"),
             '"' => {
-                let literal = self.read_string();
-                Token::new(TokenType::String, &literal)
-            }
-            '\0' => Token::new(TokenType::Eof, ""),
-            _ => {
-                if Self::is_letter(ch) {
-                    let literal = self.read_identifier();
-                    Token::new(token::look_up_keywords(&literal), &literal)
-                } else if Self::is_digit(ch) {
-                    let literal = self.read_number();
-                    Token::new(TokenType::Number, &literal)
-                } else {
-                    Token::new(TokenType::Illegal, "")
-                }
+                return match self.read_string() {
+                    Some(literal) => (TokenType::String, literal),
+                    // 未闭合的字符串只保留开头的引号，用于错误信息
+                    None => (TokenType::Illegal, start..start + 1),
+                };
+            }
+            _ if Self::is_letter(ch) => {
+                self.skip_while(Self::is_letter);
+                token::look_up_keywords(&self.input[start..self.index])
+            }
+            _ if Self::is_digit(ch) => {
+                self.skip_while(Self::is_digit);
+                TokenType::Number
+            }
+            // 保留无法识别的字符，包括NUL，用于错误信息
+            _ => TokenType::Illegal,
+        };
+        (token_type, start..self.index)
+    }
+
+    // 输入是否已经读完；以位置判断，输入中的NUL是普通的字符
+    pub fn at_end(&mut self) -> bool {
+        while self.index >= self.input.len() {
+            if !self.refill() {
+                return true;
             }
         }
+        false
     }
 
-    pub fn peek_char(&self) -> char {
-        if self.pos as usize == self.input.len() - 1 {
-            0 as char
-        } else {
-            self.input[(self.pos + 1) as usize]
+    // 输入结束时返回'\0'，与输入中的NUL字符以 at_end 区分
+    pub fn peek_char(&mut self) -> char {
+        if self.at_end() {
+            return '\0';
         }
+        self.input[self.index..].chars().next().unwrap_or('\0')
     }
 
     pub fn next_char(&mut self) -> char {
-        let next = self.peek_char();
-        if next != 0 as char {
-            self.pos += 1;
+        if self.at_end() {
+            return '\0';
         }
-        return next;
+        let next = self.peek_char();
+        self.pos += 1;
+        self.index += next.len_utf8();
+        next
     }
 
     pub fn consume_spaces(&mut self) {
@@ -81,77 +425,320 @@ impl Lexer {
     }
 
     fn is_letter(ch: char) -> bool {
-        ch >= 'a' && ch <= 'z' || ch >= 'A' && ch <= 'Z'
+        ch.is_ascii_lowercase() || ch.is_ascii_uppercase()
     }
 
     fn is_digit(ch: char) -> bool {
-        ch >= '0' && ch <= '9'
+        ch.is_ascii_digit()
     }
 
-    fn read_identifier(&mut self) -> String {
-        self.pos -= 1;
-        let mut output = String::new();
-        loop {
-            let ch = self.peek_char();
-            if Self::is_letter(ch) {
-                output.push(ch);
-                self.next_char();
-            } else {
-                break;
-            }
+    fn skip_while(&mut self, predicate: fn(char) -> bool) {
+        while predicate(self.peek_char()) {
+            self.next_char();
         }
-        output
     }
 
-    fn read_number(&mut self) -> String {
-        self.pos -= 1;
-        let mut output = String::new();
-        loop {
-            let ch = self.peek_char();
-            if Self::is_digit(ch) {
-                output.push(ch);
-                self.next_char();
-            } else {
-                break;
+    // 读取到与开头的{配对的}为止，注释可以嵌套，如 { a { b } c }
+    // 注释包括花括号在内；未闭合的注释延续到输入结束
+    fn read_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 && !self.at_end() {
+            match self.next_char() {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
             }
         }
-        output
     }
 
-    pub fn read_string(&mut self) -> String {
-        let mut output = String::new();
+    // 读取到闭合的引号为止，返回引号之间的内容在input中的范围
+    // 字符串不能跨行，遇到换行或输入结束时返回None，并停在该位置
+    fn read_string(&mut self) -> Option<Range<usize>> {
+        let start = self.index;
         loop {
-            let ch = self.next_char();
-            if ch == '"' {
-                break;
+            if self.at_end() {
+                return None;
+            }
+            match self.peek_char() {
+                '"' => {
+                    let end = self.index;
+                    self.next_char();
+                    return Some(start..end);
+                }
+                '\n' => return None,
+                _ => {
+                    self.next_char();
+                }
             }
-            output.push(ch)
         }
-        output
     }
+}
 
-    // 去除输入中的注释部分
-    fn remove_comment(input: &str) -> String {
-        let mut output = String::new();
-        let mut in_comment = false;
-        for ch in input.chars() {
-            if ch == '{' {
-                in_comment = true;
-            } else if ch == '}' {
-                in_comment = false;
-            } else if !in_comment {
-                output.push(ch);
-            }
+// 依次产生所有token，最后一个为Eof
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
         }
-        return output;
+        let token = self.next_token();
+        self.finished = token.token_type == TokenType::Eof;
+        Some(token)
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::token::TokenType;
+    #[cfg(feature = "std")]
+    use std::io;
+
+    use super::{decode_source, format_tokens, tokenize, Lexer};
+
+    #[test]
+    fn test_decode_source() {
+        let (text, diagnostics) = decode_source(b"\xEF\xBB\xBFread x;\r\nwrite x;\r");
+        assert_eq!(text, "read x;\nwrite x;\n");
+        assert!(diagnostics.is_empty());
+
+        let (text, diagnostics) = decode_source(b"write \xFFx;");
+        assert_eq!(text, "write \u{fffd}x;");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].contains("offset 6"));
+    }
 
-    use super::Lexer;
+    #[test]
+    fn test_iterator() {
+        let types: Vec<_> = Lexer::new("read x; write x").map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Read,
+                TokenType::Ident,
+                TokenType::SemiColon,
+                TokenType::Write,
+                TokenType::Ident,
+                TokenType::Eof
+            ]
+        );
+        let mut l = Lexer::new("").peekable();
+        assert_eq!(l.peek().map(|token| token.token_type), Some(TokenType::Eof));
+        assert_eq!(l.count(), 1);
+    }
+
+    #[test]
+    fn test_format_tokens() {
+        let tokens = tokenize("{ sample }\nread x;\nif 0 < x then\n  write \"hi\" @\nend");
+        assert_eq!(tokens.len(), 13);
+        assert_eq!(
+            format_tokens(&tokens),
+            "\t2: reserved word: read
+\t2: ID, name= x
+\t2: ;
+\t3: reserved word: if
+\t3: NUM, val= 0
+\t3: <
+\t3: ID, name= x
+\t3: reserved word: then
+\t4: reserved word: write
+\t4: STRING, val= hi
+\t4: ERROR: @
+\t5: reserved word: end
+\t5: EOF
+"
+        );
+    }
+
+    #[test]
+    fn test_comparison() {
+        let tokens: Vec<_> = Lexer::new("< <= > >= = <> !=")
+            .map(|token| (token.token_type, token.literal))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::LessThan, "<".to_string()),
+                (TokenType::EqualLessThan, "<=".to_string()),
+                (TokenType::GreaterThan, ">".to_string()),
+                (TokenType::EqualGreaterThan, ">=".to_string()),
+                (TokenType::Equal, "=".to_string()),
+                (TokenType::NotEqual, "<>".to_string()),
+                (TokenType::NotEqual, "!=".to_string()),
+                (TokenType::Eof, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_illegal() {
+        let mut l = Lexer::new("x := 1;\n  y @ : 2");
+        let token = l.by_ref().find(|token| token.token_type == TokenType::Illegal).unwrap();
+        assert_eq!(
+            (token.literal.as_str(), token.span.to_string()),
+            ("@", "2:5".to_string())
+        );
+        let token = l.next().unwrap();
+        assert_eq!((token.token_type, token.literal.as_str()), (TokenType::Illegal, ":"));
+
+        // 输入中的NUL是无法识别的字符，不是输入的结束
+        let tokens: Vec<_> = Lexer::new("x\0 y { \0 } \"a\0\"")
+            .map(|token| (token.token_type, token.literal, token.span.start))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::Ident, "x".to_string(), 0),
+                (TokenType::Illegal, "\0".to_string(), 1),
+                (TokenType::Ident, "y".to_string(), 3),
+                (TokenType::String, "a\0".to_string(), 11),
+                (TokenType::Eof, "".to_string(), 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let tokens: Vec<_> = Lexer::new("write \"hello, world\";\nwrite \"oops\nx")
+            .map(|token| (token.token_type, token.literal))
+            .collect();
+        assert_eq!(
+            tokens[1..],
+            [
+                (TokenType::String, "hello, world".to_string()),
+                (TokenType::SemiColon, ";".to_string()),
+                (TokenType::Write, "write".to_string()),
+                (TokenType::Illegal, "\"".to_string()),
+                (TokenType::Ident, "x".to_string()),
+                (TokenType::Eof, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        let source = "read { a } x; }\nwrite \"{ not a comment }\" { unterminated\n";
+        let types: Vec<_> = Lexer::new(source).map(|token| token.token_type).collect();
+        assert!(!types.contains(&TokenType::Comment));
+        assert_eq!(types[3], TokenType::Illegal);
+
+        let comments: Vec<_> = Lexer::new(source)
+            .with_comments(true)
+            .filter(|token| token.token_type == TokenType::Comment)
+            .map(|token| (token.literal, token.span.to_string(), token.span.end))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                ("{ a }".to_string(), "1:6".to_string(), 10),
+                (
+                    "{ unterminated\n".to_string(),
+                    "2:27".to_string(),
+                    source.chars().count()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_comment() {
+        let mut l = Lexer::new("{ a { b } c } read\n  { open { inner }\nx").with_comments(true);
+        let tokens: Vec<_> = l.by_ref().map(|token| (token.token_type, token.literal)).collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::Comment, "{ a { b } c }".to_string()),
+                (TokenType::Read, "read".to_string()),
+                (TokenType::Comment, "{ open { inner }\nx".to_string()),
+                (TokenType::Eof, "".to_string()),
+            ]
+        );
+        let errors = l.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "2:3: unterminated comment starting at line 2, column 3"
+        );
+        assert!(Lexer::new("{ a } }").take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_lexeme() {
+        let source = "read αβ; { c } write \"s\" x10";
+        let mut l = Lexer::new(source).with_comments(true);
+        let mut lexemes = vec![];
+        loop {
+            let lexeme = l.next_lexeme();
+            // 字面量直接指向源程序
+            assert!(source.as_bytes().as_ptr_range().contains(&lexeme.literal.as_ptr()) || lexeme.literal.is_empty());
+            lexemes.push((lexeme.token_type, lexeme.literal.to_string(), lexeme.span.start));
+            if lexeme.token_type == TokenType::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            lexemes,
+            [
+                (TokenType::Read, "read".to_string(), 0),
+                (TokenType::Illegal, "α".to_string(), 5),
+                (TokenType::Illegal, "β".to_string(), 6),
+                (TokenType::SemiColon, ";".to_string(), 7),
+                (TokenType::Comment, "{ c }".to_string(), 9),
+                (TokenType::Write, "write".to_string(), 15),
+                (TokenType::String, "s".to_string(), 21),
+                (TokenType::Ident, "x".to_string(), 25),
+                (TokenType::Number, "10".to_string(), 26),
+                (TokenType::Eof, "".to_string(), 28),
+            ]
+        );
+        let owned: Vec<_> = Lexer::new(source.to_string()).collect();
+        assert_eq!(owned, tokenize(source));
+    }
+
+    #[test]
+    fn test_bom() {
+        let mut l = Lexer::new("\u{feff}read");
+        assert_eq!(l.next_token().token_type, TokenType::Read);
+        let token = Lexer::new("\u{feff}read".to_string()).next_token();
+        assert_eq!((token.literal.as_str(), token.span.start), ("read", 0));
+    }
+
+    #[test]
+    fn test_span() {
+        let mut l = Lexer::new("read x; { comment }\n  y := 10");
+        let spans: Vec<_> = l
+            .by_ref()
+            .take_while(|token| token.token_type != TokenType::Eof)
+            .map(|token| (token.literal, token.span.to_string(), token.span.end))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("read".to_string(), "1:1".to_string(), 4),
+                ("x".to_string(), "1:6".to_string(), 6),
+                (";".to_string(), "1:7".to_string(), 7),
+                ("y".to_string(), "2:3".to_string(), 23),
+                (":=".to_string(), "2:5".to_string(), 26),
+                ("10".to_string(), "2:8".to_string(), 29),
+            ]
+        );
+        assert_eq!(l.next_token().span.to_string(), "2:10");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_reader() {
+        let source = "read x; { a comment\nover two lines }\r\nwrite \"hi\";\rwrite x";
+        let mut bytes = b"\xEF\xBB\xBF".to_vec();
+        bytes.extend(source.as_bytes());
+        bytes.extend(b" \xFF");
+        let (text, diagnostics) = decode_source(&bytes);
+        let expected: Vec<_> = Lexer::new(&text).collect();
+        let mut l = Lexer::from_reader(io::Cursor::new(bytes));
+        assert_eq!(l.by_ref().collect::<Vec<_>>(), expected);
+        assert_eq!(l.take_warnings(), diagnostics);
+        assert!(l.take_error().is_none());
+    }
 
     #[test]
     pub fn unit_test() {
@@ -1,10 +1,92 @@
-#![feature(downcast_unchecked)]
-#![feature(trait_upcasting)]
+// 关闭std特性时只构建前端与代码生成：token、词法分析、语法分析、AST与TM代码的生成，只依赖core与alloc
+// 单元测试仍链接std，以使用测试框架
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 
+extern crate alloc;
+
+// 日志宏需要在其他模块之前声明
+#[macro_use]
+pub mod logging;
+
+pub mod arena;
 pub mod ast;
+#[cfg(feature = "std")]
+pub mod build;
+#[cfg(feature = "std")]
+pub mod cfg;
 pub mod code;
 pub mod compiler;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod differential;
+#[cfg(feature = "std")]
+pub mod driver;
 pub mod environment;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+pub mod golden;
+#[cfg(feature = "std")]
+pub mod highlight;
+#[cfg(feature = "std")]
+pub mod incremental;
+pub mod interner;
+#[cfg(feature = "std")]
+pub mod interpreter;
 pub mod lexer;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "std")]
+pub mod object;
+pub mod optimizer;
 pub mod parser;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod relocate;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod semantic;
+#[cfg(feature = "std")]
+pub mod source_map;
 pub mod token;
+pub mod token_stream;
+#[cfg(feature = "std")]
+pub mod transpiler;
+#[cfg(feature = "std")]
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+// no_std时std的prelude不可用，前端与代码生成模块从这里引入alloc中的同名类型与宏
+#[cfg(not(feature = "std"))]
+mod prelude {
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec::Vec;
+    pub(crate) use alloc::{format, vec};
+}
+
+#[cfg(feature = "std")]
+pub use driver::{
+    compile, compile_file, compile_files, compile_many, compile_program, compile_to_tm, Artifacts, CompilationOutput,
+    FileError, Options,
+};
+pub use error::CodegenError;
+#[cfg(feature = "std")]
+pub use error::CompileError;
@@ -0,0 +1,413 @@
+use crate::ast::{BlockStatement, CallExpression, Expr, FunctionStatement, Identifier, IndexExpression, Program, Stmt};
+use crate::interner::Name;
+use crate::semantic::{Warning, DIVISION_BY_ZERO};
+use crate::token::Span;
+use std::collections::{HashMap, HashSet};
+
+// 警告的名字，可以通过 --warn 或 tinycc.toml 的 [warnings] 设置级别
+// 赋值或声明之后从未读取的变量、数组与参数
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+// 读取变量时，并非所有的执行路径都已为它赋值，如只在if的一个分支中赋值
+pub const MAYBE_UNINITIALIZED: &str = "maybe-uninitialized";
+// write输出的变量或数组在整个程序中从未被赋值
+pub const NEVER_ASSIGNED: &str = "never-assigned";
+
+// 所有警告的名字，包括语义检查中的 DIVISION_BY_ZERO
+pub const LINTS: [&str; 4] = [DIVISION_BY_ZERO, UNUSED_VARIABLE, MAYBE_UNINITIALIZED, NEVER_ASSIGNED];
+
+// 对通过语义检查的程序做数据流分析，返回按位置排序的警告
+// 函数体中的参数与局部变量单独分析，函数体中读写的全局变量计入全局变量的使用
+// 变量第一次出现时没有赋值属于语义错误，由 semantic::check 报告，这里不再重复
+// predeclared为已有初始值的变量，见 Compiler::preset，它们不会被报告为未使用
+pub fn lint<'a>(program: &Program, predeclared: impl IntoIterator<Item = &'a str>) -> Vec<Warning> {
+    let predeclared: HashSet<Name> = predeclared.into_iter().map(Name::new).collect();
+    let mut linter = Linter {
+        globals: Scope::default(),
+        function: None,
+        assigned: Some(predeclared.clone()),
+        reported: HashSet::new(),
+        writes: vec![],
+        warnings: vec![],
+    };
+    for name in &predeclared {
+        linter.globals.define(name.clone(), None, Kind::Predeclared, true);
+    }
+    for stmt in &program.statements {
+        linter.statement(stmt);
+    }
+    let globals = std::mem::take(&mut linter.globals);
+    linter.finish_scope(&globals, false);
+    linter.warnings.sort_by_key(|warning| warning.span.start);
+    linter.warnings
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Variable,
+    Array,
+    Parameter,
+    Predeclared,
+}
+
+#[derive(Debug)]
+struct Variable {
+    name: Name,
+    // 第一次出现的位置
+    span: Option<Span>,
+    kind: Kind,
+    assigned: bool,
+    read: bool,
+}
+
+// 全局变量或一个函数的参数与局部变量，按第一次出现的顺序
+#[derive(Debug, Default)]
+struct Scope {
+    variables: Vec<Variable>,
+    indices: HashMap<Name, usize>,
+    // 函数的参数与局部变量，全局作用域中为空
+    locals: HashSet<Name>,
+}
+
+impl Scope {
+    fn define(&mut self, name: Name, span: Option<Span>, kind: Kind, assigned: bool) -> &mut Variable {
+        let index = *self.indices.entry(name.clone()).or_insert_with(|| {
+            self.variables.push(Variable {
+                name,
+                span,
+                kind,
+                assigned: false,
+                read: false,
+            });
+            self.variables.len() - 1
+        });
+        let variable = &mut self.variables[index];
+        variable.assigned |= assigned;
+        variable
+    }
+
+    fn get_mut(&mut self, name: &Name) -> Option<&mut Variable> {
+        self.indices.get(name).map(|index| &mut self.variables[*index])
+    }
+}
+
+struct Linter {
+    globals: Scope,
+    // 正在分析的函数体的作用域
+    function: Option<Scope>,
+    // 当前位置上所有执行路径都已赋值的变量，None表示不可达，如return之后
+    assigned: Option<HashSet<Name>>,
+    // 已经报告过 MAYBE_UNINITIALIZED 的变量，同一个变量只报告一次
+    reported: HashSet<Name>,
+    // write直接输出的变量，以及它是否为当前函数的参数或局部变量
+    writes: Vec<(Identifier, bool)>,
+    warnings: Vec<Warning>,
+}
+
+impl Linter {
+    // 名字name所在的作用域：函数的参数与局部变量，或者全局变量
+    fn scope(&mut self, name: &Name) -> &mut Scope {
+        match &mut self.function {
+            Some(function) if function.locals.contains(name) => function,
+            _ => &mut self.globals,
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assign(assign) => {
+                self.expression(&assign.value);
+                match &assign.index {
+                    Some(index) => {
+                        self.expression(index);
+                        self.scope(&assign.name.value).define(
+                            assign.name.value.clone(),
+                            Some(assign.name.span),
+                            Kind::Array,
+                            true,
+                        );
+                    }
+                    None => self.assign(&assign.name),
+                }
+            }
+            Stmt::Read(read) => self.assign(&read.name),
+            Stmt::Write(write) => {
+                if let Expr::Identifier(ident) | Expr::Index(IndexExpression { name: ident, .. }) = &write.value {
+                    let local = self
+                        .function
+                        .as_ref()
+                        .is_some_and(|scope| scope.locals.contains(&ident.value));
+                    self.writes.push((ident.clone(), local));
+                }
+                self.expression(&write.value);
+            }
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    let kind = if declaration.size.is_some() {
+                        Kind::Array
+                    } else {
+                        Kind::Variable
+                    };
+                    let name = &declaration.name;
+                    self.scope(&name.value)
+                        .define(name.value.clone(), Some(name.span), kind, false);
+                }
+            }
+            Stmt::If(if_stmt) => {
+                self.expression(&if_stmt.cond);
+                let before = self.assigned.clone();
+                self.block(&if_stmt.consequence);
+                let consequence = std::mem::replace(&mut self.assigned, before);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.block(alternative);
+                }
+                self.assigned = intersect(consequence, self.assigned.take());
+            }
+            // 循环体至少执行一次
+            Stmt::Repeat(repeat) => {
+                self.block(&repeat.consequence);
+                self.expression(&repeat.cond);
+            }
+            // 循环体可能一次也不执行
+            Stmt::While(while_stmt) => {
+                self.expression(&while_stmt.cond);
+                let before = self.assigned.clone();
+                self.block(&while_stmt.consequence);
+                self.assigned = before;
+            }
+            Stmt::Function(function) => self.function(function),
+            Stmt::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    self.expression(value);
+                }
+                self.assigned = None;
+            }
+            Stmt::Call(call) => self.call(&call.call),
+            Stmt::Error(_) => {}
+        }
+    }
+
+    // 函数体中可以使用定义之前已赋值的全局变量，其余被赋值的变量为局部变量
+    fn function(&mut self, function: &FunctionStatement) {
+        if self.function.is_some() {
+            return;
+        }
+        let locals = function.locals(|name| self.globals.indices.contains_key(name));
+        let mut scope = Scope {
+            locals: function.params.iter().map(|param| param.value.clone()).collect(),
+            ..Scope::default()
+        };
+        scope.locals.extend(locals);
+        let mut assigned = self.assigned.clone().unwrap_or_default();
+        for name in &scope.locals {
+            assigned.remove(name);
+        }
+        for param in &function.params {
+            scope.define(param.value.clone(), Some(param.span), Kind::Parameter, true);
+            assigned.insert(param.value.clone());
+        }
+
+        let saved_assigned = self.assigned.replace(assigned);
+        let saved_reported = std::mem::take(&mut self.reported);
+        let saved_writes = std::mem::take(&mut self.writes);
+        self.function = Some(scope);
+        self.block(&function.body);
+        let scope = self.function.take().unwrap();
+        self.finish_scope(&scope, true);
+        let mut deferred = std::mem::replace(&mut self.writes, saved_writes);
+        self.writes.append(&mut deferred);
+        self.assigned = saved_assigned;
+        self.reported = saved_reported;
+    }
+
+    fn assign(&mut self, name: &Identifier) {
+        self.scope(&name.value)
+            .define(name.value.clone(), Some(name.span), Kind::Variable, true);
+        if let Some(assigned) = &mut self.assigned {
+            assigned.insert(name.value.clone());
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Prefix(prefix) => self.expression(&prefix.right),
+            Expr::Infix(infix) => {
+                self.expression(&infix.left);
+                self.expression(&infix.right);
+            }
+            Expr::Index(index) => {
+                self.expression(&index.index);
+                if let Some(variable) = self.scope(&index.name.value).get_mut(&index.name.value) {
+                    variable.read = true;
+                }
+            }
+            Expr::Call(call) => self.call(call),
+            Expr::Identifier(ident) => self.read(ident),
+            Expr::Boolean(_) | Expr::String(_) | Expr::Number(_) | Expr::Error(_) => {}
+        }
+    }
+
+    fn call(&mut self, call: &CallExpression) {
+        for arg in &call.args {
+            self.expression(arg);
+        }
+    }
+
+    fn read(&mut self, ident: &Identifier) {
+        let name = ident.value.clone();
+        let Some(variable) = self.scope(&name).get_mut(&name) else {
+            return;
+        };
+        variable.read = true;
+        // 在此之前从未被赋值的变量属于语义错误
+        if variable.kind == Kind::Array || !variable.assigned {
+            return;
+        }
+        let Some(assigned) = &self.assigned else {
+            return;
+        };
+        if !assigned.contains(&name) && self.reported.insert(name.clone()) {
+            self.warnings.push(Warning {
+                lint: MAYBE_UNINITIALIZED,
+                span: ident.span,
+                message: format!("variable `{}` may be used before assignment", name),
+            });
+        }
+    }
+
+    // 报告scope中从未读取的变量，以及write输出的从未赋值的变量
+    // 函数体中输出的全局变量留到全局作用域结束时检查，它可能在函数定义之后才被赋值
+    fn finish_scope(&mut self, scope: &Scope, in_function: bool) {
+        for (ident, local) in std::mem::take(&mut self.writes) {
+            if in_function && !local {
+                self.writes.push((ident, local));
+                continue;
+            }
+            let Some(index) = scope.indices.get(&ident.value) else {
+                continue;
+            };
+            if !scope.variables[*index].assigned {
+                self.warnings.push(Warning {
+                    lint: NEVER_ASSIGNED,
+                    span: ident.span,
+                    message: format!("`{}` is written but never assigned", ident.value),
+                });
+            }
+        }
+        for variable in &scope.variables {
+            let Some(span) = variable.span else {
+                continue;
+            };
+            if variable.read || variable.kind == Kind::Predeclared {
+                continue;
+            }
+            let message = match variable.kind {
+                Kind::Parameter => format!("parameter `{}` is never used", variable.name),
+                Kind::Array => format!("array `{}` is never read", variable.name),
+                _ if variable.assigned => format!("variable `{}` is assigned but never used", variable.name),
+                _ => format!("variable `{}` is never used", variable.name),
+            };
+            self.warnings.push(Warning {
+                lint: UNUSED_VARIABLE,
+                span,
+                message,
+            });
+        }
+    }
+}
+
+// 两条执行路径汇合之后都已赋值的变量
+fn intersect(left: Option<HashSet<Name>>, right: Option<HashSet<Name>>) -> Option<HashSet<Name>> {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(left.intersection(&right).cloned().collect()),
+        (left, None) => left,
+        (None, right) => right,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lint::{lint, MAYBE_UNINITIALIZED, NEVER_ASSIGNED, UNUSED_VARIABLE};
+    use crate::parser::Parser;
+
+    fn warnings(input: &str, predeclared: &[&str]) -> Vec<String> {
+        let program = Parser::new(input).parse_program().unwrap();
+        lint(&program, predeclared.iter().copied())
+            .iter()
+            .map(|warning| format!("{} [{}]", warning, warning.lint))
+            .collect()
+    }
+
+    #[test]
+    fn test_unused() {
+        let input = "
+read x;
+y := x + 1;
+var z;
+var a[3];
+a[0] := limit;
+fn f(p, q)
+    t := p;
+    return p
+end;
+write f(1, 2)";
+        assert_eq!(
+            warnings(input, &["limit"]),
+            [
+                format!("3:1: variable `y` is assigned but never used [{}]", UNUSED_VARIABLE),
+                format!("4:5: variable `z` is never used [{}]", UNUSED_VARIABLE),
+                format!("5:5: array `a` is never read [{}]", UNUSED_VARIABLE),
+                format!("7:9: parameter `q` is never used [{}]", UNUSED_VARIABLE),
+                format!("8:5: variable `t` is assigned but never used [{}]", UNUSED_VARIABLE),
+            ]
+        );
+        assert!(warnings("read x; write x", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_maybe_uninitialized() {
+        let input = "
+read x;
+if x > 0 then y := 1 else y := 2 end;
+if x > 1 then z := 1 end;
+while x > 2 do w := 1; x := x - 1 end;
+repeat v := x; x := x - 1 until x < 0;
+write y + z + w + v + z;
+fn f(n)
+    if n > 0 then m := 1 else return 0 end;
+    return m + x
+end;
+write f(x)";
+        assert_eq!(
+            warnings(input, &[]),
+            [
+                format!(
+                    "7:11: variable `z` may be used before assignment [{}]",
+                    MAYBE_UNINITIALIZED
+                ),
+                format!(
+                    "7:15: variable `w` may be used before assignment [{}]",
+                    MAYBE_UNINITIALIZED
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_never_assigned() {
+        let input = "var a[2]; var b[2]; write a[0]; write a[1]; read i; repeat write b[i]; b[i] := 1 until true";
+        let warnings = warnings(input, &[]);
+        assert_eq!(
+            warnings,
+            [
+                format!("1:27: `a` is written but never assigned [{}]", NEVER_ASSIGNED),
+                format!("1:39: `a` is written but never assigned [{}]", NEVER_ASSIGNED),
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+// 编译流程中的日志：token流、语法分析的决定、产生的指令与跳转偏移量的回填
+// 启用log特性时转发到log crate，目标为所在的模块，如 tiny_cc::compiler；
+// 未启用时不产生任何代码，参数仍会做类型检查
+
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        log::trace!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        log::debug!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "log")]
+pub use env::init_from_env;
+
+#[cfg(feature = "log")]
+mod env {
+    use log::{LevelFilter, Log, Metadata, Record};
+    use std::str::FromStr;
+
+    // 按RUST_LOG过滤日志并写到标准错误
+    // RUST_LOG为逗号分隔的若干项，每项为一个级别，或 模块路径=级别，如 info,tiny_cc::compiler=trace
+    // 模块路径取最长的匹配项，没有匹配项时使用单独给出的级别，默认不输出
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) struct Filter {
+        default: LevelFilter,
+        targets: Vec<(String, LevelFilter)>,
+    }
+
+    impl Filter {
+        pub(super) fn parse(spec: &str) -> Result<Self, String> {
+            let mut filter = Filter {
+                default: LevelFilter::Off,
+                targets: vec![],
+            };
+            for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+                let parse_level = |level: &str| {
+                    LevelFilter::from_str(level).map_err(|_| format!("invalid log level `{}` in RUST_LOG", level))
+                };
+                match directive.split_once('=') {
+                    Some((target, level)) => filter.targets.push((target.to_string(), parse_level(level)?)),
+                    None => filter.default = parse_level(directive)?,
+                }
+            }
+            Ok(filter)
+        }
+
+        pub(super) fn level(&self, target: &str) -> LevelFilter {
+            self.targets
+                .iter()
+                .filter(|(prefix, _)| {
+                    target == prefix || target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::")
+                })
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map_or(self.default, |(_, level)| *level)
+        }
+
+        fn max_level(&self) -> LevelFilter {
+            self.targets
+                .iter()
+                .map(|(_, level)| *level)
+                .fold(self.default, Ord::max)
+        }
+    }
+
+    impl Log for Filter {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level(metadata.target())
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    // 按环境变量RUST_LOG安装日志记录器，未设置RUST_LOG时什么也不做
+    // 已经安装过记录器时返回错误
+    pub fn init_from_env() -> Result<(), String> {
+        let Ok(spec) = std::env::var("RUST_LOG") else {
+            return Ok(());
+        };
+        let filter = Filter::parse(&spec)?;
+        let max_level = filter.max_level();
+        log::set_boxed_logger(Box::new(filter)).map_err(|err| err.to_string())?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "log"))]
+mod test {
+    use crate::logging::env::Filter;
+    use log::LevelFilter;
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, tiny_cc::compiler=trace,tiny_cc=debug").unwrap();
+        assert_eq!(filter.level("tiny_cc::compiler"), LevelFilter::Trace);
+        assert_eq!(filter.level("tiny_cc::parser"), LevelFilter::Debug);
+        assert_eq!(filter.level("tiny_cc_other"), LevelFilter::Warn);
+        assert_eq!(filter.level("other"), LevelFilter::Warn);
+        assert_eq!(Filter::parse("").unwrap().level("tiny_cc"), LevelFilter::Off);
+        assert!(Filter::parse("tiny_cc=loud").is_err());
+    }
+}
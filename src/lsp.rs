@@ -0,0 +1,690 @@
+use crate::ast::{BlockStatement, CallExpression, Expr, Identifier, Program, Stmt};
+use crate::diagnostics::{Diagnostic, DiagnosticBag, Severity};
+use crate::driver::{self, Options};
+use crate::format;
+use crate::interner::Name;
+use crate::lint;
+use crate::parser::Parser;
+use crate::semantic;
+use crate::token::Span;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Formatting, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+// TINY的Language Server：打开或修改文档时发布诊断信息，支持变量与函数的跳转到定义、
+// 悬停显示类型与存储位置，以及格式化整个文档；文档只支持整体同步
+
+// 一个变量或函数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // 定义处的名字：变量第一次被赋值、read或以var声明的位置，函数定义中的函数名
+    pub definition: Span,
+    // 所有出现的位置，包括定义处
+    pub references: Vec<Span>,
+    // 全局变量与数组的数据地址，函数的入口地址；程序有错误而无法编译时为None
+    pub address: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Array(i32),
+    // 函数的参数与局部变量，保存在函数的栈帧中
+    Parameter(String),
+    Local(String),
+    // 参数的名字
+    Function(Vec<String>),
+}
+
+impl Symbol {
+    // 悬停时显示的Markdown文本：推断出的类型与存储位置
+    pub fn hover(&self) -> String {
+        let (signature, location) = match &self.kind {
+            SymbolKind::Variable => (
+                format!("{}: integer", self.name),
+                match self.address {
+                    Some(address) => format!("global variable at data address {}", address),
+                    None => "global variable".to_string(),
+                },
+            ),
+            SymbolKind::Array(size) => (
+                format!("{}: integer[{}]", self.name, size),
+                match self.address {
+                    Some(address) => format!(
+                        "global array at data addresses {}..{}",
+                        address,
+                        address + (*size).max(1) as usize - 1
+                    ),
+                    None => "global array".to_string(),
+                },
+            ),
+            SymbolKind::Parameter(function) => (
+                format!("{}: integer", self.name),
+                format!("parameter of `{}`, stored in its stack frame", function),
+            ),
+            SymbolKind::Local(function) => (
+                format!("{}: integer", self.name),
+                format!("local variable of `{}`, stored in its stack frame", function),
+            ),
+            SymbolKind::Function(params) => (
+                format!("fn {}({}): integer", self.name, params.join(", ")),
+                match self.address {
+                    Some(address) => format!("function with entry at instruction {}", address),
+                    None => "function".to_string(),
+                },
+            ),
+        };
+        format!("```tiny\n{}\n```\n{}", signature, location)
+    }
+}
+
+// 一个文档的分析结果
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub diagnostics: DiagnosticBag,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Analysis {
+    // 字符偏移量offset处的名字所指的符号，offset位于名字之中或紧接在名字之后
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| {
+            symbol
+                .references
+                .iter()
+                .any(|span| span.start <= offset && offset <= span.end)
+        })
+    }
+}
+
+// 分析源程序：语法错误、语义错误与警告、代码生成的错误，以及所有的变量与函数
+// 存在语法错误时仍然分析能够解析的部分
+pub fn analyze(source: &str) -> Analysis {
+    let mut diagnostics = DiagnosticBag::new();
+    let (program, errors) = Parser::new(source).parse_program_partial();
+    diagnostics.extend(&errors);
+    let mut resolver = Resolver::default();
+    resolver.program(&program);
+    let mut symbols = resolver.symbols;
+    if errors.is_empty() {
+        let (errors, warnings) = semantic::check_with_warnings(&program, []);
+        diagnostics.extend(&errors);
+        diagnostics.extend(&warnings);
+        if errors.is_empty() {
+            diagnostics.extend(&lint::lint(&program, []));
+        }
+        if errors.is_empty() {
+            match driver::compile_to_tm(source, &Options::default()) {
+                Ok(artifacts) => {
+                    for symbol in &mut symbols {
+                        symbol.address = match symbol.kind {
+                            SymbolKind::Variable | SymbolKind::Array(_) => artifacts
+                                .symbol_table
+                                .address(&symbol.name)
+                                .and_then(|loc| usize::try_from(loc).ok()),
+                            SymbolKind::Function(_) => artifacts
+                                .symbol_table
+                                .function(&symbol.name)
+                                .map(|function| function.entry),
+                            SymbolKind::Parameter(_) | SymbolKind::Local(_) => None,
+                        };
+                    }
+                }
+                Err(err) => diagnostics.extend(err.diagnostics().iter().cloned()),
+            }
+        }
+    }
+    Analysis { diagnostics, symbols }
+}
+
+// 按编译器的规则确定每个名字所指的符号：函数可以在定义之前调用；
+// 函数体中，参数与局部变量遮蔽定义在函数之前的同名全局变量，见 FunctionStatement::locals
+#[derive(Default)]
+struct Resolver {
+    symbols: Vec<Symbol>,
+    globals: HashMap<Name, usize>,
+    functions: HashMap<Name, usize>,
+    // 当前所在的函数名，参数与局部变量，以及尚未出现的局部变量
+    function: Option<(Name, HashMap<Name, usize>, Vec<Name>)>,
+}
+
+impl Resolver {
+    fn program(&mut self, program: &Program) {
+        for stmt in &program.statements {
+            if let Stmt::Function(function) = stmt {
+                if !self.functions.contains_key(&function.name.value) {
+                    let params = function.params.iter().map(|param| param.value.to_string()).collect();
+                    let index = self.define(&function.name, SymbolKind::Function(params));
+                    self.functions.insert(function.name.value.clone(), index);
+                }
+            }
+        }
+        for stmt in &program.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn define(&mut self, name: &Identifier, kind: SymbolKind) -> usize {
+        self.symbols.push(Symbol {
+            name: name.value.to_string(),
+            kind,
+            definition: name.span,
+            references: vec![name.span],
+            address: None,
+        });
+        self.symbols.len() - 1
+    }
+
+    fn reference(&mut self, name: &Identifier) {
+        let local = self
+            .function
+            .as_ref()
+            .and_then(|(_, locals, _)| locals.get(&name.value));
+        if let Some(&index) = local.or_else(|| self.globals.get(&name.value)) {
+            self.symbols[index].references.push(name.span);
+        }
+    }
+
+    // 被赋值、read或以var声明的名字，第一次出现时定义变量
+    fn assign(&mut self, name: &Identifier, kind: SymbolKind) {
+        if let Some((function, locals, pending)) = &mut self.function {
+            if let Some(position) = pending.iter().position(|local| *local == name.value) {
+                pending.remove(position);
+                let kind = SymbolKind::Local(function.to_string());
+                let index = self.define(name, kind);
+                if let Some((_, locals, _)) = &mut self.function {
+                    locals.insert(name.value.clone(), index);
+                }
+                return;
+            }
+            if locals.contains_key(&name.value) {
+                return self.reference(name);
+            }
+        }
+        if self.function.is_none() && !self.globals.contains_key(&name.value) {
+            let index = self.define(name, kind);
+            self.globals.insert(name.value.clone(), index);
+        } else {
+            self.reference(name);
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assign(assign) => {
+                self.expression(&assign.value);
+                match &assign.index {
+                    Some(index) => {
+                        self.expression(index);
+                        self.reference(&assign.name);
+                    }
+                    None => self.assign(&assign.name, SymbolKind::Variable),
+                }
+            }
+            Stmt::Read(read) => self.assign(&read.name, SymbolKind::Variable),
+            Stmt::Write(write) => self.expression(&write.value),
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    match &declaration.size {
+                        Some(size) => self.assign(&declaration.name, SymbolKind::Array(size.value)),
+                        None => self.assign(&declaration.name, SymbolKind::Variable),
+                    }
+                }
+            }
+            Stmt::Function(function) => {
+                if let Some(&index) = self.functions.get(&function.name.value) {
+                    if self.symbols[index].definition != function.name.span {
+                        self.symbols[index].references.push(function.name.span);
+                    }
+                }
+                let mut locals = HashMap::new();
+                for param in &function.params {
+                    let index = self.define(param, SymbolKind::Parameter(function.name.value.to_string()));
+                    locals.entry(param.value.clone()).or_insert(index);
+                }
+                let pending = function.locals(|name| self.globals.contains_key(name));
+                self.function = Some((function.name.value.clone(), locals, pending));
+                self.block(&function.body);
+                self.function = None;
+            }
+            Stmt::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Call(call) => self.call(&call.call),
+            Stmt::If(if_stmt) => {
+                self.expression(&if_stmt.cond);
+                self.block(&if_stmt.consequence);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.block(alternative);
+                }
+            }
+            Stmt::Repeat(repeat) => {
+                self.block(&repeat.consequence);
+                self.expression(&repeat.cond);
+            }
+            Stmt::While(while_stmt) => {
+                self.expression(&while_stmt.cond);
+                self.block(&while_stmt.consequence);
+            }
+            Stmt::Error(_) => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Prefix(prefix) => self.expression(&prefix.right),
+            Expr::Infix(infix) => {
+                self.expression(&infix.left);
+                self.expression(&infix.right);
+            }
+            Expr::Index(index) => {
+                self.reference(&index.name);
+                self.expression(&index.index);
+            }
+            Expr::Call(call) => self.call(call),
+            Expr::Identifier(ident) => self.reference(ident),
+            Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Error(_) => {}
+        }
+    }
+
+    fn call(&mut self, call: &CallExpression) {
+        if let Some(&index) = self.functions.get(&call.name.value) {
+            self.symbols[index].references.push(call.name.span);
+        }
+        for arg in &call.args {
+            self.expression(arg);
+        }
+    }
+}
+
+// 字符偏移量与LSP位置之间的转换，LSP的列以UTF-16编码单元计数
+fn to_position(text: &str, offset: usize) -> Position {
+    let (mut line, mut character) = (0, 0);
+    for ch in text.chars().take(offset) {
+        if ch == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += ch.len_utf16() as u32;
+        }
+    }
+    Position { line, character }
+}
+
+fn to_offset(text: &str, position: Position) -> usize {
+    let (mut line, mut character) = (0, 0);
+    for (offset, ch) in text.chars().enumerate() {
+        if line == position.line && (character >= position.character || ch == '\n') {
+            return offset;
+        }
+        if ch == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += ch.len_utf16() as u32;
+        }
+    }
+    text.chars().count()
+}
+
+fn to_range(text: &str, span: Span) -> Range {
+    Range {
+        start: to_position(text, span.start),
+        end: to_position(text, span.end),
+    }
+}
+
+// 没有位置的诊断信息显示在文档开头
+fn to_lsp_diagnostic(text: &str, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let range = diagnostic.span.map_or_else(Range::default, |span| to_range(text, span));
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Note => DiagnosticSeverity::INFORMATION,
+    };
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("tinycc".to_string()),
+        message: diagnostic.message.clone(),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+// 通过标准输入输出运行服务器，直到客户端发出exit
+pub fn serve_stdio() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+// 在已建立的连接上完成初始化并处理消息，直到客户端发出shutdown
+pub fn serve(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                server.request(request)?;
+            }
+            Message::Notification(notification) => server.notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    // 打开的文档及其分析结果
+    documents: HashMap<Url, (String, Analysis)>,
+}
+
+impl Server<'_> {
+    fn notification(&mut self, notification: Notification) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.update(params.text_document.uri, params.text_document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                match params.content_changes.into_iter().last() {
+                    Some(change) => self.update(params.text_document.uri, change.text),
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish(params.text_document.uri, vec![])
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 重新分析文档并发布诊断信息
+    fn update(&mut self, uri: Url, text: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let analysis = analyze(&text);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| to_lsp_diagnostic(&text, diagnostic))
+            .collect();
+        self.documents.insert(uri.clone(), (text, analysis));
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                serde_json::to_value(self.definition(position.text_document.uri, position.position))?
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                serde_json::to_value(self.hover(&position.text_document.uri, position.position))?
+            }
+            Formatting::METHOD => {
+                let params: DocumentFormattingParams = serde_json::from_value(request.params)?;
+                serde_json::to_value(self.format(&params.text_document.uri))?
+            }
+            _ => {
+                let message = format!("unsupported request {}", request.method);
+                return self.respond(Response::new_err(request.id, -32601, message));
+            }
+        };
+        self.respond(Response {
+            id: request.id,
+            result: Some(result),
+            error: None,
+        })
+    }
+
+    fn respond(&self, response: Response) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.connection.sender.send(response.into())?;
+        Ok(())
+    }
+
+    fn symbol_at(&self, uri: &Url, position: Position) -> Option<(&str, &Symbol)> {
+        let (text, analysis) = self.documents.get(uri)?;
+        let symbol = analysis.symbol_at(to_offset(text, position))?;
+        Some((text, symbol))
+    }
+
+    fn definition(&self, uri: Url, position: Position) -> Option<GotoDefinitionResponse> {
+        let (text, symbol) = self.symbol_at(&uri, position)?;
+        let range = to_range(text, symbol.definition);
+        Some(GotoDefinitionResponse::Scalar(Location { uri, range }))
+    }
+
+    fn hover(&self, uri: &Url, position: Position) -> Option<Hover> {
+        let (text, symbol) = self.symbol_at(uri, position)?;
+        let offset = to_offset(text, position);
+        let span = symbol
+            .references
+            .iter()
+            .find(|span| span.start <= offset && offset <= span.end)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: symbol.hover(),
+            }),
+            range: Some(to_range(text, *span)),
+        })
+    }
+
+    // 以一个替换整个文档的编辑返回格式化的结果，存在语法错误时不做格式化
+    fn format(&self, uri: &Url) -> Option<Vec<TextEdit>> {
+        let (text, _) = self.documents.get(uri)?;
+        let formatted = format::format_source(text).ok()?;
+        if formatted == *text {
+            return Some(vec![]);
+        }
+        let range = Range {
+            start: Position::default(),
+            end: to_position(text, text.chars().count()),
+        };
+        Some(vec![TextEdit {
+            range,
+            new_text: formatted,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lsp::{analyze, serve, to_offset, to_position, SymbolKind};
+    use lsp_server::{Connection, Message, Notification, Request, RequestId};
+    use lsp_types::{Position, Url};
+    use serde_json::json;
+    use std::thread;
+
+    const SOURCE: &str = "read x;\nvar a[4];\nfn twice(n) t := n * 2; return t end;\na[0] := twice(x);\nwrite a[0] + x";
+
+    #[test]
+    fn test_analyze() {
+        let analysis = analyze(SOURCE);
+        assert!(analysis.diagnostics.is_empty());
+        let symbols: Vec<_> = analysis
+            .symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    symbol.name.as_str(),
+                    symbol.definition.to_string(),
+                    symbol.references.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("twice", "3:4".to_string(), 2),
+                ("x", "1:6".to_string(), 3),
+                ("a", "2:5".to_string(), 3),
+                ("n", "3:10".to_string(), 2),
+                ("t", "3:13".to_string(), 2),
+            ]
+        );
+        let x = analysis.symbol_at(to_offset(SOURCE, Position::new(4, 14))).unwrap();
+        assert_eq!(x.name, "x");
+        assert_eq!(x.hover(), "```tiny\nx: integer\n```\nglobal variable at data address 1");
+        let a = &analysis.symbols[2];
+        assert_eq!(a.kind, SymbolKind::Array(4));
+        assert!(a.hover().ends_with("global array at data addresses 2..5"));
+        assert!(analysis.symbols[4]
+            .hover()
+            .ends_with("local variable of `twice`, stored in its stack frame"));
+
+        let analysis = analyze("write y;\nx := 1 / 0;\nread");
+        let messages: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(messages.len(), 1);
+        assert!(analyze("write y").diagnostics.has_errors());
+        assert_eq!(analyze("x := 1 / 0; write x").diagnostics.len(), 1);
+        let analysis = analyze("x := 1 / 0");
+        let messages: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| &diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            ["division by zero", "variable `x` is assigned but never used"]
+        );
+    }
+
+    #[test]
+    fn test_position() {
+        let text = "write \"\u{1F600}\"; x\n  y";
+        assert_eq!(to_position(text, 11), Position::new(0, 12));
+        assert_eq!(to_offset(text, Position::new(0, 12)), 11);
+        assert_eq!(to_offset(text, Position::new(1, 2)), 15);
+        assert_eq!(to_offset(text, Position::new(0, 100)), 12);
+    }
+
+    #[test]
+    fn test_server() {
+        let (server, client) = Connection::memory();
+        let handle = thread::spawn(move || serve(&server).unwrap());
+        let uri = Url::parse("file:///demo.tny").unwrap();
+        let request = |id: i32, method: &str, params| Request::new(RequestId::from(id), method.to_string(), params);
+        let receive = || match client.receiver.recv().unwrap() {
+            Message::Response(response) => response.result.unwrap(),
+            Message::Notification(notification) => notification.params,
+            Message::Request(request) => panic!("unexpected request {:?}", request),
+        };
+
+        client
+            .sender
+            .send(request(1, "initialize", json!({ "capabilities": {} })).into())
+            .unwrap();
+        assert_eq!(receive()["capabilities"]["hoverProvider"], true);
+        client
+            .sender
+            .send(Notification::new("initialized".to_string(), json!({})).into())
+            .unwrap();
+        let document = json!({ "uri": uri, "languageId": "tiny", "version": 1, "text": "read x; write y" });
+        client
+            .sender
+            .send(Notification::new("textDocument/didOpen".to_string(), json!({ "textDocument": document })).into())
+            .unwrap();
+        let diagnostics = receive();
+        assert_eq!(diagnostics["diagnostics"].as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics["diagnostics"][0]["range"]["start"]["character"], 14);
+
+        let change = json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": "read x;write x" }],
+        });
+        client
+            .sender
+            .send(Notification::new("textDocument/didChange".to_string(), change).into())
+            .unwrap();
+        assert!(receive()["diagnostics"].as_array().unwrap().is_empty());
+
+        let position = json!({ "textDocument": { "uri": uri }, "position": { "line": 0, "character": 13 } });
+        client
+            .sender
+            .send(request(2, "textDocument/definition", position.clone()).into())
+            .unwrap();
+        assert_eq!(receive()["range"]["start"]["character"], 5);
+        client
+            .sender
+            .send(request(3, "textDocument/hover", position).into())
+            .unwrap();
+        assert!(receive()["contents"]["value"].as_str().unwrap().contains("x: integer"));
+        let options = json!({ "tabSize": 4, "insertSpaces": true });
+        client
+            .sender
+            .send(
+                request(
+                    4,
+                    "textDocument/formatting",
+                    json!({ "textDocument": { "uri": uri }, "options": options }),
+                )
+                .into(),
+            )
+            .unwrap();
+        assert_eq!(receive()[0]["newText"], "read x;\nwrite x;\n");
+
+        client.sender.send(request(5, "shutdown", json!(null)).into()).unwrap();
+        receive();
+        client
+            .sender
+            .send(Notification::new("exit".to_string(), json!(null)).into())
+            .unwrap();
+        handle.join().unwrap();
+    }
+}
@@ -1,3 +1,885 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tiny_cc::cfg::ControlFlowGraph;
+use tiny_cc::config::{self, Config, WarningLevel};
+use tiny_cc::debugger::Debugger;
+use tiny_cc::diagnostics::{Diagnostic, DiagnosticBag, Severity};
+use tiny_cc::format;
+use tiny_cc::highlight;
+use tiny_cc::incremental::Document;
+use tiny_cc::lexer;
+use tiny_cc::parser::{Parser, MAX_DEPTH};
+use tiny_cc::repl::Repl;
+use tiny_cc::token::Span;
+use tiny_cc::vm::TinyMachine;
+use tiny_cc::{compile_many, compile_program, compile_to_tm, Artifacts, FileError, Options};
+
+const USAGE: &str = "usage: tinycc <command> [options] <path>
+
+commands:
+    compile <file>    compile a TINY program into a TM listing next to it, named after
+                      the program header (<name>.tm) or else the file (<file>.tm)
+    watch <file>      compile, then recompile whenever the file changes, reparsing only the
+                      statements around the change; with --run or --input, rerun the program
+                      after every successful build
+    check <dir>       compile every .tny file under a directory and report a summary
+    debug <file>      compile a program and run it under the debugger, reading debugger
+                      commands and `read` input from stdin; type `help` for the commands
+    fmt <file>        rewrite a TINY program in the canonical layout, keeping its comments
+    repl              read and run TINY statements interactively
+    lsp               run a language server on stdin/stdout (requires the `lsp` feature)
+
+options:
+    -o <path>         write the output to <path> instead, `-` for stdout
+    --emit=<tokens|ast|cfg|html|tm>
+                      what to output: the token stream in the textbook scanner format, the syntax
+                      tree, the control-flow graph of the compiled code in Graphviz DOT format,
+                      the source as syntax-highlighted HTML or the TM listing (default);
+                      all but tm go to stdout unless -o is given
+    --run             execute the compiled program on the built-in TM virtual machine,
+                      reading `read` input from stdin; the listing is only written with -o
+    --input <file>    like --run, but read `read` input from <file>; watch replays the same
+                      input on every rebuild
+    --trace           with --run, print every executed instruction and the registers after it
+                      to stderr
+    --profile         with --run, print the most executed source lines and instructions
+                      to stderr when the program ends
+    --max-instructions=<n>
+                      with --run, stop the program with a trap after n instructions
+    --timeout=<ms>    with --run, stop the program with a trap after running for ms milliseconds
+    --debug-info      append the source position of every instruction to the listing
+                      as .SPAN directives
+    --message-format=<human|json>
+                      print results as human readable text (default) or as one JSON object per line
+    --opt-level=<n>   optimization level: 0 (default), 1 to fold constants, simplify multiplications
+                      by powers of two, compute repeated subexpressions of a statement once and
+                      remove dead code, 2 to also keep intermediate results in spare registers,
+                      compute loop-invariant expressions once before the loop and replace variables
+                      holding constants with their values
+    --memory-size=<n> data memory size in words
+    --max-depth=<n>   maximum nesting depth of statements and expressions (default 128)
+    --strict          require every variable to be declared with var before it is assigned or read
+    --check           with fmt, only report whether the file is formatted; exits with 1 if not
+    --warn=<lint>=<allow|warn|deny>
+                      set the level of a warning: division-by-zero, unused-variable,
+                      maybe-uninitialized or never-assigned
+
+when built with the `log` feature, RUST_LOG=<level> (e.g. trace or tiny_cc::compiler=trace) prints
+the token stream, parse decisions and emitted instructions to stderr
+
+settings not given on the command line are read from the nearest tinycc.toml,
+searched upward from the input file
+
+exit codes:
+    0    success
+    1    the program has errors
+    2    internal error (bad invocation, I/O failure)
+    3    runtime trap while running the program";
+
+// 退出码，见USAGE
+const EXIT_ERRORS: i32 = 1;
+const EXIT_INTERNAL: i32 = 2;
+const EXIT_TRAP: i32 = 3;
+
+// --profile 输出的行数与指令数
+const PROFILE_ROWS: usize = 10;
+
+// watch模式下检查文件修改的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
 fn main() {
-    println!("Hello, world!");
+    #[cfg(feature = "log")]
+    if let Err(err) = tiny_cc::logging::init_from_env() {
+        eprintln!("warning: {}", err);
+    }
+    let mut format = MessageFormat::Human;
+    let mut flags = Config::default();
+    let mut output = Output {
+        emit: Emit::Tm,
+        path: None,
+        run: false,
+        input: None,
+        debug_info: false,
+        trace: false,
+        profile: false,
+        instruction_limit: None,
+        timeout: None,
+        check: false,
+    };
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output.path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())));
+        } else if let Some(value) = arg.strip_prefix("--emit=") {
+            output.emit = match value {
+                "tokens" => Emit::Tokens,
+                "ast" => Emit::Ast,
+                "cfg" => Emit::Cfg,
+                "html" => Emit::Html,
+                "tm" => Emit::Tm,
+                _ => usage(),
+            };
+        } else if arg == "--run" {
+            output.run = true;
+        } else if arg == "--input" {
+            output.input = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())));
+            output.run = true;
+        } else if arg == "--debug-info" {
+            output.debug_info = true;
+        } else if arg == "--trace" {
+            output.trace = true;
+        } else if arg == "--check" {
+            output.check = true;
+        } else if arg == "--profile" {
+            output.profile = true;
+        } else if let Some(value) = arg.strip_prefix("--max-instructions=") {
+            output.instruction_limit = Some(value.parse().unwrap_or_else(|_| usage()));
+        } else if let Some(value) = arg.strip_prefix("--timeout=") {
+            output.timeout = Some(Duration::from_millis(value.parse().unwrap_or_else(|_| usage())));
+        } else if let Some(value) = arg.strip_prefix("--message-format=") {
+            format = match value {
+                "human" => MessageFormat::Human,
+                "json" => MessageFormat::Json,
+                _ => usage(),
+            };
+        } else if let Some(value) = arg.strip_prefix("--opt-level=") {
+            flags.opt_level = Some(value.parse().unwrap_or_else(|_| usage()));
+        } else if let Some(value) = arg.strip_prefix("--memory-size=") {
+            flags.memory_size = Some(value.parse().unwrap_or_else(|_| usage()));
+        } else if let Some(value) = arg.strip_prefix("--max-depth=") {
+            flags.max_depth = Some(value.parse().unwrap_or_else(|_| usage()));
+        } else if arg == "--strict" {
+            flags.strict = Some(true);
+        } else if let Some(value) = arg.strip_prefix("--warn=") {
+            let (name, level) = value.split_once('=').unwrap_or_else(|| usage());
+            config::check_lint(name).unwrap_or_else(|err| bad_flag(&err));
+            let level = WarningLevel::parse(level).unwrap_or_else(|err| bad_flag(&err));
+            flags.warnings.insert(name.to_string(), level);
+        } else if arg.starts_with('-') && arg != "-" {
+            usage();
+        } else {
+            positional.push(arg);
+        }
+    }
+    let (command, path) = match positional.as_slice() {
+        [command, path] => (command.as_str(), Path::new(path)),
+        [command] if command == "repl" => {
+            if let Err(err) = Repl::with_stdio().run() {
+                eprintln!("error: {}", err);
+                process::exit(EXIT_INTERNAL);
+            }
+            return;
+        }
+        [command] if command == "lsp" => serve_lsp(),
+        _ => usage(),
+    };
+    let reporter = Reporter { format, flags };
+    let result = match command {
+        "compile" => compile_file(&reporter, path, &output),
+        "watch" => watch(&reporter, path, &output),
+        "check" => check_dir(&reporter, path),
+        "debug" => debug_file(&reporter, path),
+        "fmt" => format_file(&reporter, path, &output),
+        _ => usage(),
+    };
+    if let Err(code) = result {
+        process::exit(code);
+    }
+}
+
+#[cfg(feature = "lsp")]
+fn serve_lsp() -> ! {
+    match tiny_cc::lsp::serve_stdio() {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_INTERNAL)
+        }
+    }
+}
+
+#[cfg(not(feature = "lsp"))]
+fn serve_lsp() -> ! {
+    bad_flag("tinycc was built without the `lsp` feature")
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(EXIT_INTERNAL)
+}
+
+fn bad_flag(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(EXIT_INTERNAL)
+}
+
+enum CliError {
+    // 源程序中的错误，附带源程序文本用于输出片段
+    Diagnostic(DiagnosticBag, String),
+    // 编译器自身或环境导致的错误
+    Internal(String),
+    // 在虚拟机中运行程序时发生的运行时错误
+    Trap(String),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Diagnostic(..) => EXIT_ERRORS,
+            CliError::Internal(_) => EXIT_INTERNAL,
+            CliError::Trap(_) => EXIT_TRAP,
+        }
+    }
+
+    // 错误与警告的个数；内部错误与运行时错误各算一个错误
+    fn counts(&self) -> (usize, usize) {
+        match self {
+            CliError::Internal(_) | CliError::Trap(_) => (1, 0),
+            CliError::Diagnostic(diagnostics, _) => {
+                let count = |severity: Severity| {
+                    diagnostics
+                        .iter()
+                        .filter(|diagnostic| diagnostic.severity == severity)
+                        .count()
+                };
+                (count(Severity::Error), count(Severity::Warning))
+            }
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            CliError::Internal(message) | CliError::Trap(message) => message.clone(),
+            CliError::Diagnostic(diagnostics, _) => {
+                let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+                messages.join("\n")
+            }
+        }
+    }
+}
+
+// 单个文件的编译错误附带该文件的源程序
+impl From<FileError> for CliError {
+    fn from(err: FileError) -> Self {
+        match err {
+            FileError::Compile(errors) => {
+                let mut diagnostics = DiagnosticBag::new();
+                let mut source = String::new();
+                for (file, err) in errors {
+                    diagnostics.extend(err.diagnostics().iter().cloned());
+                    source = file.text;
+                }
+                CliError::Diagnostic(diagnostics, source)
+            }
+            FileError::Io { error, .. } => CliError::Internal(format!("cannot read file: {}", error)),
+            FileError::NoSources => CliError::Internal(err.to_string()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Emit {
+    Tokens,
+    Ast,
+    Cfg,
+    Html,
+    Tm,
+}
+
+// compile与watch命令的输出设置
+struct Output {
+    emit: Emit,
+    // None时使用默认的输出位置，Some("-")表示标准输出
+    path: Option<PathBuf>,
+    run: bool,
+    // 与run一起使用，程序的输入来自该文件而不是标准输入
+    input: Option<PathBuf>,
+    // 在清单中附带每条指令在源程序中的位置
+    debug_info: bool,
+    // 与run一起使用，见 TinyMachine::with_trace 与 TinyMachine::with_profile
+    trace: bool,
+    profile: bool,
+    // 与run一起使用，见 TinyMachine::with_instruction_limit 与 TinyMachine::with_timeout
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+    // 与fmt一起使用，只检查文件是否已经格式化
+    check: bool,
+}
+
+#[derive(Copy, Clone)]
+enum MessageFormat {
+    Human,
+    Json,
+}
+
+// 统一输出编译结果，json格式下每条消息占一行
+struct Reporter {
+    format: MessageFormat,
+    // 命令行中给出的设置，优先于配置文件
+    flags: Config,
+}
+
+impl Reporter {
+    fn compiled(&self, file: &Path, output: Option<&Path>, instructions: usize) {
+        match self.format {
+            MessageFormat::Human => match output {
+                Some(output) => println!(
+                    "compiled {} -> {} ({} instructions)",
+                    file.display(),
+                    output.display(),
+                    instructions
+                ),
+                None => println!("ok      {} ({} instructions)", file.display(), instructions),
+            },
+            MessageFormat::Json => {
+                let output = match output {
+                    Some(output) => json_string(&output.display().to_string()),
+                    None => "null".to_string(),
+                };
+                println!(
+                    "{{\"type\":\"compiled\",\"file\":{},\"output\":{},\"instructions\":{}}}",
+                    json_string(&file.display().to_string()),
+                    output,
+                    instructions
+                );
+            }
+        }
+    }
+
+    fn error(&self, file: &Path, err: &CliError) {
+        match self.format {
+            MessageFormat::Human => match err {
+                CliError::Diagnostic(diagnostics, source) => {
+                    eprint!("{}", diagnostics.render(source, &file.display().to_string()))
+                }
+                _ => eprintln!("error: {}: {}", file.display(), err.message()),
+            },
+            MessageFormat::Json => match err {
+                CliError::Diagnostic(diagnostics, _) => {
+                    for diagnostic in diagnostics.iter() {
+                        println!("{}", diagnostic_json(file, diagnostic));
+                    }
+                }
+                CliError::Internal(message) => println!("{}", json_diagnostic(file, "internal", None, message, None)),
+                CliError::Trap(message) => println!("{}", json_diagnostic(file, "trap", None, message, None)),
+            },
+        }
+    }
+
+    // check中编译失败的文件，之后是它的错误
+    fn failed(&self, file: &Path) {
+        match self.format {
+            MessageFormat::Human => println!("FAILED  {}", file.display()),
+            MessageFormat::Json => println!(
+                "{{\"type\":\"failed\",\"file\":{}}}",
+                json_string(&file.display().to_string())
+            ),
+        }
+    }
+
+    fn warnings(&self, file: &Path, warnings: &[Diagnostic]) {
+        for warning in warnings {
+            match self.format {
+                MessageFormat::Human => eprintln!("warning: {}: {}", file.display(), warning),
+                MessageFormat::Json => println!("{}", diagnostic_json(file, warning)),
+            }
+        }
+    }
+
+    fn unformatted(&self, file: &Path) {
+        match self.format {
+            MessageFormat::Human => println!("unformatted {}", file.display()),
+            MessageFormat::Json => println!(
+                "{{\"type\":\"unformatted\",\"file\":{}}}",
+                json_string(&file.display().to_string())
+            ),
+        }
+    }
+
+    fn summary(&self, files: usize, failed: usize, errors: usize, warnings: usize) {
+        match self.format {
+            MessageFormat::Human => println!(
+                "checked {} files: {} passed, {} failed; {} errors, {} warnings",
+                files,
+                files - failed,
+                failed,
+                errors,
+                warnings
+            ),
+            MessageFormat::Json => println!(
+                "{{\"type\":\"summary\",\"files\":{},\"passed\":{},\"failed\":{},\"errors\":{},\"warnings\":{}}}",
+                files,
+                files - failed,
+                failed,
+                errors,
+                warnings
+            ),
+        }
+    }
+}
+
+// 读取源文件，返回源程序文本、编码问题的警告以及对该文件生效的配置
+fn read_source(file: &Path, flags: &Config) -> Result<(String, Vec<Diagnostic>, Config), CliError> {
+    let config = load_config(file, flags)?;
+    let bytes = fs::read(file).map_err(|err| CliError::Internal(format!("cannot read file: {}", err)))?;
+    let (source, warnings) = lexer::decode_source(&bytes);
+    let warnings = warnings.into_iter().map(|warning| Diagnostic::warning(None, warning));
+    Ok((source, warnings.collect(), config))
+}
+
+// 对该文件生效的配置对应的编译选项
+fn compile_options(config: Config, debug_info: bool) -> Options {
+    Options {
+        opt_level: config.opt_level.unwrap_or(0),
+        warnings: config.warnings,
+        memory_size: config.memory_size,
+        max_depth: config.max_depth,
+        strict: config.strict.unwrap_or(false),
+        debug_info,
+        ..Options::default()
+    }
+}
+
+// 读取并编译单个文件，源文件编码的问题作为警告附加在诊断信息中
+fn compile_source(file: &Path, flags: &Config, debug_info: bool) -> Result<Artifacts, CliError> {
+    let (source, mut warnings, config) = read_source(file, flags)?;
+    let mut artifacts = compile_to_tm(&source, &compile_options(config, debug_info))
+        .map_err(|err| CliError::Diagnostic(err.diagnostics(), source.clone()))?;
+    warnings.append(&mut artifacts.diagnostics);
+    artifacts.diagnostics = warnings;
+    Ok(artifacts)
+}
+
+// 编译单个文件并写出.tm文件，需要时在虚拟机中运行，失败时返回退出码
+fn compile_file(reporter: &Reporter, file: &Path, output: &Output) -> Result<(), i32> {
+    if output.emit != Emit::Tm {
+        return emit_file(reporter, file, output);
+    }
+    let result = compile_source(file, &reporter.flags, output.debug_info);
+    finish_compile(reporter, file, output, result)
+}
+
+// 写出编译的结果并报告，需要时在虚拟机中运行
+fn finish_compile(
+    reporter: &Reporter,
+    file: &Path,
+    output: &Output,
+    result: Result<Artifacts, CliError>,
+) -> Result<(), i32> {
+    let result = result.and_then(|artifacts| {
+        let path = match &output.path {
+            Some(path) => Some(path.clone()),
+            None if output.run => None,
+            None => Some(output_path(file, artifacts.program_name.as_deref())),
+        };
+        if let Some(path) = &path {
+            write_output(path, &artifacts.listing)?;
+        }
+        Ok((artifacts, path))
+    });
+    let artifacts = match result {
+        Ok((artifacts, path)) => {
+            reporter.warnings(file, &artifacts.diagnostics);
+            // 输出到标准输出或直接运行时，不打印编译结果以免混入程序的输出
+            if let Some(path) = path.as_deref().filter(|path| *path != Path::new("-")) {
+                reporter.compiled(file, Some(path), artifacts.stats.instructions);
+            }
+            artifacts
+        }
+        Err(err) => {
+            reporter.error(file, &err);
+            return Err(err.exit_code());
+        }
+    };
+    if output.run {
+        if let Err(err) = run_listing(&artifacts, output) {
+            reporter.error(file, &err);
+            return Err(err.exit_code());
+        }
+    }
+    Ok(())
+}
+
+// 输出token流、语法树、控制流图或高亮的HTML
+fn emit_file(reporter: &Reporter, file: &Path, output: &Output) -> Result<(), i32> {
+    let result = read_source(file, &reporter.flags).and_then(|(source, warnings, config)| {
+        let text = match output.emit {
+            Emit::Tokens => lexer::format_tokens(&lexer::tokenize(&source)),
+            Emit::Html => highlight::to_html(&source),
+            Emit::Cfg => {
+                let artifacts = compile_to_tm(&source, &compile_options(config, false))
+                    .map_err(|err| CliError::Diagnostic(err.diagnostics(), source.clone()))?;
+                ControlFlowGraph::build(&artifacts.instructions).to_dot()
+            }
+            _ => {
+                let program = Parser::new(&source).parse_program().map_err(|errors| {
+                    let mut diagnostics = DiagnosticBag::new();
+                    diagnostics.extend(&errors);
+                    CliError::Diagnostic(diagnostics, source.clone())
+                })?;
+                program.dump()
+            }
+        };
+        write_output(output.path.as_deref().unwrap_or(Path::new("-")), &text)?;
+        Ok(warnings)
+    });
+    match result {
+        Ok(warnings) => {
+            reporter.warnings(file, &warnings);
+            Ok(())
+        }
+        Err(err) => {
+            reporter.error(file, &err);
+            Err(err.exit_code())
+        }
+    }
+}
+
+// 格式化源文件，默认原地改写；--check时只报告未格式化的文件
+fn format_file(reporter: &Reporter, file: &Path, output: &Output) -> Result<(), i32> {
+    let result = read_source(file, &reporter.flags).and_then(|(source, warnings, config)| {
+        let indent = config.format_indent.unwrap_or(format::DEFAULT_INDENT);
+        let formatted = format::format_with_indent(&source, indent).map_err(|errors| {
+            let mut diagnostics = DiagnosticBag::new();
+            diagnostics.extend(&errors);
+            CliError::Diagnostic(diagnostics, source.clone())
+        })?;
+        if !output.check {
+            write_output(output.path.as_deref().unwrap_or(file), &formatted)?;
+        }
+        Ok((warnings, formatted == source))
+    });
+    match result {
+        Ok((warnings, formatted)) => {
+            reporter.warnings(file, &warnings);
+            if output.check && !formatted {
+                reporter.unformatted(file);
+                return Err(EXIT_ERRORS);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            reporter.error(file, &err);
+            Err(err.exit_code())
+        }
+    }
+}
+
+// 在虚拟机中运行清单，输入来自--input给出的文件或标准输入；运行时错误附带出错指令所属的源程序行号
+fn run_listing(artifacts: &Artifacts, output: &Output) -> Result<(), CliError> {
+    let debug_info = &artifacts.debug_info;
+    let input: Box<dyn BufRead> = match &output.input {
+        Some(path) => {
+            let bytes =
+                fs::read(path).map_err(|err| CliError::Internal(format!("cannot read {}: {}", path.display(), err)))?;
+            Box::new(io::Cursor::new(bytes))
+        }
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut vm = TinyMachine::new(input, io::stdout())
+        .with_memory_size(artifacts.memory_size)
+        .with_profile(output.profile);
+    if output.trace {
+        vm = vm.with_trace(io::stderr());
+    }
+    if let Some(limit) = output.instruction_limit {
+        vm = vm.with_instruction_limit(limit);
+    }
+    if let Some(timeout) = output.timeout {
+        vm = vm.with_timeout(timeout);
+    }
+    vm.load(&artifacts.listing)
+        .map_err(|err| CliError::Internal(format!("cannot load listing: {}", err)))?;
+    vm.debug_info = debug_info.clone();
+    let result = vm.run();
+    if let Some(report) = vm.profile_report(PROFILE_ROWS) {
+        eprint!("{}", report);
+    }
+    result.map_err(|err| match err.pc().and_then(|pc| debug_info.line(pc)) {
+        Some(line) => CliError::Trap(format!("{} (line {})", err, line)),
+        None => CliError::Trap(err.to_string()),
+    })
+}
+
+// 编译程序并在调试器中运行，调试命令与程序的输入都来自标准输入
+fn debug_file(reporter: &Reporter, file: &Path) -> Result<(), i32> {
+    let result = compile_source(file, &reporter.flags, false).and_then(|artifacts| {
+        reporter.warnings(file, &artifacts.diagnostics);
+        let mut vm = TinyMachine::new(io::stdin().lock(), io::stdout()).with_memory_size(artifacts.memory_size);
+        vm.load(&artifacts.listing)
+            .map_err(|err| CliError::Internal(format!("cannot load listing: {}", err)))?;
+        let mut debugger = Debugger::new(vm, artifacts.debug_info, artifacts.symbol_table);
+        debugger
+            .run(io::stdout())
+            .map_err(|err| CliError::Internal(err.to_string()))
+    });
+    result.map_err(|err| {
+        reporter.error(file, &err);
+        err.exit_code()
+    })
+}
+
+// path为"-"时写到标准输出
+fn write_output(path: &Path, text: &str) -> Result<(), CliError> {
+    if path == Path::new("-") {
+        print!("{}", text);
+        return Ok(());
+    }
+    fs::write(path, text).map_err(|err| CliError::Internal(format!("cannot write {}: {}", path.display(), err)))
+}
+
+// 监视文件的修改并重新编译，--input时每次编译成功后以同样的输入重新运行程序
+fn watch(reporter: &Reporter, file: &Path, output: &Output) -> ! {
+    let mut last_modified = None;
+    let mut document = None;
+    loop {
+        let modified = modified_time(file);
+        if modified != last_modified {
+            if modified.is_some() {
+                if last_modified.is_some() {
+                    if let MessageFormat::Human = reporter.format {
+                        println!("---- {} changed, recompiling", file.display());
+                    }
+                }
+                let _ = if output.emit == Emit::Tm {
+                    let result = rebuild(&mut document, file, &reporter.flags, output.debug_info);
+                    finish_compile(reporter, file, output, result)
+                } else {
+                    compile_file(reporter, file, output)
+                };
+            } else {
+                reporter.error(file, &CliError::Internal("cannot read file".to_string()));
+            }
+            last_modified = modified;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// 以增量解析重新编译：文件的新内容作为对上一次内容的修改，只重新解析受影响的最外层语句
+// 与 compile_source 的结果相同；配置中的嵌套深度改变时重新解析整个文件
+fn rebuild(
+    document: &mut Option<Document>,
+    file: &Path,
+    flags: &Config,
+    debug_info: bool,
+) -> Result<Artifacts, CliError> {
+    let (source, mut warnings, config) = read_source(file, flags)?;
+    let max_depth = config.max_depth.unwrap_or(MAX_DEPTH);
+    match document.as_mut() {
+        Some(document) if document.max_depth() == max_depth => {
+            document.replace_text(&source);
+        }
+        _ => *document = Some(Document::with_max_depth(&source, max_depth)),
+    }
+    let document = document.as_ref().unwrap();
+    let errors = document.errors();
+    if !errors.is_empty() {
+        let mut diagnostics = DiagnosticBag::new();
+        diagnostics.extend(&errors);
+        return Err(CliError::Diagnostic(diagnostics, source));
+    }
+    let mut artifacts = compile_program(document.program(), &compile_options(config, debug_info))
+        .map_err(|err| CliError::Diagnostic(err.diagnostics(), source.clone()))?;
+    warnings.append(&mut artifacts.diagnostics);
+    artifacts.diagnostics = warnings;
+    Ok(artifacts)
+}
+
+// 以 compile_many 并行编译目录下的所有.tny文件，逐个打印结果并汇总
+// 各文件生效的配置可能不同，配置相同的文件作为一组编译
+fn check_dir(reporter: &Reporter, dir: &Path) -> Result<(), i32> {
+    let mut files = vec![];
+    if let Err(err) = collect_sources(dir, &mut files) {
+        let err = CliError::Internal(format!("cannot read directory: {}", err));
+        reporter.error(dir, &err);
+        return Err(err.exit_code());
+    }
+    files.sort();
+
+    let mut results: Vec<Option<Result<Artifacts, CliError>>> = files.iter().map(|_| None).collect();
+    let mut groups: Vec<(Config, Vec<usize>)> = vec![];
+    for (index, file) in files.iter().enumerate() {
+        match load_config(file, &reporter.flags) {
+            Ok(config) => match groups.iter_mut().find(|(other, _)| *other == config) {
+                Some((_, indices)) => indices.push(index),
+                None => groups.push((config, vec![index])),
+            },
+            Err(err) => results[index] = Some(Err(err)),
+        }
+    }
+    for (config, indices) in groups {
+        let paths: Vec<&PathBuf> = indices.iter().map(|&index| &files[index]).collect();
+        let compiled = compile_many(&paths, &compile_options(config, false));
+        for (index, result) in indices.into_iter().zip(compiled) {
+            // 文件名由 Reporter 输出
+            let result = result.map(|mut artifacts| {
+                artifacts
+                    .diagnostics
+                    .iter_mut()
+                    .for_each(|diagnostic| diagnostic.file = None);
+                artifacts
+            });
+            results[index] = Some(result.map_err(CliError::from));
+        }
+    }
+
+    let mut failed = 0;
+    let (mut errors, mut warnings) = (0, 0);
+    let mut exit_code = 0;
+    for (file, result) in files.iter().zip(results.into_iter().flatten()) {
+        match &result {
+            Ok(artifacts) => {
+                warnings += artifacts.diagnostics.len();
+                reporter.warnings(file, &artifacts.diagnostics);
+                reporter.compiled(file, None, artifacts.stats.instructions);
+            }
+            Err(err) => {
+                failed += 1;
+                let (file_errors, file_warnings) = err.counts();
+                errors += file_errors;
+                warnings += file_warnings;
+                exit_code = exit_code.max(err.exit_code());
+                reporter.failed(file);
+                reporter.error(file, err);
+            }
+        }
+    }
+    reporter.summary(files.len(), failed, errors, warnings);
+    if exit_code == 0 {
+        Ok(())
+    } else {
+        Err(exit_code)
+    }
+}
+
+// 读取离file最近的tinycc.toml，并以命令行参数覆盖
+fn load_config(file: &Path, flags: &Config) -> Result<Config, CliError> {
+    let mut config =
+        Config::load_for(file).map_err(|err| CliError::Internal(format!("invalid configuration: {}", err)))?;
+    config.merge(flags);
+    Ok(config)
+}
+
+fn collect_sources(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_sources(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "tny") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn modified_time(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+// 默认以程序头部中的程序名作为输出文件名，没有时使用输入文件名
+fn output_path(file: &Path, program_name: Option<&str>) -> PathBuf {
+    match program_name {
+        Some(name) => file.with_file_name(name).with_extension("tm"),
+        None => file.with_extension("tm"),
+    }
+}
+
+// 每条诊断信息一个对象，span为 {"line", "column", "start", "end"}，与源程序中的位置无关时为null
+fn diagnostic_json(file: &Path, diagnostic: &Diagnostic) -> String {
+    json_diagnostic(
+        file,
+        &diagnostic.severity.to_string(),
+        diagnostic.span,
+        &diagnostic.message,
+        diagnostic.help.as_deref(),
+    )
+}
+
+fn json_diagnostic(file: &Path, severity: &str, span: Option<Span>, message: &str, help: Option<&str>) -> String {
+    let span = match span {
+        Some(span) => format!(
+            "{{\"line\":{},\"column\":{},\"start\":{},\"end\":{}}}",
+            span.line, span.column, span.start, span.end
+        ),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"type\":\"diagnostic\",\"file\":{},\"severity\":\"{}\",\"message\":{},\"help\":{},\"span\":{}}}",
+        json_string(&file.display().to_string()),
+        severity,
+        json_string(message),
+        help.map_or("null".to_string(), json_string),
+        span
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => output.push(ch),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{diagnostic_json, rebuild, CliError};
+    use std::fs;
+    use std::path::Path;
+    use tiny_cc::compile_to_tm;
+    use tiny_cc::config::Config;
+
+    #[test]
+    fn test_warning_json() {
+        let artifacts = compile_to_tm("x := 1 / 0;\nwrite x", &Default::default()).unwrap();
+        assert_eq!(
+            diagnostic_json(Path::new("a.tny"), &artifacts.diagnostics[0]),
+            "{\"type\":\"diagnostic\",\"file\":\"a.tny\",\"severity\":\"warning\",\"message\":\"division by zero\",\
+             \"help\":null,\"span\":{\"line\":1,\"column\":10,\"start\":9,\"end\":10}}"
+        );
+    }
+
+    #[test]
+    fn test_rebuild_max_depth() {
+        let root = std::env::temp_dir().join(format!("tiny_cc_rebuild_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("a.tny");
+        fs::write(&file, "x := 1;\nwrite - - -x").unwrap();
+        let mut document = None;
+        assert!(rebuild(&mut document, &file, &Config::default(), false).is_ok());
+
+        // 嵌套深度的限制改变时重新解析，之后的修改同样受其限制
+        let flags = Config {
+            max_depth: Some(3),
+            ..Config::default()
+        };
+        let deep = |result: Result<_, _>| match result {
+            Err(CliError::Diagnostic(diagnostics, _)) => diagnostics
+                .iter()
+                .any(|diag| diag.message.starts_with("nesting too deep")),
+            _ => false,
+        };
+        assert!(deep(rebuild(&mut document, &file, &flags, false)));
+        fs::write(&file, "x := 1;\nwrite -x").unwrap();
+        assert!(rebuild(&mut document, &file, &flags, false).is_ok());
+        fs::write(&file, "x := 1;\nwrite - - -x + 1").unwrap();
+        assert!(deep(rebuild(&mut document, &file, &flags, false)));
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,255 @@
+use crate::code::{DataImage, Instruction, OpCode};
+use crate::driver::Artifacts;
+use std::fmt::{Display, Formatter};
+
+// 目标文件以此开头
+pub const MAGIC: &[u8; 4] = b"TMOB";
+pub const VERSION: u16 = 1;
+
+// 操作码在目标文件中的编号即其在此表中的下标，只能在末尾追加
+const OPCODES: [OpCode; 18] = [
+    OpCode::LDC,
+    OpCode::LD,
+    OpCode::LDA,
+    OpCode::ST,
+    OpCode::IN,
+    OpCode::OUT,
+    OpCode::ADD,
+    OpCode::SUB,
+    OpCode::MUL,
+    OpCode::DIV,
+    OpCode::JLT,
+    OpCode::JLE,
+    OpCode::JEQ,
+    OpCode::JGT,
+    OpCode::JGE,
+    OpCode::JNE,
+    OpCode::HALT,
+    OpCode::OUTS,
+];
+
+// 编译结果的二进制形式，可以缓存并直接由虚拟机装载，不需要再解析文本清单
+// 所有整数均为小端序：
+//   头部     magic(4) version(u16) 程序名长度(u16) 程序名(UTF-8)
+//   指令段   条数(u32)，每条8字节：操作码(u8) 格式(u8, 0为RM、1为RO) r(u8) s(u8) d或t(i32)
+//   数据段   条数(u32)，每条：地址(u32) 值(i32)
+//   符号段   条数(u32)，每条：地址(i32) 名字长度(u16) 名字(UTF-8)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectFile {
+    pub program_name: Option<String>,
+    pub instructions: Vec<Instruction>,
+    pub data: DataImage,
+    // (变量名, 地址)，按地址排序
+    pub symbols: Vec<(String, i32)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ObjectError {
+    // 出错处在目标文件中的字节偏移
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for ObjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl ObjectFile {
+    pub fn from_artifacts(artifacts: &Artifacts) -> Self {
+        let symbols = artifacts
+            .symbol_table
+            .symbols_in_order()
+            .map(|symbol| (symbol.name.to_string(), symbol.address))
+            .collect();
+        Self {
+            program_name: artifacts.program_name.clone(),
+            instructions: artifacts.instructions.clone(),
+            data: artifacts.data.clone(),
+            symbols,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::from(&MAGIC[..]);
+        output.extend_from_slice(&VERSION.to_le_bytes());
+        write_str(&mut output, self.program_name.as_deref().unwrap_or(""));
+
+        output.extend_from_slice(&(self.instructions.len() as u32).to_le_bytes());
+        for instruction in &self.instructions {
+            let code = OPCODES.iter().position(|op| *op == instruction.op()).unwrap() as u8;
+            let (format, r, s, last) = match *instruction {
+                Instruction::Rm { r, d, s, .. } => (0, r, s, d),
+                Instruction::Ro { r, s, t, .. } => (1, r, s, t as i32),
+            };
+            output.extend_from_slice(&[code, format, r as u8, s as u8]);
+            output.extend_from_slice(&last.to_le_bytes());
+        }
+
+        output.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        for (addr, value) in self.data.iter() {
+            output.extend_from_slice(&(addr as u32).to_le_bytes());
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+
+        output.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        for (name, loc) in &self.symbols {
+            output.extend_from_slice(&loc.to_le_bytes());
+            write_str(&mut output, name);
+        }
+        output
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(reader.error(0, "not a TM object file".to_string()));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(reader.error(4, format!("unsupported object file version {}", version)));
+        }
+        let name = reader.str()?;
+        let program_name = (!name.is_empty()).then_some(name);
+
+        let mut instructions = vec![];
+        for _ in 0..reader.u32()? {
+            let start = reader.offset;
+            let [code, format, r, s] = <[u8; 4]>::try_from(reader.take(4)?).unwrap();
+            let last = reader.i32()?;
+            let op = *OPCODES
+                .get(code as usize)
+                .ok_or_else(|| reader.error(start, format!("unknown opcode {}", code)))?;
+            let (r, s) = (r as usize, s as usize);
+            instructions.push(match format {
+                0 => Instruction::Rm { op, r, d: last, s },
+                1 => Instruction::Ro {
+                    op,
+                    r,
+                    s,
+                    t: usize::try_from(last).map_err(|_| reader.error(start + 4, format!("no register {}", last)))?,
+                },
+                _ => return Err(reader.error(start + 1, format!("unknown instruction format {}", format))),
+            });
+        }
+
+        let mut data = DataImage::new();
+        for _ in 0..reader.u32()? {
+            let addr = reader.u32()?;
+            data.set(addr as usize, reader.i32()?);
+        }
+
+        let mut symbols = vec![];
+        for _ in 0..reader.u32()? {
+            let loc = reader.i32()?;
+            symbols.push((reader.str()?, loc));
+        }
+
+        if reader.offset != bytes.len() {
+            return Err(reader.error(reader.offset, "trailing bytes after the symbol section".to_string()));
+        }
+        Ok(Self {
+            program_name,
+            instructions,
+            data,
+            symbols,
+        })
+    }
+}
+
+fn write_str(output: &mut Vec<u8>, value: &str) {
+    output.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    output.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, offset: usize, message: String) -> ObjectError {
+        ObjectError { offset, message }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| self.error(self.offset, "unexpected end of object file".to_string()))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, ObjectError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, ObjectError> {
+        let start = self.offset;
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error(start, "name is not valid UTF-8".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::driver::compile;
+    use crate::object::ObjectFile;
+    use crate::vm::TinyMachine;
+
+    #[test]
+    fn test_round_trip() {
+        let artifacts = compile("program demo; read x; y := x * 100000; write y end.").unwrap();
+        let object = ObjectFile::from_artifacts(&artifacts);
+        // 100000 进入常量池，占用地址2
+        assert_eq!(object.symbols, [("x".to_string(), 1), ("y".to_string(), 3)]);
+        assert_eq!(object.data.get(2), Some(100000));
+        let bytes = object.to_bytes();
+        assert_eq!(&bytes[..6], b"TMOB\x01\x00");
+        let loaded = ObjectFile::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, object);
+        assert_eq!(loaded.program_name.as_deref(), Some("demo"));
+
+        let mut vm = TinyMachine::new("3\n".as_bytes(), vec![]);
+        vm.load_object(&loaded).unwrap();
+        vm.run().unwrap();
+        assert_eq!(String::from_utf8(vm.output).unwrap(), "300000\n");
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = ObjectFile::default().to_bytes();
+        assert_eq!(
+            ObjectFile::from_bytes(b"TMOX").unwrap_err().to_string(),
+            "byte 0: not a TM object file"
+        );
+        assert_eq!(
+            ObjectFile::from_bytes(&bytes[..bytes.len() - 1])
+                .unwrap_err()
+                .to_string(),
+            "byte 16: unexpected end of object file"
+        );
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(ObjectFile::from_bytes(&extra).is_err());
+        let mut version = bytes;
+        version[4] = 9;
+        assert_eq!(
+            ObjectFile::from_bytes(&version).unwrap_err().to_string(),
+            "byte 4: unsupported object file version 9"
+        );
+    }
+}
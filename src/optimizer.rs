@@ -0,0 +1,773 @@
+use crate::ast::{BlockStatement, Boolean, Expr, InfixExpression, Number, Program, Stmt};
+use crate::code::OpCode::{HALT, JEQ, JGE, JGT, JLE, JLT, JNE, LD, LDA};
+use crate::code::{Instruction, RegisterCode};
+use crate::interner::Name;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::TokenType;
+use alloc::collections::{BTreeMap, BTreeSet};
+
+// 常量折叠：将只由数字组成的子表达式替换为它的值
+// 运算与虚拟机一致（溢出时回绕），除数为0的表达式保留到运行时
+pub fn fold_constants(program: &mut Program) {
+    for stmt in &mut program.statements {
+        fold_statement(stmt);
+    }
+}
+
+fn fold_block(block: &mut BlockStatement) {
+    for stmt in &mut block.statements {
+        fold_statement(stmt);
+    }
+}
+
+fn fold_statement(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Assign(assign) => {
+            if let Some(index) = &mut assign.index {
+                fold_expression(index);
+            }
+            fold_expression(&mut assign.value);
+        }
+        Stmt::If(if_stmt) => {
+            fold_expression(&mut if_stmt.cond);
+            fold_block(&mut if_stmt.consequence);
+            if let Some(alternative) = &mut if_stmt.alternative {
+                fold_block(alternative);
+            }
+        }
+        Stmt::Repeat(repeat) => {
+            fold_block(&mut repeat.consequence);
+            fold_expression(&mut repeat.cond);
+        }
+        Stmt::While(while_stmt) => {
+            fold_expression(&mut while_stmt.cond);
+            fold_block(&mut while_stmt.consequence);
+        }
+        Stmt::Write(write) => fold_expression(&mut write.value),
+        Stmt::Function(function) => fold_block(&mut function.body),
+        Stmt::Return(ret) => {
+            if let Some(value) = &mut ret.value {
+                fold_expression(value);
+            }
+        }
+        Stmt::Call(call) => call.call.args.iter_mut().for_each(fold_expression),
+        Stmt::Read(_) | Stmt::Var(_) | Stmt::Error(_) => {}
+    }
+}
+
+pub fn fold_expression(expr: &mut Expr) {
+    let span = match expr {
+        Expr::Prefix(prefix) => {
+            fold_expression(&mut prefix.right);
+            prefix.span
+        }
+        Expr::Infix(infix) => {
+            fold_expression(&mut infix.left);
+            fold_expression(&mut infix.right);
+            infix.span
+        }
+        Expr::Index(index) => {
+            fold_expression(&mut index.index);
+            return;
+        }
+        Expr::Call(call) => {
+            call.args.iter_mut().for_each(fold_expression);
+            return;
+        }
+        Expr::Identifier(_) | Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Error(_) => return,
+    };
+    if let Some(value) = constant_value(expr) {
+        *expr = Expr::Number(Number { value, span });
+    }
+}
+
+// 若表达式在编译期即可求值，返回它的值
+pub fn constant_value(expr: &Expr) -> Option<i32> {
+    match expr {
+        Expr::Number(number) => Some(number.value),
+        Expr::Boolean(boolean) => Some(boolean.value as i32),
+        Expr::Prefix(prefix) => {
+            let right = constant_value(&prefix.right)?;
+            match prefix.op.token_type {
+                TokenType::Minus => Some(right.wrapping_neg()),
+                TokenType::Not => Some((right == 0) as i32),
+                _ => None,
+            }
+        }
+        Expr::Infix(infix) => {
+            let left = constant_value(&infix.left)?;
+            let right = constant_value(&infix.right)?;
+            eval_infix(infix.op.token_type, left, right)
+        }
+        Expr::Index(_) | Expr::Call(_) | Expr::Identifier(_) | Expr::String(_) | Expr::Error(_) => None,
+    }
+}
+
+// 二元运算的值，与虚拟机的语义一致；除数为0或不是二元运算符时返回None
+// 生成的代码以两数之差的符号比较大小，差溢出时的结果与数学上的大小关系相反
+pub(crate) fn eval_infix(op: TokenType, left: i32, right: i32) -> Option<i32> {
+    let difference = left.wrapping_sub(right);
+    match op {
+        TokenType::Add => Some(left.wrapping_add(right)),
+        TokenType::Minus => Some(left.wrapping_sub(right)),
+        TokenType::Mul => Some(left.wrapping_mul(right)),
+        TokenType::Divide if right != 0 => Some(left.wrapping_div(right)),
+        TokenType::LessThan => Some((difference < 0) as i32),
+        TokenType::EqualLessThan => Some((difference <= 0) as i32),
+        TokenType::GreaterThan => Some((difference > 0) as i32),
+        TokenType::EqualGreaterThan => Some((difference >= 0) as i32),
+        TokenType::Equal => Some((difference == 0) as i32),
+        TokenType::NotEqual => Some((difference != 0) as i32),
+        // 操作数为0或1
+        TokenType::And => Some((left != 0 && right != 0) as i32),
+        TokenType::Or => Some((left != 0 || right != 0) as i32),
+        _ => None,
+    }
+}
+
+// 常量传播：变量被赋值为常量之后，将对它的使用替换为该常量，直到它被重新赋值或read，并折叠替换后的表达式
+// 按语句的控制流做数据流分析：if的两个分支汇合时只保留两边值相同的变量，
+// 在循环中被赋值的变量在整个循环中都不是常量；调用函数之后，在任何函数中被赋值的变量都不再是常量
+// 函数体单独分析，进入函数时没有已知的常量；返回替换的次数
+pub fn propagate_constants(program: &mut Program) -> usize {
+    let mut propagation = Propagation::default();
+    for stmt in &program.statements {
+        if let Stmt::Function(function) = stmt {
+            assigned_variables(&function.body, &mut propagation.clobbered);
+        }
+    }
+    let mut constants = BTreeMap::new();
+    for stmt in &mut program.statements {
+        propagation.statement(stmt, &mut constants);
+    }
+    propagation.propagated
+}
+
+// 变量 => 它当前的值，为数字或布尔值
+type Constants = BTreeMap<Name, Expr>;
+
+#[derive(Default)]
+struct Propagation {
+    // 调用函数时可能被修改的变量
+    clobbered: BTreeSet<Name>,
+    propagated: usize,
+}
+
+impl Propagation {
+    fn block(&mut self, block: &mut BlockStatement, constants: &mut Constants) {
+        for stmt in &mut block.statements {
+            self.statement(stmt, constants);
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Stmt, constants: &mut Constants) {
+        match stmt {
+            Stmt::Assign(assign) => {
+                if let Some(index) = &mut assign.index {
+                    self.expression(index, constants);
+                }
+                self.expression(&mut assign.value, constants);
+                constants.remove(&assign.name.value);
+                if assign.index.is_none() && matches!(assign.value, Expr::Number(_) | Expr::Boolean(_)) {
+                    constants.insert(assign.name.value.clone(), assign.value.clone());
+                }
+            }
+            Stmt::Read(read) => {
+                constants.remove(&read.name.value);
+            }
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    constants.remove(&declaration.name.value);
+                }
+            }
+            Stmt::Write(write) => self.expression(&mut write.value, constants),
+            Stmt::If(if_stmt) => {
+                self.expression(&mut if_stmt.cond, constants);
+                let mut alternative = constants.clone();
+                self.block(&mut if_stmt.consequence, constants);
+                if let Some(block) = &mut if_stmt.alternative {
+                    self.block(block, &mut alternative);
+                }
+                constants.retain(|name, value| {
+                    alternative
+                        .get(name)
+                        .is_some_and(|other| constant_value(other) == constant_value(value))
+                });
+            }
+            // 循环体至少执行一次，条件在循环体之后计算，循环结束时的常量即条件处的常量
+            Stmt::Repeat(repeat) => {
+                self.enter_loop(&repeat.consequence, &repeat.cond, constants);
+                self.block(&mut repeat.consequence, constants);
+                self.expression(&mut repeat.cond, constants);
+            }
+            Stmt::While(while_stmt) => {
+                self.enter_loop(&while_stmt.consequence, &while_stmt.cond, constants);
+                self.expression(&mut while_stmt.cond, constants);
+                self.block(&mut while_stmt.consequence, &mut constants.clone());
+            }
+            Stmt::Return(ret) => {
+                if let Some(value) = &mut ret.value {
+                    self.expression(value, constants);
+                }
+            }
+            // 实参在调用之前计算
+            Stmt::Call(call) => {
+                for arg in &mut call.call.args {
+                    self.expression(arg, constants);
+                }
+                self.clobber(constants);
+            }
+            Stmt::Function(function) => self.block(&mut function.body, &mut Constants::new()),
+            Stmt::Error(_) => {}
+        }
+    }
+
+    // 删除在循环中可能被修改的变量
+    fn enter_loop(&self, body: &BlockStatement, cond: &Expr, constants: &mut Constants) {
+        let mut exprs = vec![cond];
+        let mut modified = BTreeSet::new();
+        let mut calls = false;
+        loop_expressions(body, &mut exprs, &mut modified, &mut calls);
+        if calls || exprs.iter().any(|expr| contains_call(expr)) {
+            self.clobber(constants);
+        }
+        constants.retain(|name, _| !modified.contains(name.as_str()));
+    }
+
+    fn clobber(&self, constants: &mut Constants) {
+        constants.retain(|name, _| !self.clobbered.contains(name));
+    }
+
+    // 含有函数调用的表达式中，可能被调用修改的变量不作替换
+    fn expression(&mut self, expr: &mut Expr, constants: &mut Constants) {
+        if contains_call(expr) {
+            self.clobber(constants);
+        }
+        self.substitute(expr, constants);
+        fold_expression(expr);
+    }
+
+    fn substitute(&mut self, expr: &mut Expr, constants: &Constants) {
+        match expr {
+            Expr::Identifier(ident) => {
+                if let Some(value) = constants.get(&ident.value) {
+                    let span = ident.span;
+                    *expr = match value {
+                        Expr::Boolean(boolean) => Expr::Boolean(Boolean {
+                            value: boolean.value,
+                            span,
+                        }),
+                        value => Expr::Number(Number {
+                            value: constant_value(value).expect("only constants are recorded"),
+                            span,
+                        }),
+                    };
+                    self.propagated += 1;
+                }
+            }
+            Expr::Prefix(prefix) => self.substitute(&mut prefix.right, constants),
+            Expr::Infix(infix) => {
+                self.substitute(&mut infix.left, constants);
+                self.substitute(&mut infix.right, constants);
+            }
+            Expr::Index(index) => self.substitute(&mut index.index, constants),
+            Expr::Call(call) => {
+                for arg in &mut call.args {
+                    self.substitute(arg, constants);
+                }
+            }
+            Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Error(_) => {}
+        }
+    }
+}
+
+// block中被赋值、read或声明的变量，包括数组
+fn assigned_variables(block: &BlockStatement, variables: &mut BTreeSet<Name>) {
+    for stmt in &block.statements {
+        match stmt {
+            Stmt::Assign(assign) => {
+                variables.insert(assign.name.value.clone());
+            }
+            Stmt::Read(read) => {
+                variables.insert(read.name.value.clone());
+            }
+            Stmt::Var(var) => {
+                variables.extend(
+                    var.declarations
+                        .iter()
+                        .map(|declaration| declaration.name.value.clone()),
+                );
+            }
+            Stmt::If(if_stmt) => {
+                assigned_variables(&if_stmt.consequence, variables);
+                if let Some(alternative) = &if_stmt.alternative {
+                    assigned_variables(alternative, variables);
+                }
+            }
+            Stmt::Repeat(repeat) => assigned_variables(&repeat.consequence, variables),
+            Stmt::While(while_stmt) => assigned_variables(&while_stmt.consequence, variables),
+            Stmt::Function(function) => assigned_variables(&function.body, variables),
+            Stmt::Write(_) | Stmt::Return(_) | Stmt::Call(_) | Stmt::Error(_) => {}
+        }
+    }
+}
+
+// 强度削减的结果，见 reduce_strength
+#[derive(Debug, Copy, Clone)]
+pub enum Reduction<'a> {
+    // 运算的结果就是这个操作数
+    Identity(&'a Expr),
+    // 操作数乘以2的times次方，可以用times次加法计算
+    Double(&'a Expr, u32),
+}
+
+// 强度削减：x * 1、1 * x、x + 0、0 + x、x - 0与x / 1即为x，乘以2的幂可以化为连续的加法
+// 被省略的操作数都是常量，不会丢失副作用；加法溢出时与乘法同样回绕
+// TM没有移位指令，除以2的幂无法化为更简单的运算，保持不变
+pub fn reduce_strength(infix: &InfixExpression) -> Option<Reduction<'_>> {
+    let left = constant_value(&infix.left);
+    let right = constant_value(&infix.right);
+    match (infix.op.token_type, left, right) {
+        (TokenType::Mul | TokenType::Divide, _, Some(1)) | (TokenType::Add | TokenType::Minus, _, Some(0)) => {
+            Some(Reduction::Identity(&infix.left))
+        }
+        (TokenType::Mul, Some(1), _) | (TokenType::Add, Some(0), _) => Some(Reduction::Identity(&infix.right)),
+        (TokenType::Mul, _, Some(value)) if is_power_of_two(value) => {
+            Some(Reduction::Double(&infix.left, value.trailing_zeros()))
+        }
+        (TokenType::Mul, Some(value), _) if is_power_of_two(value) => {
+            Some(Reduction::Double(&infix.right, value.trailing_zeros()))
+        }
+        _ => None,
+    }
+}
+
+fn is_power_of_two(value: i32) -> bool {
+    value > 1 && value.count_ones() == 1
+}
+
+// 公共子表达式消除：按求值的顺序遍历一个语句中的表达式，返回第一次计算之后还会再次出现的子表达式，
+// 以 subexpression_key 表示；再次出现时可以直接使用保存的结果，其中的子表达式也不必再计算
+// and、or的右操作数不一定执行，其中第一次出现的子表达式不能在之后复用
+// 表达式中含有函数调用时不做消除，调用可能修改全局变量与数组
+pub fn common_subexpressions(exprs: &[&Expr]) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    let mut reused = vec![];
+    if exprs.iter().all(|expr| key(expr).is_some()) {
+        for expr in exprs {
+            visit_subexpressions(expr, false, &mut seen, &mut reused);
+        }
+    }
+    reused
+}
+
+fn visit_subexpressions(expr: &Expr, conditional: bool, seen: &mut BTreeSet<String>, reused: &mut Vec<String>) {
+    // 常量表达式在编译时折叠
+    if constant_value(expr).is_some() {
+        return;
+    }
+    if let Some(key) = subexpression_key(expr) {
+        if seen.contains(&key) {
+            if !reused.contains(&key) {
+                reused.push(key);
+            }
+            return;
+        }
+        if !conditional {
+            seen.insert(key);
+        }
+    }
+    match expr {
+        Expr::Prefix(prefix) => visit_subexpressions(&prefix.right, conditional, seen, reused),
+        Expr::Infix(infix) => {
+            visit_subexpressions(&infix.left, conditional, seen, reused);
+            let conditional = conditional || infix.op.token_type.is_logical();
+            visit_subexpressions(&infix.right, conditional, seen, reused);
+        }
+        Expr::Index(index) => visit_subexpressions(&index.index, conditional, seen, reused),
+        _ => {}
+    }
+}
+
+// 可以被公共子表达式消除复用的表达式的完全加括号的形式，相同的表达式有相同的key
+// 变量与常量直接读取即可，不值得复用；含有函数调用的表达式不能复用
+pub fn subexpression_key(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Prefix(_) | Expr::Infix(_) | Expr::Index(_) => key(expr),
+        _ => None,
+    }
+}
+
+// 循环不变量外提：循环中每次计算的结果都相同的表达式，可以在进入循环之前计算一次
+// 表达式中的变量在循环体与条件中不被赋值或read，并且不含数组元素、函数调用与除法：
+// 外提的表达式即使在循环中不会被计算也会被计算，它们不能引起运行时错误
+// 循环中含有函数调用时不做外提，调用可能修改全局变量
+// 返回每个不变量第一次出现的表达式，按出现的顺序，不含其中的子表达式
+pub fn loop_invariants<'a>(body: &'a BlockStatement, cond: &'a Expr) -> Vec<&'a Expr> {
+    let mut exprs = vec![cond];
+    let mut modified = BTreeSet::new();
+    let mut calls = false;
+    loop_expressions(body, &mut exprs, &mut modified, &mut calls);
+    if calls || exprs.iter().any(|expr| contains_call(expr)) {
+        return vec![];
+    }
+    let mut seen = BTreeSet::new();
+    let mut invariants = vec![];
+    for expr in exprs {
+        collect_invariants(expr, &modified, &mut seen, &mut invariants);
+    }
+    invariants
+}
+
+// 循环中计算的所有表达式，被赋值的变量，以及是否含有调用语句
+fn loop_expressions<'a>(
+    block: &'a BlockStatement,
+    exprs: &mut Vec<&'a Expr>,
+    modified: &mut BTreeSet<&'a str>,
+    calls: &mut bool,
+) {
+    for stmt in &block.statements {
+        match stmt {
+            Stmt::Assign(assign) => {
+                exprs.push(&assign.value);
+                match &assign.index {
+                    Some(index) => exprs.push(index),
+                    None => {
+                        modified.insert(&assign.name.value);
+                    }
+                }
+            }
+            Stmt::Read(read) => {
+                modified.insert(&read.name.value);
+            }
+            Stmt::Write(write) => exprs.push(&write.value),
+            Stmt::If(if_stmt) => {
+                exprs.push(&if_stmt.cond);
+                loop_expressions(&if_stmt.consequence, exprs, modified, calls);
+                if let Some(alternative) = &if_stmt.alternative {
+                    loop_expressions(alternative, exprs, modified, calls);
+                }
+            }
+            Stmt::Repeat(repeat) => {
+                exprs.push(&repeat.cond);
+                loop_expressions(&repeat.consequence, exprs, modified, calls);
+            }
+            Stmt::While(while_stmt) => {
+                exprs.push(&while_stmt.cond);
+                loop_expressions(&while_stmt.consequence, exprs, modified, calls);
+            }
+            Stmt::Return(ret) => exprs.extend(&ret.value),
+            Stmt::Call(_) => *calls = true,
+            Stmt::Function(function) => loop_expressions(&function.body, exprs, modified, calls),
+            Stmt::Var(_) | Stmt::Error(_) => {}
+        }
+    }
+}
+
+fn collect_invariants<'a>(
+    expr: &'a Expr,
+    modified: &BTreeSet<&str>,
+    seen: &mut BTreeSet<String>,
+    invariants: &mut Vec<&'a Expr>,
+) {
+    if constant_value(expr).is_some() {
+        return;
+    }
+    if matches!(expr, Expr::Prefix(_) | Expr::Infix(_)) && is_invariant(expr, modified) {
+        if let Some(key) = key(expr) {
+            if seen.insert(key) {
+                invariants.push(expr);
+            }
+        }
+        return;
+    }
+    match expr {
+        Expr::Prefix(prefix) => collect_invariants(&prefix.right, modified, seen, invariants),
+        Expr::Infix(infix) => {
+            collect_invariants(&infix.left, modified, seen, invariants);
+            collect_invariants(&infix.right, modified, seen, invariants);
+        }
+        Expr::Index(index) => collect_invariants(&index.index, modified, seen, invariants),
+        _ => {}
+    }
+}
+
+fn is_invariant(expr: &Expr, modified: &BTreeSet<&str>) -> bool {
+    match expr {
+        Expr::Prefix(prefix) => is_invariant(&prefix.right, modified),
+        Expr::Infix(infix) => {
+            infix.op.token_type != TokenType::Divide
+                && is_invariant(&infix.left, modified)
+                && is_invariant(&infix.right, modified)
+        }
+        Expr::Identifier(ident) => !modified.contains(ident.value.as_str()),
+        Expr::Number(_) | Expr::Boolean(_) => true,
+        Expr::Index(_) | Expr::Call(_) | Expr::String(_) | Expr::Error(_) => false,
+    }
+}
+
+fn contains_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) => true,
+        Expr::Prefix(prefix) => contains_call(&prefix.right),
+        Expr::Infix(infix) => contains_call(&infix.left) || contains_call(&infix.right),
+        Expr::Index(index) => contains_call(&index.index),
+        Expr::Identifier(_) | Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Error(_) => false,
+    }
+}
+
+fn key(expr: &Expr) -> Option<String> {
+    Some(match expr {
+        Expr::Prefix(prefix) => format!("({} {})", prefix.op.literal, key(&prefix.right)?),
+        Expr::Infix(infix) => format!("({} {} {})", key(&infix.left)?, infix.op.literal, key(&infix.right)?),
+        Expr::Index(index) => format!("{}[{}]", index.name.value, key(&index.index)?),
+        Expr::Identifier(ident) => ident.value.to_string(),
+        Expr::Number(number) => number.value.to_string(),
+        Expr::Boolean(boolean) => boolean.value.to_string(),
+        Expr::Call(_) | Expr::String(_) | Expr::Error(_) => return None,
+    })
+}
+
+// 死代码消除：删除从第0条指令出发无法到达的指令，并重新计算跳转的偏移量
+// 返回新的指令序列，以及每条原指令的新地址（被删除的为None）
+// 若存在无法静态确定目标的跳转（如以PC以外的寄存器为基址），返回None
+pub fn eliminate_dead_code(instructions: &[Instruction]) -> Option<(Vec<Instruction>, Vec<Option<usize>>)> {
+    let pc = usize::from(RegisterCode::PC);
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = vec![0];
+    while let Some(loc) = pending.pop() {
+        if loc >= instructions.len() || reachable[loc] {
+            continue;
+        }
+        reachable[loc] = true;
+        match instructions[loc] {
+            Instruction::Ro { op: HALT, .. } => {}
+            Instruction::Rm { op: LDA, r, d, s } if r == pc => {
+                if s != pc {
+                    return None;
+                }
+                pending.push(jump_target(loc, d)?);
+            }
+            Instruction::Rm { op: LD, r, .. } if r == pc => return None,
+            Instruction::Rm {
+                op: JLT | JLE | JEQ | JGT | JGE | JNE,
+                d,
+                s,
+                ..
+            } => {
+                if s != pc {
+                    return None;
+                }
+                pending.push(jump_target(loc, d)?);
+                pending.push(loc + 1);
+            }
+            _ => pending.push(loc + 1),
+        }
+    }
+
+    let mut locations = vec![None; instructions.len()];
+    let mut next = 0;
+    for (loc, location) in locations.iter_mut().enumerate() {
+        if reachable[loc] {
+            *location = Some(next);
+            next += 1;
+        }
+    }
+    // 跳转目标的新地址；目标可能是最后一条指令之后的位置
+    let relocate = |target: usize| locations.get(target).copied().flatten().unwrap_or(next);
+    let mut output = Vec::with_capacity(next);
+    for (loc, instruction) in instructions.iter().enumerate() {
+        if !reachable[loc] {
+            continue;
+        }
+        let mut instruction = *instruction;
+        if let Instruction::Rm { op, r, d, s } = &mut instruction {
+            let is_jump = matches!(op, JLT | JLE | JEQ | JGT | JGE | JNE) || *op == LDA && *r == pc;
+            if is_jump && *s == pc {
+                let target = relocate(jump_target(loc, *d)?);
+                *d = target as i32 - (output.len() as i32 + 1);
+            }
+        }
+        output.push(instruction);
+    }
+    Some((output, locations))
+}
+
+fn jump_target(loc: usize, offset: i32) -> Option<usize> {
+    usize::try_from(loc as i64 + 1 + offset as i64).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{to_source, Expr, Node, Stmt};
+    use crate::code::Instruction;
+    use crate::code::OpCode::{HALT, JEQ, LDA, LDC, OUT};
+    use crate::code::RegisterCode::{AC, PC};
+    use crate::optimizer::{
+        common_subexpressions, constant_value, eliminate_dead_code, fold_constants, loop_invariants,
+        propagate_constants, reduce_strength, Reduction,
+    };
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_fold_constants() {
+        let input = "x := 2 * 4; if 3 < 5 then y := x + 1 end; z := 1 / 0; var a[4]; a[1 + 2] := a[3 - 1];";
+        let mut program = Parser::new(input).parse_program().unwrap();
+        fold_constants(&mut program);
+        assert_eq!(
+            to_source(&program),
+            "x := 8;\nif 1 then\n    y := x + 1;\nend;\nz := 1 / 0;\nvar a[4];\na[3] := a[2];\n"
+        );
+        match &program.statements[0] {
+            Stmt::Assign(assign) => assert!(matches!(&assign.value, Expr::Number(number) if number.value == 8)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_propagate_constants() {
+        let propagate = |input: &str| {
+            let mut program = Parser::new(input).parse_program().unwrap();
+            let propagated = propagate_constants(&mut program);
+            (to_source(&program), propagated)
+        };
+        assert_eq!(
+            propagate("x := 5; y := x + 1; write x * y; read x; write x"),
+            ("x := 5;\ny := 6;\nwrite 30;\nread x;\nwrite x;\n".to_string(), 3)
+        );
+        // 两个分支中值相同的变量在if之后仍是常量
+        let input = "read c; if c then x := 1; y := 2 else x := 1; y := 3 end; write x + y";
+        assert_eq!(propagate(input).0.lines().last(), Some("write 1 + y;"));
+        // 循环中被赋值的变量在循环条件与循环体中都不是常量
+        let input = "i := 0; n := 3; while i < n do i := i + 1 end; repeat write n; n := n - 1 until n = 0";
+        assert_eq!(
+            propagate(input).0,
+            "i := 0;\nn := 3;\nwhile i < 3 do\n    i := i + 1;\nend;\nrepeat\n    write n;\n    n := n - 1;\nuntil n = 0;\n"
+        );
+        // 函数中可能修改的变量在调用之后不是常量，函数中不使用函数之外的常量
+        let input = "fn f(a) g := a; return k end; g := 1; k := 2; write f(g) + g + k; write k";
+        assert_eq!(
+            propagate(input).0,
+            "fn f(a)\n    g := a;\n    return k;\nend;\ng := 1;\nk := 2;\nwrite f(g) + g + 2;\nwrite 2;\n"
+        );
+    }
+
+    #[test]
+    fn test_constant_value() {
+        let value = |input: &str| match &Parser::new(input).parse_program().unwrap().statements[0] {
+            Stmt::Assign(assign) => constant_value(&assign.value),
+            _ => unreachable!(),
+        };
+        assert_eq!(value("x := 2147483647 + 1"), Some(i32::MIN));
+        assert_eq!(value("x := 7 / 2"), Some(3));
+        assert_eq!(value("x := -7 + 2"), Some(-5));
+        assert_eq!(value("x := 3 = 3"), Some(1));
+        assert_eq!(value("x := 1 + 2 * 3"), Some(7));
+        assert_eq!(value("x := true and not 1 < 2"), Some(0));
+        assert_eq!(value("x := false or 2 > 1"), Some(1));
+        assert_eq!(value("x := 3 / 0"), None);
+        assert_eq!(value("x := y + 1"), None);
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let reused = |input: &str| {
+            let program = Parser::new(input).parse_program().unwrap();
+            let Stmt::Assign(assign) = &program.statements[0] else {
+                unreachable!()
+            };
+            let exprs: Vec<_> = core::iter::once(&assign.value).chain(&assign.index).collect();
+            common_subexpressions(&exprs)
+        };
+        assert_eq!(reused("y := a * b + a * b"), ["(a * b)"]);
+        // 复用外层的表达式时，其中的子表达式不再计算
+        assert_eq!(reused("y := a[i + 1] - a[i + 1] * 2"), ["a[(i + 1)]"]);
+        assert_eq!(reused("y := -x * -x + x * 2 * 3 + -x"), ["(- x)"]);
+        assert_eq!(reused("c[i * 2] := i * 2 + 1"), ["(i * 2)"]);
+        // and的右操作数中第一次出现的表达式不能复用，之前出现的可以
+        assert_eq!(reused("y := a + 1 < 2 and a + 1 > b * 2 and b * 2 > 0"), ["(a + 1)"]);
+        assert!(reused("y := f(a) * a + f(a) * a").is_empty());
+        assert!(reused("y := 2 * 3 + 2 * 3").is_empty());
+    }
+
+    #[test]
+    fn test_loop_invariants() {
+        let invariants = |input: &str| {
+            let program = Parser::new(input).parse_program().unwrap();
+            let (body, cond) = match program.statements.last().unwrap() {
+                Stmt::Repeat(repeat) => (&repeat.consequence, &repeat.cond),
+                Stmt::While(while_stmt) => (&while_stmt.consequence, &while_stmt.cond),
+                _ => unreachable!(),
+            };
+            loop_invariants(body, cond)
+                .iter()
+                .map(|expr| input[expr.span().start..expr.span().end].to_string())
+                .collect::<Vec<_>>()
+        };
+        let input = "repeat s := s + n * m - 1; i := i + 1; if -k < i then write n * m end until i > n + 2 * 3";
+        assert_eq!(invariants(input), ["n + 2 * 3", "n * m", "-k"]);
+        // 除法与数组元素可能引起运行时错误，被赋值的变量不是不变量
+        let input = "while i < n do a[i] := a[n] + n / m + n * i; read m; i := i + 1 end";
+        assert!(invariants(input).is_empty());
+        let input = "while i < n * 2 do f(i); i := i + 1 end";
+        assert!(invariants(input).is_empty());
+    }
+
+    #[test]
+    fn test_reduce_strength() {
+        let reduce = |input: &str| {
+            let program = Parser::new(input).parse_program().unwrap();
+            let Stmt::Assign(assign) = &program.statements[0] else {
+                unreachable!()
+            };
+            let Expr::Infix(infix) = &assign.value else {
+                unreachable!()
+            };
+            let text = |operand: &Expr| input[operand.span().start..operand.span().end].to_string();
+            match reduce_strength(infix) {
+                Some(Reduction::Identity(operand)) => text(operand),
+                Some(Reduction::Double(operand, times)) => format!("{} << {}", text(operand), times),
+                None => "none".to_string(),
+            }
+        };
+        assert_eq!(reduce("x := y * 1"), "y");
+        assert_eq!(reduce("x := 0 + y * z"), "y * z");
+        assert_eq!(reduce("x := f(y) - 0"), "f(y)");
+        assert_eq!(reduce("x := y / 1"), "y");
+        assert_eq!(reduce("x := y * 8"), "y << 3");
+        assert_eq!(reduce("x := 2 * y"), "y << 1");
+        assert_eq!(reduce("x := 0 - y"), "none");
+        assert_eq!(reduce("x := y / 4"), "none");
+        assert_eq!(reduce("x := y * 6"), "none");
+        assert_eq!(reduce("x := y * -4"), "none");
+    }
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let instructions = [
+            Instruction::rm(LDC, AC, 1, AC),
+            Instruction::rm(JEQ, AC, 3, PC),
+            Instruction::rm(LDA, PC, 1, PC),
+            // 无法到达
+            Instruction::ro(OUT, AC, AC, AC),
+            Instruction::ro(OUT, AC, AC, AC),
+            Instruction::ro(HALT, AC, AC, AC),
+            Instruction::ro(OUT, AC, AC, AC),
+        ];
+        let (output, locations) = eliminate_dead_code(&instructions).unwrap();
+        assert_eq!(
+            output,
+            [
+                Instruction::rm(LDC, AC, 1, AC),
+                Instruction::rm(JEQ, AC, 2, PC),
+                Instruction::rm(LDA, PC, 0, PC),
+                Instruction::ro(OUT, AC, AC, AC),
+                Instruction::ro(HALT, AC, AC, AC),
+            ]
+        );
+        assert_eq!(locations, [Some(0), Some(1), Some(2), None, Some(3), Some(4), None]);
+        // 以其他寄存器为基址的跳转无法分析
+        assert!(eliminate_dead_code(&[Instruction::rm(LDA, PC, 0, AC)]).is_none());
+    }
+}
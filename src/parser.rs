@@ -1,193 +1,887 @@
+use crate::arena::Ast;
 use crate::ast::{
-    AssignStatement, BlockStatement, Expression, Identifier, IfStatement, InfixExpression, Number, Program,
-    ReadStatement, RepeatStatement, Statement, WriteStatement,
+    AssignStatement, BlockStatement, Boolean, CallExpression, CallStatement, Declaration, ErrorExpression,
+    ErrorStatement, Expr, FunctionStatement, Identifier, IfStatement, IndexExpression, InfixExpression, Node, Number,
+    PrefixExpression, Program, ReadStatement, RepeatStatement, ReturnStatement, Stmt, StringLiteral, VarStatement,
+    WhileStatement, WriteStatement,
 };
+use crate::interner::Name;
 use crate::lexer::Lexer;
-use crate::token::TokenType::Until;
-use crate::token::{Token, TokenType};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{Precedence, Span, Token, TokenType};
+use crate::token_stream::TokenStream;
+use core::fmt::{Display, Formatter};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, BufReader, Read};
+#[cfg(feature = "std")]
+use std::path::Path;
 
-pub struct Parser {
-    lexer: Lexer,
-    peek: Token,
+// 语句与表达式嵌套的默认最大深度，见 Parser::with_max_depth
+pub const MAX_DEPTH: usize = 128;
+
+pub struct Parser<'a> {
+    tokens: TokenStream<'a>,
+    errors: Vec<ParseError>,
+    warnings: Vec<String>,
+    // 正在解析的语句与表达式的嵌套深度
+    depth: usize,
+    max_depth: usize,
+    // 嵌套过深的错误在errors中的下标
+    too_deep: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    // 出错处的token在token流中的序号，从0开始；源程序中的位置见found.span
+    pub position: usize,
+    // 此处期望出现的token类型，可能为空
+    pub expected: Vec<TokenType>,
+    pub found: Token,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: {}", self.found.span, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+// 见 Parser::parse_statements，供 incremental 使用
+#[cfg(feature = "std")]
+pub(crate) struct ParsedStatement {
+    pub(crate) stmt: Stmt,
+    // 消耗的token数
+    pub(crate) tokens: usize,
+    // 解析时读取过的源程序的终点，可能超过语句本身，如用于判断语句结束的下一个token
+    pub(crate) examined: usize,
+    // 错误中的position从解析开始处计数
+    pub(crate) errors: Vec<ParseError>,
 }
 
-impl Parser {
-    pub fn new(input: &str) -> Self {
-        let mut lexer = Lexer::new(input);
-        let peek = lexer.next_token();
-        Self { lexer, peek }
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self::from_lexer(Lexer::new(input))
+    }
+
+    // 从字节流读取整个源程序，处理BOM、CRLF换行以及非法的UTF-8字节
+    #[cfg(feature = "std")]
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let (input, warnings) = crate::lexer::decode_source(&bytes);
+        let mut parser = Self::from_lexer(Lexer::new(input));
+        parser.warnings = warnings;
+        Ok(parser)
     }
 
-    fn peek_token(&self) -> Token {
-        self.peek.clone()
+    // 在解析的同时逐行读取源程序，见 Lexer::from_buf_read
+    // 读取出错时，错误作为语法错误在解析结束时报告
+    #[cfg(feature = "std")]
+    pub fn from_buf_read(reader: impl BufRead + 'static) -> Self {
+        Self::from_lexer(Lexer::from_buf_read(reader))
+    }
+
+    // 使用给定的词法分析器，见 Lexer::starting_at
+    pub fn from_lexer(lexer: Lexer<'a>) -> Self {
+        Self {
+            tokens: TokenStream::new(lexer),
+            errors: vec![],
+            warnings: vec![],
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            too_deep: None,
+        }
     }
 
+    #[cfg(feature = "std")]
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_buf_read(BufReader::new(fs::File::open(path)?)))
+    }
+
+    // 保留源程序中的注释，解析结果中的注释见 Program::comments
+    // 需要在解析之前调用
+    pub fn with_comments(self, comments: bool) -> Self {
+        Self {
+            tokens: TokenStream::new(self.tokens.into_lexer().with_comments(comments)),
+            ..self
+        }
+    }
+
+    // 语句与表达式嵌套的最大深度，默认为 MAX_DEPTH
+    // 超过时报告一个错误并跳过其余的源程序，避免递归下降的解析以及之后对语法树的处理耗尽栈空间
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    // 不影响解析结果的问题，如源文件编码有误
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    fn peek_token(&mut self) -> &Token {
+        self.tokens.peek_token()
+    }
+
+    // 需要保存在AST或错误中的token才复制，否则使用 Parser::advance
     fn next_token(&mut self) -> Token {
-        let cur = self.peek_token();
-        self.peek = self.lexer.next_token();
-        cur
+        self.tokens.next_token()
     }
 
-    pub fn parse_program(&mut self) -> Program {
-        let mut program = Program::new();
-        while self.peek_token().token_type != TokenType::Eof {
+    // 消耗下一个token，返回它的位置
+    fn advance(&mut self) -> Span {
+        self.tokens.advance().span
+    }
+
+    // 从start开始到最近消耗的token为止的区间，没有消耗token时为start处的空区间
+    fn finish(&self, start: Span) -> Span {
+        match self.tokens.previous() {
+            Some(token) if token.span.start >= start.start => start.to(token.span),
+            _ => start.empty(),
+        }
+    }
+
+    // 解析整个程序，存在语法错误时返回所有的错误
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
+        let (program, errors) = self.parse_program_partial();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // 即使存在语法错误也返回完整的AST，出错的语句和表达式以ErrorStatement/ErrorExpression表示
+    // 供格式化、编辑器等需要处理不完整程序的场景使用
+    pub fn parse_program_partial(&mut self) -> (Program, Vec<ParseError>) {
+        let mut program = self.parse_program_body();
+        self.finish_source();
+        let comments = self.tokens.take_comments();
+        if !comments.is_empty() {
+            program.attach_comments(comments);
+        }
+        (program, core::mem::take(&mut self.errors))
+    }
+
+    // 与 parse_program 相同，但结果为以编号引用子节点的 Ast，每个最外层语句解析后即存入其中，不收集注释
+    pub fn parse_arena(&mut self) -> Result<Ast, Vec<ParseError>> {
+        let mut ast = Ast::new();
+        let (name, span) = self.parse_program_with(|stmt| {
+            ast.push(stmt);
+        });
+        ast.name = name;
+        ast.span = span;
+        self.finish_source();
+        match core::mem::take(&mut self.errors) {
+            errors if errors.is_empty() => Ok(ast),
+            errors => Err(errors),
+        }
+    }
+
+    // 逐个解析最外层的语句，直到输入结束或stop对下一个token返回true，不处理program头
+    // 返回每个语句的解析结果；输入结束时另外返回词法错误与读取错误
+    #[cfg(feature = "std")]
+    pub(crate) fn parse_statements(
+        &mut self,
+        mut stop: impl FnMut(&Token) -> bool,
+    ) -> (Vec<ParsedStatement>, Vec<ParseError>) {
+        let mut statements = vec![];
+        loop {
+            let next = self.peek_token();
+            if next.token_type == TokenType::Eof {
+                self.finish_source();
+                return (statements, core::mem::take(&mut self.errors));
+            }
+            if stop(next) {
+                return (statements, vec![]);
+            }
+            let position = self.tokens.position();
             let stmt = self.parse_statement();
-            program.statements.push(stmt);
+            self.drop_errors_after_too_deep();
+            statements.push(ParsedStatement {
+                stmt,
+                tokens: self.tokens.position() - position,
+                examined: self.tokens.furthest().map_or(0, |token| token.span.end),
+                errors: core::mem::take(&mut self.errors),
+            });
         }
+    }
+
+    // 收集词法错误，以及逐行读取源程序时产生的警告与读取错误
+    fn finish_source(&mut self) {
+        self.drop_errors_after_too_deep();
+        let lexer = self.tokens.lexer_mut();
+        let mut warnings = lexer.take_warnings();
+        #[cfg(feature = "std")]
+        let error = lexer.take_error();
+        let lex_errors = lexer.take_errors();
+        self.warnings.append(&mut warnings);
+        for err in lex_errors {
+            self.errors.push(ParseError {
+                position: self.tokens.position(),
+                expected: vec![],
+                found: Token {
+                    token_type: TokenType::Comment,
+                    literal: "{".to_string(),
+                    span: err.span,
+                },
+                message: err.message,
+            });
+        }
+        #[cfg(feature = "std")]
+        if let Some(err) = error {
+            let error = self.error(vec![], format!("cannot read the source: {}", err));
+            self.errors.push(error);
+        }
+    }
+
+    fn parse_program_body(&mut self) -> Program {
+        let mut statements = vec![];
+        let (name, span) = self.parse_program_with(|stmt| statements.push(stmt));
+        let mut program = Program::new();
+        program.name = name;
+        program.statements = statements;
+        program.span = span;
         program
     }
 
-    fn parse_statement(&mut self) -> Box<dyn Statement> {
-        match self.peek_token().token_type {
-            TokenType::Ident => Box::new(self.parse_assign_statement()),
-            TokenType::If => Box::new(self.parse_if_statement()),
-            TokenType::Repeat => Box::new(self.parse_repeat_statement()),
-            TokenType::Read => Box::new(self.parse_read_statement()),
-            TokenType::Write => Box::new(self.parse_write_statement()),
-            _ => panic!(
-                "the token type represents no statement:{:?}",
+    // 将每个最外层语句交给push，返回程序名与程序的范围
+    fn parse_program_with(&mut self, mut push: impl FnMut(Stmt)) -> (Option<String>, Span) {
+        if self.peek_token().token_type == TokenType::Program {
+            return self.parse_program_header(push);
+        }
+        let start = self.peek_token().span;
+        while self.peek_token().token_type != TokenType::Eof {
+            push(self.parse_statement());
+        }
+        (None, self.finish(start))
+    }
+
+    // program name; ... end.
+    fn parse_program_header(&mut self, mut push: impl FnMut(Stmt)) -> (Option<String>, Span) {
+        let mut name = None;
+        let start = self.advance(); // pass program
+        match self.expect_token(TokenType::Ident) {
+            Ok(_) => name = self.tokens.previous().map(|token| token.literal.clone()),
+            Err(err) => self.errors.push(err),
+        }
+        if let Err(err) = self.expect_token(TokenType::SemiColon) {
+            self.errors.push(err);
+        }
+        while self.peek_token().token_type != TokenType::End && self.peek_token().token_type != TokenType::Eof {
+            push(self.parse_statement());
+        }
+        let tail = self
+            .expect_token(TokenType::End)
+            .and_then(|_| self.expect_token(TokenType::Dot))
+            .and_then(|_| self.expect_token(TokenType::Eof));
+        if let Err(err) = tail {
+            self.errors.push(err);
+        }
+        (name, self.finish(start))
+    }
+
+    // 消耗给定类型的token，返回它的位置
+    fn expect_token(&mut self, token_type: TokenType) -> Result<Span, ParseError> {
+        if self.peek_token().token_type != token_type {
+            let message = format!(
+                "expected TokenType::{:?}, found: {:?}",
+                token_type,
                 self.peek_token().token_type
-            ),
+            );
+            return Err(self.error(vec![token_type], message));
+        }
+        Ok(self.advance())
+    }
+
+    fn expect_ident(&mut self) -> Result<Identifier, ParseError> {
+        self.expect_token(TokenType::Ident)?;
+        let token = self.tokens.previous().expect("an identifier was just consumed");
+        Ok(Identifier {
+            value: Name::new(&token.literal),
+            span: token.span,
+        })
+    }
+
+    // 在下一个token处产生一个错误
+    // 若下一个token是无法识别的字符，报告该字符而不是给定的信息
+    fn error(&mut self, expected: Vec<TokenType>, message: String) -> ParseError {
+        let found = self.peek_token().clone();
+        let message = if found.token_type == TokenType::Illegal && found.literal == "\"" {
+            format!(
+                "unterminated string at line {}, column {}",
+                found.span.line, found.span.column
+            )
+        } else if found.token_type == TokenType::Illegal {
+            format!(
+                "unexpected character '{}' at line {}, column {}",
+                found.literal.escape_debug(),
+                found.span.line,
+                found.span.column
+            )
+        } else {
+            message
+        };
+        ParseError {
+            position: self.tokens.position(),
+            expected,
+            found,
+            message,
+        }
+    }
+
+    // 缺少token时只记录错误，当作该token存在继续解析，避免跳过之后的语句块
+    fn expect_or_insert(&mut self, token_type: TokenType) {
+        if let Err(err) = self.expect_token(token_type) {
+            debug!("inserted missing {:?} at {}", token_type, err.found.span);
+            self.errors.push(err);
+        }
+    }
+
+    // 语句以;结尾，在End、Else、Until之前以及文件末尾可以省略
+    fn expect_terminator(&mut self) -> Result<(), ParseError> {
+        match self.peek_token().token_type {
+            TokenType::SemiColon => {
+                self.next_token();
+                Ok(())
+            }
+            TokenType::End | TokenType::Else | TokenType::Until | TokenType::Eof => Ok(()),
+            _ => self.expect_token(TokenType::SemiColon).map(|_| ()),
+        }
+    }
+
+    // 解析失败时记录错误，并跳过直到下一个语句边界，以ErrorStatement代替该语句
+    fn parse_statement(&mut self) -> Stmt {
+        let start = self.peek_token().span;
+        if self.depth >= self.max_depth {
+            return Stmt::Error(self.skip_too_deep(start));
+        }
+        let position = self.tokens.position();
+        self.depth += 1;
+        let result = self.try_parse_statement();
+        self.depth -= 1;
+        match result {
+            Ok(stmt) => stmt,
+            Err(err) => {
+                let message = err.message.clone();
+                self.errors.push(err);
+                let progressed = self.tokens.position() > position;
+                Stmt::Error(self.recover_statement(message, start, progressed))
+            }
+        }
+    }
+
+    fn try_parse_statement(&mut self) -> Result<Stmt, ParseError> {
+        let Token { token_type, span, .. } = *self.peek_token();
+        debug!("statement starting with {:?} at {}", token_type, span);
+        Ok(match token_type {
+            TokenType::Ident if self.tokens.lookahead(1).token_type == TokenType::LeftParen => {
+                Stmt::Call(self.parse_call_statement()?)
+            }
+            TokenType::Ident => Stmt::Assign(self.parse_assign_statement()?),
+            TokenType::If => Stmt::If(self.parse_if_statement()?),
+            TokenType::Repeat => Stmt::Repeat(self.parse_repeat_statement()?),
+            TokenType::While => Stmt::While(self.parse_while_statement()?),
+            TokenType::Read => Stmt::Read(self.parse_read_statement()?),
+            TokenType::Write => Stmt::Write(self.parse_write_statement()?),
+            TokenType::Var => Stmt::Var(self.parse_var_statement()?),
+            TokenType::Fn => Stmt::Function(self.parse_function_statement()?),
+            TokenType::Return => Stmt::Return(self.parse_return_statement()?),
+            _ => {
+                let message = format!(
+                    "the token type represents no statement:{:?}",
+                    self.peek_token().token_type
+                );
+                return Err(self.error(vec![], message));
+            }
+        })
+    }
+
+    // 跳过token直到;（一并跳过）或End、Else、Until、Eof以及下一个语句的关键字（不跳过）
+    // 若出错的语句没有消耗任何token，则至少跳过一个，保证解析能够继续前进
+    fn recover_statement(&mut self, message: String, start: Span, progressed: bool) -> ErrorStatement {
+        let mut skipped = vec![];
+        loop {
+            match self.peek_token().token_type {
+                TokenType::SemiColon => {
+                    skipped.push(self.next_token());
+                    break;
+                }
+                TokenType::Eof => break,
+                token_type
+                    if (progressed || !skipped.is_empty())
+                        && (matches!(token_type, TokenType::End | TokenType::Else | TokenType::Until)
+                            || token_type.is_keyword() && token_type.starts_statement()) =>
+                {
+                    break
+                }
+                _ => skipped.push(self.next_token()),
+            }
         }
+        let span = self.finish(start);
+        debug!("skipped {} tokens at {} after `{}`", skipped.len(), span, message);
+        ErrorStatement { message, skipped, span }
     }
 
-    fn parse_assign_statement(&mut self) -> AssignStatement {
-        let ident = self.next_token(); // 一定是 TokenType::Ident
-        if self.peek_token().token_type != TokenType::Assign {
-            panic!("expected TokenType::Assign, found: {:?}", self.peek_token().token_type);
+    // 嵌套超过max_depth时报告错误并跳过其余的源程序：无法在不继续递归的情况下找到嵌套的结构的终点
+    fn skip_too_deep(&mut self, start: Span) -> ErrorStatement {
+        let message = format!(
+            "nesting too deep: more than {} levels of nested statements and expressions",
+            self.max_depth
+        );
+        let err = self.error(vec![], message.clone());
+        self.too_deep.get_or_insert(self.errors.len());
+        self.errors.push(err);
+        let mut skipped = vec![];
+        while self.peek_token().token_type != TokenType::Eof {
+            skipped.push(self.next_token());
+        }
+        ErrorStatement {
+            message,
+            skipped,
+            span: self.finish(start),
         }
-        self.next_token(); // pass :=
+    }
+
+    // 跳过其余的源程序之后，外层的语句缺少end等错误都是它的后果
+    fn drop_errors_after_too_deep(&mut self) {
+        if let Some(index) = self.too_deep.take() {
+            self.errors.truncate(index + 1);
+        }
+    }
+
+    fn parse_assign_statement(&mut self) -> Result<AssignStatement, ParseError> {
+        let name = self.parse_ident(); // 一定是 TokenType::Ident
+        let index = if self.peek_token().token_type == TokenType::LeftBracket {
+            Some(self.parse_index())
+        } else {
+            None
+        };
+        self.expect_token(TokenType::Assign)?;
         let right_exp = self.parse_expression();
-        self.next_token(); // pass ;
-        AssignStatement {
-            name: Identifier { value: ident.literal },
+        self.expect_terminator()?;
+        Ok(AssignStatement {
+            span: self.finish(name.span),
+            name,
+            index,
             value: right_exp,
+        })
+    }
+
+    // var name; 或 var name, name[size];
+    fn parse_var_statement(&mut self) -> Result<VarStatement, ParseError> {
+        let start = self.advance(); // pass var
+        let mut declarations = vec![];
+        loop {
+            declarations.push(self.parse_declaration()?);
+            if self.peek_token().token_type != TokenType::Comma {
+                break;
+            }
+            self.next_token();
         }
+        self.expect_terminator()?;
+        Ok(VarStatement {
+            declarations,
+            span: self.finish(start),
+        })
     }
 
-    fn parse_if_statement(&mut self) -> IfStatement {
-        self.next_token(); // pass If
-        let cond = self.parse_expression();
-        if self.peek_token().token_type != TokenType::Then {
-            panic!("expected TokenType::Then, found: {:?}", self.peek_token().token_type);
+    fn parse_declaration(&mut self) -> Result<Declaration, ParseError> {
+        let name = self.expect_ident()?;
+        let size = if self.peek_token().token_type == TokenType::LeftBracket {
+            self.next_token();
+            if self.peek_token().token_type != TokenType::Number {
+                let message = format!("array size must be a number, found: {:?}", self.peek_token().token_type);
+                return Err(self.error(vec![TokenType::Number], message));
+            }
+            let size = self.parse_number()?;
+            self.expect_token(TokenType::RightBracket)?;
+            Some(size)
+        } else {
+            None
+        };
+        Ok(Declaration { name, size })
+    }
+
+    fn parse_call_statement(&mut self) -> Result<CallStatement, ParseError> {
+        let name = self.parse_ident();
+        let call = self.parse_call(name);
+        self.expect_terminator()?;
+        Ok(CallStatement {
+            span: self.finish(call.span),
+            call,
+        })
+    }
+
+    // fn name(a, b) ... end
+    fn parse_function_statement(&mut self) -> Result<FunctionStatement, ParseError> {
+        let start = self.advance(); // pass fn
+        let name = self.expect_ident()?;
+        self.expect_token(TokenType::LeftParen)?;
+        let mut params = vec![];
+        if self.peek_token().token_type != TokenType::RightParen {
+            loop {
+                params.push(self.expect_ident()?);
+                if self.peek_token().token_type != TokenType::Comma {
+                    break;
+                }
+                self.next_token();
+            }
         }
-        self.next_token(); // pass then
+        self.expect_token(TokenType::RightParen)?;
+        let body = self.parse_block_statement();
+        self.expect_token(TokenType::End)?;
+        self.expect_terminator()?;
+        Ok(FunctionStatement {
+            name,
+            params,
+            body,
+            span: self.finish(start),
+        })
+    }
+
+    fn parse_return_statement(&mut self) -> Result<ReturnStatement, ParseError> {
+        let start = self.advance(); // pass return
+        let value = match self.peek_token().token_type {
+            TokenType::SemiColon | TokenType::End | TokenType::Else | TokenType::Until | TokenType::Eof => None,
+            _ => Some(self.parse_expression()),
+        };
+        self.expect_terminator()?;
+        Ok(ReturnStatement {
+            value,
+            span: self.finish(start),
+        })
+    }
+
+    fn parse_if_statement(&mut self) -> Result<IfStatement, ParseError> {
+        let start = self.advance(); // pass If
+        let cond = self.parse_expression();
+        self.expect_or_insert(TokenType::Then);
         let consequence = self.parse_block_statement();
-        self.next_token(); // pass end
-        IfStatement { cond, consequence }
+        let alternative = if self.peek_token().token_type == TokenType::Else {
+            self.next_token(); // pass else
+            Some(self.parse_block_statement())
+        } else {
+            None
+        };
+        self.expect_token(TokenType::End)?;
+        self.expect_terminator()?;
+        Ok(IfStatement {
+            cond,
+            consequence,
+            alternative,
+            span: self.finish(start),
+        })
     }
 
-    fn parse_repeat_statement(&mut self) -> RepeatStatement {
-        self.next_token(); // pass repeat
+    fn parse_repeat_statement(&mut self) -> Result<RepeatStatement, ParseError> {
+        let start = self.advance(); // pass repeat
         let consequence = self.parse_block_statement();
-        self.next_token(); // pass until
+        self.expect_token(TokenType::Until)?;
         let cond = self.parse_expression();
-        self.next_token(); // pass ;
-        RepeatStatement { cond, consequence }
+        self.expect_terminator()?;
+        Ok(RepeatStatement {
+            cond,
+            consequence,
+            span: self.finish(start),
+        })
     }
 
-    fn parse_read_statement(&mut self) -> ReadStatement {
-        self.next_token(); // pass read
-        let ident = self.next_token();
-        self.next_token(); // pass ;
-        ReadStatement {
-            name: Identifier { value: ident.literal },
-        }
+    fn parse_while_statement(&mut self) -> Result<WhileStatement, ParseError> {
+        let start = self.advance(); // pass while
+        let cond = self.parse_expression();
+        self.expect_or_insert(TokenType::Do);
+        let consequence = self.parse_block_statement();
+        self.expect_token(TokenType::End)?;
+        self.expect_terminator()?;
+        Ok(WhileStatement {
+            cond,
+            consequence,
+            span: self.finish(start),
+        })
     }
 
-    fn parse_write_statement(&mut self) -> WriteStatement {
-        self.next_token(); // pass read
-        let ident = self.next_token();
-        self.next_token(); // pass ;
-        WriteStatement {
-            name: Identifier { value: ident.literal },
-        }
+    fn parse_read_statement(&mut self) -> Result<ReadStatement, ParseError> {
+        let start = self.advance(); // pass read
+        let name = self.expect_ident()?;
+        self.expect_terminator()?;
+        Ok(ReadStatement {
+            name,
+            span: self.finish(start),
+        })
     }
 
-    // 解析到End或Until为止；并且不会消耗这两个token
+    fn parse_write_statement(&mut self) -> Result<WriteStatement, ParseError> {
+        let start = self.advance(); // pass write
+        let value = self.parse_expression();
+        self.expect_terminator()?;
+        Ok(WriteStatement {
+            value,
+            span: self.finish(start),
+        })
+    }
+
+    // 解析到End、Else或Until为止；并且不会消耗这些token
     fn parse_block_statement(&mut self) -> BlockStatement {
-        let mut block = BlockStatement { statements: vec![] };
-        while self.peek_token().token_type != TokenType::End && self.peek_token().token_type != Until {
-            let stmt = self.parse_statement();
-            block.statements.push(stmt);
+        let start = self.peek_token().span;
+        let mut statements = vec![];
+        while !matches!(
+            self.peek_token().token_type,
+            TokenType::End | TokenType::Else | TokenType::Until | TokenType::Eof
+        ) {
+            statements.push(self.parse_statement());
+        }
+        BlockStatement {
+            statements,
+            span: self.finish(start),
         }
-        block
     }
 
     // 注意到标准代码中只出现了两种表达式：单元、双元，没有复合表达式，故暂不考虑
-    fn parse_expression(&mut self) -> Box<dyn Expression> {
-        let left = self.parse_prefix_expression();
-        if self.peek_token().token_type != TokenType::SemiColon && self.peek_token().token_type != TokenType::Then {
+    fn parse_expression(&mut self) -> Expr {
+        self.parse_binary_expression(Precedence::Lowest)
+    }
+
+    // 按优先级爬升解析二元运算：只接收优先级高于precedence的运算符，同一优先级左结合
+    fn parse_binary_expression(&mut self, precedence: Precedence) -> Expr {
+        let mut left = self.parse_prefix_expression();
+        while self.peek_token().token_type.precedence() > precedence {
             let op = self.next_token();
-            Box::new(self.parse_infix_expression(op, left))
-        } else {
-            left
+            trace!(
+                "operator {:?} at {} binds tighter than {:?}",
+                op.token_type,
+                op.span,
+                precedence
+            );
+            left = Expr::Infix(self.parse_infix_expression(op, left));
+        }
+        left
+    }
+
+    // 前缀运算、二元运算的右操作数、实参与下标的嵌套都经过这里，在此限制表达式的嵌套深度
+    fn parse_prefix_expression(&mut self) -> Expr {
+        if self.depth >= self.max_depth {
+            let start = self.peek_token().span;
+            let ErrorStatement { message, skipped, span } = self.skip_too_deep(start);
+            return Expr::Error(ErrorExpression { message, skipped, span });
         }
+        self.depth += 1;
+        let expr = self.parse_operand();
+        self.depth -= 1;
+        expr
     }
 
-    fn parse_prefix_expression(&mut self) -> Box<dyn Expression> {
+    fn parse_operand(&mut self) -> Expr {
         match self.peek_token().token_type {
-            TokenType::Ident => Box::new(self.parse_ident()),
-            TokenType::Number => Box::new(self.parse_number()),
-            _ => panic!(
-                "token type: {:?} is not prefix expression",
-                self.peek_token().token_type
-            ),
+            TokenType::Ident => {
+                let name = self.parse_ident();
+                if self.peek_token().token_type == TokenType::LeftParen {
+                    Expr::Call(self.parse_call(name))
+                } else if self.peek_token().token_type == TokenType::LeftBracket {
+                    let index = self.parse_index();
+                    Expr::Index(IndexExpression {
+                        span: self.finish(name.span),
+                        name,
+                        index: Box::new(index),
+                    })
+                } else {
+                    Expr::Identifier(name)
+                }
+            }
+            TokenType::Minus => {
+                let op = self.next_token();
+                // 前缀运算符比任何二元运算符结合得更紧
+                let right = self.parse_prefix_expression();
+                Expr::Prefix(PrefixExpression {
+                    span: op.span.to(right.span()),
+                    op,
+                    right: Box::new(right),
+                })
+            }
+            TokenType::Not => {
+                let op = self.next_token();
+                // not 的操作数可以是比较运算，如 not a < b
+                let right = self.parse_binary_expression(Precedence::And);
+                Expr::Prefix(PrefixExpression {
+                    span: op.span.to(right.span()),
+                    op,
+                    right: Box::new(right),
+                })
+            }
+            TokenType::True | TokenType::False => {
+                let token = self.next_token();
+                Expr::Boolean(Boolean {
+                    value: token.token_type == TokenType::True,
+                    span: token.span,
+                })
+            }
+            TokenType::String => {
+                let token = self.next_token();
+                Expr::String(StringLiteral {
+                    value: token.literal,
+                    span: token.span,
+                })
+            }
+            TokenType::Number => match self.parse_number() {
+                Ok(number) => Expr::Number(number),
+                Err(err) => {
+                    let message = err.message.clone();
+                    self.errors.push(err);
+                    let token = self.next_token();
+                    Expr::Error(ErrorExpression {
+                        message,
+                        span: token.span,
+                        skipped: vec![token],
+                    })
+                }
+            },
+            _ => {
+                let message = format!(
+                    "token type: {:?} is not prefix expression",
+                    self.peek_token().token_type
+                );
+                let err = self.error(vec![TokenType::Ident, TokenType::Number], message.clone());
+                self.errors.push(err);
+                Expr::Error(self.recover_expression(message))
+            }
         }
     }
 
-    fn parse_infix_expression(&mut self, op: Token, left: Box<dyn Expression>) -> InfixExpression {
+    // 跳过token直到表达式可能结束的位置，不跳过结束位置的token
+    fn recover_expression(&mut self, message: String) -> ErrorExpression {
+        let start = self.peek_token().span;
+        let mut skipped = vec![];
+        while !matches!(
+            self.peek_token().token_type,
+            TokenType::SemiColon
+                | TokenType::RightBracket
+                | TokenType::RightParen
+                | TokenType::Comma
+                | TokenType::Then
+                | TokenType::Do
+                | TokenType::Else
+                | TokenType::End
+                | TokenType::Until
+                | TokenType::Eof
+        ) {
+            skipped.push(self.next_token());
+        }
+        ErrorExpression {
+            message,
+            skipped,
+            span: self.finish(start),
+        }
+    }
+
+    fn parse_infix_expression(&mut self, op: Token, left: Expr) -> InfixExpression {
+        let right = self.parse_binary_expression(op.token_type.precedence());
         InfixExpression {
+            span: left.span().to(right.span()),
             op,
-            left,
-            right: self.parse_prefix_expression(),
+            left: Box::new(left),
+            right: Box::new(right),
         }
     }
 
+    // name(args)，缺少)时只记录错误
+    fn parse_call(&mut self, name: Identifier) -> CallExpression {
+        self.next_token(); // pass (
+        let mut args = vec![];
+        if self.peek_token().token_type != TokenType::RightParen {
+            loop {
+                args.push(self.parse_expression());
+                if self.peek_token().token_type != TokenType::Comma {
+                    break;
+                }
+                self.next_token();
+            }
+        }
+        self.expect_or_insert(TokenType::RightParen);
+        CallExpression {
+            span: self.finish(name.span),
+            name,
+            args,
+        }
+    }
+
+    // [index]，缺少]时只记录错误
+    fn parse_index(&mut self) -> Expr {
+        self.next_token(); // pass [
+        let index = self.parse_expression();
+        self.expect_or_insert(TokenType::RightBracket);
+        index
+    }
+
     fn parse_ident(&mut self) -> Identifier {
+        let token = self.tokens.advance();
         Identifier {
-            value: self.next_token().literal,
+            value: Name::new(&token.literal),
+            span: token.span,
         }
     }
 
-    fn parse_number(&mut self) -> Number {
-        Number {
-            value: self.next_token().literal.parse().unwrap(),
+    fn parse_number(&mut self) -> Result<Number, ParseError> {
+        match self.peek_token().literal.parse() {
+            Ok(value) => {
+                let span = self.advance();
+                Ok(Number { value, span })
+            }
+            Err(_) => {
+                let message = format!("number {} is out of range", self.peek_token().literal);
+                Err(self.error(vec![], message))
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::lexer::Lexer;
+    use crate::ast::{Attachment, Expr, Node, NodeType, Stmt};
     use crate::parser::Parser;
+    use crate::token::TokenType;
+    #[cfg(feature = "std")]
+    use std::io::{self, BufReader, Read};
 
     #[test]
     fn test_read_statement() {
         let input = "read x;";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
     }
 
     #[test]
     fn test_write_statement() {
         let input = "write x;";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
     }
 
     #[test]
     fn test_assign_statement() {
         let input = "x := 5; y := x + 3";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
     }
 
     #[test]
     fn test_if_statement() {
         let input = "if a < b then x := 3; end";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
+    }
+
+    #[test]
+    fn test_if_else_statement() {
+        let input = "if a < b then x := 3 else x := 4; y := x end";
+        let mut parser = Parser::new(input);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.statements.len(), 1);
+        println!("{:?}", program);
     }
 
     #[test]
@@ -202,7 +896,396 @@ if 0 < x then
     write fact; { output factorial of x }
 end";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
+    }
+
+    #[test]
+    fn test_while_statement() {
+        let input = "
+while 0 < x do
+    x := x - 1
+end;
+write x";
+        let mut parser = Parser::new(input);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.statements[0].node_type(), NodeType::WhileStatement);
+        assert_eq!(program.statements.len(), 2);
+        println!("{:?}", program);
+    }
+
+    #[test]
+    fn test_prefix_expression() {
+        let mut parser = Parser::new("x := -5; y := -x + 3;");
+        let program = parser.parse_program().unwrap();
+        assert_eq!(crate::ast::to_source(&program), "x := -5;\ny := -x + 3;\n");
+        println!("{:?}", program);
+    }
+
+    #[test]
+    fn test_precedence() {
+        // 加上括号显示表达式的结构
+        fn grouped(expr: &Expr) -> String {
+            match expr {
+                Expr::Infix(infix) => format!(
+                    "({} {} {})",
+                    grouped(&infix.left),
+                    infix.op.literal,
+                    grouped(&infix.right)
+                ),
+                Expr::Prefix(prefix) => format!("({} {})", prefix.op.literal, grouped(&prefix.right)),
+                Expr::Identifier(ident) => ident.value.to_string(),
+                Expr::Number(number) => number.value.to_string(),
+                Expr::Boolean(boolean) => boolean.value.to_string(),
+                expr => panic!("unexpected expression {:?}", expr),
+            }
+        }
+        for (input, expected) in [
+            ("a + b * c - d", "((a + (b * c)) - d)"),
+            ("a < b and b < c", "((a < b) and (b < c))"),
+            ("a or b and not c = 1", "(a or (b and (not (c = 1))))"),
+            ("-a * 2 > 0 or false", "((((- a) * 2) > 0) or false)"),
+            ("not not true", "(not (not true))"),
+        ] {
+            let program = Parser::new(&format!("write {}", input)).parse_program().unwrap();
+            let Stmt::Write(write) = &program.statements[0] else {
+                panic!("expected write statement");
+            };
+            assert_eq!(grouped(&write.value), expected);
+            assert_eq!(crate::ast::to_source(&program), format!("write {};\n", input));
+        }
+    }
+
+    #[test]
+    fn test_program_header() {
+        let input = "
+program fact;
+    read x;
+    if 0 < x then
+        write x;
+    end
+end.";
+        let mut parser = Parser::new(input);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.name, Some("fact".to_string()));
+        assert_eq!(program.statements.len(), 2);
+        println!("{:?}", program);
+    }
+
+    #[test]
+    fn test_program_header_missing_dot() {
+        let mut parser = Parser::new("program fact; read x; end");
+        let errors = parser.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "expected TokenType::Dot, found: Eof");
+        assert_eq!(errors[0].expected, [TokenType::Dot]);
+        assert_eq!(errors[0].found.token_type, TokenType::Eof);
+        assert_eq!(errors[0].to_string(), "1:26: expected TokenType::Dot, found: Eof");
+    }
+
+    #[test]
+    fn test_error_statement() {
+        let input = "
+read x;
+y := := 3;
+if x < then
+    write x;
+end;
+write y;";
+        let mut parser = Parser::new(input);
+        let (program, errors) = parser.parse_program_partial();
+        let messages: Vec<_> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "token type: Assign is not prefix expression",
+                "token type: Then is not prefix expression"
+            ]
+        );
+        // 出错之后的语句仍然被解析
+        assert_eq!(program.statements.len(), 4);
+        println!("{:?}", program);
+    }
+
+    #[test]
+    fn test_illegal_character() {
+        let input = "read x;\nx := 1;\nwrite @x;\nwrite x;";
+        let (program, errors) = Parser::new(input).parse_program_partial();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "3:7: unexpected character '@' at line 3, column 7"
+        );
+        assert_eq!(program.statements.len(), 4);
+
+        let errors = Parser::new("x := 1;\0\nwrite x").parse_program().unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "1:8: unexpected character '\\0' at line 1, column 8"
+        );
+    }
+
+    #[test]
+    fn test_write_statement_value() {
+        let program = Parser::new("write \"x = \"; write x + 1").parse_program().unwrap();
+        assert_eq!(
+            program
+                .statements
+                .iter()
+                .map(|stmt| stmt.span().end)
+                .collect::<Vec<_>>(),
+            [13, 25]
+        );
+        let (_, errors) = Parser::new("write \"x = ;\nwrite x").parse_program_partial();
+        assert_eq!(errors[0].to_string(), "1:7: unterminated string at line 1, column 7");
+    }
+
+    #[test]
+    fn test_array() {
+        let input = "var a[10]; var n; a[n + 1] := a[2] * 3; write a[n]";
+        let program = Parser::new(input).parse_program().unwrap();
+        let types: Vec<_> = program.statements.iter().map(|stmt| stmt.node_type()).collect();
+        assert_eq!(
+            types,
+            [
+                NodeType::VarStatement,
+                NodeType::VarStatement,
+                NodeType::AssignStatement,
+                NodeType::WriteStatement
+            ]
+        );
+        assert_eq!(
+            crate::ast::to_source(&program),
+            "var a[10];\nvar n;\na[n + 1] := a[2] * 3;\nwrite a[n];\n"
+        );
+        assert_eq!(program.statements[2].span().end, 39);
+
+        let program = Parser::new("var i, a[4], j").parse_program().unwrap();
+        let Stmt::Var(var) = &program.statements[0] else {
+            panic!("expected a var statement");
+        };
+        assert_eq!(var.declarations.len(), 3);
+        assert_eq!(var.declarations[1].size.as_ref().map(|size| size.value), Some(4));
+        assert_eq!(crate::ast::to_source(&program), "var i, a[4], j;\n");
+        assert!(Parser::new("var i, ;").parse_program().is_err());
+
+        let (program, errors) = Parser::new("var a[n]; x := a[1;\nwrite x").parse_program_partial();
+        let errors: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "1:7: array size must be a number, found: Ident",
+                "1:19: expected TokenType::RightBracket, found: SemiColon"
+            ]
+        );
+        assert_eq!(program.statements.len(), 3);
+    }
+
+    #[test]
+    fn test_function() {
+        let input =
+            "fn add(a, b) return a + b end; fn hello() write \"hi\"; return end; hello(); x := add(1, add(2, 3));";
+        let program = Parser::new(input).parse_program().unwrap();
+        let types: Vec<_> = program.statements.iter().map(|stmt| stmt.node_type()).collect();
+        assert_eq!(
+            types,
+            [
+                NodeType::FunctionStatement,
+                NodeType::FunctionStatement,
+                NodeType::CallStatement,
+                NodeType::AssignStatement
+            ]
+        );
+        assert_eq!(
+            crate::ast::to_source(&program),
+            "fn add(a, b)\n    return a + b;\nend;\nfn hello()\n    write \"hi\";\n    return;\nend;\nhello();\nx := add(1, add(2, 3));\n"
+        );
+
+        let (program, errors) = Parser::new("x := f(1;\nfn (a) end;\nwrite x").parse_program_partial();
+        let errors: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+        // 函数头中的错误恢复到end，end本身再报告一次
+        assert_eq!(
+            errors,
+            [
+                "1:9: expected TokenType::RightParen, found: SemiColon",
+                "2:4: expected TokenType::Ident, found: LeftParen",
+                "2:8: the token type represents no statement:End"
+            ]
+        );
+        assert_eq!(program.statements.len(), 4);
+    }
+
+    #[test]
+    fn test_recover_to_next_statement() {
+        let input = "read 3; write x; until write y;";
+        let mut parser = Parser::new(input);
+        let (program, errors) = parser.parse_program_partial();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].position, 1);
+        assert_eq!(program.statements.len(), 4);
+        assert_eq!(
+            format!("{:?}", program.statements[0]),
+            "ErrorStatement [Number(3), SemiColon(;)]"
+        );
+        println!("{:?}", program);
+    }
+
+    #[test]
+    fn test_report_every_error() {
+        let input = "read x
+if x < 1 write x end;
+repeat x := x - until x = 0;
+while x write y end;
+write ;
+x := 1";
+        let (program, errors) = Parser::new(input).parse_program_partial();
+        let errors: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "2:1: expected TokenType::SemiColon, found: If",
+                "2:10: expected TokenType::Then, found: Write",
+                "3:17: token type: Until is not prefix expression",
+                "4:9: expected TokenType::Do, found: Write",
+                "5:7: token type: SemiColon is not prefix expression",
+            ]
+        );
+        // 缺少then、do时语句块照常解析，出错语句之后的if不会被跳过
+        let types: Vec<_> = program.statements.iter().map(|stmt| stmt.node_type()).collect();
+        assert_eq!(
+            types,
+            [
+                NodeType::ErrorStatement,
+                NodeType::IfStatement,
+                NodeType::RepeatStatement,
+                NodeType::WhileStatement,
+                NodeType::WriteStatement,
+                NodeType::AssignStatement,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_span() {
+        let input = "read x;\nif x < 10 then\n    write x\nend";
+        let mut parser = Parser::new(input);
+        let program = parser.parse_program().unwrap();
+        let spans: Vec<_> = program.statements.iter().map(|stmt| stmt.span()).collect();
+        assert_eq!((spans[0].to_string(), spans[0].end), ("1:1".to_string(), 7));
+        assert_eq!((spans[1].to_string(), spans[1].end), ("2:1".to_string(), input.len()));
+        assert_eq!((program.span.start, program.span.end), (0, input.len()));
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "{ header }\nread x; { input }\nif x then\n  { leading }\n  write x\n  { dangling }\nend";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(program.comments.is_empty());
+
+        let program = Parser::new(input).with_comments(true).parse_program().unwrap();
+        let read = program.statements[0].span();
+        let write = match &program.statements[1] {
+            Stmt::If(if_stmt) => if_stmt.consequence.statements[0].span(),
+            stmt => panic!("expected if, found {:?}", stmt),
+        };
+        let comments: Vec<_> = program
+            .comments
+            .iter()
+            .map(|comment| (comment.text.as_str(), comment.span.to_string(), comment.attachment))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                ("{ header }", "1:1".to_string(), Some(Attachment::Leading(read))),
+                ("{ input }", "2:9".to_string(), Some(Attachment::Trailing(read))),
+                ("{ leading }", "4:3".to_string(), Some(Attachment::Leading(write))),
+                ("{ dangling }", "6:3".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let input = "read x;\nwrite x { done\n{ nested } write y";
+        let (program, errors) = Parser::new(input).parse_program_partial();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "2:9: unterminated comment starting at line 2, column 9"
+        );
+        assert!(Parser::new("read x; { a { b } c } write x").parse_program().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_reader() {
+        let input: &[u8] = b"\xEF\xBB\xBFread x;\r\nwrite x; { caf\xE9 }\r\n";
+        let mut parser = Parser::from_reader(input).unwrap();
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(parser.warnings().len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_buf_read() {
+        let input: &[u8] = b"read x;\r\nwrite x; { caf\xE9 }\r\n";
+        let mut parser = Parser::from_buf_read(input);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(parser.warnings().len(), 1);
+
+        // 读取出错时，已读到的部分照常解析
+        struct Failing(&'static [u8]);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+                }
+                self.0.read(buf)
+            }
+        }
+        let mut parser = Parser::from_buf_read(BufReader::new(Failing(b"read x;\nwrite x;\n")));
+        let (program, errors) = parser.parse_program_partial();
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "cannot read the source: disk on fire");
+    }
+
+    #[test]
+    fn test_number_out_of_range() {
+        let mut parser = Parser::new("x := 99999999999; write x;");
+        let (program, errors) = parser.parse_program_partial();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "number 99999999999 is out of range");
+        assert_eq!(errors[0].position, 2);
+        assert_eq!(program.statements.len(), 2);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        assert!(Parser::new("write 1").with_max_depth(2).parse_program().is_ok());
+        let errors = Parser::new("x := 1; write -1")
+            .with_max_depth(2)
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "nesting too deep: more than 2 levels of nested statements and expressions"
+        );
+        assert_eq!(errors[0].found.span.column, 16);
+
+        // 嵌套过深之后只报告一个错误，不报告外层语句缺少的end
+        let nested = format!("{}write x{}", "if 0 < x then ".repeat(1000), " end".repeat(1000));
+        let (program, errors) = Parser::new(&nested).parse_program_partial();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("nesting too deep"));
+        assert_eq!(program.statements.len(), 1);
+        let errors = Parser::new(&format!("write {}1", "- ".repeat(100_000)))
+            .parse_arena()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
     }
 
     #[test]
@@ -219,6 +1302,6 @@ if 0 < x then { don't compute if x <= 0 }
     write fact; { output factorial of x }
 end";
         let mut parser = Parser::new(input);
-        println!("{:?}", parser.parse_program());
+        println!("{:?}", parser.parse_program().unwrap());
     }
 }
@@ -0,0 +1,127 @@
+// Python扩展模块tiny_cc，供用Python编写的程序在进程内调用编译器
+// 构建：cargo rustc --lib --release --crate-type cdylib --features python,pyo3/extension-module，
+// 再将 target/release/libtiny_cc.so 复制为Python路径中的 tiny_cc.so（Windows上为 tiny_cc.pyd）
+// 源程序有错误时抛出 tiny_cc.CompileError，信息为每行一个的错误，格式为 行:列: 信息
+
+use crate::driver::{self, Options};
+use crate::lexer;
+use crate::parser::Parser;
+use crate::vm::TinyMachine;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+create_exception!(tiny_cc, CompileError, PyException);
+
+// run 默认最多执行的指令数，避免死循环
+pub const INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+// token的列表，每个token为 {"token_type", "literal", "span"} 的dict，最后一个为Eof
+#[pyfunction]
+fn tokenize(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    to_py(py, &lexer::tokenize(source))
+}
+
+// 语法树，结构与serde特性的序列化结果相同：结构体为dict，枚举为以变体名为键的dict
+#[pyfunction]
+fn parse(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    let program = Parser::new(source)
+        .parse_program()
+        .map_err(|errors| CompileError::new_err(driver::Error::Parse(errors).to_string()))?;
+    to_py(py, &program)
+}
+
+// TM代码清单
+#[pyfunction]
+#[pyo3(signature = (source, opt_level = 0))]
+fn compile(source: &str, opt_level: u8) -> PyResult<String> {
+    let options = Options {
+        opt_level,
+        ..Options::default()
+    };
+    driver::compile_to_tm(source, &options)
+        .map(|artifacts| artifacts.listing)
+        .map_err(|err| CompileError::new_err(err.to_string()))
+}
+
+// 编译并运行程序，input为read读取的输入，每行一个整数
+// 返回 {"output", "error"}：output为程序的输出，运行时错误时error为错误信息，否则为None
+#[pyfunction]
+#[pyo3(signature = (source, input = "", opt_level = 0, instruction_limit = INSTRUCTION_LIMIT))]
+fn run(py: Python<'_>, source: &str, input: &str, opt_level: u8, instruction_limit: u64) -> PyResult<PyObject> {
+    let options = Options {
+        opt_level,
+        ..Options::default()
+    };
+    let artifacts = driver::compile_to_tm(source, &options).map_err(|err| CompileError::new_err(err.to_string()))?;
+    let mut vm = TinyMachine::new(input.as_bytes(), vec![])
+        .with_memory_size(artifacts.memory_size)
+        .with_instruction_limit(instruction_limit);
+    let result = vm.load(&artifacts.listing).and_then(|_| vm.run());
+    let error = result
+        .err()
+        .map(|err| match err.pc().and_then(|pc| artifacts.debug_info.line(pc)) {
+            Some(line) => format!("{} (line {})", err, line),
+            None => err.to_string(),
+        });
+    let result = PyDict::new(py);
+    result.set_item("output", String::from_utf8_lossy(&vm.output))?;
+    result.set_item("error", error)?;
+    Ok(result.into_any().unbind())
+}
+
+#[pymodule]
+fn tiny_cc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("CompileError", m.py().get_type::<CompileError>())?;
+    m.add_function(wrap_pyfunction!(tokenize, m)?)?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
+
+// 序列化为JSON后由Python的json模块解析，得到由dict、list、str与int组成的对象
+fn to_py(py: Python<'_>, value: &impl serde::Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).expect("AST and tokens serialize to JSON");
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::python::tiny_cc;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_module() {
+        pyo3::append_to_inittab!(tiny_cc);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                pyo3::ffi::c_str!(
+                    r#"
+import tiny_cc
+tokens = tiny_cc.tokenize("read x")
+assert tokens[1]["literal"] == "x" and tokens[1]["span"]["column"] == 6, tokens
+assert len(tiny_cc.parse("write 1")["statements"]) == 1
+assert "HALT" in tiny_cc.compile("read x; write x", opt_level=1)
+assert tiny_cc.run("read x; write x * 2", "21\n") == {"output": "42\n", "error": None}
+result = tiny_cc.run("read x;\nwrite 1 / x", "0\n")
+assert "line 2" in result["error"], result
+assert tiny_cc.run("repeat x := 1 until false", instruction_limit=100)["error"]
+try:
+    tiny_cc.compile("write y")
+    assert False
+except tiny_cc.CompileError as err:
+    assert str(err).startswith("1:7: "), err
+"#
+                ),
+                None,
+                Some(&locals),
+            )
+            .unwrap_or_else(|err| panic!("{}", err));
+        });
+    }
+}
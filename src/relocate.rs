@@ -0,0 +1,134 @@
+use crate::code::{self, Instruction, ListingStyle, Relocation};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RelocateError {
+    // 出错的行号，从1开始
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for RelocateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// 将可重定位的清单（Compiler::with_relocatable(true)的输出）装载到给定的代码基址和数据基址：
+// 指令地址加上code_base，重定位表中指令的数据地址以及.DATA的地址加上data_base
+// 输出的清单使用默认格式，不再包含重定位表
+pub fn relocate(listing: &str, code_base: usize, data_base: usize) -> Result<String, RelocateError> {
+    let style = ListingStyle::default();
+    let mut relocations = HashSet::new();
+    for (index, line) in listing.lines().enumerate() {
+        if let Some(operands) = directive(line, ".RELOC") {
+            let index = parse_number(operands, index)?;
+            relocations.insert(Relocation { index });
+        }
+    }
+
+    let mut output = String::new();
+    for (index, line) in listing.lines().enumerate() {
+        let error = |message: String| RelocateError {
+            line: index + 1,
+            message,
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('*') {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+        if directive(line, ".RELOC").is_some() {
+            continue;
+        }
+        if let Some(operands) = directive(line, ".SPAN") {
+            let (addr, span) = code::parse_span_directive(operands).map_err(error)?;
+            output.push_str(&style.format_span(addr + code_base, span));
+            output.push('\n');
+            continue;
+        }
+        if let Some(operands) = directive(line, ".DATA") {
+            let (addr, value) = operands
+                .split_once(',')
+                .ok_or_else(|| error(format!("malformed data directive `{}`", trimmed)))?;
+            let addr: usize = parse_number(addr, index)?;
+            let value: i32 = parse_number(value, index)?;
+            output.push_str(&style.format_data(addr + data_base, value));
+            output.push('\n');
+            continue;
+        }
+
+        let (addr, rest) = trimmed
+            .split_once(':')
+            .ok_or_else(|| error(format!("expected an instruction, found `{}`", trimmed)))?;
+        let addr: usize = parse_number(addr, index)?;
+        let mut instruction: Instruction = rest.parse().map_err(error)?;
+        if let Instruction::Rm { d, .. } = &mut instruction {
+            if relocations.contains(&Relocation { index: addr }) {
+                *d += data_base as i32;
+            }
+        }
+        let code = style.format(addr + code_base, &instruction);
+        output.push_str(&code);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+// 若line是给定的指示，返回其操作数部分
+fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let line = line.trim();
+    let (head, rest) = line.split_once(char::is_whitespace)?;
+    if head.eq_ignore_ascii_case(name) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, index: usize) -> Result<T, RelocateError> {
+    value.trim().parse().map_err(|_| RelocateError {
+        line: index + 1,
+        message: format!("expected a number, found `{}`", value.trim()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::relocate::relocate;
+
+    #[test]
+    fn test_relocate() {
+        let mut parser = Parser::new("read x; if 0 < x then write x; end");
+        let mut compiler = Compiler::default().with_relocatable(true).with_debug_info(true);
+        compiler.preset("limit", 7);
+        compiler.compile(&parser.parse_program().unwrap()).unwrap();
+        let listing = compiler.to_intermedia_code();
+        assert!(listing.contains(".RELOC  3"));
+
+        let relocated = relocate(&listing, 100, 50).unwrap();
+        let lines: Vec<&str> = relocated.lines().filter(|line| !line.starts_with('*')).collect();
+        // 序言中以地址0为基准的指令不被重定位
+        assert_eq!(lines[0], "100:     LD  6,0(0)");
+        assert_eq!(lines[2], "102:     IN  0,0,0");
+        // ST 0,2(5) 中x的地址2被重定位到52
+        assert_eq!(lines[3], "103:     ST  0,52(5)");
+        // 以PC为基址的跳转不变
+        assert!(lines.iter().any(|line| line.ends_with("JEQ  0,3(7)")));
+        assert!(relocated.contains(".DATA  51,7"));
+        assert!(!relocated.contains(".RELOC"));
+        // 调试信息中的指令地址加上代码基址
+        assert!(relocated.contains(".SPAN  102,1,1,0,7"));
+    }
+
+    #[test]
+    fn test_relocate_error() {
+        let err = relocate("  0:  NOP  0,0,0", 0, 0).unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "unknown opcode `NOP`");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::token::TokenType;
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+
+const PROMPT: &str = "> ";
+// 语句尚未输入完整时的提示符，如 if 之后还没有 end
+const CONTINUATION_PROMPT: &str = ". ";
+
+// 交互式地逐条执行TINY语句，变量在各次输入之间保留
+// read语句从同一个输入中读取下一行作为整数
+pub struct Repl<R, W> {
+    interpreter: Interpreter<R, W>,
+}
+
+impl Repl<BufReader<Stdin>, Stdout> {
+    pub fn with_stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> Repl<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            interpreter: Interpreter::new(input, output),
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<R, W> {
+        &self.interpreter
+    }
+
+    // 读取并执行语句直到输入结束；错误信息输出后继续执行下一条输入
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut source = String::new();
+        loop {
+            let prompt = if source.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            write!(self.interpreter.output, "{}", prompt)?;
+            self.interpreter.output.flush()?;
+            let mut line = String::new();
+            if self.interpreter.input.read_line(&mut line)? == 0 {
+                writeln!(self.interpreter.output)?;
+                return Ok(());
+            }
+            source.push_str(&line);
+            if source.trim().is_empty() {
+                source.clear();
+                continue;
+            }
+            match self.eval(&source) {
+                Outcome::Incomplete => continue,
+                Outcome::Error(message) => writeln!(self.interpreter.output, "error: {}", message)?,
+                Outcome::Done => {}
+            }
+            source.clear();
+        }
+    }
+
+    // 执行一段源程序，语法错误出现在文件末尾时认为输入还不完整
+    fn eval(&mut self, source: &str) -> Outcome {
+        let program = match Parser::new(source).parse_program() {
+            Ok(program) => program,
+            Err(errors) => {
+                if errors.iter().any(|err| err.found.token_type == TokenType::Eof) {
+                    return Outcome::Incomplete;
+                }
+                let messages: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+                return Outcome::Error(messages.join("\n"));
+            }
+        };
+        for stmt in &program.statements {
+            if let Err(err) = self.interpreter.execute(stmt) {
+                return Outcome::Error(err.to_string());
+            }
+        }
+        Outcome::Done
+    }
+}
+
+enum Outcome {
+    Done,
+    // 语法错误或运行时错误的信息
+    Error(String),
+    // 等待更多的输入
+    Incomplete,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interner::Name;
+    use crate::repl::Repl;
+
+    #[test]
+    fn test_repl() {
+        let input = "x := 2
+write x
+
+if x > 1 then
+  read y;
+  write y
+end
+7
+z := y / 0
+write z
+if 1 then
+";
+        let mut repl = Repl::new(input.as_bytes(), vec![]);
+        repl.run().unwrap();
+        assert_eq!(repl.interpreter().variables.get(&Name::new("y")), Some(&7));
+        let output = String::from_utf8(repl.interpreter.output).unwrap();
+        assert_eq!(
+            output,
+            "> > 2
+> > . . . 7
+> error: 1:6: division by zero
+> error: 1:7: variable `z` used before assignment at line 1
+> . \n"
+        );
+    }
+}
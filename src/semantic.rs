@@ -0,0 +1,627 @@
+use crate::ast::{
+    BlockStatement, CallExpression, Declaration, Expr, FunctionStatement, Identifier, Node, Program, Stmt,
+};
+use crate::interner::Name;
+use crate::optimizer;
+use crate::token::{Span, Token, TokenType};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for SemanticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for SemanticError {}
+
+// 除数为常量0的警告的名字，可以通过 --warn 或 tinycc.toml 的 [warnings] 设置级别
+pub const DIVISION_BY_ZERO: &str = "division-by-zero";
+
+// 不影响编译的问题，lint为警告的名字
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub lint: &'static str,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+// 表达式的类型：比较运算的结果为布尔值，字符串字面量为字符串，其余为整数
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Integer,
+    Boolean,
+    String,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Integer => write!(f, "integer"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::String => write!(f, "string"),
+        }
+    }
+}
+
+// 代码生成之前的语义检查，返回所有发现的错误
+// 变量在程序中第一次出现时必须被赋值（赋值语句或read语句），与编译器分配变量地址的顺序一致
+// 类型检查：条件必须是布尔值，算术运算、比较运算的操作数以及赋值的值必须是整数，
+// and、or、not的操作数必须是布尔值，write输出整数或字符串
+// 数组必须先以var声明，并且只能通过下标使用；下标必须是整数，常量下标不能越界
+// 同一个名字不能重复声明，也不能声明已赋值的变量
+// 函数只能在最外层定义，可以在定义之前调用；函数体中可以使用定义之前已赋值的全局变量，
+// 其余被赋值的变量为局部变量；调用的实参个数必须与形参一致
+// predeclared为已有初始值的变量，见 Compiler::preset
+pub fn check<'a>(program: &Program, predeclared: impl IntoIterator<Item = &'a str>) -> Vec<SemanticError> {
+    check_with_warnings(program, predeclared).0
+}
+
+// 与check相同，同时返回发现的警告，如除数为常量0
+pub fn check_with_warnings<'a>(
+    program: &Program,
+    predeclared: impl IntoIterator<Item = &'a str>,
+) -> (Vec<SemanticError>, Vec<Warning>) {
+    check_with_options(program, predeclared, false)
+}
+
+// strict为true时是严格模式：变量必须先以var声明才能被赋值或read，函数的参数视为已声明
+pub fn check_with_options<'a>(
+    program: &Program,
+    predeclared: impl IntoIterator<Item = &'a str>,
+    strict: bool,
+) -> (Vec<SemanticError>, Vec<Warning>) {
+    let declared: HashSet<_> = predeclared.into_iter().map(Name::new).collect();
+    let mut checker = Checker {
+        variables: declared.clone(),
+        declared,
+        strict,
+        arrays: HashMap::new(),
+        functions: HashMap::new(),
+        in_function: false,
+        errors: vec![],
+        warnings: vec![],
+    };
+    checker.check(program);
+    (checker.errors, checker.warnings)
+}
+
+struct Checker {
+    declared: HashSet<Name>,
+    // 以var声明的变量与参数
+    variables: HashSet<Name>,
+    strict: bool,
+    // 数组名 => 元素个数
+    arrays: HashMap<Name, i32>,
+    // 函数名 => 参数个数
+    functions: HashMap<Name, usize>,
+    in_function: bool,
+    errors: Vec<SemanticError>,
+    warnings: Vec<Warning>,
+}
+
+impl Checker {
+    fn check(&mut self, program: &Program) {
+        for s in &program.statements {
+            if let Stmt::Function(function) = s {
+                let name = &function.name;
+                match self.functions.entry(name.value.clone()) {
+                    Entry::Occupied(_) => {
+                        self.error(name.span, format!("function `{}` is already defined", name.value))
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(function.params.len());
+                    }
+                }
+            }
+        }
+        for s in &program.statements {
+            self.check_statement(s);
+        }
+    }
+
+    fn check_block(&mut self, block: &BlockStatement) {
+        for s in &block.statements {
+            self.check_statement(s);
+        }
+    }
+
+    fn check_statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Read(read) => self.check_target(&read.name),
+            Stmt::Write(write) => {
+                let found = self.check_expression(&write.value);
+                if found == Type::Boolean {
+                    self.errors.push(SemanticError {
+                        span: write.value.span(),
+                        message: format!("written value must be integer or string, found {}", found),
+                    });
+                }
+            }
+            Stmt::Assign(assign) => {
+                self.expect_type(&assign.value, Type::Integer, "assigned value");
+                match &assign.index {
+                    Some(index) => self.check_index(&assign.name, index),
+                    None => self.check_target(&assign.name),
+                }
+            }
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    self.check_declaration(declaration);
+                }
+            }
+            Stmt::If(if_stmt) => {
+                self.expect_type(&if_stmt.cond, Type::Boolean, "condition");
+                self.check_block(&if_stmt.consequence);
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.check_block(alternative);
+                }
+            }
+            Stmt::Repeat(repeat) => {
+                self.check_block(&repeat.consequence);
+                self.expect_type(&repeat.cond, Type::Boolean, "condition");
+            }
+            Stmt::While(while_stmt) => {
+                self.expect_type(&while_stmt.cond, Type::Boolean, "condition");
+                self.check_block(&while_stmt.consequence);
+            }
+            Stmt::Function(function) if self.in_function => {
+                self.error(
+                    function.span,
+                    "functions can only be defined at the top level".to_string(),
+                );
+            }
+            Stmt::Function(function) => self.check_function(function),
+            Stmt::Return(ret) => {
+                if !self.in_function {
+                    self.error(ret.span, "return outside of a function".to_string());
+                }
+                if let Some(value) = &ret.value {
+                    self.expect_type(value, Type::Integer, "returned value");
+                }
+            }
+            Stmt::Call(call) => self.check_call(&call.call),
+            Stmt::Error(_) => {}
+        }
+    }
+
+    fn check_declaration(&mut self, declaration: &Declaration) {
+        let name = &declaration.name.value;
+        if self.declared.contains(name) || self.variables.contains(name) || self.arrays.contains_key(name) {
+            self.error(declaration.name.span, format!("`{}` is already declared", name));
+            return;
+        }
+        match &declaration.size {
+            Some(size) if self.in_function => {
+                self.error(size.span, "arrays can only be declared at the top level".to_string());
+            }
+            Some(size) if size.value <= 0 => {
+                self.error(size.span, format!("array size must be positive, found {}", size.value));
+            }
+            Some(size) => {
+                self.arrays.insert(name.clone(), size.value);
+            }
+            // 声明的变量仍然需要先赋值才能使用
+            None => {
+                self.variables.insert(name.clone());
+            }
+        }
+    }
+
+    // 被赋值或read的变量，严格模式下必须已经声明；同一个变量只报告一次
+    fn check_target(&mut self, ident: &Identifier) {
+        self.expect_scalar(ident);
+        let name = ident.value.clone();
+        if self.strict
+            && !self.arrays.contains_key(&name)
+            && !self.variables.contains(&name)
+            && !self.declared.contains(&name)
+        {
+            self.error(ident.span, format!("variable `{}` is not declared", name));
+        }
+        self.declared.insert(name);
+    }
+
+    // 函数体中可见的是参数以及定义之前已赋值或已声明的全局变量，检查完成后恢复全局的状态
+    fn check_function(&mut self, function: &FunctionStatement) {
+        let locals = function.locals(|name| {
+            self.declared.contains(name) || self.variables.contains(name) || self.arrays.contains_key(name)
+        });
+        let globals = self.declared.clone();
+        let variables = self.variables.clone();
+        let arrays = self.arrays.clone();
+        for name in &locals {
+            self.declared.remove(name);
+            self.variables.remove(name);
+            self.arrays.remove(name);
+        }
+        let mut params = HashSet::new();
+        for param in &function.params {
+            if !params.insert(&param.value) {
+                self.error(param.span, format!("parameter `{}` is already declared", param.value));
+            }
+            self.declared.insert(param.value.clone());
+            self.variables.insert(param.value.clone());
+            self.arrays.remove(&param.value);
+        }
+        self.in_function = true;
+        self.check_block(&function.body);
+        self.in_function = false;
+        self.declared = globals;
+        self.variables = variables;
+        self.arrays = arrays;
+    }
+
+    fn check_call(&mut self, call: &CallExpression) {
+        let name = &call.name.value;
+        for arg in &call.args {
+            self.expect_type(arg, Type::Integer, &format!("argument of `{}`", name));
+        }
+        match self.functions.get(name) {
+            None => self.error(call.name.span, format!("function `{}` is not defined", name)),
+            Some(arity) if *arity != call.args.len() => {
+                let arity = *arity;
+                self.error(
+                    call.span,
+                    format!(
+                        "function `{}` takes {} arguments, found {}",
+                        name,
+                        arity,
+                        call.args.len()
+                    ),
+                );
+            }
+            Some(_) => {}
+        }
+    }
+
+    // 检查表达式并返回它的类型
+    fn check_expression(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Prefix(prefix) => {
+                self.expect_operand(&prefix.right, &prefix.op);
+                if prefix.op.token_type == TokenType::Not {
+                    Type::Boolean
+                } else {
+                    Type::Integer
+                }
+            }
+            Expr::Infix(infix) => {
+                self.expect_operand(&infix.left, &infix.op);
+                self.expect_operand(&infix.right, &infix.op);
+                // 常量折叠保留这样的除法，运行时出错
+                if infix.op.token_type == TokenType::Divide && optimizer::constant_value(&infix.right) == Some(0) {
+                    self.warnings.push(Warning {
+                        lint: DIVISION_BY_ZERO,
+                        span: infix.right.span(),
+                        message: "division by zero".to_string(),
+                    });
+                }
+                if infix.op.token_type.is_comparison() || infix.op.token_type.is_logical() {
+                    Type::Boolean
+                } else {
+                    Type::Integer
+                }
+            }
+            Expr::Index(index) => {
+                self.check_index(&index.name, &index.index);
+                Type::Integer
+            }
+            Expr::Call(call) => {
+                self.check_call(call);
+                Type::Integer
+            }
+            Expr::Identifier(ident) => {
+                self.expect_scalar(ident);
+                self.check_identifier(ident);
+                Type::Integer
+            }
+            Expr::Boolean(_) => Type::Boolean,
+            Expr::String(_) => Type::String,
+            Expr::Number(_) | Expr::Error(_) => Type::Integer,
+        }
+    }
+
+    fn expect_type(&mut self, expr: &Expr, expected: Type, what: &str) {
+        let found = self.check_expression(expr);
+        if found != expected {
+            self.errors.push(SemanticError {
+                span: expr.span(),
+                message: format!("{} must be {}, found {}", what, expected, found),
+            });
+        }
+    }
+
+    // 逻辑运算符的操作数必须是布尔值，其余运算符的操作数必须是整数
+    fn expect_operand(&mut self, operand: &Expr, op: &Token) {
+        let expected = if op.token_type.is_logical() || op.token_type == TokenType::Not {
+            Type::Boolean
+        } else {
+            Type::Integer
+        };
+        self.expect_type(operand, expected, &format!("operand of `{}`", op.literal));
+    }
+
+    // name[index]
+    fn check_index(&mut self, name: &Identifier, index: &Expr) {
+        self.expect_type(index, Type::Integer, "array index");
+        let Some(size) = self.arrays.get(&name.value).copied() else {
+            self.error(name.span, format!("`{}` is not an array", name.value));
+            return;
+        };
+        if let Some(value) = optimizer::constant_value(index) {
+            if !(0..size).contains(&value) {
+                self.error(
+                    index.span(),
+                    format!(
+                        "index {} is out of bounds for array `{}` of size {}",
+                        value, name.value, size
+                    ),
+                );
+            }
+        }
+    }
+
+    // 数组名不能单独使用
+    fn expect_scalar(&mut self, ident: &Identifier) {
+        if self.arrays.contains_key(&ident.value) {
+            self.error(ident.span, format!("array `{}` used without an index", ident.value));
+        }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(SemanticError { span, message });
+    }
+
+    fn check_identifier(&mut self, ident: &Identifier) {
+        if !self.declared.contains(&ident.value) {
+            self.errors.push(SemanticError {
+                span: ident.span,
+                message: format!(
+                    "variable `{}` used before assignment at line {}",
+                    ident.value, ident.span.line
+                ),
+            });
+            // 同一个变量只报告一次
+            self.declared.insert(ident.value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::Parser;
+    use crate::semantic::{check, check_with_options, check_with_warnings, DIVISION_BY_ZERO};
+
+    #[test]
+    fn test_check() {
+        let program = Parser::new("read x; y := x + 1; write y;").parse_program().unwrap();
+        assert!(check(&program, []).is_empty());
+        let program = Parser::new("write limit;").parse_program().unwrap();
+        assert!(check(&program, ["limit"]).is_empty());
+    }
+
+    #[test]
+    fn test_used_before_assignment() {
+        let input = "
+read x;
+if x < 10 then
+    write y;
+    y := z + z
+end;
+write y;";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "4:11: variable `y` used before assignment at line 4",
+                "5:10: variable `z` used before assignment at line 5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_type() {
+        let input = "write \"ok\"; x := \"no\"; write x < 1; y := \"a\" + 1;";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "1:18: assigned value must be integer, found string",
+                "1:30: written value must be integer or string, found boolean",
+                "1:42: operand of `+` must be integer, found string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        let input = "
+var a[10];
+read i;
+a[i] := a[9] + 1;
+a := 1;
+write a[10];
+x[0] := 1;
+a[i < 1] := 2;
+var i;
+var b[0];
+read a";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "5:1: array `a` used without an index",
+                "6:9: index 10 is out of bounds for array `a` of size 10",
+                "7:1: `x` is not an array",
+                "8:3: array index must be integer, found boolean",
+                "9:5: `i` is already declared",
+                "10:7: array size must be positive, found 0",
+                "11:6: array `a` used without an index",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_declaration() {
+        let input = "
+var x, y, x;
+var y;
+z := 1;
+var z;
+read w;
+fn f(n)
+  var t;
+  t := n;
+  u := t;
+  x := u;
+  return x
+end;
+x := f(w)";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors = |strict: bool| -> Vec<_> {
+            check_with_options(&program, ["limit"], strict)
+                .0
+                .iter()
+                .map(|err| err.to_string())
+                .collect()
+        };
+        assert_eq!(
+            errors(false),
+            [
+                "2:11: `x` is already declared",
+                "3:5: `y` is already declared",
+                "5:5: `z` is already declared",
+            ]
+        );
+        // 严格模式下未声明的变量只在第一次赋值时报告；函数中可以给已声明但未赋值的全局变量x赋值
+        assert_eq!(
+            errors(true),
+            [
+                "2:11: `x` is already declared",
+                "3:5: `y` is already declared",
+                "4:1: variable `z` is not declared",
+                "5:5: `z` is already declared",
+                "6:6: variable `w` is not declared",
+                "10:3: variable `u` is not declared",
+            ]
+        );
+
+        let program = Parser::new("var i, a[3]; i := 0; a[i] := limit; read i")
+            .parse_program()
+            .unwrap();
+        assert!(check_with_options(&program, ["limit"], true).0.is_empty());
+        let program = Parser::new("var x; fn f() x := 1 end; f()").parse_program().unwrap();
+        assert!(check_with_options(&program, [], true).0.is_empty());
+    }
+
+    #[test]
+    fn test_function() {
+        let input = "
+total := 0;
+fn add(a, b)
+    sum := a + b;
+    total := total + sum;
+    return sum
+end;
+write add(1, twice(2));
+fn twice(n) return add(n, n) end;
+write sum;
+fn add(x) end;
+fn bad(a, a)
+    var c[2];
+    fn inner() end;
+    write missing(1);
+    return add(1)
+end;
+return 1;
+x := add(1, 2 < 3)";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "11:4: function `add` is already defined",
+                "10:7: variable `sum` used before assignment at line 10",
+                "12:11: parameter `a` is already declared",
+                "13:11: arrays can only be declared at the top level",
+                "14:5: functions can only be defined at the top level",
+                "15:11: function `missing` is not defined",
+                "16:12: function `add` takes 2 arguments, found 1",
+                "18:1: return outside of a function",
+                "19:13: argument of `add` must be integer, found boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logical() {
+        let input = "
+read x;
+if x > 0 and not x = 3 or false then write x end;
+if x and true then write x end;
+y := not x;
+write x < 1 or x;
+z := true";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "4:4: operand of `and` must be boolean, found integer",
+                "5:10: operand of `not` must be boolean, found integer",
+                "5:6: assigned value must be integer, found boolean",
+                "6:16: operand of `or` must be boolean, found integer",
+                "6:7: written value must be integer or string, found boolean",
+                "7:6: assigned value must be integer, found boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_error() {
+        let input = "
+read x;
+if 3 then write x end;
+y := x < 1;
+repeat x := x - 1 until x;
+while x < 10 do x := x + 1 end;";
+        let program = Parser::new(input).parse_program().unwrap();
+        let errors: Vec<_> = check(&program, []).iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "3:4: condition must be boolean, found integer",
+                "4:6: assigned value must be integer, found boolean",
+                "5:25: condition must be boolean, found integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let input = "read x;\ny := x / -0;\nz := x / 0 + 1 / x;\nwrite 4 / 2";
+        let program = Parser::new(input).parse_program().unwrap();
+        let (errors, warnings) = check_with_warnings(&program, []);
+        assert!(errors.is_empty());
+        assert!(warnings.iter().all(|warning| warning.lint == DIVISION_BY_ZERO));
+        let warnings: Vec<_> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, ["2:10: division by zero", "3:10: division by zero"]);
+    }
+}
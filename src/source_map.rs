@@ -0,0 +1,155 @@
+use crate::lexer::{self, Lexer};
+use crate::token::{Span, TokenType};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 一个已读入内存的源文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    // 解码后的源程序文本，见 lexer::decode_source
+    pub text: String,
+    // 源文件编码的问题
+    pub warnings: Vec<String>,
+}
+
+impl SourceFile {
+    // 读取源文件，去除BOM并统一换行符
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (text, warnings) = lexer::decode_source(&fs::read(path)?);
+        Ok(Self {
+            path: path.to_path_buf(),
+            text,
+            warnings,
+        })
+    }
+}
+
+// 按顺序拼接成一个源程序的多个源文件
+// 每个文件都从新的一行开始，拼接后源程序中的span可以通过 SourceMap::locate 映射回所在的文件
+// 文件的最后一个语句可以省略;，拼接时在下一个文件之前补上
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    text: String,
+    // 每个文件第一个字符的偏移量，以及之前所有文件的行数
+    starts: Vec<(usize, usize)>,
+    // text的字符数与行数
+    chars: usize,
+    lines: usize,
+    // 最后一个文件是否以;以外的token结束
+    unterminated: bool,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 依次读取paths中的源文件，返回第一个读取失败的文件与错误
+    pub fn read<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Self, (PathBuf, io::Error)> {
+        let mut map = Self::new();
+        for path in paths {
+            let path = path.as_ref();
+            map.add(SourceFile::read(path).map_err(|err| (path.to_path_buf(), err))?);
+        }
+        Ok(map)
+    }
+
+    pub fn add(&mut self, file: SourceFile) {
+        if self.unterminated {
+            self.text.push_str(";\n");
+            self.chars += 2;
+            self.lines += 1;
+        }
+        self.unterminated = Lexer::new(&file.text)
+            .filter(|token| token.token_type != TokenType::Eof)
+            .last()
+            .is_some_and(|token| token.token_type != TokenType::SemiColon);
+        self.starts.push((self.chars, self.lines));
+        self.text.push_str(&file.text);
+        self.chars += file.text.chars().count();
+        self.lines += file.text.matches('\n').count();
+        if !file.text.is_empty() && !file.text.ends_with('\n') {
+            self.text.push('\n');
+            self.chars += 1;
+            self.lines += 1;
+        }
+        self.files.push(file);
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    // 拼接后的源程序
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // span所在文件的序号，以及相对于该文件的span；没有文件时返回None
+    // 源程序末尾处的span属于最后一个文件
+    pub fn locate(&self, span: Span) -> Option<(usize, Span)> {
+        let index = self
+            .starts
+            .partition_point(|(start, _)| *start <= span.start)
+            .checked_sub(1)?;
+        let (start, lines) = self.starts[index];
+        Some((
+            index,
+            Span {
+                line: span.line - lines,
+                column: span.column,
+                start: span.start - start,
+                end: span.end - start,
+            },
+        ))
+    }
+
+    // 拼接后源程序的第line行所在文件的序号，以及在该文件中的行号
+    pub fn locate_line(&self, line: usize) -> Option<(usize, usize)> {
+        let index = self.starts.partition_point(|(_, lines)| *lines < line).checked_sub(1)?;
+        Some((index, line - self.starts[index].1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Lexer;
+    use crate::source_map::{SourceFile, SourceMap};
+    use crate::token::TokenType;
+    use std::path::PathBuf;
+
+    fn file(path: &str, text: &str) -> SourceFile {
+        SourceFile {
+            path: PathBuf::from(path),
+            text: text.to_string(),
+            warnings: vec![],
+        }
+    }
+
+    #[test]
+    fn test_locate() {
+        let mut map = SourceMap::new();
+        assert_eq!(map.locate(Default::default()), None);
+        map.add(file("a.tny", "read x;\nx := x"));
+        map.add(file("b.tny", "write x;\n"));
+        map.add(file("c.tny", "\n  write x * 2 { done }"));
+        assert_eq!(map.text(), "read x;\nx := x\n;\nwrite x;\n\n  write x * 2 { done }\n");
+
+        let writes: Vec<_> = Lexer::new(map.text())
+            .filter(|token| token.token_type == TokenType::Write)
+            .map(|token| map.locate(token.span).unwrap())
+            .map(|(index, span)| (index, span.to_string(), span.start))
+            .collect();
+        assert_eq!(writes, [(1, "1:1".to_string(), 0), (2, "2:3".to_string(), 3)]);
+
+        let eof = Lexer::new(map.text()).last().unwrap();
+        assert_eq!(map.locate(eof.span).unwrap().0, 2);
+        assert_eq!(map.locate_line(2), Some((0, 2)));
+        assert_eq!(map.locate_line(4), Some((1, 1)));
+        assert_eq!(map.locate_line(6), Some((2, 2)));
+    }
+}
@@ -1,7 +1,13 @@
-#[derive(Debug, Clone)]
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use core::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub token_type: TokenType,
     pub literal: String,
+    pub span: Span,
 }
 
 impl Token {
@@ -9,11 +15,45 @@ impl Token {
         Self {
             token_type,
             literal: literal.to_string(),
+            span: Span::default(),
+        }
+    }
+}
+
+// 源程序中的一段区间
+// line与column从1开始，表示区间起点；start与end为字符偏移量，end不包含在内
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // 从self的起点到other的终点
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+
+    // self起点处长度为0的区间
+    pub fn empty(self) -> Span {
+        Span {
+            end: self.start,
+            ..self
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     Eof,
     Illegal,
@@ -21,19 +61,36 @@ pub enum TokenType {
     Ident,
     Number,
     String,
+    // { ... }，只在 Lexer::with_comments 时产生
+    Comment,
 
+    Program,
     Read,
     If,
     Then,
+    Else,
     Repeat,
     Until,
+    While,
+    Do,
     Write,
     End,
+    Var,
+    Fn,
+    Return,
+    True,
+    False,
+    And,
+    Or,
+    Not,
 
     LessThan,
     Assign,
     EqualLessThan,
+    GreaterThan,
+    EqualGreaterThan,
     Equal,
+    NotEqual,
 
     Mul,
     Minus,
@@ -41,17 +98,149 @@ pub enum TokenType {
     Divide,
 
     SemiColon,
+    Dot,
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    Comma,
 }
 
 pub fn look_up_keywords(ident: &str) -> TokenType {
     match ident {
+        "program" => TokenType::Program,
         "read" => TokenType::Read,
         "if" => TokenType::If,
         "then" => TokenType::Then,
+        "else" => TokenType::Else,
         "repeat" => TokenType::Repeat,
         "until" => TokenType::Until,
+        "while" => TokenType::While,
+        "do" => TokenType::Do,
         "write" => TokenType::Write,
         "end" => TokenType::End,
+        "var" => TokenType::Var,
+        "fn" => TokenType::Fn,
+        "return" => TokenType::Return,
+        "true" => TokenType::True,
+        "false" => TokenType::False,
+        "and" => TokenType::And,
+        "or" => TokenType::Or,
+        "not" => TokenType::Not,
         _ => TokenType::Ident,
     }
 }
+
+// 二元运算符的优先级，数值越大结合越紧
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub enum Precedence {
+    Lowest,
+    Or,         // or
+    And,        // and
+    Comparison, // < <= > >= = <>
+    Sum,        // + -
+    Product,    // * /
+}
+
+impl TokenType {
+    pub fn is_keyword(self) -> bool {
+        matches!(
+            self,
+            TokenType::Program
+                | TokenType::Read
+                | TokenType::If
+                | TokenType::Then
+                | TokenType::Else
+                | TokenType::Repeat
+                | TokenType::Until
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::Write
+                | TokenType::End
+                | TokenType::Var
+                | TokenType::Fn
+                | TokenType::Return
+                | TokenType::True
+                | TokenType::False
+                | TokenType::And
+                | TokenType::Or
+                | TokenType::Not
+        )
+    }
+
+    // 二元运算符
+    pub fn is_operator(self) -> bool {
+        self.precedence() > Precedence::Lowest
+    }
+
+    pub fn is_comparison(self) -> bool {
+        self.precedence() == Precedence::Comparison
+    }
+
+    // 操作数与结果都是布尔值的二元运算符
+    pub fn is_logical(self) -> bool {
+        matches!(self, TokenType::And | TokenType::Or)
+    }
+
+    // 可以作为一条语句开头的token
+    pub fn starts_statement(self) -> bool {
+        matches!(
+            self,
+            TokenType::Ident
+                | TokenType::If
+                | TokenType::Repeat
+                | TokenType::While
+                | TokenType::Read
+                | TokenType::Write
+                | TokenType::Var
+                | TokenType::Fn
+                | TokenType::Return
+        )
+    }
+
+    // 非运算符返回Precedence::Lowest
+    pub fn precedence(self) -> Precedence {
+        match self {
+            TokenType::Or => Precedence::Or,
+            TokenType::And => Precedence::And,
+            TokenType::LessThan
+            | TokenType::EqualLessThan
+            | TokenType::GreaterThan
+            | TokenType::EqualGreaterThan
+            | TokenType::Equal
+            | TokenType::NotEqual => Precedence::Comparison,
+            TokenType::Add | TokenType::Minus => Precedence::Sum,
+            TokenType::Mul | TokenType::Divide => Precedence::Product,
+            _ => Precedence::Lowest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::token::{look_up_keywords, Precedence, TokenType};
+
+    #[test]
+    fn test_classification() {
+        for keyword in [
+            "program", "read", "if", "then", "else", "repeat", "until", "while", "do", "write", "end", "var", "fn",
+            "return", "true", "false", "and", "or", "not",
+        ] {
+            assert!(look_up_keywords(keyword).is_keyword());
+        }
+        assert!(!TokenType::Ident.is_keyword());
+        assert!(TokenType::Mul.is_operator());
+        assert!(!TokenType::Assign.is_operator());
+        assert!(TokenType::EqualLessThan.is_comparison());
+        assert!(TokenType::NotEqual.is_comparison());
+        assert!(TokenType::Or.is_logical());
+        assert!(!TokenType::Not.is_operator());
+        assert!(TokenType::Equal.precedence() > TokenType::And.precedence());
+        assert!(TokenType::And.precedence() > TokenType::Or.precedence());
+        assert!(TokenType::Mul.precedence() > TokenType::Add.precedence());
+        assert!(TokenType::Add.precedence() > TokenType::Equal.precedence());
+        assert_eq!(TokenType::SemiColon.precedence(), Precedence::Lowest);
+        assert!(TokenType::Write.starts_statement());
+        assert!(!TokenType::End.starts_statement());
+    }
+}
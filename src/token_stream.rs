@@ -0,0 +1,144 @@
+use crate::lexer::Lexer;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::token::{Token, TokenType};
+
+// 带缓冲的token流，支持 mark()/rewind(mark)，使解析器可以尝试某个产生式失败后回退
+// 已读取的token保存在buffer中，回退时不需要重新词法分析
+// 词法分析器产生的注释token不进入流中，而是按顺序收集起来，见 TokenStream::take_comments
+pub struct TokenStream<'a> {
+    lexer: Lexer<'a>,
+    buffer: Vec<Token>,
+    pos: usize,
+    comments: Vec<Token>,
+}
+
+// token流中的一个位置，只能用于产生它的TokenStream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mark(usize);
+
+impl<'a> TokenStream<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Self {
+            lexer,
+            buffer: vec![],
+            pos: 0,
+            comments: vec![],
+        }
+    }
+
+    pub fn peek_token(&mut self) -> &Token {
+        self.lookahead(0)
+    }
+
+    // 向前查看第n个token（0为下一个token），不消耗token
+    pub fn lookahead(&mut self, n: usize) -> &Token {
+        while self.buffer.len() <= self.pos + n {
+            // Eof之后继续返回Eof
+            let token = match self.buffer.last() {
+                Some(last) if last.token_type == TokenType::Eof => last.clone(),
+                _ => self.lexer.next_token(),
+            };
+            if token.token_type == TokenType::Comment {
+                self.comments.push(token);
+                continue;
+            }
+            self.buffer.push(token);
+        }
+        &self.buffer[self.pos + n]
+    }
+
+    pub fn next_token(&mut self) -> Token {
+        self.advance().clone()
+    }
+
+    // 消耗下一个token并返回它的引用，不复制token；Eof不会被消耗
+    pub fn advance(&mut self) -> &Token {
+        let pos = self.pos;
+        if self.peek_token().token_type != TokenType::Eof {
+            self.pos += 1;
+        }
+        &self.buffer[pos]
+    }
+
+    pub fn lexer_mut(&mut self) -> &mut Lexer<'a> {
+        &mut self.lexer
+    }
+
+    // 取回词法分析器，已读入buffer的token会被丢弃
+    pub fn into_lexer(self) -> Lexer<'a> {
+        self.lexer
+    }
+
+    // 到目前为止读到的注释，取出后清空
+    pub fn take_comments(&mut self) -> Vec<Token> {
+        core::mem::take(&mut self.comments)
+    }
+
+    // 已经从词法分析器读取的最远的token，解析到目前为止只受到它之前的源程序的影响
+    pub fn furthest(&self) -> Option<&Token> {
+        self.buffer.last()
+    }
+
+    // 最近一个被消耗的token
+    pub fn previous(&self) -> Option<&Token> {
+        self.pos.checked_sub(1).map(|pos| &self.buffer[pos])
+    }
+
+    // 下一个token在流中的序号
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark(self.pos)
+    }
+
+    // 回退到mark所在的位置，之后的token会被重新读取
+    pub fn rewind(&mut self, mark: Mark) {
+        assert!(mark.0 <= self.buffer.len(), "rewind to a mark from another stream");
+        self.pos = mark.0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Lexer;
+    use crate::token::TokenType;
+    use crate::token_stream::TokenStream;
+
+    #[test]
+    fn test_mark_rewind() {
+        let mut stream = TokenStream::new(Lexer::new("x := 1;"));
+        let mark = stream.mark();
+        assert_eq!(stream.next_token().token_type, TokenType::Ident);
+        assert_eq!(stream.next_token().token_type, TokenType::Assign);
+        stream.rewind(mark);
+        assert_eq!(stream.next_token().literal, "x");
+        assert_eq!(stream.lookahead(1).token_type, TokenType::Number);
+        assert_eq!(stream.peek_token().token_type, TokenType::Assign);
+    }
+
+    #[test]
+    fn test_comments() {
+        let mut stream = TokenStream::new(Lexer::new("x { a } := { b } 1").with_comments(true));
+        let types: Vec<_> = (0..3).map(|_| stream.next_token().token_type).collect();
+        assert_eq!(types, [TokenType::Ident, TokenType::Assign, TokenType::Number]);
+        let comments: Vec<_> = stream.take_comments().into_iter().map(|token| token.literal).collect();
+        assert_eq!(comments, ["{ a }", "{ b }"]);
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut stream = TokenStream::new(Lexer::new("x"));
+        stream.next_token();
+        assert_eq!(stream.next_token().token_type, TokenType::Eof);
+        assert_eq!(stream.lookahead(3).token_type, TokenType::Eof);
+        let mark = stream.mark();
+        stream.next_token();
+        stream.rewind(mark);
+        assert_eq!(stream.peek_token().token_type, TokenType::Eof);
+        assert_eq!(stream.advance().token_type, TokenType::Eof);
+        assert_eq!(stream.position(), 1);
+    }
+}
@@ -0,0 +1,460 @@
+use crate::ast::{BlockStatement, CallExpression, Expr, FunctionStatement, Node, Program, Stmt};
+use crate::error::CodegenError;
+use crate::token::TokenType;
+
+// C程序的开头：运算与虚拟机一致，整数运算溢出时回绕，除数为0或输入结束时终止程序
+const PRELUDE: &str = r#"#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static int tiny_add(int a, int b) { return (int)((unsigned)a + (unsigned)b); }
+static int tiny_sub(int a, int b) { return (int)((unsigned)a - (unsigned)b); }
+static int tiny_mul(int a, int b) { return (int)((unsigned)a * (unsigned)b); }
+
+static int tiny_div(int a, int b, int line) {
+    if (b == 0) {
+        fprintf(stderr, "line %d: division by zero\n", line);
+        exit(3);
+    }
+    if (a == INT_MIN && b == -1) {
+        return INT_MIN;
+    }
+    return a / b;
+}
+
+static int tiny_index(int i, int size, int line) {
+    if (i < 0 || i >= size) {
+        fprintf(stderr, "line %d: index %d out of bounds for array of size %d\n", line, i, size);
+        exit(3);
+    }
+    return i;
+}
+
+#define TINY_INDEX(a, i, line) ((a)[tiny_index((i), (int)(sizeof(a) / sizeof((a)[0])), (line))])
+
+static int tiny_read(void) {
+    int value;
+    if (scanf("%d", &value) != 1) {
+        fprintf(stderr, "unexpected end of input\n");
+        exit(3);
+    }
+    return value;
+}
+"#;
+
+// 将TINY程序翻译为可移植的C程序，变量均为初值为0的int全局变量，数组为int全局数组
+// read对应scanf，write对应printf，每行输出一个整数
+// 函数对应返回int的C函数，函数中定义之前未出现的变量为C的局部变量
+// 程序含有语法错误等无法翻译的语法树时返回第一个错误
+pub fn to_c(program: &Program) -> Result<String, CodegenError> {
+    let mut writer = Writer {
+        output: String::new(),
+        level: 1,
+    };
+    writer.statements(&program.statements)?;
+
+    let mut variables = vec![];
+    let mut functions = vec![];
+    for stmt in &program.statements {
+        match stmt {
+            Stmt::Function(function) => functions.push((function, function_definition(function, &variables)?)),
+            stmt => collect_variables(std::slice::from_ref(stmt), &mut variables),
+        }
+    }
+    let mut output = String::new();
+    if let Some(name) = &program.name {
+        output.push_str(&format!("/* program {} */\n", name));
+    }
+    output.push_str(PRELUDE);
+    output.push('\n');
+    for (name, size) in &variables {
+        match size {
+            Some(size) => output.push_str(&format!("static int {}[{}];\n", c_name(name), size)),
+            None => output.push_str(&format!("static int {} = 0;\n", c_name(name))),
+        }
+    }
+    if !variables.is_empty() {
+        output.push('\n');
+    }
+    // 先声明所有函数，函数可以在定义之前调用
+    for (function, _) in &functions {
+        output.push_str(&format!("{};\n", signature(function)));
+    }
+    if !functions.is_empty() {
+        output.push('\n');
+    }
+    for (_, definition) in &functions {
+        output.push_str(definition);
+        output.push('\n');
+    }
+    output.push_str("int main(void) {\n");
+    output.push_str(&writer.output);
+    output.push_str("    return 0;\n}\n");
+    Ok(output)
+}
+
+struct Writer {
+    output: String,
+    level: usize,
+}
+
+impl Writer {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&"    ".repeat(self.level));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn block(&mut self, block: &BlockStatement) -> Result<(), CodegenError> {
+        self.level += 1;
+        self.statements(&block.statements)?;
+        self.level -= 1;
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), CodegenError> {
+        for stmt in statements {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CodegenError> {
+        match stmt {
+            Stmt::Assign(assign) => {
+                let target = match &assign.index {
+                    Some(index) => element(&assign.name.value, index)?,
+                    None => c_name(&assign.name.value),
+                };
+                self.line(&format!("{} = {};", target, expression(&assign.value)?))
+            }
+            Stmt::Read(read) => self.line(&format!("{} = tiny_read();", c_name(&read.name.value))),
+            Stmt::Write(write) => match &write.value {
+                Expr::String(string) => self.line(&format!("printf(\"%s\\n\", {});", c_string(&string.value))),
+                value => self.line(&format!("printf(\"%d\\n\", {});", expression(value)?)),
+            },
+            Stmt::If(if_stmt) => {
+                self.line(&format!("if ({}) {{", expression(&if_stmt.cond)?));
+                self.block(&if_stmt.consequence)?;
+                if let Some(alternative) = &if_stmt.alternative {
+                    self.line("} else {");
+                    self.block(alternative)?;
+                }
+                self.line("}");
+            }
+            Stmt::Repeat(repeat) => {
+                self.line("do {");
+                self.block(&repeat.consequence)?;
+                self.line(&format!("}} while (!({}));", expression(&repeat.cond)?));
+            }
+            Stmt::While(while_stmt) => {
+                self.line(&format!("while ({}) {{", expression(&while_stmt.cond)?));
+                self.block(&while_stmt.consequence)?;
+                self.line("}");
+            }
+            // 变量与数组都声明为全局变量，函数中的变量在函数开头声明
+            Stmt::Var(_) => {}
+            // 函数定义在main之前输出
+            Stmt::Function(_) => {}
+            Stmt::Return(ret) => match &ret.value {
+                Some(value) => self.line(&format!("return {};", expression(value)?)),
+                None => self.line("return 0;"),
+            },
+            Stmt::Call(call) => self.line(&format!("{};", call_expression(&call.call)?)),
+            Stmt::Error(error) => {
+                return Err(CodegenError::SyntaxError {
+                    span: error.span,
+                    message: error.message.clone(),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+fn expression(expr: &Expr) -> Result<String, CodegenError> {
+    Ok(match expr {
+        Expr::Prefix(prefix) => match prefix.op.token_type {
+            TokenType::Minus => format!("tiny_sub(0, {})", expression(&prefix.right)?),
+            TokenType::Not => format!("!({})", expression(&prefix.right)?),
+            token_type => {
+                return Err(CodegenError::InvalidPrefixOperator {
+                    token_type,
+                    span: prefix.op.span,
+                })
+            }
+        },
+        Expr::Infix(infix) => {
+            let left = expression(&infix.left)?;
+            let right = expression(&infix.right)?;
+            match infix.op.token_type {
+                TokenType::Add => format!("tiny_add({}, {})", left, right),
+                TokenType::Minus => format!("tiny_sub({}, {})", left, right),
+                TokenType::Mul => format!("tiny_mul({}, {})", left, right),
+                TokenType::Divide => format!("tiny_div({}, {}, {})", left, right, infix.span.line),
+                TokenType::LessThan => format!("{} < {}", left, right),
+                TokenType::EqualLessThan => format!("{} <= {}", left, right),
+                TokenType::GreaterThan => format!("{} > {}", left, right),
+                TokenType::EqualGreaterThan => format!("{} >= {}", left, right),
+                TokenType::Equal => format!("{} == {}", left, right),
+                TokenType::NotEqual => format!("{} != {}", left, right),
+                // 与TINY一样短路求值
+                TokenType::And => format!("({}) && ({})", left, right),
+                TokenType::Or => format!("({}) || ({})", left, right),
+                token_type => {
+                    return Err(CodegenError::InvalidInfixOperator {
+                        token_type,
+                        span: infix.op.span,
+                    })
+                }
+            }
+        }
+        Expr::Index(index) => element(&index.name.value, &index.index)?,
+        Expr::Call(call) => call_expression(call)?,
+        Expr::Identifier(ident) => c_name(&ident.value),
+        // INT_MIN 不能直接写成字面量
+        Expr::Number(number) if number.value == i32::MIN => "INT_MIN".to_string(),
+        Expr::Number(number) => number.value.to_string(),
+        Expr::Boolean(boolean) => (boolean.value as i32).to_string(),
+        Expr::String(string) => {
+            return Err(CodegenError::StringNotWritable {
+                value: string.value.clone(),
+                span: string.span,
+            })
+        }
+        Expr::Error(error) => {
+            return Err(CodegenError::SyntaxError {
+                span: error.span,
+                message: error.message.clone(),
+            })
+        }
+    })
+}
+
+// C中实参的求值顺序未指定，实参中含有带副作用的调用时输出的顺序可能与虚拟机不同
+fn call_expression(call: &CallExpression) -> Result<String, CodegenError> {
+    let args = call.args.iter().map(expression).collect::<Result<Vec<_>, _>>()?;
+    Ok(format!("{}({})", function_name(&call.name.value), args.join(", ")))
+}
+
+fn signature(function: &FunctionStatement) -> String {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .map(|param| format!("int {}", c_name(&param.value)))
+        .collect();
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    format!("static int {}({})", function_name(&function.name.value), params)
+}
+
+// globals为函数定义之前出现的全局变量
+fn function_definition(
+    function: &FunctionStatement,
+    globals: &[(String, Option<i32>)],
+) -> Result<String, CodegenError> {
+    let mut writer = Writer {
+        output: String::new(),
+        level: 1,
+    };
+    for name in function.locals(|name| globals.iter().any(|(global, _)| name == global.as_str())) {
+        writer.line(&format!("int {} = 0;", c_name(&name)));
+    }
+    writer.statements(&function.body.statements)?;
+    Ok(format!(
+        "{} {{\n{}    return 0;\n}}\n",
+        signature(function),
+        writer.output
+    ))
+}
+
+fn element(name: &str, index: &Expr) -> Result<String, CodegenError> {
+    Ok(format!(
+        "TINY_INDEX({}, {}, {})",
+        c_name(name),
+        expression(index)?,
+        index.span().line
+    ))
+}
+
+// C的字符串字面量，非ASCII字符以UTF-8字节的八进制转义表示
+fn c_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => output.push_str(&format!("\\{}", byte as char)),
+            0x20..=0x7e => output.push(byte as char),
+            _ => output.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    output.push('"');
+    output
+}
+
+// 变量名加上前缀，避免与C的关键字及库函数重名
+fn c_name(name: &str) -> String {
+    format!("v_{}", name)
+}
+
+fn function_name(name: &str) -> String {
+    format!("f_{}", name)
+}
+
+// 按第一次出现的顺序收集所有变量名，以及数组的元素个数
+fn collect_variables(statements: &[Stmt], variables: &mut Vec<(String, Option<i32>)>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::Assign(assign) => {
+                if let Some(index) = &assign.index {
+                    expression_variables(index, variables);
+                } else {
+                    add_variable(variables, &assign.name.value, None);
+                }
+                expression_variables(&assign.value, variables);
+            }
+            Stmt::Read(read) => add_variable(variables, &read.name.value, None),
+            Stmt::Var(var) => {
+                for declaration in &var.declarations {
+                    let size = declaration.size.as_ref().map(|size| size.value);
+                    add_variable(variables, &declaration.name.value, size);
+                }
+            }
+            Stmt::Write(write) => expression_variables(&write.value, variables),
+            Stmt::If(if_stmt) => {
+                expression_variables(&if_stmt.cond, variables);
+                collect_variables(&if_stmt.consequence.statements, variables);
+                if let Some(alternative) = &if_stmt.alternative {
+                    collect_variables(&alternative.statements, variables);
+                }
+            }
+            Stmt::Repeat(repeat) => {
+                collect_variables(&repeat.consequence.statements, variables);
+                expression_variables(&repeat.cond, variables);
+            }
+            Stmt::While(while_stmt) => {
+                expression_variables(&while_stmt.cond, variables);
+                collect_variables(&while_stmt.consequence.statements, variables);
+            }
+            Stmt::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    expression_variables(value, variables);
+                }
+            }
+            Stmt::Call(call) => call
+                .call
+                .args
+                .iter()
+                .for_each(|arg| expression_variables(arg, variables)),
+            // 函数中的变量不是全局变量
+            Stmt::Function(_) | Stmt::Error(_) => {}
+        }
+    }
+}
+
+fn expression_variables(expr: &Expr, variables: &mut Vec<(String, Option<i32>)>) {
+    match expr {
+        Expr::Prefix(prefix) => expression_variables(&prefix.right, variables),
+        Expr::Infix(infix) => {
+            expression_variables(&infix.left, variables);
+            expression_variables(&infix.right, variables);
+        }
+        Expr::Index(index) => expression_variables(&index.index, variables),
+        Expr::Call(call) => call.args.iter().for_each(|arg| expression_variables(arg, variables)),
+        Expr::Identifier(ident) => add_variable(variables, &ident.value, None),
+        Expr::Number(_) | Expr::Boolean(_) | Expr::String(_) | Expr::Error(_) => {}
+    }
+}
+
+fn add_variable(variables: &mut Vec<(String, Option<i32>)>, name: &str, size: Option<i32>) {
+    if !variables.iter().any(|(variable, _)| variable == name) {
+        variables.push((name.to_string(), size));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::CodegenError;
+    use crate::parser::Parser;
+    use crate::transpiler::to_c;
+
+    #[test]
+    fn test_to_c() {
+        let input = "program fact;
+read x;
+if 0 < x then
+    fact := 1;
+    repeat
+        fact := fact * x;
+        x := x - 1
+    until x = 0;
+    write fact
+end;
+while x <> 3 and not false do x := x + -1 end;
+y := x / 2;
+write \"déjà vu?\";
+write y + 1;
+var a[4];
+a[y] := a[1] - y;
+write sum(y);
+fn sum(n)
+    s := 0;
+    while n > 0 do s := s + n; n := n - 1 end;
+    return s
+end
+end.";
+        let program = Parser::new(input).parse_program().unwrap();
+        let output = to_c(&program).unwrap();
+        assert!(output.starts_with("/* program fact */\n#include <limits.h>\n"));
+        let main = &output[output.find("static int v_x").unwrap()..];
+        assert_eq!(
+            main,
+            "static int v_x = 0;
+static int v_fact = 0;
+static int v_y = 0;
+static int v_a[4];
+
+static int f_sum(int v_n);
+
+static int f_sum(int v_n) {
+    int v_s = 0;
+    v_s = 0;
+    while (v_n > 0) {
+        v_s = tiny_add(v_s, v_n);
+        v_n = tiny_sub(v_n, 1);
+    }
+    return v_s;
+    return 0;
+}
+
+int main(void) {
+    v_x = tiny_read();
+    if (0 < v_x) {
+        v_fact = 1;
+        do {
+            v_fact = tiny_mul(v_fact, v_x);
+            v_x = tiny_sub(v_x, 1);
+        } while (!(v_x == 0));
+        printf(\"%d\\n\", v_fact);
+    }
+    while ((v_x != 3) && (!(0))) {
+        v_x = tiny_add(v_x, tiny_sub(0, 1));
+    }
+    v_y = tiny_div(v_x, 2, 12);
+    printf(\"%s\\n\", \"d\\303\\251j\\303\\240 vu\\?\");
+    printf(\"%d\\n\", tiny_add(v_y, 1));
+    TINY_INDEX(v_a, v_y, 16) = tiny_sub(TINY_INDEX(v_a, 1, 16), v_y);
+    printf(\"%d\\n\", f_sum(v_y));
+    return 0;
+}
+"
+        );
+
+        let program = Parser::new("fn f() return \"s\" end; write f()")
+            .parse_program()
+            .unwrap();
+        assert!(matches!(to_c(&program), Err(CodegenError::StringNotWritable { .. })));
+    }
+}
@@ -0,0 +1,921 @@
+use crate::code::OpCode::{ADD, DIV, HALT, IN, JEQ, JGE, JGT, JLE, JLT, JNE, LD, LDA, LDC, MUL, OUT, OUTS, ST, SUB};
+use crate::code::{self, DataImage, DebugInfo, Instruction, RegisterCode};
+use crate::object::ObjectFile;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+use std::time::{Duration, Instant};
+
+pub use crate::code::MEMORY_SIZE;
+
+// 寄存器个数，其中7号寄存器为PC
+pub const REGISTER_COUNT: usize = 8;
+
+// 指令存储器的大小，清单与目标文件中的指令地址不能超出；参考实现为1024，这里放宽以运行较大的程序
+pub const INSTRUCTION_MEMORY_SIZE: usize = 1 << 16;
+
+// 设置了运行时间上限时，每执行这么多条指令检查一次时间
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+// 执行编译器产生的TM清单的虚拟机
+// IN/OUT 指令从input读取、向output写出整数，每行一个
+pub struct TinyMachine<R, W> {
+    instructions: Vec<Option<Instruction>>,
+    halted: bool,
+    // 已执行的指令数
+    executed: u64,
+    // 执行的指令数与运行时间的上限，见 TinyMachine::with_instruction_limit
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+    pub registers: [i32; REGISTER_COUNT],
+    pub memory: Vec<i32>,
+    // 清单中 .SPAN 指示给出的调试信息，见 Compiler::with_debug_info
+    pub debug_info: DebugInfo,
+    pub input: R,
+    pub output: W,
+    // 逐条记录执行的指令及执行后的寄存器，见 TinyMachine::with_trace
+    trace: Option<Box<dyn Write>>,
+    profile: Option<Profile>,
+}
+
+// 每条指令的执行次数，用于找出程序中执行最频繁的部分
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    // 下标为指令地址
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    fn record(&mut self, addr: usize) {
+        if self.counts.len() <= addr {
+            self.counts.resize(addr + 1, 0);
+        }
+        self.counts[addr] += 1;
+    }
+
+    pub fn count(&self, addr: usize) -> u64 {
+        self.counts.get(addr).copied().unwrap_or(0)
+    }
+
+    // 执行的指令总数
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // 执行过的指令及其次数，次数多的在前，次数相同时按地址
+    pub fn instructions(&self) -> Vec<(usize, u64)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(addr, count)| (addr, *count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    // 按源程序的行汇总执行次数，排序同instructions；没有调试信息的指令不计入
+    pub fn lines(&self, debug_info: &DebugInfo) -> Vec<(usize, u64)> {
+        let mut lines = BTreeMap::new();
+        for (addr, count) in self.counts.iter().enumerate() {
+            if let (Some(line), true) = (debug_info.line(addr), *count > 0) {
+                *lines.entry(line).or_insert(0) += count;
+            }
+        }
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        lines
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VmError {
+    // 清单格式错误，line从1开始
+    Load {
+        line: usize,
+        message: String,
+    },
+    // 执行第pc条指令时发生的运行时错误
+    Trap {
+        pc: usize,
+        message: String,
+    },
+    // 第pc条DIV指令的除数为0
+    DivisionByZero {
+        pc: usize,
+    },
+    // 执行第pc条指令之前超出了执行的指令数或运行时间的上限
+    ExecutionLimitExceeded {
+        pc: usize,
+        limit: ExecutionLimit,
+    },
+    // 第pc条指令访问的数据地址addr不在大小为size的数据存储器中
+    MemoryOutOfBounds {
+        pc: usize,
+        addr: i64,
+        size: usize,
+    },
+    // 第pc条指令执行后PC为target，不是已装载的size条指令之一；pc为None时尚未执行任何指令
+    PcOutOfRange {
+        pc: Option<usize>,
+        target: i32,
+        size: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExecutionLimit {
+    Instructions(u64),
+    Time(Duration),
+}
+
+impl Display for ExecutionLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionLimit::Instructions(limit) => write!(f, "instruction limit of {}", limit),
+            ExecutionLimit::Time(timeout) => write!(f, "time limit of {:?}", timeout),
+        }
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::Load { line, message } => write!(f, "line {}: {}", line, message),
+            VmError::Trap { pc, message } => write!(f, "trap at {}: {}", pc, message),
+            VmError::DivisionByZero { pc } => write!(f, "trap at {}: division by zero", pc),
+            VmError::ExecutionLimitExceeded { pc, limit } => write!(f, "trap at {}: {} exceeded", pc, limit),
+            VmError::MemoryOutOfBounds { pc, addr, size } => write!(
+                f,
+                "trap at {}: data address {} out of range (memory size {})",
+                pc, addr, size
+            ),
+            VmError::PcOutOfRange { pc, target, size } => {
+                if let Some(pc) = pc {
+                    write!(f, "trap at {}: ", pc)?;
+                }
+                write!(f, "pc {} is outside of the program ({} instructions)", target, size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl VmError {
+    // 发生运行时错误的指令地址，装载错误为None
+    pub fn pc(&self) -> Option<usize> {
+        match self {
+            VmError::Load { .. } => None,
+            VmError::PcOutOfRange { pc, .. } => *pc,
+            VmError::Trap { pc, .. }
+            | VmError::DivisionByZero { pc }
+            | VmError::ExecutionLimitExceeded { pc, .. }
+            | VmError::MemoryOutOfBounds { pc, .. } => Some(*pc),
+        }
+    }
+}
+
+impl TinyMachine<BufReader<Stdin>, Stdout> {
+    // 使用标准输入输出
+    pub fn with_stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> TinyMachine<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        // 与参考实现一致，地址0保存数据存储器的最大地址，由标准序言读入MP
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[0] = MEMORY_SIZE as i32 - 1;
+        Self {
+            instructions: vec![],
+            halted: false,
+            executed: 0,
+            instruction_limit: None,
+            timeout: None,
+            registers: [0; REGISTER_COUNT],
+            memory,
+            debug_info: DebugInfo::new(),
+            input,
+            output,
+            trace: None,
+            profile: None,
+        }
+    }
+
+    // 数据存储器的大小，默认为 MEMORY_SIZE；地址0中的最大地址随之改变
+    // 改变大小会清空数据存储器，应在装载程序之前调用
+    pub fn with_memory_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "data memory needs at least one word");
+        self.memory = vec![0; size];
+        self.memory[0] = size as i32 - 1;
+        self
+    }
+
+    // 每执行一条指令，向trace写出一行：地址、指令以及执行后的全部寄存器
+    pub fn with_trace(mut self, trace: impl Write + 'static) -> Self {
+        self.trace = Some(Box::new(trace));
+        self
+    }
+
+    // 最多执行limit条指令，之后返回 VmError::ExecutionLimitExceeded，防止死循环无法结束
+    pub fn with_instruction_limit(mut self, limit: u64) -> Self {
+        self.instruction_limit = Some(limit);
+        self
+    }
+
+    // run最多运行timeout的时间
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // 已执行的指令数
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    // 统计每条指令的执行次数，见 TinyMachine::profile_report
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile.then(Profile::default);
+        self
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // 执行次数最多的limit行源程序与limit条指令，行号来自debug_info；未开启统计时为None
+    pub fn profile_report(&self, limit: usize) -> Option<String> {
+        let profile = self.profile.as_ref()?;
+        let total = profile.total();
+        let share = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        let mut report = format!("executed {} instructions\n", total);
+        let lines = profile.lines(&self.debug_info);
+        if !lines.is_empty() {
+            report.push_str("\n line      count   share\n");
+            for (line, count) in lines.into_iter().take(limit) {
+                report.push_str(&format!("{:>5} {:>10} {:>6.1}%\n", line, count, share(count)));
+            }
+        }
+        report.push_str("\n addr      count   share  instruction\n");
+        for (addr, count) in profile.instructions().into_iter().take(limit) {
+            let instruction = self
+                .instruction(addr)
+                .map_or(String::new(), |instruction| instruction.to_string());
+            let mut row = format!("{:>5} {:>10} {:>6.1}%  {}", addr, count, share(count), instruction);
+            if let Some(line) = self.debug_info.line(addr) {
+                row.push_str(&format!("  (line {})", line));
+            }
+            report.push_str(&row);
+            report.push('\n');
+        }
+        Some(report)
+    }
+
+    // 装载Compiler::to_intermedia_code产生的清单，包括.DATA指示；也接受参考实现输出的带注释的清单
+    pub fn load(&mut self, listing: &str) -> Result<(), VmError> {
+        for (index, line) in listing.lines().enumerate() {
+            let error = |message: String| VmError::Load {
+                line: index + 1,
+                message,
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('*') {
+                continue;
+            }
+            let (head, operands) = trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, ""));
+            if head.eq_ignore_ascii_case(".RELOC") {
+                continue;
+            }
+            if head.eq_ignore_ascii_case(".SPAN") {
+                let (addr, span) = code::parse_span_directive(operands).map_err(error)?;
+                self.debug_info.set(addr, Some(span));
+                continue;
+            }
+            if head.eq_ignore_ascii_case(".DATA") {
+                let (addr, value) = operands
+                    .split_once(',')
+                    .ok_or_else(|| error(format!("malformed data directive `{}`", trimmed)))?;
+                let addr: usize = parse_number(addr).map_err(error)?;
+                let value: i32 = parse_number(value).map_err(error)?;
+                self.store(addr as i64, value)
+                    .ok_or_else(|| error(format!("data address {} out of range", addr)))?;
+                continue;
+            }
+
+            let (addr, instruction) = code::parse_listing_line(trimmed).map_err(error)?;
+            if addr >= INSTRUCTION_MEMORY_SIZE {
+                return Err(error(format!(
+                    "instruction address {} out of range (instruction memory size {})",
+                    addr, INSTRUCTION_MEMORY_SIZE
+                )));
+            }
+            check_registers(&instruction).map_err(error)?;
+            if self.instructions.len() <= addr {
+                self.instructions.resize(addr + 1, None);
+            }
+            self.instructions[addr] = Some(instruction);
+        }
+        Ok(())
+    }
+
+    // 装载目标文件中的指令与数据，指令从地址0开始
+    pub fn load_object(&mut self, object: &ObjectFile) -> Result<(), VmError> {
+        if object.instructions.len() > INSTRUCTION_MEMORY_SIZE {
+            return Err(VmError::Load {
+                line: 0,
+                message: format!(
+                    "{} instructions do not fit in the instruction memory of size {}",
+                    object.instructions.len(),
+                    INSTRUCTION_MEMORY_SIZE
+                ),
+            });
+        }
+        for (index, instruction) in object.instructions.iter().enumerate() {
+            check_registers(instruction).map_err(|message| VmError::Load {
+                line: 0,
+                message: format!("instruction {}: {}", index, message),
+            })?;
+        }
+        self.instructions = object.instructions.iter().copied().map(Some).collect();
+        self.load_data(&object.data)
+    }
+
+    // 装载数据存储器的初始内容，地址相对GP
+    pub fn load_data(&mut self, data: &DataImage) -> Result<(), VmError> {
+        let gp = self.registers[usize::from(RegisterCode::GP)] as i64;
+        for (addr, value) in data.iter() {
+            self.store(gp + addr as i64, value).ok_or_else(|| VmError::Load {
+                line: 0,
+                message: format!("data address {} out of range", gp + addr as i64),
+            })?;
+        }
+        Ok(())
+    }
+
+    // 地址addr处的指令，未装载时为None
+    pub fn instruction(&self, addr: usize) -> Option<Instruction> {
+        self.instructions.get(addr).copied().flatten()
+    }
+
+    // 下一条要执行的指令的地址；PC为负数时为usize::MAX，不是任何指令的地址
+    pub fn pc(&self) -> usize {
+        usize::try_from(self.registers[usize::from(RegisterCode::PC)]).unwrap_or(usize::MAX)
+    }
+
+    // 执行了HALT；PC跳出程序之外是运行时错误，见 VmError::PcOutOfRange
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // 执行到停机为止
+    pub fn run(&mut self) -> Result<(), VmError> {
+        let deadline = self.timeout.map(|timeout| (Instant::now() + timeout, timeout));
+        while !self.is_halted() {
+            // 每执行一批指令检查一次时间
+            if let Some((deadline, timeout)) = deadline {
+                if self.executed % TIMEOUT_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                    return Err(VmError::ExecutionLimitExceeded {
+                        pc: self.pc(),
+                        limit: ExecutionLimit::Time(timeout),
+                    });
+                }
+            }
+            self.step()?;
+        }
+        self.output.flush().map_err(|err| VmError::Trap {
+            pc: self.pc(),
+            message: err.to_string(),
+        })
+    }
+
+    // 执行一条指令，已停机时不做任何事
+    pub fn step(&mut self) -> Result<(), VmError> {
+        let pc = self.pc();
+        if self.is_halted() {
+            return Ok(());
+        }
+        self.check_pc(None)?;
+        if let Some(limit) = self.instruction_limit.filter(|limit| self.executed >= *limit) {
+            return Err(VmError::ExecutionLimitExceeded {
+                pc,
+                limit: ExecutionLimit::Instructions(limit),
+            });
+        }
+        let trap = |message: String| VmError::Trap { pc, message };
+        let instruction = self.instructions[pc].ok_or_else(|| trap(format!("no instruction at {}", pc)))?;
+        self.executed += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc);
+        }
+        self.execute(pc, instruction)?;
+        if !self.halted {
+            self.check_pc(Some(pc))?;
+        }
+        if let Some(trace) = &mut self.trace {
+            let registers: Vec<_> = self.registers.iter().map(i32::to_string).collect();
+            writeln!(
+                trace,
+                "{:>4}: {:<16} {}",
+                pc,
+                instruction.to_string(),
+                registers.join(" ")
+            )
+            .map_err(|err| trap(err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // PC必须指向已装载的指令，pc为刚执行的指令
+    fn check_pc(&self, pc: Option<usize>) -> Result<(), VmError> {
+        if self.pc() < self.instructions.len() {
+            return Ok(());
+        }
+        Err(VmError::PcOutOfRange {
+            pc,
+            target: self.registers[usize::from(RegisterCode::PC)],
+            size: self.instructions.len(),
+        })
+    }
+
+    fn execute(&mut self, pc: usize, instruction: Instruction) -> Result<(), VmError> {
+        let trap = |message: String| VmError::Trap { pc, message };
+        let size = self.memory.len();
+        let out_of_bounds = |addr: i64| VmError::MemoryOutOfBounds { pc, addr, size };
+        // 与参考实现一致，执行指令前PC已指向下一条指令
+        self.registers[usize::from(RegisterCode::PC)] = pc as i32 + 1;
+        match instruction {
+            Instruction::Rm { op, r, d, s } => {
+                let addr = self.registers[s].wrapping_add(d);
+                match op {
+                    LDC => self.registers[r] = d,
+                    LDA => self.registers[r] = addr,
+                    LD => self.registers[r] = self.fetch(addr as i64).ok_or_else(|| out_of_bounds(addr as i64))?,
+                    ST => self
+                        .store(addr as i64, self.registers[r])
+                        .ok_or_else(|| out_of_bounds(addr as i64))?,
+                    JLT | JLE | JEQ | JGT | JGE | JNE => {
+                        let value = self.registers[r];
+                        let taken = match op {
+                            JLT => value < 0,
+                            JLE => value <= 0,
+                            JEQ => value == 0,
+                            JGT => value > 0,
+                            JGE => value >= 0,
+                            _ => value != 0,
+                        };
+                        if taken {
+                            self.registers[usize::from(RegisterCode::PC)] = addr;
+                        }
+                    }
+                    _ => return Err(trap(format!("{} is not a register to memory instruction", op))),
+                }
+            }
+            Instruction::Ro { op, r, s, t } => match op {
+                HALT => self.halted = true,
+                IN => self.registers[r] = self.read_input().map_err(trap)?,
+                OUT => writeln!(self.output, "{}", self.registers[r]).map_err(|err| trap(err.to_string()))?,
+                OUTS => {
+                    let text = self.read_string(self.registers[r] as i64).map_err(out_of_bounds)?;
+                    writeln!(self.output, "{}", text).map_err(|err| trap(err.to_string()))?;
+                }
+                ADD => self.registers[r] = self.registers[s].wrapping_add(self.registers[t]),
+                SUB => self.registers[r] = self.registers[s].wrapping_sub(self.registers[t]),
+                MUL => self.registers[r] = self.registers[s].wrapping_mul(self.registers[t]),
+                DIV => {
+                    if self.registers[t] == 0 {
+                        return Err(VmError::DivisionByZero { pc });
+                    }
+                    self.registers[r] = self.registers[s].wrapping_div(self.registers[t]);
+                }
+                _ => return Err(trap(format!("{} is not a register only instruction", op))),
+            },
+        }
+        Ok(())
+    }
+
+    // 地址越界时为None
+    fn fetch(&self, addr: i64) -> Option<i32> {
+        self.memory.get(usize::try_from(addr).ok()?).copied()
+    }
+
+    fn store(&mut self, addr: i64, value: i32) -> Option<()> {
+        *self.memory.get_mut(usize::try_from(addr).ok()?)? = value;
+        Some(())
+    }
+
+    // 从addr开始读取以0结尾的字符串，每个存储单元为一个字符的Unicode码点
+    // 越界时返回越界的地址
+    fn read_string(&self, mut addr: i64) -> Result<String, i64> {
+        let mut text = String::new();
+        loop {
+            let code = self.fetch(addr).ok_or(addr)?;
+            if code == 0 {
+                return Ok(text);
+            }
+            text.push(char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
+            addr += 1;
+        }
+    }
+
+    fn read_input(&mut self) -> Result<i32, String> {
+        read_integer(&mut self.input)
+    }
+}
+
+fn check_registers(instruction: &Instruction) -> Result<(), String> {
+    let registers = match *instruction {
+        Instruction::Rm { r, s, .. } => [r, s, 0],
+        Instruction::Ro { r, s, t, .. } => [r, s, t],
+    };
+    match registers.iter().find(|register| **register >= REGISTER_COUNT) {
+        Some(register) => Err(format!("no register {}", register)),
+        None => Ok(()),
+    }
+}
+
+// 读取一行输入中的整数，跳过空行
+pub(crate) fn read_integer(input: &mut impl BufRead) -> Result<i32, String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = input.read_line(&mut line).map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Err("unexpected end of input".to_string());
+        }
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    line.trim()
+        .parse()
+        .map_err(|_| format!("expected an integer input, found `{}`", line.trim()))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a number, found `{}`", value.trim()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::vm::{ExecutionLimit, TinyMachine, VmError};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    fn run(input: &str, stdin: &str) -> Result<String, VmError> {
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let mut vm = TinyMachine::new(stdin.as_bytes(), vec![]);
+        vm.load(&compiler.to_intermedia_code())?;
+        vm.run()?;
+        Ok(String::from_utf8(vm.output).unwrap())
+    }
+
+    #[test]
+    fn test_read_write() {
+        assert_eq!(run("read x; read y; write x; write y;", "3\n4\n").unwrap(), "3\n4\n");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let input = "read x; y := x * 3; z := y - 25; w := z / 2; write w;";
+        assert_eq!(run(input, "7\n").unwrap(), "-2\n");
+    }
+
+    #[test]
+    fn test_prefix_expression() {
+        assert_eq!(run("read x; y := -x + 3; write y;", "5").unwrap(), "-2\n");
+        assert_eq!(run("x := -5; y := 1 - -x; write y;", "").unwrap(), "-4\n");
+    }
+
+    #[test]
+    fn test_if_statement() {
+        let input = "
+read x;
+if x < 10 then
+    write x;
+end;
+if x = 10 then
+    y := 0;
+    write y;
+end;";
+        assert_eq!(run(input, "3").unwrap(), "3\n");
+        assert_eq!(run(input, "10").unwrap(), "0\n");
+        assert_eq!(run(input, "12").unwrap(), "");
+    }
+
+    #[test]
+    fn test_if_else_statement() {
+        let input = "
+read x;
+if x < 10 then
+    y := 1
+else
+    y := 2;
+    y := y * 10
+end;
+write y;";
+        assert_eq!(run(input, "3").unwrap(), "1\n");
+        assert_eq!(run(input, "12").unwrap(), "20\n");
+    }
+
+    #[test]
+    fn test_comparison() {
+        let input = "
+read x;
+a := x > 3;
+b := x >= 3;
+c := x <> 3;
+d := x != 4;
+write a; write b; write c; write d;";
+        assert_eq!(run(input, "3").unwrap(), "0\n1\n0\n1\n");
+        assert_eq!(run(input, "4").unwrap(), "1\n1\n1\n0\n");
+        assert_eq!(run(input, "2").unwrap(), "0\n0\n1\n1\n");
+    }
+
+    #[test]
+    fn test_less_equal() {
+        let input = "
+read x;
+if x <= 10 then
+    y := 1
+else
+    y := 0
+end;
+write y;";
+        assert_eq!(run(input, "9").unwrap(), "1\n");
+        assert_eq!(run(input, "10").unwrap(), "1\n");
+        assert_eq!(run(input, "11").unwrap(), "0\n");
+    }
+
+    #[test]
+    fn test_repeat_statement() {
+        let input = "
+read x;
+fact := 1;
+repeat
+    fact := fact * x;
+    x := x - 1
+until x = 0;
+write fact;";
+        assert_eq!(run(input, "5").unwrap(), "120\n");
+    }
+
+    #[test]
+    fn test_while_statement() {
+        let input = "
+read x;
+sum := 0;
+while 0 < x do
+    sum := sum + x;
+    x := x - 1
+end;
+write sum;";
+        assert_eq!(run(input, "4").unwrap(), "10\n");
+        assert_eq!(run(input, "0").unwrap(), "0\n");
+    }
+
+    #[test]
+    fn test_opt_level() {
+        let input = "
+read x;
+if 0 < x then
+    fact := 2 - 1;
+    repeat
+        fact := fact * x;
+        x := x - 1
+    until x = 0;
+    write fact
+end";
+        let mut compiler = Compiler::default().with_opt_level(1);
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let mut vm = TinyMachine::new(&b"5"[..], vec![]);
+        vm.load(&compiler.to_intermedia_code()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.output, b"120\n");
+    }
+
+    #[test]
+    fn test_write_string() {
+        let input = "write \"héllo\"; x := 2; write x + 1; write \"héllo\"; write \"\"";
+        let mut compiler = Compiler::default();
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        // 相同的字符串只保存一份，包括结尾的0
+        assert_eq!(compiler.data.len(), 7);
+        assert_eq!(run(input, "").unwrap(), "héllo\n3\nhéllo\n\n");
+    }
+
+    #[test]
+    fn test_data() {
+        let mut compiler = Compiler::default();
+        compiler.preset("limit", 10);
+        compiler
+            .compile(&Parser::new("x := 100000 + limit; write x;").parse_program().unwrap())
+            .unwrap();
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        vm.load(&compiler.to_intermedia_code()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.output, b"100010\n");
+    }
+
+    // 测试中保存trace的输出
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut compiler = Compiler::default();
+        compiler
+            .compile(&Parser::new("x := 7; write x").parse_program().unwrap())
+            .unwrap();
+        let buffer = SharedBuffer::default();
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_trace(buffer.clone());
+        vm.load(&compiler.to_intermedia_code()).unwrap();
+        vm.run().unwrap();
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "   0: LD 6,0(0)        0 0 0 0 0 0 1023 1");
+        assert_eq!(lines[2], "   2: LDC 0,7(0)       7 0 0 0 0 0 1023 3");
+    }
+
+    #[test]
+    fn test_profile() {
+        let input = "read x;\nrepeat\n    x := x - 1\nuntil x = 0;\nwrite x";
+        let mut compiler = Compiler::default().with_debug_info(true);
+        compiler.compile(&Parser::new(input).parse_program().unwrap()).unwrap();
+        let mut vm = TinyMachine::new(&b"10"[..], vec![]).with_profile(true);
+        vm.load(&compiler.to_intermedia_code()).unwrap();
+        vm.run().unwrap();
+        let profile = vm.profile().unwrap();
+        let lines = profile.lines(&vm.debug_info);
+        // 循环体与条件各执行10次
+        assert_eq!(lines[0].0, 4);
+        assert_eq!(lines.iter().find(|(line, _)| *line == 3), Some(&(3, 60)));
+        assert_eq!(profile.instructions()[0].1, 10);
+        assert_eq!(profile.count(0), 1);
+        let report = vm.profile_report(3).unwrap();
+        assert!(report.starts_with(&format!("executed {} instructions\n", profile.total())));
+        assert!(report.contains("    3         60"));
+        assert!(TinyMachine::new(&b""[..], vec![]).profile_report(3).is_none());
+    }
+
+    #[test]
+    fn test_execution_limit() {
+        let mut compiler = Compiler::default();
+        compiler
+            .compile(
+                &Parser::new("x := 0; repeat x := x + 1 until x < 0")
+                    .parse_program()
+                    .unwrap(),
+            )
+            .unwrap();
+        let code = compiler.to_intermedia_code();
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_instruction_limit(1000);
+        vm.load(&code).unwrap();
+        let err = vm.run().unwrap_err();
+        assert!(matches!(
+            err,
+            VmError::ExecutionLimitExceeded {
+                limit: ExecutionLimit::Instructions(1000),
+                ..
+            }
+        ));
+        assert_eq!(vm.executed(), 1000);
+        assert_eq!(err.pc(), Some(vm.pc()));
+        assert!(err.to_string().ends_with(": instruction limit of 1000 exceeded"));
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_timeout(Duration::from_millis(20));
+        vm.load(&code).unwrap();
+        assert!(matches!(
+            vm.run(),
+            Err(VmError::ExecutionLimitExceeded {
+                limit: ExecutionLimit::Time(_),
+                ..
+            })
+        ));
+
+        // 在上限之内结束的程序不受影响
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_instruction_limit(7);
+        vm.load("0: LDC 0,1(0)\n1: HALT 0,0,0").unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.executed(), 2);
+    }
+
+    #[test]
+    fn test_memory_size() {
+        let vm = TinyMachine::new(&b""[..], vec![]);
+        assert_eq!(vm.memory.len(), 1024);
+        assert_eq!(vm.memory[0], 1023);
+
+        // 标准序言从地址0读入MP，临时区域随之位于较小的存储器的末尾
+        let mut compiler = Compiler::default().with_memory_size(16);
+        compiler
+            .compile(&Parser::new("read x; write x * 2 + 1").parse_program().unwrap())
+            .unwrap();
+        let mut vm = TinyMachine::new(&b"4"[..], vec![]).with_memory_size(16);
+        vm.load(&compiler.to_intermedia_code()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.output, b"9\n");
+        assert_eq!(vm.registers[6], 15);
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_memory_size(16);
+        vm.load("0: LDC 0,16(0)\n1: LD 1,0(0)\n2: HALT 0,0,0").unwrap();
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err,
+            VmError::MemoryOutOfBounds {
+                pc: 1,
+                addr: 16,
+                size: 16
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "trap at 1: data address 16 out of range (memory size 16)"
+        );
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        vm.load("0: ST 0,-1(0)\n1: HALT 0,0,0").unwrap();
+        assert_eq!(
+            vm.run(),
+            Err(VmError::MemoryOutOfBounds {
+                pc: 0,
+                addr: -1,
+                size: 1024
+            })
+        );
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]).with_memory_size(16);
+        assert!(matches!(vm.load(".DATA 16,1"), Err(VmError::Load { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_trap() {
+        let err = run("read x; y := 1 / x; write y;", "0").unwrap_err();
+        assert_eq!(err, VmError::DivisionByZero { pc: 8 });
+        assert_eq!(err.to_string(), "trap at 8: division by zero");
+        assert_eq!(
+            run("read x;", "").unwrap_err().to_string(),
+            "trap at 2: unexpected end of input"
+        );
+    }
+
+    #[test]
+    fn test_pc_out_of_range() {
+        // 跳转到负数地址或程序之外不被当作停机
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        vm.load("0: LDA 7,-5(7)\n1: HALT 0,0,0").unwrap();
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err,
+            VmError::PcOutOfRange {
+                pc: Some(0),
+                target: -4,
+                size: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "trap at 0: pc -4 is outside of the program (2 instructions)"
+        );
+        assert!(!vm.is_halted());
+
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        vm.load("0: LDC 7,9(0)\n1: HALT 0,0,0").unwrap();
+        assert_eq!(vm.run().unwrap_err().pc(), Some(0));
+
+        // 没有HALT时执行到最后一条指令之后
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        vm.load("0: LDC 0,1(0)").unwrap();
+        assert!(matches!(vm.run(), Err(VmError::PcOutOfRange { target: 1, .. })));
+
+        // 指令地址超出指令存储器时拒绝装载，而不是分配巨大的空间
+        let mut vm = TinyMachine::new(&b""[..], vec![]);
+        assert!(matches!(
+            vm.load("4294967295: HALT 0,0,0"),
+            Err(VmError::Load { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::diagnostics::{Diagnostic, DiagnosticBag};
+use crate::driver::{self, Artifacts, Options};
+use crate::lexer;
+use crate::token::Span;
+use crate::vm::TinyMachine;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+// 供浏览器中的playground使用的wasm-bindgen接口，参数为源程序等字符串，返回值为JSON文本
+// 构建：cargo rustc --lib --release --crate-type cdylib --target wasm32-unknown-unknown --features wasm，
+// 再以wasm-bindgen命令行工具生成JS胶水代码
+// 所有的位置都是字符偏移量，与 Span 一致
+
+// run 最多执行的指令数，避免死循环使页面失去响应
+pub const INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+// 每个token为 {"type", "literal", "span"}，最后一个为Eof
+#[wasm_bindgen]
+pub fn tokenize(source: &str) -> String {
+    let tokens: Vec<_> = lexer::tokenize(source)
+        .into_iter()
+        .map(|token| {
+            json!({
+                "type": format!("{:?}", token.token_type),
+                "literal": token.literal,
+                "span": span_json(token.span),
+            })
+        })
+        .collect();
+    Value::Array(tokens).to_string()
+}
+
+// {"ok", "diagnostics", "listing"}，编译失败时ok为false，listing为null
+#[wasm_bindgen]
+pub fn compile(source: &str, opt_level: u8) -> String {
+    let (diagnostics, artifacts) = check(source, opt_level);
+    compile_json(&diagnostics, artifacts.as_ref()).to_string()
+}
+
+// 在compile的结果之外，"output"为程序的输出，运行时错误加入diagnostics；
+// input为read读取的输入，每行一个整数。编译失败时output为null
+#[wasm_bindgen]
+pub fn run(source: &str, input: &str, opt_level: u8) -> String {
+    let (mut diagnostics, artifacts) = check(source, opt_level);
+    let output = artifacts.as_ref().map(|artifacts| {
+        let mut vm = TinyMachine::new(input.as_bytes(), vec![])
+            .with_memory_size(artifacts.memory_size)
+            .with_instruction_limit(INSTRUCTION_LIMIT);
+        let result = vm.load(&artifacts.listing).and_then(|_| vm.run());
+        if let Err(err) = result {
+            let span = err.pc().and_then(|pc| artifacts.debug_info.span(pc));
+            diagnostics.push(Diagnostic::error(span, err.to_string()));
+        }
+        String::from_utf8_lossy(&vm.output).into_owned()
+    });
+    let mut result = compile_json(&diagnostics, artifacts.as_ref());
+    result["output"] = json!(output);
+    result.to_string()
+}
+
+// 以默认选项编译，返回带位置的诊断信息与编译的产物
+fn check(source: &str, opt_level: u8) -> (DiagnosticBag, Option<Artifacts>) {
+    let options = Options {
+        opt_level,
+        ..Options::default()
+    };
+    match driver::compile_to_tm(source, &options) {
+        Ok(artifacts) => {
+            let mut diagnostics = DiagnosticBag::new();
+            diagnostics.extend(artifacts.diagnostics.iter().cloned());
+            (diagnostics, Some(artifacts))
+        }
+        Err(err) => (err.diagnostics(), None),
+    }
+}
+
+fn compile_json(diagnostics: &DiagnosticBag, artifacts: Option<&Artifacts>) -> Value {
+    let diagnostics: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            json!({
+                "severity": diagnostic.severity.to_string(),
+                "message": diagnostic.message,
+                "help": diagnostic.help,
+                "span": diagnostic.span.map(span_json),
+            })
+        })
+        .collect();
+    json!({
+        "ok": artifacts.is_some(),
+        "diagnostics": diagnostics,
+        "listing": artifacts.map(|artifacts| artifacts.listing.as_str()),
+    })
+}
+
+fn span_json(span: Span) -> Value {
+    json!({
+        "line": span.line,
+        "column": span.column,
+        "start": span.start,
+        "end": span.end,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::wasm::{compile, run, tokenize};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_bindings() {
+        let tokens: Value = serde_json::from_str(&tokenize("read x")).unwrap();
+        assert_eq!(
+            tokens[1],
+            json!({ "type": "Ident", "literal": "x", "span": { "line": 1, "column": 6, "start": 5, "end": 6 } })
+        );
+        assert_eq!(tokens.as_array().unwrap().len(), 3);
+
+        let result: Value = serde_json::from_str(&compile("read x;\nwrite x / 0", 0)).unwrap();
+        assert_eq!(result["ok"], true);
+        assert!(result["listing"].as_str().unwrap().contains("HALT"));
+        assert_eq!(result["diagnostics"][0]["severity"], "warning");
+        assert_eq!(result["diagnostics"][0]["message"], "division by zero");
+        assert_eq!(result["diagnostics"][0]["span"]["line"], 2);
+
+        let result: Value = serde_json::from_str(&compile("write y", 0)).unwrap();
+        assert_eq!(result["ok"], false);
+        assert_eq!(result["listing"], Value::Null);
+        assert_eq!(result["diagnostics"][0]["severity"], "error");
+
+        let result: Value = serde_json::from_str(&run("read x; write x * 2", "21\n", 1)).unwrap();
+        assert_eq!(result["output"], "42\n");
+        assert!(result["diagnostics"].as_array().unwrap().is_empty());
+
+        let result: Value = serde_json::from_str(&run("read x;\nwrite 1 / x", "0\n", 0)).unwrap();
+        let error = &result["diagnostics"][0];
+        assert_eq!(error["severity"], "error");
+        assert_eq!(error["span"]["line"], 2);
+    }
+}
@@ -0,0 +1,48 @@
+* TINY Compilation to TM Code
+* Standard prelude:
+  0:     LD  6,0(0)
+  1:     ST  0,0(0)
+* End of standard prelude.
+  2:    LDC  0,0(0)
+  3:     ST  0,6(5)
+  4:     LD  0,6(5)
+  5:     ST  0,0(6)
+  6:     LD  0,6(5)
+  7:     LD  1,0(6)
+  8:    MUL  0,1,0
+  9:     ST  0,0(6)
+ 10:     LD  0,6(5)
+ 11:    LDA  1,1(5)
+ 12:    ADD  1,1,0
+ 13:     LD  0,0(6)
+ 14:     ST  0,0(1)
+ 15:     LD  0,6(5)
+ 16:     ST  0,0(6)
+ 17:    LDC  0,1(0)
+ 18:     LD  1,0(6)
+ 19:    ADD  0,1,0
+ 20:     ST  0,6(5)
+ 21:     LD  0,6(5)
+ 22:     ST  0,0(6)
+ 23:    LDC  0,5(0)
+ 24:     LD  1,0(6)
+ 25:    SUB  0,1,0
+ 26:    JEQ  0,2(7)
+ 27:    LDC  0,0(0)
+ 28:    LDA  7,1(7)
+ 29:    LDC  0,1(0)
+ 30:    JEQ  0,-27(7)
+ 31:    LDC  0,2(0)
+ 32:    LDA  1,1(5)
+ 33:    ADD  1,1,0
+ 34:     LD  0,0(1)
+ 35:     ST  0,0(6)
+ 36:    LDC  0,4(0)
+ 37:    LDA  1,1(5)
+ 38:    ADD  1,1,0
+ 39:     LD  0,0(1)
+ 40:     LD  1,0(6)
+ 41:    ADD  0,1,0
+ 42:    OUT  0,0,0
+* End of execution.
+ 43:   HALT  0,0,0
@@ -0,0 +1,53 @@
+* TINY Compilation to TM Code
+* Standard prelude:
+  0:     LD  6,0(0)
+  1:     ST  0,0(0)
+* End of standard prelude.
+  2:    LDA  7,34(7)
+  3:     ST  0,0(6)
+  4:     LD  0,-2(6)
+  5:     ST  0,-4(6)
+  6:    LDC  0,0(0)
+  7:     LD  1,-4(6)
+  8:    SUB  0,1,0
+  9:    JNE  0,2(7)
+ 10:    LDC  0,0(0)
+ 11:    LDA  7,1(7)
+ 12:    LDC  0,1(0)
+ 13:    JEQ  0,19(7)
+ 14:     LD  0,-2(6)
+ 15:     ST  0,-3(6)
+ 16:     LD  0,-1(6)
+ 17:     ST  0,-4(6)
+ 18:     LD  0,-1(6)
+ 19:     ST  0,-5(6)
+ 20:     LD  0,-2(6)
+ 21:     LD  1,-5(6)
+ 22:    DIV  0,1,0
+ 23:     ST  0,-5(6)
+ 24:     LD  0,-2(6)
+ 25:     LD  1,-5(6)
+ 26:    MUL  0,1,0
+ 27:     LD  1,-4(6)
+ 28:    SUB  0,1,0
+ 29:     ST  0,-2(6)
+ 30:     LD  0,-3(6)
+ 31:     ST  0,-1(6)
+ 32:    LDA  7,-29(7)
+ 33:     LD  0,-1(6)
+ 34:     LD  7,0(6)
+ 35:    LDC  0,0(0)
+ 36:     LD  7,0(6)
+ 37:     IN  0,0,0
+ 38:     ST  0,1(5)
+ 39:     IN  0,0,0
+ 40:     ST  0,2(5)
+ 41:     LD  0,1(5)
+ 42:     ST  0,-1(6)
+ 43:     LD  0,2(5)
+ 44:     ST  0,-2(6)
+ 45:    LDA  0,1(7)
+ 46:    LDA  7,-44(7)
+ 47:    OUT  0,0,0
+* End of execution.
+ 48:   HALT  0,0,0
@@ -0,0 +1,69 @@
+* TINY Compilation to TM Code
+* Standard prelude:
+  0:     LD  6,0(0)
+  1:     ST  0,0(0)
+* End of standard prelude.
+  2:     IN  0,0,0
+  3:     ST  0,1(5)
+  4:    LDC  0,0(0)
+  5:     ST  0,2(5)
+  6:     LD  0,1(5)
+  7:     ST  0,0(6)
+  8:    LDC  0,0(0)
+  9:     LD  1,0(6)
+ 10:    SUB  0,1,0
+ 11:    JGT  0,2(7)
+ 12:    LDC  0,0(0)
+ 13:    LDA  7,1(7)
+ 14:    LDC  0,1(0)
+ 15:    JEQ  0,13(7)
+ 16:     LD  0,1(5)
+ 17:     ST  0,0(6)
+ 18:    LDC  0,13(0)
+ 19:     LD  1,0(6)
+ 20:    SUB  0,1,0
+ 21:    JEQ  0,2(7)
+ 22:    LDC  0,0(0)
+ 23:    LDA  7,1(7)
+ 24:    LDC  0,1(0)
+ 25:    JEQ  0,2(7)
+ 26:    LDC  0,0(0)
+ 27:    LDA  7,1(7)
+ 28:    LDC  0,1(0)
+ 29:    JEQ  0,13(7)
+ 30:     LD  0,2(5)
+ 31:     ST  0,0(6)
+ 32:     LD  0,1(5)
+ 33:     LD  1,0(6)
+ 34:    ADD  0,1,0
+ 35:     ST  0,2(5)
+ 36:     LD  0,1(5)
+ 37:     ST  0,0(6)
+ 38:    LDC  0,1(0)
+ 39:     LD  1,0(6)
+ 40:    SUB  0,1,0
+ 41:     ST  0,1(5)
+ 42:    LDA  7,-37(7)
+ 43:     LD  0,2(5)
+ 44:     ST  0,0(6)
+ 45:    LDC  0,100(0)
+ 46:     LD  1,0(6)
+ 47:    SUB  0,1,0
+ 48:    JGE  0,2(7)
+ 49:    LDC  0,0(0)
+ 50:    LDA  7,1(7)
+ 51:    LDC  0,1(0)
+ 52:    JEQ  0,3(7)
+ 53:    LDA  0,3(5)
+ 54:   OUTS  0,0,0
+ 55:    LDA  7,2(7)
+ 56:     LD  0,2(5)
+ 57:    OUT  0,0,0
+* End of execution.
+ 58:   HALT  0,0,0
+      .DATA  3,108
+      .DATA  4,97
+      .DATA  5,114
+      .DATA  6,103
+      .DATA  7,101
+      .DATA  8,0